@@ -0,0 +1,158 @@
+// FORM failures and their classification
+use std::fmt;
+
+/// Custom error type for FORM execution errors
+/// Provides better type safety and error context than String
+#[derive(Debug)]
+pub enum FormError {
+    SpawnError(std::io::Error),
+    WriteError(std::io::Error),
+    ReadError(std::io::Error),
+    ExecutionError {
+        status: i32,
+        stderr: String,
+        kind: FormErrorKind,
+        /// Line of the input FORM's diagnostic points at, if it names one
+        line: Option<usize>,
+    },
+    Timeout,
+    InvalidUtf8(std::string::FromUtf8Error),
+    NotFound,
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormError::SpawnError(e) => write!(f, "Failed to spawn FORM: {}", e),
+            FormError::WriteError(e) => write!(f, "Failed to write to FORM: {}", e),
+            FormError::ReadError(e) => write!(f, "Failed to read from FORM: {}", e),
+            FormError::ExecutionError { status, stderr, .. } => {
+                if stderr.is_empty() {
+                    write!(f, "FORM exited with status {}", status)
+                } else {
+                    write!(f, "FORM error (exit {}): {}", status, stderr.trim())
+                }
+            }
+            FormError::Timeout => write!(f, "FORM execution timed out"),
+            FormError::InvalidUtf8(e) => write!(f, "Invalid UTF-8 in output: {}", e),
+            FormError::NotFound => write!(f, "FORM executable not found"),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+/// Input line named by a FORM diagnostic (`stdin Line 3 --> ...`,
+/// `calc.frm Line 12 ==> ...`) in its combined output
+pub fn diagnostic_line(text: &str) -> Option<usize> {
+    use regex::Regex;
+    use std::sync::LazyLock;
+
+    static DIAGNOSTIC_RE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?i)\bline\s+(\d+)\s*(?:-->|==>)").unwrap());
+    DIAGNOSTIC_RE.captures(text)?[1].parse().ok()
+}
+
+/// Category of a FORM failure, derived from its error output
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormErrorKind {
+    UndefinedSymbol(String),
+    SyntaxError { line: u32, message: String },
+    Overflow,
+    Timeout,
+    UnknownError(String),
+}
+
+impl FormErrorKind {
+    /// A short suggestion for the user, if there is one for this kind
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            FormErrorKind::UndefinedSymbol(name) => Some(format!(
+                "'{}' is not declared; add e.g. `Symbol {};` before using it",
+                name, name
+            )),
+            FormErrorKind::SyntaxError { line, .. } => {
+                Some(format!("Check the statement on line {} of the input", line))
+            }
+            FormErrorKind::Overflow => Some(String::from(
+                "FORM ran out of buffer space; raise WorkSpace/MaxTermSize in form.set",
+            )),
+            FormErrorKind::Timeout => Some(String::from(
+                "The computation hit a time limit; try a smaller problem or a larger timeout",
+            )),
+            FormErrorKind::UnknownError(_) => None,
+        }
+    }
+}
+
+/// Classify FORM's error output into a [`FormErrorKind`].
+///
+/// FORM reports problems as `Line N --> message`; a few messages
+/// (undeclared names, workspace overflow, time limits) get their own kinds.
+pub fn classify_error(stderr: &str) -> FormErrorKind {
+    use regex::Regex;
+    use std::sync::LazyLock;
+
+    static UNDEFINED_RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)(?:undeclared|undefined|unknown)\s+(?:variable|symbol|function|index|vector|expression|name)\s*:?\s*([A-Za-z_$][A-Za-z0-9_]*)").unwrap()
+    });
+    static OVERFLOW_RE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?i)overflow|is not enough|term too (?:complex|large)").unwrap());
+    static TIMEOUT_RE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?i)timed?\s*out|time\s*limit").unwrap());
+    static SYNTAX_RE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?i)line\s+(\d+)\s*-->\s*(.*)").unwrap());
+
+    if let Some(caps) = UNDEFINED_RE.captures(stderr) {
+        return FormErrorKind::UndefinedSymbol(caps[1].to_string());
+    }
+    if OVERFLOW_RE.is_match(stderr) {
+        return FormErrorKind::Overflow;
+    }
+    if TIMEOUT_RE.is_match(stderr) {
+        return FormErrorKind::Timeout;
+    }
+    if let Some(caps) = SYNTAX_RE.captures(stderr) {
+        return FormErrorKind::SyntaxError {
+            line: caps[1].parse().unwrap_or(0),
+            message: caps[2].trim().to_string(),
+        };
+    }
+
+    let first = stderr.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("");
+    FormErrorKind::UnknownError(first.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_classify_error() {
+        assert_eq!(
+            classify_error("test.frm Line 3 --> Undeclared variable y\nProgram terminated"),
+            FormErrorKind::UndefinedSymbol("y".to_string())
+        );
+        assert_eq!(
+            classify_error("<stdin> Line 2 --> Illegal character: @"),
+            FormErrorKind::SyntaxError { line: 2, message: "Illegal character: @".to_string() }
+        );
+        assert_eq!(
+            classify_error("WorkSpace overflow. 10000000 bytes is not enough."),
+            FormErrorKind::Overflow
+        );
+        assert_eq!(classify_error("Time limit exceeded"), FormErrorKind::Timeout);
+        assert_eq!(
+            classify_error("\nSomething odd happened\n"),
+            FormErrorKind::UnknownError("Something odd happened".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_diagnostic_line() {
+        assert_eq!(diagnostic_line("Symbol x;\nstdin Line 3 --> Undeclared variable y"), Some(3));
+        assert_eq!(diagnostic_line("calc.frm Line 12 ==> Illegal position for id"), Some(12));
+        // Echoed program text mentioning a line is not a diagnostic
+        assert_eq!(diagnostic_line("* see line 4 below\nProgram terminated"), None);
+    }
+}
@@ -0,0 +1,26 @@
+//! Shared FORM driver for the form-repl CLI and GUI.
+//!
+//! Finds the FORM executable, runs code through it (all at once or
+//! streamed line by line), strips FORM's banner and statistics from
+//! the output and tokenizes FORM code for highlighting.
+
+mod error;
+mod locate;
+mod output;
+mod run;
+mod tokenize;
+
+pub use error::{classify_error, diagnostic_line, FormError, FormErrorKind};
+pub use locate::{explicit_form_path, find_form_executable, locate_form};
+pub use output::{
+    format_output, format_output_with, is_stats_line, truncate_output, truncation_point, FormOutputParser,
+    FormStatistics, OutputConfig, ParsedOutput,
+};
+pub use run::{
+    prepare_input, resolve_form_flags, run_form, run_form_batch, run_form_streaming, split_flags, terminate_input,
+    FormResult, IdleWatchdog, RunOptions, StreamFilter, Terminator, IDLE_GRACE, STREAM_END,
+};
+pub use tokenize::{
+    form_words, highlight_code_with_spans, highlight_output_with_spans, is_closing_bracket, is_form_word,
+    is_function, is_opening_bracket, tokenize, tokenize_continued, LineState, StyledSpan, Token, TokenType,
+};
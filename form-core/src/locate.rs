@@ -0,0 +1,126 @@
+// Locating the FORM executable
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Finds the FORM executable in common locations.
+///
+/// Searches in this order:
+/// 1. `FORM_PATH` environment variable (if set)
+/// 2. `sources/form` (local directory)
+/// 3. `../sources/form` (parent directory)  
+/// 4. Directories in PATH environment variable
+///
+/// # Returns
+///
+/// `Some(PathBuf)` if found, `None` otherwise.
+pub fn find_form_executable() -> Option<PathBuf> {
+    // 1. Check FORM_PATH environment variable first
+    if let Ok(form_path) = env::var("FORM_PATH") {
+        let path = PathBuf::from(&form_path);
+        if path.is_file() {
+            return Some(path);
+        }
+        // Also try as directory containing 'form'
+        let form_in_dir = path.join("form");
+        if form_in_dir.exists() {
+            return Some(form_in_dir);
+        }
+    }
+    
+    // 2. Check local sources directory
+    let local = PathBuf::from("sources/form");
+    if local.exists() {
+        return Some(local);
+    }
+
+    // 3. Check parent sources directory
+    let parent = PathBuf::from("../sources/form");
+    if parent.exists() {
+        return Some(parent);
+    }
+
+    // 4. Search in PATH
+    if let Ok(path) = env::var("PATH") {
+        for dir in env::split_paths(&path) {
+            let form_path = dir.join("form");
+            if form_path.exists() {
+                return Some(form_path);
+            }
+        }
+    }
+    
+    None
+}
+
+/// Checks a FORM path given explicitly on the command line.
+///
+/// Accepts the executable itself or a directory containing `form`, like
+/// `FORM_PATH`. Fails if nothing is there or the file is not executable.
+pub fn explicit_form_path(path: &Path) -> Result<PathBuf, String> {
+    let candidate = if path.is_dir() { path.join("form") } else { path.to_path_buf() };
+    if !candidate.is_file() {
+        return Err(format!("FORM executable not found at {}", candidate.display()));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = candidate
+            .metadata()
+            .map(|m| m.permissions().mode())
+            .unwrap_or(0);
+        if mode & 0o111 == 0 {
+            return Err(format!("{} is not executable", candidate.display()));
+        }
+    }
+    Ok(candidate)
+}
+
+/// Picks the FORM executable: an explicit `--form-path` wins, then the
+/// configured path, then [`find_form_executable`].
+pub fn locate_form(
+    explicit: Option<&Path>,
+    configured: Option<PathBuf>,
+) -> Result<Option<PathBuf>, String> {
+    match explicit {
+        Some(path) => explicit_form_path(path).map(Some),
+        None => Ok(configured.or_else(find_form_executable)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_explicit_form_path_wins_over_env() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let dir = env::temp_dir().join(format!("form-repl-path-test-{}", std::process::id()));
+        let from_env = dir.join("env");
+        let explicit = dir.join("explicit");
+        for sub in [&from_env, &explicit] {
+            std::fs::create_dir_all(sub).unwrap();
+            let exe = sub.join("form");
+            std::fs::write(&exe, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        env::set_var("FORM_PATH", &from_env);
+        
+        assert_eq!(locate_form(Some(&explicit), None), Ok(Some(explicit.join("form"))));
+        assert_eq!(
+            locate_form(Some(&explicit.join("form")), None),
+            Ok(Some(explicit.join("form")))
+        );
+        assert_eq!(locate_form(None, None), Ok(Some(from_env.join("form"))));
+        
+        std::fs::write(explicit.join("notes.txt"), "").unwrap();
+        assert!(locate_form(Some(&explicit.join("notes.txt")), None).is_err());
+        assert!(locate_form(Some(&dir.join("missing")), None).is_err());
+        
+        env::remove_var("FORM_PATH");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+}
@@ -0,0 +1,528 @@
+// Formatting FORM output for display
+use serde::Deserialize;
+
+/// How FORM output is displayed
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// Truncate long outputs to this many lines (0 = unlimited)
+    pub max_lines: usize,
+    /// Show FORM's module statistics (terms in output, bytes used)
+    pub show_stats: bool,
+    /// Strip FORM's version banner
+    pub trim_header: bool,
+    /// Show FORM warnings and the REPL's pre-run warnings
+    pub show_warnings: bool,
+    /// Wrap expression lines at this width instead of the terminal's (0 = never)
+    pub wrap_width: Option<usize>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            max_lines: 50,
+            show_stats: true,
+            trim_header: true,
+            show_warnings: true,
+            wrap_width: None,
+        }
+    }
+}
+
+/// The first line of FORM's banner, e.g.
+/// `FORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits  Run: Thu Oct 16 10:00:00 2026`
+fn is_version_line(line: &str) -> bool {
+    use regex::Regex;
+    use std::sync::LazyLock;
+
+    static VERSION_RE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"^\s*(?:T|Par)?FORM\s+v?\d|(?i)^\s*FORM\b.*\bversion\s+\d").unwrap());
+    VERSION_RE.is_match(line)
+}
+
+/// Lines FORM prints about a run rather than its results: the run date,
+/// per-module statistics and timing
+fn is_metadata_line(line: &str) -> bool {
+    use regex::Regex;
+    use std::sync::LazyLock;
+
+    static METADATA_RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^\s*(?:Time\s*=|Generated terms|(?:\S+\s+)?(?:Terms in output|Terms active|Bytes used|Bytes in use)\s*=)|sec out of|\bRun(?: at)?:")
+            .unwrap()
+    });
+    METADATA_RE.is_match(line)
+}
+
+/// A line of input FORM echoes back, which it indents by four spaces
+fn is_echo_line(line: &str) -> bool {
+    line.trim().is_empty() || line.starts_with("    ")
+}
+
+/// FORM's per-module term and memory statistics, including the
+/// `Terms active`/`Bytes in use` lines of runs still in progress
+pub fn is_stats_line(line: &str) -> bool {
+    ["Terms in output", "Terms active", "Bytes used", "Bytes in use"]
+        .iter()
+        .any(|stat| line.contains(stat))
+}
+
+/// FORM's per-module timing statistics
+pub(crate) fn is_timing_line(line: &str) -> bool {
+    line.contains("sec out of") || line.trim_start().starts_with("Time =")
+}
+
+/// Numbers from FORM's per-module statistics. Term and byte counts are
+/// those of the last module, summed over its expressions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormStatistics {
+    /// Modules FORM reported on, one `Time =` block each
+    pub modules: usize,
+    /// CPU seconds used by the end of the last module
+    pub time_secs: Option<f64>,
+    pub generated_terms: Option<u64>,
+    pub terms_in_output: Option<u64>,
+    pub bytes_used: Option<u64>,
+}
+
+impl FormStatistics {
+    fn update(&mut self, line: &str) {
+        use regex::Regex;
+        use std::sync::LazyLock;
+
+        static TIME_RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"Time\s*=\s*([\d.]+)\s*sec").unwrap());
+        static COUNT_RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"(Generated terms|Terms in output|Bytes used)\s*=\s*(\d+)").unwrap()
+        });
+
+        if let Some(caps) = TIME_RE.captures(line) {
+            self.modules += 1;
+            self.time_secs = caps[1].parse().ok();
+            self.generated_terms = None;
+            self.terms_in_output = None;
+            self.bytes_used = None;
+        }
+        for caps in COUNT_RE.captures_iter(line) {
+            let Ok(n) = caps[2].parse::<u64>() else { continue };
+            let field = match &caps[1] {
+                "Generated terms" => &mut self.generated_terms,
+                "Terms in output" => &mut self.terms_in_output,
+                _ => &mut self.bytes_used,
+            };
+            *field = Some(field.unwrap_or(0) + n);
+        }
+    }
+}
+
+/// FORM output split into its parts by `FormOutputParser`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedOutput {
+    /// FORM's banner: the version line, the echoed input and the
+    /// statistics printed before the first results
+    pub header: String,
+    /// The results, statistics after the header included, without timing
+    /// lines or trailing blank lines
+    pub content: String,
+    /// The last timing line, trimmed, if `show_timing` was asked for
+    pub timing: Option<String>,
+    pub stats: FormStatistics,
+}
+
+/// Where `FormOutputParser` is in FORM's output
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParseState {
+    /// Nothing but blank lines seen yet
+    Start,
+    /// After the version line: echoed input, blank lines included, until
+    /// the first `Time =` line or a line FORM printed itself, such as an
+    /// expression header or `#write` output
+    Header,
+    /// In the first module's statistics; the next line that isn't blank
+    /// or metadata starts the content
+    Stats,
+    Content,
+}
+
+/// What a line of FORM output is, as decided by `FormOutputParser::classify`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LineKind {
+    Header,
+    Timing,
+    Content,
+}
+
+/// Splits FORM output into banner, results, timing and statistics.
+///
+/// After the version line the echoed input, blank lines and all, is banner
+/// up to the first line that isn't echo; if that is the first module's
+/// `Time =` line, its statistics and the blank lines after them are banner
+/// too. So neither ever reaches the results, while result lines that merely
+/// look like metadata (`Version = 2;`) are kept. Output without a version
+/// line is all content.
+#[derive(Debug)]
+pub struct FormOutputParser {
+    state: ParseState,
+}
+
+impl Default for FormOutputParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FormOutputParser {
+    pub fn new() -> Self {
+        FormOutputParser { state: ParseState::Start }
+    }
+
+    /// Parses a complete run's output
+    pub fn parse(raw: &str, show_timing: bool) -> ParsedOutput {
+        let mut parser = FormOutputParser::new();
+        let mut header = Vec::new();
+        let mut content = Vec::new();
+        let mut timing = None;
+        let mut stats = FormStatistics::default();
+
+        for line in raw.lines() {
+            stats.update(line);
+            match parser.classify(line) {
+                LineKind::Header => header.push(line),
+                LineKind::Timing => timing = Some(line.trim().to_string()),
+                LineKind::Content => content.push(line),
+            }
+        }
+        while content.last().is_some_and(|l| l.trim().is_empty()) {
+            content.pop();
+        }
+
+        ParsedOutput {
+            header: header.join("\n"),
+            content: content.join("\n"),
+            timing: timing.filter(|_| show_timing),
+            stats,
+        }
+    }
+
+    /// Classifies the next line, one at a time as FORM prints them
+    pub(crate) fn classify(&mut self, line: &str) -> LineKind {
+        let blank = line.trim().is_empty();
+        self.state = match self.state {
+            ParseState::Start if blank => ParseState::Start,
+            ParseState::Start if is_version_line(line) => ParseState::Header,
+            ParseState::Start => ParseState::Content,
+            ParseState::Header if is_timing_line(line) => ParseState::Stats,
+            ParseState::Header if is_echo_line(line) || is_metadata_line(line) => ParseState::Header,
+            ParseState::Header => ParseState::Content,
+            ParseState::Stats if blank || is_metadata_line(line) => ParseState::Stats,
+            ParseState::Stats => ParseState::Content,
+            ParseState::Content => ParseState::Content,
+        };
+
+        if is_timing_line(line) {
+            LineKind::Timing
+        } else if self.state == ParseState::Content {
+            LineKind::Content
+        } else {
+            LineKind::Header
+        }
+    }
+}
+
+/// Formats FORM output by removing timing and metadata lines.
+///
+/// Filters out FORM version info, timing statistics, and other metadata,
+/// leaving only the actual computation results.
+///
+/// # Arguments
+///
+/// * `output` - Raw output from FORM execution
+/// * `show_timing` - Whether to include timing information
+/// * `max_lines` - Truncate to this many lines (0 = unlimited)
+///
+/// # Returns
+///
+/// Formatted output string with metadata removed.
+pub fn format_output(output: &str, show_timing: bool, max_lines: usize) -> String {
+    let config = OutputConfig {
+        max_lines,
+        ..OutputConfig::default()
+    };
+    format_output_with(output, show_timing, &config)
+}
+
+/// `format_output` controlled by the `[output]` config section: line limit,
+/// FORM banner and statistics, and FORM warnings
+pub fn format_output_with(output: &str, show_timing: bool, config: &OutputConfig) -> String {
+    let parsed = FormOutputParser::parse(output, show_timing);
+    let header = if config.trim_header { "" } else { parsed.header.as_str() };
+
+    let result: Vec<&str> = header
+        .lines()
+        .filter(|line| !is_timing_line(line))
+        .chain(parsed.content.lines())
+        .filter(|line| {
+            (config.show_stats || !is_stats_line(line))
+                && (config.show_warnings || !line.contains("Warning"))
+        })
+        .collect();
+
+    let mut formatted = truncate_output(&result.join("\n"), config.max_lines);
+
+    // Optionally append timing
+    if let Some(timing) = parsed.timing {
+        if !formatted.is_empty() {
+            formatted.push_str("\n\n");
+        }
+        formatted.push_str(&timing);
+    }
+
+    formatted
+}
+
+/// Whether FORM carries the term on `line` over to the next one, as it
+/// does when a term is longer than its line width (`210*` then `x^4*y^6`,
+/// or a long number split with `\`)
+fn continues_term(line: &str) -> bool {
+    line.trim_end().ends_with(['*', '^', '/', '(', ',', '=', '\\'])
+}
+
+/// How many of `lines` to show for a limit of `max_lines` (0 = unlimited).
+///
+/// The cut goes between terms: it backs off from `max_lines` to the last
+/// line that doesn't continue onto the next, or, if there is none, goes on
+/// to the first such line after it.
+pub fn truncation_point(lines: &[&str], max_lines: usize) -> usize {
+    if max_lines == 0 || lines.len() <= max_lines {
+        return lines.len();
+    }
+    let ends_term = |n: &usize| !continues_term(lines[n - 1]);
+    (1..=max_lines)
+        .rev()
+        .find(ends_term)
+        .or_else(|| (max_lines + 1..lines.len()).find(ends_term))
+        .unwrap_or(lines.len())
+}
+
+/// Keep the first `max_lines` lines (0 = unlimited), noting how many were cut
+pub fn truncate_output(text: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let keep = truncation_point(&lines, max_lines);
+    if keep == lines.len() {
+        return text.to_string();
+    }
+    format!(
+        "{}\n... ({} more lines, %expand to show)",
+        lines[..keep].join("\n"),
+        lines.len() - keep
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_format_output() {
+        let output = "FORM 4.3\n\n   E =\n      x^2;\n\n  0.00 sec out of 0.00 sec\n";
+        let formatted = format_output(output, false, 0);
+        assert!(formatted.contains("E ="));
+        assert!(!formatted.contains("FORM"));
+        assert!(!formatted.contains("sec out of"));
+    }
+    
+    #[test]
+    fn test_format_output_with_output_config() {
+        let output = "FORM 4.3 (Jan 1 2024)\n\n   E = x;\n\
+                      Time =       0.00 sec    Generated terms =          1\n\
+                      \x20              E         Terms in output =          1\n\
+                      \x20                        Bytes used      =         44\n";
+        let mut config = OutputConfig::default();
+        assert_eq!(
+            format_output_with(output, false, &config).lines().filter(|l| l.contains("Terms")).count(),
+            1
+        );
+        
+        config.show_stats = false;
+        assert_eq!(format_output_with(output, false, &config), "   E = x;");
+        
+        config.trim_header = false;
+        assert!(format_output_with(output, false, &config).starts_with("FORM 4.3"));
+        
+        let warned = "   E = x;\nfoo.frm Line 2 --> Warning: unused symbol y\n";
+        config.show_warnings = false;
+        assert_eq!(format_output_with(warned, false, &config), "   E = x;");
+    }
+    
+    #[test]
+    fn test_is_stats_line() {
+        assert!(is_stats_line("               E         Terms in output =          3"));
+        assert!(is_stats_line("                         Bytes used      =         52"));
+        assert!(is_stats_line("               E         Terms active    =     120312"));
+        assert!(is_stats_line("                         Bytes in use    =    4812480"));
+        assert!(!is_stats_line("   E = x + y;"));
+        assert!(!is_stats_line("Time =       0.00 sec    Generated terms =          3"));
+    }
+
+    /// `form` 4.3.1 on `Symbols x,y; Local E = (x+y)^2; Print; .end`,
+    /// with the input echoed as usual
+    const ECHOED_RUN: &str = "\
+FORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits         Run: Thu Oct 16 10:00:00 2026
+    Symbols x,y;
+    Local E = (x+y)^2;
+    Print;
+    .end
+
+Time =       0.00 sec    Generated terms =          3
+               E         Terms in output =          3
+                         Bytes used      =         52
+
+   E =
+      y^2 + 2*x*y + x^2;
+
+  0.00 sec out of 0.00 sec
+";
+
+    /// `tform -w2` on two modules, each with two expressions
+    const TFORM_RUN: &str = "\
+TFORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits 2 workers  Run: Thu Oct 16 10:00:00 2026
+    #-
+
+Time =       0.01 sec    Generated terms =          4
+               E         Terms in output =          3
+                         Bytes used      =         52
+               F         Terms in output =          1
+                         Bytes used      =         20
+
+Time =       0.02 sec    Generated terms =          6
+               E         Terms in output =          2
+                         Bytes used      =         40
+               F         Terms in output =          2
+                         Bytes used      =         36
+
+   E =
+      x + y;
+
+   F =
+      2*x;
+
+  0.01 sec + 0.01 sec: 0.02 sec out of 0.03 sec
+";
+
+    #[test]
+    fn test_parser_splits_echoed_run() {
+        let parsed = FormOutputParser::parse(ECHOED_RUN, true);
+        assert!(parsed.header.starts_with("FORM 4.3.1"));
+        assert!(parsed.header.contains("Local E = (x+y)^2;"));
+        assert_eq!(parsed.content, "   E =\n      y^2 + 2*x*y + x^2;");
+        assert_eq!(parsed.timing.as_deref(), Some("0.00 sec out of 0.00 sec"));
+        assert_eq!(
+            parsed.stats,
+            FormStatistics {
+                modules: 1,
+                time_secs: Some(0.0),
+                generated_terms: Some(3),
+                terms_in_output: Some(3),
+                bytes_used: Some(52),
+            }
+        );
+        assert_eq!(FormOutputParser::parse(ECHOED_RUN, false).timing, None);
+        // The echoed input no longer leaks into the results
+        assert_eq!(format_output(ECHOED_RUN, false, 0), "   E =\n      y^2 + 2*x*y + x^2;");
+    }
+
+    #[test]
+    fn test_parser_keeps_echo_with_blank_lines_out_of_results() {
+        let output = "\
+FORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits         Run: Thu Oct 16 10:00:00 2026
+    Symbols x,y;
+
+    Local E = (x+y)^2;
+    Print;
+    .end
+
+Time =       0.00 sec    Generated terms =          3
+               E         Terms in output =          3
+                         Bytes used      =         52
+
+   E =
+      y^2 + 2*x*y + x^2;
+
+  0.00 sec out of 0.00 sec
+";
+        let parsed = FormOutputParser::parse(output, false);
+        assert!(parsed.header.contains("Local E = (x+y)^2;"));
+        assert!(parsed.header.contains(".end"));
+        assert_eq!(parsed.content, "   E =\n      y^2 + 2*x*y + x^2;");
+
+        // Preprocessor output between echoed lines is a result
+        let output = "FORM 4.3 (Jan 1 2024)\n\n    #write \"hi\"\nhi\n\n  0.00 sec out of 0.00 sec\n";
+        assert_eq!(FormOutputParser::parse(output, false).content, "hi");
+    }
+
+    #[test]
+    fn test_parser_sums_last_module_stats() {
+        let parsed = FormOutputParser::parse(TFORM_RUN, true);
+        assert!(parsed.header.starts_with("TFORM"));
+        assert_eq!(parsed.content, "   E =\n      x + y;\n\n   F =\n      2*x;");
+        assert_eq!(parsed.timing.as_deref(), Some("0.01 sec + 0.01 sec: 0.02 sec out of 0.03 sec"));
+        assert_eq!(parsed.stats.modules, 2);
+        assert_eq!(parsed.stats.time_secs, Some(0.02));
+        assert_eq!(parsed.stats.generated_terms, Some(6));
+        assert_eq!(parsed.stats.terms_in_output, Some(4));
+        assert_eq!(parsed.stats.bytes_used, Some(76));
+    }
+
+    #[test]
+    fn test_parser_keeps_content_that_looks_like_metadata() {
+        // Expressions named like banner words used to be cut as header
+        let output = "FORM 4.3 (Jan 1 2024)\n\n   Version =\n      2;\n\n   Run =\n      1;\n";
+        let parsed = FormOutputParser::parse(output, false);
+        assert_eq!(parsed.header, "FORM 4.3 (Jan 1 2024)\n");
+        assert_eq!(parsed.content, "   Version =\n      2;\n\n   Run =\n      1;");
+
+        // Without a banner everything is content
+        let parsed = FormOutputParser::parse("\n   E = x;\n", false);
+        assert_eq!(parsed.header, "");
+        assert_eq!(parsed.content, "   E = x;");
+        assert_eq!(parsed.stats, FormStatistics::default());
+    }
+
+    #[test]
+    fn test_format_output_truncates() {
+        let output = "FORM 4.3\n\n   E =\n      + x\n      + x^2\n      + x^3\n      + x^4;\n";
+        let formatted = format_output(output, false, 2);
+        assert_eq!(formatted, "   E =\n      + x\n... (3 more lines, %expand to show)");
+        assert_eq!(format_output(output, false, 0).lines().count(), 5);
+        assert_eq!(format_output(output, false, 5).lines().count(), 5);
+    }
+
+    #[test]
+    fn test_truncation_keeps_wrapped_terms_whole() {
+        // FORM split the term 210*x^4*y^6 and a long number across lines
+        let lines = [
+            "   E =",
+            "       + 10*x^9*y + 45*x^8*y^2 + 210*",
+            "      x^4*y^6 + 12345678901234567890\\",
+            "      123 + y^10",
+            "       + x^10;",
+            "",
+            "   F = 1;",
+        ];
+        // Never right after the label or inside a wrapped term: with no
+        // term boundary before the limit the cut moves past it
+        for max_lines in 1..=4 {
+            assert_eq!(truncation_point(&lines, max_lines), 4, "{}", max_lines);
+        }
+        assert_eq!(truncation_point(&lines, 5), 5);
+        assert_eq!(truncation_point(&lines, 0), lines.len());
+        assert_eq!(truncation_point(&lines, 7), lines.len());
+
+        // Otherwise it backs off to the last whole term
+        let lines = ["   E =", "      + a*", "      b + c", "      + d*", "      e;"];
+        assert_eq!(truncation_point(&lines, 4), 3);
+        assert_eq!(
+            truncate_output(&lines.join("\n"), 4),
+            "   E =\n      + a*\n      b + c\n... (2 more lines, %expand to show)"
+        );
+    }
+}
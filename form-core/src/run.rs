@@ -0,0 +1,892 @@
+// Running FORM as a child process
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::{classify_error, diagnostic_line, FormError, FormErrorKind};
+use crate::output::{FormOutputParser, LineKind};
+
+/// How long FORM may stay silent before the user is warned it may be stuck
+pub const IDLE_GRACE: Duration = Duration::from_secs(10);
+
+/// Polling interval used while waiting for FORM output
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Options controlling how FORM is invoked
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Extra command-line arguments, placed before the `-` stdin argument
+    pub extra_args: Vec<String>,
+    /// Kill FORM if it runs longer than this
+    pub timeout: Option<Duration>,
+    /// Working directory for the FORM process
+    pub workdir: Option<PathBuf>,
+    /// Instruction appended to the input if missing; `None` sends the
+    /// input as written (`[settings] auto_end = false`)
+    pub terminator: Option<Terminator>,
+    /// Environment variables set for FORM only, on top of this process's own
+    pub env: Vec<(String, String)>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            extra_args: Vec::new(),
+            timeout: None,
+            workdir: None,
+            terminator: Some(Terminator::End),
+            env: Vec::new(),
+        }
+    }
+}
+
+/// How the input assembled for FORM ends
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Terminator {
+    /// `.end`: FORM finishes the program and exits
+    #[default]
+    End,
+    /// `.sort`: the module ends and FORM waits for more, as between the
+    /// cells of a program that keeps running
+    Sort,
+}
+
+impl Terminator {
+    /// The module instruction itself
+    pub fn instruction(self) -> &'static str {
+        match self {
+            Terminator::End => ".end",
+            Terminator::Sort => ".sort",
+        }
+    }
+}
+
+/// Result of FORM execution with timing information
+#[derive(Debug, Clone)]
+pub struct FormResult {
+    pub output: String,
+    pub stderr: String,
+    pub duration: Duration,
+    pub exit_code: i32,
+}
+
+/// Detects when a running FORM process has gone quiet for too long.
+///
+/// FORM can block waiting on stdin (e.g. a `#prompt` or an unterminated
+/// module). The watchdog fires once per idle stretch so the caller can
+/// tell the user to interrupt instead of staring at a silent prompt.
+#[derive(Debug)]
+pub struct IdleWatchdog {
+    grace: Duration,
+    last_activity: Instant,
+    warned: bool,
+}
+
+impl IdleWatchdog {
+    pub fn new(grace: Duration) -> Self {
+        IdleWatchdog {
+            grace,
+            last_activity: Instant::now(),
+            warned: false,
+        }
+    }
+
+    /// Record activity (output received), re-arming the watchdog
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+        self.warned = false;
+    }
+
+    /// Time elapsed since the last recorded activity
+    pub fn idle_for(&self) -> Duration {
+        self.last_activity.elapsed()
+    }
+
+    /// Returns true the first time the grace period is exceeded
+    pub fn check(&mut self) -> bool {
+        if !self.warned && self.idle_for() >= self.grace {
+            self.warned = true;
+            return true;
+        }
+        false
+    }
+}
+
+/// Assembles the exact text piped to FORM's stdin.
+///
+/// Appends `.end` unless the input already ends with it.
+pub fn prepare_input(input: &str) -> String {
+    terminate_input(input, Some(Terminator::End))
+}
+
+/// Ends `input` with `terminator` unless it already ends that way.
+///
+/// `.sort` is not added after another module instruction (such as `.store`
+/// or a closing `.end`); with no terminator the input is left untouched.
+pub fn terminate_input(input: &str, terminator: Option<Terminator>) -> String {
+    let ended = match terminator {
+        None => true,
+        Some(Terminator::End) => input.trim_end().ends_with(".end"),
+        Some(Terminator::Sort) => input.lines().rev().find(|l| !l.trim().is_empty()).is_some_and(|l| {
+            let l = l.trim().to_lowercase();
+            l.starts_with(".end") || MODULE_ENDS.iter().any(|end| l.starts_with(end))
+        }),
+    };
+    match terminator {
+        Some(terminator) if !ended => format!("{}\n{}", input, terminator.instruction()),
+        _ => input.to_string(),
+    }
+}
+
+/// Split a flags string such as `FORM_FLAGS="-w4 -t /scratch"` into arguments
+pub fn split_flags(flags: &str) -> Vec<String> {
+    flags.split_whitespace().map(String::from).collect()
+}
+
+/// Chooses the extra FORM arguments.
+///
+/// Precedence: `--form-flags` on the command line overrides the config
+/// file, which overrides the `FORM_FLAGS` environment variable.
+pub fn resolve_form_flags(cli: Option<&str>, config: &[String], env_flags: Option<&str>) -> Vec<String> {
+    if let Some(flags) = cli {
+        return split_flags(flags);
+    }
+    if !config.is_empty() {
+        return config.to_vec();
+    }
+    env_flags.map(split_flags).unwrap_or_default()
+}
+
+/// Build the FORM command line; `-` (read from stdin) is always the last argument
+fn build_command(form_path: &Path, options: &RunOptions) -> Command {
+    let mut command = Command::new(form_path);
+    command
+        .args(options.extra_args.iter().filter(|arg| arg.as_str() != "-"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .envs(options.env.iter().map(|(name, value)| (name, value)));
+    if let Some(ref workdir) = options.workdir {
+        command.current_dir(workdir);
+    }
+    command
+}
+
+/// Feeds `input` to FORM from its own thread, closing stdin when done.
+///
+/// FORM echoes what it reads, so writing a long program before reading
+/// stdout could fill the stdout pipe and leave both sides waiting.
+fn write_input(mut stdin: ChildStdin, input: String) -> JoinHandle<io::Result<()>> {
+    thread::spawn(move || stdin.write_all(input.as_bytes()))
+}
+
+/// The result of the thread started by `write_input`
+fn finish_input(writer: JoinHandle<io::Result<()>>) -> Result<(), FormError> {
+    writer
+        .join()
+        .map_err(|_| FormError::WriteError(io::Error::other("stdin writer panicked")))?
+        .map_err(FormError::WriteError)
+}
+
+/// Classify a failure to start FORM.
+///
+/// `NotFound` means the executable is gone (e.g. moved or deleted mid-session)
+/// only if `form_path` no longer exists; a missing working directory gives
+/// the same error kind and stays a `SpawnError`.
+fn spawn_error(form_path: &Path, e: io::Error) -> FormError {
+    if e.kind() == io::ErrorKind::NotFound && !form_path.is_file() {
+        FormError::NotFound
+    } else {
+        FormError::SpawnError(e)
+    }
+}
+
+/// Executes FORM with the given input.
+///
+/// # Arguments
+///
+/// * `input` - The FORM code to execute
+/// * `form_path` - Path to the FORM executable
+/// * `options` - Extra arguments, timeout and working directory
+///
+/// Each invocation is logged at debug level (see `term::init_logging`).
+///
+/// # Returns
+///
+/// `Ok(FormResult)` with FORM output on success, `Err(FormError)` on failure.
+pub fn run_form(
+    input: &str,
+    form_path: &Path,
+    options: &RunOptions,
+) -> Result<FormResult, FormError> {
+    let result = run_to_exit(input, form_path, options)?;
+    if result.exit_code != 0 {
+        return Err(execution_error(&result));
+    }
+    Ok(result)
+}
+
+/// The error for a run FORM finished unsuccessfully
+fn execution_error(result: &FormResult) -> FormError {
+    // FORM prints most diagnostics on stdout, so classify both streams
+    let diagnostics = format!("{}\n{}", result.stderr, result.output);
+    FormError::ExecutionError {
+        status: result.exit_code,
+        stderr: result.stderr.clone(),
+        kind: classify_error(&diagnostics),
+        line: diagnostic_line(&diagnostics),
+    }
+}
+
+/// `run_form` without the exit status check: whatever FORM printed, even
+/// when it failed. If FORM goes quiet for `IDLE_GRACE`, a warning is logged
+/// at `warn` level for the embedder's logger to show or drop.
+fn run_to_exit(
+    input: &str,
+    form_path: &Path,
+    options: &RunOptions,
+) -> Result<FormResult, FormError> {
+    let start = Instant::now();
+    
+    log::debug!(
+        "run_form: {} bytes of input, form_path={}, workdir={}, args={:?}",
+        input.len(),
+        form_path.display(),
+        options
+            .workdir
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| ".".to_string()),
+        options.extra_args
+    );
+
+    let mut command = build_command(form_path, options);
+    let mut child = command.spawn().map_err(|e| spawn_error(form_path, e))?;
+
+    // Get handles to stdin, stdout, and stderr
+    let stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+
+    // Prepare input - end it with .end unless the options say otherwise
+    let full_input = terminate_input(input, options.terminator);
+
+    // Write input to FORM while its output is being read
+    let writer = write_input(stdin, full_input);
+
+    // Read stderr in the background so a chatty stderr can't block stdout
+    let stderr_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    // Read stdout in chunks, watching for FORM going silent
+    let (tx, rx) = mpsc::channel();
+    let stdout_reader = thread::spawn(move || -> io::Result<()> {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stdout.read(&mut buf)?;
+            if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                return Ok(());
+            }
+        }
+    });
+
+    let mut output = Vec::new();
+    let mut watchdog = IdleWatchdog::new(IDLE_GRACE);
+    loop {
+        // Checked on every pass, so FORM can't outrun it by printing
+        if options.timeout.is_some_and(|limit| start.elapsed() >= limit) {
+            log::debug!("run_form: timed out after {:?}, killing FORM", start.elapsed());
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(FormError::Timeout);
+        }
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(chunk) => {
+                output.extend_from_slice(&chunk);
+                watchdog.touch();
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if watchdog.check() {
+                    log::warn!(
+                        "FORM has produced no output for {}s; it may be waiting for input. \
+                         Press Ctrl+C to interrupt.",
+                        watchdog.idle_for().as_secs()
+                    );
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    finish_input(writer)?;
+    stdout_reader
+        .join()
+        .map_err(|_| FormError::ReadError(io::Error::other("stdout reader panicked")))?
+        .map_err(FormError::ReadError)?;
+    let stderr_output = stderr_reader
+        .join()
+        .map_err(|_| FormError::ReadError(io::Error::other("stderr reader panicked")))?
+        .map_err(FormError::ReadError)?;
+
+    let status = child.wait().map_err(FormError::ReadError)?;
+    let duration = start.elapsed();
+    
+    let output_str = String::from_utf8(output).map_err(FormError::InvalidUtf8)?;
+    let stderr_str = String::from_utf8_lossy(&stderr_output).to_string();
+
+    let exit_code = status.code().unwrap_or(-1);
+
+    log::debug!("run_form: exit code {} after {:?}", exit_code, duration);
+    if !stderr_str.is_empty() {
+        log::debug!("run_form: stderr: {}", stderr_str.trim_end());
+    }
+    
+    Ok(FormResult {
+        output: output_str,
+        stderr: stderr_str,
+        duration,
+        exit_code,
+    })
+}
+
+/// Printed between the blocks of a batch, as `~~~form-repl:block N`
+const BATCH_MARKER: &str = "form-repl:block";
+
+/// Module instructions that already end a block
+const MODULE_ENDS: &[&str] = &[".sort", ".store", ".global", ".clear"];
+
+/// The program run by `run_form_batch`, and the line each block's code
+/// starts on in it (0-based)
+fn batch_program(inputs: &[String]) -> (String, Vec<usize>) {
+    // Without the echoed input, only results come between the markers
+    let mut lines = vec!["#-".to_string()];
+    let mut starts = Vec::with_capacity(inputs.len());
+    for (i, input) in inputs.iter().enumerate() {
+        if i > 0 {
+            lines.push(format!("#message {} {}", BATCH_MARKER, i));
+        }
+        starts.push(lines.len());
+        let body: Vec<&str> = input.lines().filter(|l| l.trim() != ".end").collect();
+        let block = terminate_input(&body.join("\n"), Some(Terminator::Sort));
+        lines.extend(block.lines().map(String::from));
+    }
+    (lines.join("\n"), starts)
+}
+
+/// Splits a batch's output at its markers, one piece per block that was
+/// reached. Each piece after the first gets FORM's banner line, so every
+/// piece reads like the output of a run of its own.
+fn split_batch_output(output: &str) -> Vec<String> {
+    let banner = output.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    let mut pieces = vec![String::new()];
+    for line in output.lines() {
+        let marker = line
+            .trim()
+            .strip_prefix("~~~")
+            .and_then(|rest| rest.strip_prefix(BATCH_MARKER))
+            .and_then(|n| n.trim().parse::<usize>().ok());
+        if marker == Some(pieces.len()) {
+            pieces.push(format!("{}\n\n", banner));
+            continue;
+        }
+        let piece = pieces.last_mut().unwrap();
+        piece.push_str(line);
+        piece.push('\n');
+    }
+    pieces
+}
+
+/// CPU seconds FORM reported by the end of `piece`, from its `Time =` lines
+fn cpu_time(piece: &str) -> Option<Duration> {
+    FormOutputParser::parse(piece, false)
+        .stats
+        .time_secs
+        .map(Duration::from_secs_f64)
+}
+
+/// Runs several cells with one FORM process instead of one each.
+///
+/// The cells run in order as modules of a single program (each ends with
+/// `.sort` unless it already ends a module), so later ones see what earlier
+/// ones declared, just as when they are run cumulatively. FORM prints a
+/// marker between them, by which the output is split back up; a block's
+/// duration is the CPU time FORM reported for it.
+///
+/// If FORM fails, the blocks before the failing one still get their
+/// output, the failing one gets the error (its line number counted from
+/// the block's own first line), and the ones after it are reported as not
+/// run. An error before FORM could run at all goes to the first block.
+pub fn run_form_batch(inputs: &[String], form_path: &Path, options: &RunOptions) -> Vec<Result<FormResult, FormError>> {
+    if inputs.is_empty() {
+        return Vec::new();
+    }
+    let (program, starts) = batch_program(inputs);
+    let mut results = Vec::with_capacity(inputs.len());
+    // The program is ours to end, whatever the cells do
+    let options = RunOptions {
+        terminator: Some(Terminator::End),
+        ..options.clone()
+    };
+    let run = match run_to_exit(&program, form_path, &options) {
+        Ok(run) => run,
+        Err(e) => {
+            results.push(Err(e));
+            results.extend((1..inputs.len()).map(|_| Err(not_run())));
+            return results;
+        }
+    };
+
+    let pieces = split_batch_output(&run.output);
+    let mut previous_time = Duration::ZERO;
+    for piece in pieces.into_iter().take(inputs.len()) {
+        let time = cpu_time(&piece).unwrap_or(previous_time);
+        results.push(Ok(FormResult {
+            output: piece,
+            stderr: String::new(),
+            duration: time.saturating_sub(previous_time),
+            exit_code: 0,
+        }));
+        previous_time = time;
+    }
+    // Only the last block FORM reached can have failed
+    if run.exit_code != 0 {
+        if let Some(Ok(failed)) = results.pop() {
+            let failed = FormResult {
+                stderr: run.stderr.clone(),
+                exit_code: run.exit_code,
+                ..failed
+            };
+            let block = results.len();
+            let error = match execution_error(&failed) {
+                FormError::ExecutionError { status, stderr, kind, line } => FormError::ExecutionError {
+                    status,
+                    stderr,
+                    kind,
+                    line: line.and_then(|n| n.checked_sub(starts[block])).filter(|&n| n > 0),
+                },
+                other => other,
+            };
+            results.push(Err(error));
+        }
+    }
+    while results.len() < inputs.len() {
+        results.push(Err(not_run()));
+    }
+    results
+}
+
+/// The error for a batch block after the one that failed
+fn not_run() -> FormError {
+    let message = "Not run: an earlier block of the batch failed".to_string();
+    FormError::ExecutionError {
+        status: -1,
+        stderr: message.clone(),
+        kind: FormErrorKind::UnknownError(message),
+        line: None,
+    }
+}
+
+/// Sent through the channel by `run_form_streaming` once FORM has finished
+pub const STREAM_END: &str = "\u{4}form-repl:end";
+
+/// Per-line version of `format_output`'s filtering, for streamed output.
+///
+/// Feed it one run's lines in order, as FORM prints them.
+#[derive(Debug)]
+pub struct StreamFilter {
+    parser: FormOutputParser,
+}
+
+impl Default for StreamFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamFilter {
+    pub fn new() -> Self {
+        StreamFilter { parser: FormOutputParser::new() }
+    }
+
+    /// Returns the line if it belongs in the displayed output
+    pub fn accept<'a>(&mut self, line: &'a str) -> Option<&'a str> {
+        let kind = self.parser.classify(line);
+        (kind == LineKind::Content && !line.trim().is_empty()).then_some(line)
+    }
+}
+
+/// Executes FORM on a background thread, streaming output as it arrives.
+///
+/// Every non-empty line that `format_output` would keep is sent through
+/// `tx` as soon as FORM prints it, followed by `STREAM_END` when the run
+/// is over (successfully or not). The joined handle yields the same
+/// result `run_form` would, so callers can still inspect the full output
+/// or error afterwards. A dropped receiver does not stop the run.
+pub fn run_form_streaming(
+    input: &str,
+    form_path: &Path,
+    options: &RunOptions,
+    tx: mpsc::Sender<String>,
+) -> JoinHandle<Result<FormResult, FormError>> {
+    let input = terminate_input(input, options.terminator);
+    let form_path = form_path.to_path_buf();
+    let options = options.clone();
+
+    thread::spawn(move || {
+        let result = stream_form(&input, &form_path, &options, &tx);
+        let _ = tx.send(STREAM_END.to_string());
+        result
+    })
+}
+
+fn stream_form(
+    full_input: &str,
+    form_path: &Path,
+    options: &RunOptions,
+    tx: &mpsc::Sender<String>,
+) -> Result<FormResult, FormError> {
+    let start = Instant::now();
+    log::debug!(
+        "run_form_streaming: {} bytes of input, form_path={}, args={:?}",
+        full_input.len(),
+        form_path.display(),
+        options.extra_args
+    );
+
+    let mut child = build_command(form_path, options)
+        .spawn()
+        .map_err(|e| spawn_error(form_path, e))?;
+    let stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+
+    let writer = write_input(stdin, full_input.to_string());
+
+    let stderr_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let (line_tx, line_rx) = mpsc::channel();
+    let stdout_reader = thread::spawn(move || -> io::Result<()> {
+        for line in BufReader::new(stdout).lines() {
+            if line_tx.send(line?).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    });
+
+    let mut output = String::new();
+    let mut filter = StreamFilter::new();
+    loop {
+        if options.timeout.is_some_and(|limit| start.elapsed() >= limit) {
+            log::debug!("run_form_streaming: timed out, killing FORM");
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(FormError::Timeout);
+        }
+        match line_rx.recv_timeout(POLL_INTERVAL) {
+            Ok(line) => {
+                if let Some(shown) = filter.accept(&line) {
+                    let _ = tx.send(shown.to_string());
+                }
+                output.push_str(&line);
+                output.push('\n');
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    finish_input(writer)?;
+    stdout_reader
+        .join()
+        .map_err(|_| FormError::ReadError(io::Error::other("stdout reader panicked")))?
+        .map_err(FormError::ReadError)?;
+    let stderr_output = stderr_reader
+        .join()
+        .map_err(|_| FormError::ReadError(io::Error::other("stderr reader panicked")))?
+        .map_err(FormError::ReadError)?;
+
+    let status = child.wait().map_err(FormError::ReadError)?;
+    let stderr_str = String::from_utf8_lossy(&stderr_output).to_string();
+    let exit_code = status.code().unwrap_or(-1);
+    log::debug!("run_form_streaming: exit code {} after {:?}", exit_code, start.elapsed());
+
+    if !status.success() {
+        let diagnostics = format!("{}\n{}", stderr_str, output);
+        return Err(FormError::ExecutionError {
+            status: exit_code,
+            stderr: stderr_str,
+            kind: classify_error(&diagnostics),
+            line: diagnostic_line(&diagnostics),
+        });
+    }
+
+    Ok(FormResult {
+        output,
+        stderr: stderr_str,
+        duration: start.elapsed(),
+        exit_code,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    
+    #[test]
+    fn test_form_flags_env_before_stdin_arg() {
+        let extra_args = resolve_form_flags(None, &[], Some("-w4"));
+        let options = RunOptions { extra_args, ..RunOptions::default() };
+        let command = build_command(Path::new("tform"), &options);
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["-w4", "-"]);
+    }
+    
+    
+    #[test]
+    fn test_missing_executable_is_not_found() {
+        let missing = Path::new("/nonexistent/form-repl-test/form");
+        let e = io::Error::from(io::ErrorKind::NotFound);
+        assert!(matches!(spawn_error(missing, e), FormError::NotFound));
+        assert!(matches!(
+            run_form("Print;", missing, &RunOptions::default()),
+            Err(FormError::NotFound)
+        ));
+        // Other spawn failures keep their io::Error
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(matches!(spawn_error(missing, denied), FormError::SpawnError(_)));
+    }
+    
+    
+    #[test]
+    fn test_form_flags_precedence() {
+        let config = vec!["-w2".to_string()];
+        assert_eq!(resolve_form_flags(Some("-w8 -q"), &config, Some("-w4")), ["-w8", "-q"]);
+        assert_eq!(resolve_form_flags(None, &config, Some("-w4")), ["-w2"]);
+        assert!(resolve_form_flags(None, &[], None).is_empty());
+        
+        // A stray "-" never ends up before the user's flags
+        let options = RunOptions { extra_args: split_flags("- -w4"), ..RunOptions::default() };
+        let command = build_command(Path::new("tform"), &options);
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["-w4", "-"]);
+    }
+    
+    
+    #[test]
+    fn test_prepare_input_single_end() {
+        for input in ["Symbol x;", "Symbol x;\n.end", "Symbol x;\n.end\n"] {
+            let full = prepare_input(input);
+            assert!(full.trim_end().ends_with(".end"));
+            assert_eq!(full.matches(".end").count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_terminate_input_choices() {
+        let end = Some(Terminator::End);
+        let sort = Some(Terminator::Sort);
+        assert_eq!(terminate_input("Symbol x;", end), "Symbol x;\n.end");
+        assert_eq!(terminate_input("Symbol x;", sort), "Symbol x;\n.sort");
+        assert_eq!(terminate_input("Local E = x;\n.sort\n", sort), "Local E = x;\n.sort\n");
+        assert_eq!(terminate_input("Local E = x;\n.store", sort), "Local E = x;\n.store");
+        assert_eq!(terminate_input("Print;\n.end", sort), "Print;\n.end");
+        // A module ended with .sort still needs .end to finish the program
+        assert_eq!(terminate_input("Print;\n.sort", end), "Print;\n.sort\n.end");
+        // auto_end = false: the input goes to FORM as written
+        assert_eq!(terminate_input("Print;", None), "Print;");
+        assert_eq!(RunOptions::default().terminator, end);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_form_without_auto_end_sends_input_as_written() {
+        let exe = mock_form("echo-input", "#!/bin/sh\ncat\n");
+        let options = RunOptions { terminator: None, ..RunOptions::default() };
+        assert_eq!(run_form("Print;", &exe, &options).unwrap().output, "Print;");
+        let result = run_form("Print;", &exe, &RunOptions::default()).unwrap();
+        assert_eq!(result.output, "Print;\n.end");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_env_is_passed_to_form_only() {
+        let exe = mock_form("env", "#!/bin/sh\ncat >/dev/null\necho \"$FORM_REPL_RUN_ENV\"\n");
+        let env = vec![("FORM_REPL_RUN_ENV".to_string(), "/tmp/procs".to_string())];
+        let options = RunOptions { env, ..RunOptions::default() };
+        assert_eq!(run_form("Print;", &exe, &options).unwrap().output.trim(), "/tmp/procs");
+        assert!(env::var("FORM_REPL_RUN_ENV").is_err());
+        let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    }
+
+    
+    #[test]
+    fn test_idle_watchdog_fires_once_per_idle_stretch() {
+        let mut watchdog = IdleWatchdog::new(Duration::from_millis(20));
+        assert!(!watchdog.check());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(watchdog.check());
+        assert!(!watchdog.check());
+        watchdog.touch();
+        assert!(!watchdog.check());
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout_kills_form_that_keeps_printing() {
+        let exe = mock_form("chatty", "#!/bin/sh\nwhile :; do echo '   x'; done\n");
+        let options = RunOptions { timeout: Some(Duration::from_millis(300)), ..RunOptions::default() };
+        assert!(matches!(run_form("Print;", &exe, &options), Err(FormError::Timeout)));
+        let (tx, _rx) = mpsc::channel();
+        let handle = run_form_streaming("Print;", &exe, &options, tx);
+        assert!(matches!(handle.join().unwrap(), Err(FormError::Timeout)));
+        let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_long_input_echoed_back_does_not_deadlock() {
+        // Far more than a pipe buffer, all echoed before FORM "finishes"
+        let exe = mock_form("echo", "#!/bin/sh\ncat\n");
+        let options = RunOptions { timeout: Some(Duration::from_secs(20)), ..RunOptions::default() };
+        let input = "Local E = x;\n".repeat(50_000);
+        let result = run_form(&input, &exe, &options).unwrap();
+        assert!(result.output.len() > input.len());
+        let (tx, _rx) = mpsc::channel();
+        let handle = run_form_streaming(&input, &exe, &options, tx);
+        assert!(handle.join().unwrap().is_ok());
+        let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    }
+
+    #[cfg(unix)]
+    fn mock_form(name: &str, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("form-repl-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("form");
+        std::fs::write(&exe, script).unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+        exe
+    }
+    
+    /// A FORM stand-in for batches: prints `~~~` for `#message`, a `Time =`
+    /// line (the input line number as CPU seconds) and the result for each
+    /// `Local`, and fails on a `fail` line
+    #[cfg(unix)]
+    const BATCH_FORM: &str = "#!/bin/sh
+echo 'FORM 4.3 (Jan 1 2024)  Run: today'
+echo
+n=0
+while IFS= read -r line; do
+  n=$((n+1))
+  case \"$line\" in
+    '#message '*) echo \"~~~${line#'#message '}\";;
+    'Local '*) echo \"Time =       $n.00 sec    Generated terms =          1\"; echo; echo \"   ${line#Local }\"; echo;;
+    fail) echo \"stdin Line $n --> Illegal statement\"; exit 1;;
+  esac
+done
+echo '  0.00 sec out of 0.00 sec'
+";
+
+    #[test]
+    fn test_batch_program_separates_blocks() {
+        let inputs = vec!["Symbol x;".to_string(), "Local E = x;\n.sort".to_string(), "Print;\n.end".to_string()];
+        let (program, starts) = batch_program(&inputs);
+        assert_eq!(
+            program,
+            "#-\nSymbol x;\n.sort\n#message form-repl:block 1\nLocal E = x;\n.sort\n\
+             #message form-repl:block 2\nPrint;\n.sort"
+        );
+        assert_eq!(starts, [1, 4, 7]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_form_batch_attributes_outputs() {
+        let exe = mock_form("batch", BATCH_FORM);
+        let inputs: Vec<String> = (0..10).map(|i| format!("Local E{} = {};", i, i)).collect();
+        let results = run_form_batch(&inputs, &exe, &RunOptions::default());
+        assert_eq!(results.len(), 10);
+        for (i, result) in results.iter().enumerate() {
+            let result = result.as_ref().unwrap();
+            assert_eq!(crate::format_output(&result.output, false, 0), format!("   E{} = {};", i, i));
+            // Block i's Local is on line 2 + 3i of the batch
+            let seconds = if i == 0 { 2 } else { 3 };
+            assert_eq!(result.duration, Duration::from_secs(seconds), "block {}", i);
+        }
+        assert!(run_form_batch(&[], &exe, &RunOptions::default()).is_empty());
+        let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_form_batch_stops_at_failing_block() {
+        let exe = mock_form("batch-fail", BATCH_FORM);
+        let inputs = ["Local A = 1;", "fail", "Local B = 2;"].map(String::from);
+        let results = run_form_batch(&inputs, &exe, &RunOptions::default());
+        assert_eq!(crate::format_output(&results[0].as_ref().unwrap().output, false, 0), "   A = 1;");
+        match &results[1] {
+            // Line 5 of the batch is the failing block's first line
+            Err(FormError::ExecutionError { status, line, .. }) => assert_eq!((*status, *line), (1, Some(1))),
+            other => panic!("expected execution error, got {:?}", other),
+        }
+        assert!(matches!(
+            &results[2],
+            Err(FormError::ExecutionError { kind: FormErrorKind::UnknownError(m), .. }) if m.starts_with("Not run")
+        ));
+        let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_form_streaming_sends_lines_as_they_arrive() {
+        let exe = mock_form(
+            "stream",
+            "#!/bin/sh\ncat >/dev/null\necho 'FORM 4.3 (Jan 1 2024)'\necho\n\
+             echo '   E ='\nsleep 0.3\necho '      x^2;'\necho\n\
+             echo '  0.00 sec out of 0.00 sec'\nsleep 0.3\n",
+        );
+        let (tx, rx) = mpsc::channel();
+        let start = Instant::now();
+        let handle = run_form_streaming("Symbol x;", &exe, &RunOptions::default(), tx);
+
+        assert_eq!(rx.recv().unwrap(), "   E =");
+        assert!(start.elapsed() < Duration::from_millis(300), "first line was not streamed");
+        let rest: Vec<String> = rx.iter().collect();
+        assert_eq!(rest, vec!["      x^2;".to_string(), STREAM_END.to_string()]);
+
+        let result = handle.join().unwrap().unwrap();
+        assert!(result.output.contains("sec out of"));
+        assert!(start.elapsed() >= Duration::from_millis(600));
+        let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_run_form_streaming_ends_on_failure() {
+        let exe = mock_form(
+            "stream-fail",
+            "#!/bin/sh\ncat >/dev/null\necho '   E ='\necho 'Undefined symbol y' >&2\nexit 1\n",
+        );
+        let (tx, rx) = mpsc::channel();
+        let handle = run_form_streaming("E = y;", &exe, &RunOptions::default(), tx);
+
+        let lines: Vec<String> = rx.iter().collect();
+        assert_eq!(lines.last().map(String::as_str), Some(STREAM_END));
+        match handle.join().unwrap() {
+            Err(FormError::ExecutionError { status, .. }) => assert_eq!(status, 1),
+            other => panic!("expected execution error, got {:?}", other),
+        }
+        let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    }
+}
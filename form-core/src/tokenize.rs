@@ -0,0 +1,665 @@
+// Tokenizing FORM code for syntax highlighting
+use regex::Regex;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+use crate::output::is_timing_line;
+
+/// FORM language token types for syntax highlighting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenType {
+    Keyword,
+    Declaration,
+    Function,
+    Preprocessor,
+    DollarVar,
+    PreprocArg,
+    Number,
+    Operator,
+    /// Set braces `{...}` and the brackets of `[name]`s
+    SetOrIndex,
+    Comment,
+    BlockComment,
+    String,
+    Identifier,
+    Punctuation,
+    Whitespace,
+}
+
+/// A token with its type and text content
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub token_type: TokenType,
+    pub text: String,
+}
+
+/// Keywords that should be highlighted
+const KEYWORDS: &[&str] = &[
+    "if", "else", "elseif", "endif", "while", "endwhile", "repeat", "endrepeat",
+    "do", "enddo", "goto", "label", "exit", "break", "continue", "return",
+    "procedure", "endprocedure", "call", "argument", "endargument",
+    "switch", "case", "default", "endswitch", "inside", "endinside",
+    "term", "endterm", "sort", "endsort", "multiply", "also", "once", "only",
+    "multi", "all", "first", "last", "disorder", "antisymmetrize", "symmetrize",
+    "cyclesymmetrize", "rcyclesymmetrize", "identify", "idnew", "idold",
+    "chainout", "chainin", "splitarg", "splitfirstarg", "splitlastarg",
+    "factarg", "normalize", "makeinteger", "torat", "topolynomial",
+    "frompolynomial", "argtoextrasymbol", "dropcoefficient", "dropextrasymbols",
+    "polyratfun", "ratfun", "keep", "drop", "hide", "unhide", "skip", "nskip",
+    "moduleoption", "on", "off", "format", "write", "redefine", "renumber",
+    "contract", "trace4", "tracen", "chisholm", "unittrace", "delete", "discard",
+    "print", "nprint", "collect", "bracket", "antibracket", "putinside",
+    "polyfun", "sum", "id", "fill", "fillexpression", "table", "ctable",
+    "tablebase", "testuse", "apply", "transform", "replace", "replaceloop",
+    "totensor", "tovector", "fromtensor", "metric", "dimension", "load", "save",
+    "copyspecs", "setexitflag", "nwrite", "threadbucketsize", "processbucketsize",
+];
+
+/// Declaration keywords
+const DECLARATIONS: &[&str] = &[
+    "symbol", "symbols", "index", "indices", "vector", "vectors",
+    "tensor", "tensors", "ntensor", "ntensors", "function", "functions",
+    "cfunction", "cfunctions", "ctensor", "ctensors", "nfunction", "nfunctions",
+    "ncfunction", "ncfunctions", "table", "tables", "ctable", "ctables",
+    "set", "local", "global", "auto", "autodeclare", "dimension",
+    "fixindex", "unfixindex", "extrasymbol", "extrasymbol", "commuting",
+    "noncommuting",
+];
+
+/// Built-in functions (without the trailing parenthesis check for simplicity)
+const FUNCTIONS: &[&str] = &[
+    "abs", "sign", "min", "max", "mod", "div", "gcd", "fac", "binom",
+    "bernoulli", "sqrt", "sin", "cos", "tan", "asin", "acos", "atan",
+    "atan2", "sinh", "cosh", "tanh", "asinh", "acosh", "atanh", "exp",
+    "ln", "log", "log10", "li2", "li3", "nielsen", "hpl", "mzv", "zeta",
+    "gamma", "polygamma", "psi", "digamma", "theta", "delta_", "d_", "e_",
+    "i_", "f_", "g_", "gi_", "dd_", "conjg_", "deno", "farg", "nargs",
+    "firstarg", "lastarg", "numterms", "termsin", "maxpow", "minpow",
+    "exponent", "coeff", "content", "integer_", "symbol_", "index_",
+    "vector_", "fixed_", "match", "count", "occurs", "multipleof", "prime",
+    "random_", "tbl_", "term_", "expression_", "dummyindices", "extrasymbol_",
+    "getdummies", "nterms", "sump_", "sum_", "prod_", "inv_", "root_",
+    "replace_", "setfun", "putfirst", "addargs", "mulargs", "permute",
+    "reverse", "delta", "epsilon", "distrib_", "sig_", "factorin_", "gcd_",
+    "div_", "rem_", "inverse_", "makerational", "rat", "num_", "den_",
+    "derive", "accum", "pcount_", "firstbracket_", "table_", "defined_",
+    "termsinbracket_", "maxpower_", "minpower_", "ranperm_", "exists_",
+    "pattern_", "setspec_", "exec_", "partitions_", "compargs_",
+    "commutearg_", "sortarg_", "dedup_", "abs_", "fac_",
+];
+
+/// Compiled regex patterns for FORM syntax (without lookahead)
+struct FormPatterns {
+    preprocessor: Regex,
+    dollar_var: Regex,
+    preproc_arg: Regex,
+    number: Regex,
+    operator: Regex,
+    string: Regex,
+    identifier: Regex,
+}
+
+// Lazily compiled regex patterns
+static PATTERNS: LazyLock<FormPatterns> = LazyLock::new(|| FormPatterns {
+    preprocessor: Regex::new(r"^(#[a-zA-Z]+|\.end|\.sort|\.store|\.global|\.clear)").unwrap(),
+    dollar_var: Regex::new(r"^\$[a-zA-Z_][a-zA-Z0-9_]*").unwrap(),
+    preproc_arg: Regex::new(r"^`[^']*'").unwrap(),
+    number: Regex::new(r"^-?[0-9]+\.?[0-9]*([eE][+-]?[0-9]+)?").unwrap(),
+    operator: Regex::new(r"^(==|!=|<=|>=|<>|<|>|&&|\|\||[+\-*/^?=,;:])").unwrap(),
+    string: Regex::new(r#"^"([^"\\]|\\.)*""#).unwrap(),
+    identifier: Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*").unwrap(),
+});
+
+/// Check if an identifier is a keyword (case-insensitive)
+fn is_keyword(word: &str) -> bool {
+    let lower = word.to_lowercase();
+    KEYWORDS.contains(&lower.as_str())
+}
+
+/// Check if an identifier is a declaration keyword (case-insensitive)
+fn is_declaration(word: &str) -> bool {
+    let lower = word.to_lowercase();
+    DECLARATIONS.contains(&lower.as_str())
+}
+
+/// Check if an identifier is a built-in function (case-insensitive)
+pub fn is_function(word: &str) -> bool {
+    let lower = word.to_lowercase();
+    FUNCTIONS.contains(&lower.as_str())
+}
+
+/// Check if a word is any FORM keyword, declaration or built-in function
+pub fn is_form_word(word: &str) -> bool {
+    is_keyword(word) || is_declaration(word) || is_function(word)
+}
+
+/// Every keyword, declaration and built-in function name, for completion
+pub fn form_words() -> impl Iterator<Item = &'static str> {
+    KEYWORDS.iter().chain(DECLARATIONS).chain(FUNCTIONS).copied()
+}
+
+/// Tokenizer state carried from one line to the next
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineState {
+    /// Inside a `/* ... */` comment that started on an earlier line
+    pub in_block_comment: bool,
+}
+
+/// Tokenize a single, standalone line of FORM code
+pub fn tokenize(line: &str) -> Vec<Token> {
+    tokenize_continued(line, &mut LineState::default())
+}
+
+/// Tokenize a line of FORM code that may continue a block comment
+pub fn tokenize_continued(line: &str, state: &mut LineState) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut remaining = line;
+    
+    // Finish a block comment left open by a previous line
+    if state.in_block_comment {
+        match line.find("*/") {
+            Some(end) => {
+                tokens.push(Token {
+                    token_type: TokenType::BlockComment,
+                    text: line[..end + 2].to_string(),
+                });
+                remaining = &line[end + 2..];
+                state.in_block_comment = false;
+            }
+            None => {
+                tokens.push(Token {
+                    token_type: TokenType::BlockComment,
+                    text: line.to_string(),
+                });
+                return tokens;
+            }
+        }
+    } else if remaining.trim_start().starts_with('*') {
+        // FORM comments start with * as the first non-blank character of a line
+        tokens.push(Token {
+            token_type: TokenType::Comment,
+            text: line.to_string(),
+        });
+        return tokens;
+    }
+    
+    while !remaining.is_empty() {
+        // Skip whitespace but preserve it
+        if remaining.starts_with(|c: char| c.is_whitespace()) {
+            let ws_len = remaining
+                .chars()
+                .take_while(|c| c.is_whitespace())
+                .count();
+            let ws: String = remaining.chars().take(ws_len).collect();
+            let byte_len: usize = ws.len();
+            tokens.push(Token {
+                token_type: TokenType::Whitespace,
+                text: ws,
+            });
+            remaining = &remaining[byte_len..];
+            continue;
+        }
+        
+        // Check for /* ... */ comments, which may run past the end of the line
+        if remaining.starts_with("/*") {
+            let len = match remaining[2..].find("*/") {
+                Some(end) => end + 4,
+                None => {
+                    state.in_block_comment = true;
+                    remaining.len()
+                }
+            };
+            tokens.push(Token {
+                token_type: TokenType::BlockComment,
+                text: remaining[..len].to_string(),
+            });
+            remaining = &remaining[len..];
+            continue;
+        }
+        
+        // Check for string literal
+        if let Some(m) = PATTERNS.string.find(remaining) {
+            tokens.push(Token {
+                token_type: TokenType::String,
+                text: m.as_str().to_string(),
+            });
+            remaining = &remaining[m.end()..];
+            continue;
+        }
+        
+        // Check for dollar variables ($x) and preprocessor substitutions (`N')
+        if let Some(m) = PATTERNS.dollar_var.find(remaining) {
+            tokens.push(Token {
+                token_type: TokenType::DollarVar,
+                text: m.as_str().to_string(),
+            });
+            remaining = &remaining[m.end()..];
+            continue;
+        }
+        if let Some(m) = PATTERNS.preproc_arg.find(remaining) {
+            tokens.push(Token {
+                token_type: TokenType::PreprocArg,
+                text: m.as_str().to_string(),
+            });
+            remaining = &remaining[m.end()..];
+            continue;
+        }
+        
+        // A dot right after a name and before another is a dot product
+        // (p.q); only elsewhere can it start .sort and friends
+        if remaining.starts_with('.') && is_dot_product(&tokens, &remaining[1..]) {
+            tokens.push(Token {
+                token_type: TokenType::Operator,
+                text: ".".to_string(),
+            });
+            remaining = &remaining[1..];
+            continue;
+        }
+
+        // Check for preprocessor directives
+        if let Some(m) = PATTERNS.preprocessor.find(remaining) {
+            tokens.push(Token {
+                token_type: TokenType::Preprocessor,
+                text: m.as_str().to_string(),
+            });
+            remaining = &remaining[m.end()..];
+            continue;
+        }
+        
+        // Check for identifiers (then classify as keyword/declaration/function/identifier)
+        if let Some(m) = PATTERNS.identifier.find(remaining) {
+            let word = m.as_str();
+            let token_type = if is_declaration(word) {
+                TokenType::Declaration
+            } else if is_keyword(word) {
+                TokenType::Keyword
+            } else if is_function(word) {
+                // Check if followed by '(' to confirm it's a function call
+                let after = &remaining[m.end()..];
+                if after.trim_start().starts_with('(') {
+                    TokenType::Function
+                } else {
+                    TokenType::Identifier
+                }
+            } else {
+                TokenType::Identifier
+            };
+            
+            tokens.push(Token {
+                token_type,
+                text: word.to_string(),
+            });
+            remaining = &remaining[m.end()..];
+            continue;
+        }
+        
+        // Check for numbers; a leading '-' belongs to the number only when unary
+        let number = if !remaining.starts_with('-') || minus_is_unary(&tokens) {
+            PATTERNS.number.find(remaining)
+        } else {
+            None
+        };
+        if let Some(m) = number {
+            tokens.push(Token {
+                token_type: TokenType::Number,
+                text: m.as_str().to_string(),
+            });
+            remaining = &remaining[m.end()..];
+            continue;
+        }
+        
+        // Check for operators
+        if let Some(m) = PATTERNS.operator.find(remaining) {
+            tokens.push(Token {
+                token_type: TokenType::Operator,
+                text: m.as_str().to_string(),
+            });
+            remaining = &remaining[m.end()..];
+            continue;
+        }
+        
+        // Punctuation and other characters
+        if let Some(c) = remaining.chars().next() {
+            let token_type = if matches!(c, '{' | '}' | '[' | ']') {
+                TokenType::SetOrIndex
+            } else {
+                TokenType::Punctuation
+            };
+            tokens.push(Token {
+                token_type,
+                text: c.to_string(),
+            });
+            remaining = &remaining[c.len_utf8()..];
+        }
+    }
+    
+    tokens
+}
+
+/// Whether a '-' following `tokens` is a unary minus.
+///
+/// It is unary at the start of a line (or of an expression opened with a
+/// bracket) and right after another operator, as in `*-3` or `^-2`; after
+/// an operand such as `x` or `2` it is a binary operator, as in `2-3`.
+fn minus_is_unary(tokens: &[Token]) -> bool {
+    let previous = tokens.iter().rev().find(|t| {
+        !matches!(
+            t.token_type,
+            TokenType::Whitespace | TokenType::BlockComment | TokenType::Comment
+        )
+    });
+    match previous {
+        None => true,
+        Some(t) if t.token_type == TokenType::Operator => true,
+        Some(t) => is_opening_bracket(t),
+    }
+}
+
+/// Whether a token is `(`, `[` or `{`
+pub fn is_opening_bracket(token: &Token) -> bool {
+    matches!(token.token_type, TokenType::Punctuation | TokenType::SetOrIndex)
+        && matches!(token.text.as_str(), "(" | "[" | "{")
+}
+
+/// Whether a token is `)`, `]` or `}`
+pub fn is_closing_bracket(token: &Token) -> bool {
+    matches!(token.token_type, TokenType::Punctuation | TokenType::SetOrIndex)
+        && matches!(token.text.as_str(), ")" | "]" | "}")
+}
+
+/// Whether a '.' between `tokens` and `after` joins two vectors, as in
+/// `p.q` or `p.[q1]`: it must touch a name on both sides
+fn is_dot_product(tokens: &[Token], after: &str) -> bool {
+    let touches_name = tokens.last().is_some_and(|t| {
+        matches!(t.token_type, TokenType::Identifier | TokenType::DollarVar)
+            || (t.token_type == TokenType::SetOrIndex && t.text == "]")
+    });
+    touches_name && after.starts_with(|c: char| c.is_ascii_alphabetic() || c == '[' || c == '$')
+}
+
+impl TokenType {
+    /// CSS class a web view styles this kind of token with; empty for
+    /// punctuation and whitespace, which are left plain
+    pub fn css_class(self) -> &'static str {
+        match self {
+            TokenType::Keyword => "hl-keyword",
+            TokenType::Declaration => "hl-declaration",
+            TokenType::Function => "hl-function",
+            TokenType::Preprocessor => "hl-preprocessor",
+            TokenType::DollarVar => "hl-dollar-var",
+            TokenType::PreprocArg => "hl-preproc-arg",
+            TokenType::Number => "hl-number",
+            TokenType::Operator => "hl-operator",
+            TokenType::SetOrIndex => "hl-set-or-index",
+            TokenType::Comment | TokenType::BlockComment => "hl-comment",
+            TokenType::String => "hl-string",
+            TokenType::Identifier => "hl-identifier",
+            TokenType::Punctuation | TokenType::Whitespace => "",
+        }
+    }
+}
+
+/// A piece of highlighted text for the GUI: `text` styled by the CSS class
+/// `css_class`, or plain when that is empty
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StyledSpan {
+    pub text: String,
+    pub css_class: String,
+}
+
+impl StyledSpan {
+    fn new(text: impl Into<String>, css_class: &str) -> Self {
+        StyledSpan {
+            text: text.into(),
+            css_class: css_class.to_string(),
+        }
+    }
+}
+
+/// Splits `text` at line breaks, turning each line into spans with
+/// `line_spans` and each break into a plain `\n` span
+fn spans_by_line(text: &str, mut line_spans: impl FnMut(&str) -> Vec<StyledSpan>) -> Vec<StyledSpan> {
+    let mut spans = Vec::new();
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            spans.push(StyledSpan::new("\n", ""));
+        }
+        spans.extend(line_spans(line));
+    }
+    spans
+}
+
+fn token_spans(tokens: Vec<Token>) -> Vec<StyledSpan> {
+    tokens
+        .into_iter()
+        .map(|token| StyledSpan::new(token.text, token.token_type.css_class()))
+        .collect()
+}
+
+/// Highlights FORM code as spans with CSS classes instead of ANSI colors.
+/// Their texts add up to `code`, line breaks included.
+pub fn highlight_code_with_spans(code: &str) -> Vec<StyledSpan> {
+    let mut state = LineState::default();
+    spans_by_line(code, |line| token_spans(tokenize_continued(line, &mut state)))
+}
+
+/// Highlights FORM output as spans: `E =` labels, timing lines and errors
+/// or warnings each become one span (`hl-label`, `hl-timing`, `hl-error`),
+/// and the expressions in between are tokenized like code
+pub fn highlight_output_with_spans(output: &str) -> Vec<StyledSpan> {
+    spans_by_line(output, |line| {
+        let trimmed = line.trim();
+        let class = if trimmed.ends_with(" =") || trimmed == "=" {
+            "hl-label"
+        } else if is_timing_line(line) {
+            "hl-timing"
+        } else if trimmed.starts_with("Error") || trimmed.starts_with("Warning") {
+            "hl-error"
+        } else {
+            return token_spans(tokenize(line));
+        };
+        vec![StyledSpan::new(line, class)]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_tokenize_declaration() {
+        let tokens = tokenize("Symbol x,y;");
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::Declaration && t.text == "Symbol"));
+    }
+    
+    #[test]
+    fn test_tokenize_keyword() {
+        let tokens = tokenize("id f(x) = g(x);");
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::Keyword && t.text == "id"));
+    }
+    
+    #[test]
+    fn test_tokenize_comment() {
+        let tokens = tokenize("* This is a comment");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].token_type, TokenType::Comment);
+        
+        let tokens = tokenize("    * indented comment");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].token_type, TokenType::Comment);
+        
+        // A * later in the line is multiplication, not a comment
+        let tokens = tokenize("Local E = 2 * x;");
+        assert!(tokens.iter().all(|t| t.token_type != TokenType::Comment));
+    }
+    
+    #[test]
+    fn test_tokenize_comment_boundaries() {
+        let types = |line: &str| tokenize(line).into_iter().map(|t| t.token_type).collect::<Vec<_>>();
+        
+        assert_eq!(
+            types("x*y"),
+            vec![TokenType::Identifier, TokenType::Operator, TokenType::Identifier]
+        );
+        // A bare * or whitespace then * is still a (whole-line) comment
+        assert_eq!(types("*"), vec![TokenType::Comment]);
+        assert_eq!(types(" \t*"), vec![TokenType::Comment]);
+        assert_eq!(tokenize("  * comment")[0].text, "  * comment");
+        // Whitespace alone is not
+        assert_eq!(types("   "), vec![TokenType::Whitespace]);
+        // After a block comment, * is an operator again
+        assert_eq!(types("/* c */*x")[1], TokenType::Operator);
+        let mut state = LineState { in_block_comment: true };
+        let tokens = tokenize_continued("end */ *y", &mut state);
+        assert_eq!(tokens[2].token_type, TokenType::Operator);
+    }
+    
+    #[test]
+    fn test_tokenize_inline_block_comment() {
+        let tokens = tokenize("Local E = x /* note */ + 1;");
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::BlockComment && t.text == "/* note */"));
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::Operator && t.text == "+"));
+    }
+    
+    #[test]
+    fn test_tokenize_multiline_block_comment() {
+        let mut state = LineState::default();
+        let first = tokenize_continued("Local E = x; /* starts here", &mut state);
+        assert_eq!(first.last().unwrap().token_type, TokenType::BlockComment);
+        assert!(state.in_block_comment);
+        
+        let middle = tokenize_continued("  id x = y;", &mut state);
+        assert_eq!(middle.len(), 1);
+        assert_eq!(middle[0].token_type, TokenType::BlockComment);
+        
+        let last = tokenize_continued("ends here */ Print;", &mut state);
+        assert_eq!(last[0].token_type, TokenType::BlockComment);
+        assert_eq!(last[0].text, "ends here */");
+        assert!(last.iter().any(|t| t.token_type == TokenType::Keyword && t.text == "Print"));
+        assert!(!state.in_block_comment);
+    }
+    
+    #[test]
+    fn test_tokenize_define() {
+        let tokens = tokenize("#define N \"4\"");
+        assert_eq!(tokens[0].token_type, TokenType::Preprocessor);
+        assert_eq!(tokens[0].text, "#define");
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::Identifier && t.text == "N"));
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::String && t.text == "\"4\""));
+    }
+    
+    #[test]
+    fn test_tokenize_dot_commands() {
+        for cmd in [".end", ".sort", ".store", ".global", ".clear"] {
+            let tokens = tokenize(cmd);
+            assert_eq!(tokens.len(), 1, "{}", cmd);
+            assert_eq!(tokens[0].token_type, TokenType::Preprocessor);
+            assert_eq!(tokens[0].text, cmd);
+        }
+    }
+    
+    #[test]
+    fn test_tokenize_sets_and_names() {
+        let kinds = |line: &str| -> Vec<(TokenType, String)> {
+            tokenize(line).into_iter().map(|t| (t.token_type, t.text)).collect()
+        };
+        let set = |s: &str| (TokenType::SetOrIndex, s.to_string());
+
+        let tokens = kinds("{1,...,n}");
+        assert_eq!(tokens.first(), Some(&set("{")));
+        assert_eq!(tokens.last(), Some(&set("}")));
+        assert!(tokens.contains(&(TokenType::Identifier, "n".to_string())));
+        assert!(!tokens.iter().any(|(kind, _)| *kind == TokenType::Preprocessor));
+
+        assert_eq!(
+            kinds("[x+1]"),
+            vec![
+                set("["),
+                (TokenType::Identifier, "x".to_string()),
+                (TokenType::Operator, "+".to_string()),
+                (TokenType::Number, "1".to_string()),
+                set("]"),
+            ]
+        );
+        // A minus after an opening set brace is unary
+        assert_eq!(kinds("{-1}")[1], (TokenType::Number, "-1".to_string()));
+    }
+
+    #[test]
+    fn test_tokenize_dot_product() {
+        let kinds = |line: &str| -> Vec<(TokenType, String)> {
+            tokenize(line).into_iter().map(|t| (t.token_type, t.text)).collect()
+        };
+        let id = |s: &str| (TokenType::Identifier, s.to_string());
+        let op = |s: &str| (TokenType::Operator, s.to_string());
+
+        assert_eq!(kinds("p.q"), vec![id("p"), op("."), id("q")]);
+        assert_eq!(kinds("[p1].q")[3], op("."));
+        // A module instruction is not a dot product, even after a name
+        assert_eq!(kinds(".sort")[0].0, TokenType::Preprocessor);
+        assert_eq!(kinds("x .sort")[2].0, TokenType::Preprocessor);
+        // Nor is a decimal point
+        assert_eq!(kinds("1.5"), vec![(TokenType::Number, "1.5".to_string())]);
+    }
+
+    #[test]
+    fn test_tokenize_preproc_arg() {
+        let tokens = tokenize("multiply `N';");
+        assert_eq!(tokens[0].token_type, TokenType::Keyword);
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::PreprocArg && t.text == "`N'"));
+        assert_eq!(tokens.last().unwrap().text, ";");
+    }
+    
+    #[test]
+    fn test_tokenize_dollar_var() {
+        let tokens = tokenize("$sum = $sum + 1;");
+        assert_eq!(tokens[0].token_type, TokenType::DollarVar);
+        assert_eq!(tokens[0].text, "$sum");
+        assert_eq!(tokens.iter().filter(|t| t.token_type == TokenType::DollarVar).count(), 2);
+    }
+    
+    #[test]
+    fn test_spans_add_up_to_the_text() {
+        let joined = |spans: &[StyledSpan]| spans.iter().map(|s| s.text.as_str()).collect::<String>();
+
+        let code = "#define N \"4\"\nSymbols x, y;\r\n/* a\n   b */ Local E = (x+y)^`N';\n* note\n\n";
+        let spans = highlight_code_with_spans(code);
+        assert_eq!(joined(&spans), code);
+        assert!(spans.iter().all(|s| !s.text.is_empty()));
+        assert!(spans.contains(&StyledSpan::new("Symbols", "hl-declaration")));
+        assert!(spans.contains(&StyledSpan::new("   b */", "hl-comment")));
+        assert!(spans.contains(&StyledSpan::new("`N'", "hl-preproc-arg")));
+        assert!(spans.contains(&StyledSpan::new(" ", "")));
+
+        let output = "   E =\n      x^2 + 2*x*y;\n\n  0.00 sec out of 0.00 sec";
+        let spans = highlight_output_with_spans(output);
+        assert_eq!(joined(&spans), output);
+        assert_eq!(spans[0], StyledSpan::new("   E =", "hl-label"));
+        assert!(spans.contains(&StyledSpan::new("2", "hl-number")));
+        assert_eq!(spans.last(), Some(&StyledSpan::new("  0.00 sec out of 0.00 sec", "hl-timing")));
+    }
+
+    #[test]
+    fn test_tokenize_number() {
+        let tokens = tokenize("x^10 + 2*y");
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::Number && t.text == "10"));
+        assert!(tokens.iter().any(|t| t.token_type == TokenType::Number && t.text == "2"));
+    }
+
+    #[test]
+    fn test_tokenize_minus() {
+        let kinds = |line: &str| -> Vec<(TokenType, String)> {
+            tokenize(line).into_iter().map(|t| (t.token_type, t.text)).collect()
+        };
+        let num = |s: &str| (TokenType::Number, s.to_string());
+        let op = |s: &str| (TokenType::Operator, s.to_string());
+
+        assert_eq!(kinds("2-3"), vec![num("2"), op("-"), num("3")]);
+        assert_eq!(kinds("-3"), vec![num("-3")]);
+        assert_eq!(kinds("x*-3"), vec![(TokenType::Identifier, "x".to_string()), op("*"), num("-3")]);
+        assert_eq!(kinds("x^-2"), vec![(TokenType::Identifier, "x".to_string()), op("^"), num("-2")]);
+        assert_eq!(kinds("x - 2")[2], op("-"));
+        assert_eq!(kinds("E = -2")[4], num("-2"));
+        assert_eq!(kinds("f(-1)")[2], num("-1"));
+    }
+}
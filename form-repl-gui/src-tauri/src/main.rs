@@ -1,20 +1,28 @@
 // Prevents additional console window on Windows in release builds
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use form_core::{FormOutputParser, FormStatistics, OutputConfig, RunOptions, StreamFilter, StyledSpan};
 use serde::{Deserialize, Serialize};
-use std::env;
 use std::io::{Read, Write};
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::sync::Mutex;
-use std::time::Instant;
-use tauri::State;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
 
 /// Session state managed by Tauri
 struct AppState {
     history: Mutex<Vec<HistoryEntry>>,
     session_count: Mutex<usize>,
     form_path: Mutex<Option<PathBuf>>,
+    /// Persistent FORM process, started on first use
+    form: Mutex<Option<FormSession>>,
+    /// Process running the current cell, for cancel_execution
+    running: RunningForm,
+    /// How FORM is started and how long a cell may run
+    run_options: RunOptions,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,15 +32,47 @@ struct HistoryEntry {
     output: Option<String>,
     error: Option<String>,
     duration_ms: Option<u64>,
+    #[serde(default)]
+    stats: FormStats,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 struct FormResult {
     success: bool,
     output: String,
     error: Option<String>,
     duration_ms: u64,
     session_number: usize,
+    /// Statistics of the run's last module, flattened into this object
+    #[serde(flatten)]
+    stats: FormStats,
+}
+
+/// Statistics FORM prints after each module; `null` when absent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct FormStats {
+    time_sec: Option<f64>,
+    generated_terms: Option<u64>,
+    terms_in_output: Option<u64>,
+    bytes_used: Option<u64>,
+}
+
+impl FormStats {
+    /// The statistics of the last module in a run's output
+    fn parse(output: &str) -> Self {
+        FormOutputParser::parse(output, false).stats.into()
+    }
+}
+
+impl From<FormStatistics> for FormStats {
+    fn from(stats: FormStatistics) -> Self {
+        FormStats {
+            time_sec: stats.time_secs,
+            generated_terms: stats.generated_terms,
+            terms_in_output: stats.terms_in_output,
+            bytes_used: stats.bytes_used,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -43,164 +83,272 @@ struct AppInfo {
     history_count: usize,
 }
 
-/// Find FORM executable
-fn find_form_executable() -> Option<PathBuf> {
-    // Check FORM_PATH environment variable
-    if let Ok(form_path) = env::var("FORM_PATH") {
-        let path = PathBuf::from(&form_path);
-        if path.exists() {
-            return Some(path);
-        }
-        let form_in_dir = path.join("form");
-        if form_in_dir.exists() {
-            return Some(form_in_dir);
-        }
+/// Marker written after each cell so its output can be told apart
+const CELL_SENTINEL: &str = "__FORM_REPL_CELL_END__";
+
+/// A long-running FORM process that keeps its state between cells
+struct FormSession {
+    /// Shared with `RunningForm` so a cell can be cancelled mid-run
+    child: Arc<Mutex<Child>>,
+    stdin: ChildStdin,
+    /// Stdout chunks from the reader thread
+    chunks: Receiver<String>,
+    /// Output received but not yet claimed by a cell
+    pending: String,
+    /// Everything FORM has written to stderr so far
+    stderr: Arc<Mutex<Vec<u8>>>,
+}
+
+impl FormSession {
+    fn spawn(form_path: &PathBuf, options: &RunOptions) -> Result<Self, String> {
+        let workdir = options.workdir.clone().unwrap_or_else(std::env::temp_dir);
+        let mut child = Command::new(form_path)
+            .args(&options.extra_args)
+            .arg("-")
+            .envs(options.env.iter().map(|(name, value)| (name, value)))
+            .current_dir(workdir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to spawn FORM: {}", e))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+
+        // Drain both pipes continuously so FORM never blocks on a full pipe,
+        // however large its output
+        let (tx, chunks) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = stdout.read(&mut buf) {
+                if n == 0 || tx.send(String::from_utf8_lossy(&buf[..n]).to_string()).is_err() {
+                    break;
+                }
+            }
+        });
+        let stderr_buf = Arc::new(Mutex::new(Vec::new()));
+        let stderr_sink = stderr_buf.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = stderr.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                stderr_sink.lock().unwrap().extend_from_slice(&buf[..n]);
+            }
+        });
+
+        Ok(FormSession {
+            child: Arc::new(Mutex::new(child)),
+            stdin,
+            chunks,
+            pending: String::new(),
+            stderr: stderr_buf,
+        })
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.lock().unwrap().try_wait(), Ok(None))
     }
 
-    // Check common locations
-    let locations = [
-        "form",
-        "sources/form",
-        "../sources/form",
-        "/usr/local/bin/form",
-        "/usr/bin/form",
-    ];
-
-    for loc in &locations {
-        let path = PathBuf::from(loc);
-        if path.exists() {
-            return Some(path);
+    fn child(&self) -> Arc<Mutex<Child>> {
+        self.child.clone()
+    }
+
+    /// Run one cell, returning its formatted output, duration and statistics.
+    ///
+    /// `on_line` gets each result line as FORM prints it. An error means
+    /// FORM exited or the cell ran past `timeout`; the session is then
+    /// unusable.
+    fn run_cell<F: FnMut(&str)>(
+        &mut self,
+        input: &str,
+        timeout: Option<Duration>,
+        mut on_line: F,
+    ) -> Result<(String, u64, FormStats), String> {
+        let start = Instant::now();
+        self.stdin
+            .write_all(cell_input(input).as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("Failed to write to FORM: {}", e))?;
+
+        let mut filter = StreamFilter::new();
+        // Length of the start of `pending` already passed through `filter`
+        let mut scanned = 0;
+        loop {
+            for line in self.pending[scanned..].split_inclusive('\n') {
+                if !line.ends_with('\n') || line.trim() == CELL_SENTINEL {
+                    break;
+                }
+                scanned += line.len();
+                if let Some(shown) = filter.accept(line.trim_end()) {
+                    if !form_core::is_stats_line(shown) {
+                        on_line(shown);
+                    }
+                }
+            }
+            if let Some(output) = split_cell(&mut self.pending) {
+                let duration_ms = start.elapsed().as_millis() as u64;
+                return Ok((format_output(&output), duration_ms, FormStats::parse(&output)));
+            }
+            let chunk = match timeout {
+                Some(limit) => self.chunks.recv_timeout(limit.saturating_sub(start.elapsed())),
+                None => self.chunks.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match chunk {
+                Ok(chunk) => self.pending.push_str(&chunk),
+                Err(RecvTimeoutError::Timeout) => return Err(form_core::FormError::Timeout.to_string()),
+                Err(RecvTimeoutError::Disconnected) => return Err(self.exit_error()),
+            }
         }
     }
 
-    // Search in PATH
-    if let Ok(path) = env::var("PATH") {
-        for dir in env::split_paths(&path) {
-            let form_path = dir.join("form");
-            if form_path.exists() {
-                return Some(form_path);
+    /// Describe why FORM stopped, preferring stderr, then its last output
+    fn exit_error(&mut self) -> String {
+        let status = self.child.lock().unwrap().wait();
+        let stderr = String::from_utf8_lossy(&self.stderr.lock().unwrap()).to_string();
+        if !stderr.trim().is_empty() {
+            stderr
+        } else if !self.pending.trim().is_empty() {
+            // Sometimes FORM writes errors to stdout
+            std::mem::take(&mut self.pending)
+        } else {
+            match status {
+                Ok(status) => format!("FORM exited with status: {}", status),
+                Err(e) => format!("FORM exited: {}", e),
             }
         }
     }
+}
 
-    None
+impl Drop for FormSession {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
 }
 
-/// Execute FORM code
-fn run_form(input: &str, form_path: &PathBuf) -> Result<(String, u64), String> {
-    let start = Instant::now();
+/// Tracks the FORM process that is busy with a cell
+#[derive(Default)]
+struct RunningForm {
+    child: Mutex<Option<Arc<Mutex<Child>>>>,
+    cancelled: AtomicBool,
+}
 
-    // Get a writable temp directory for FORM to use
-    let temp_dir = std::env::temp_dir();
-    
-    // Prepare input - ensure it ends with .end
-    let full_input = if !input.trim_end().ends_with(".end") {
-        format!("{}\n.end\n", input)
-    } else {
-        format!("{}\n", input)
-    };
+impl RunningForm {
+    fn start(&self, child: Arc<Mutex<Child>>) {
+        self.cancelled.store(false, Ordering::SeqCst);
+        *self.child.lock().unwrap() = Some(child);
+    }
 
-    let mut child = Command::new(form_path)
-        .arg("-")
-        .current_dir(&temp_dir)  // Set working directory to temp so FORM can write temp files
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to spawn FORM: {}", e))?;
-
-    let mut stdin = child.stdin.take().unwrap();
-    let mut stdout = child.stdout.take().unwrap();
-    let mut stderr = child.stderr.take().unwrap();
-
-    stdin
-        .write_all(full_input.as_bytes())
-        .map_err(|e| format!("Failed to write to FORM: {}", e))?;
-    drop(stdin);
-
-    let mut output = Vec::new();
-    stdout
-        .read_to_end(&mut output)
-        .map_err(|e| format!("Failed to read FORM output: {}", e))?;
-
-    let mut stderr_output = Vec::new();
-    stderr
-        .read_to_end(&mut stderr_output)
-        .map_err(|e| format!("Failed to read FORM stderr: {}", e))?;
-
-    let status = child
-        .wait()
-        .map_err(|e| format!("Failed to wait for FORM: {}", e))?;
-
-    let duration_ms = start.elapsed().as_millis() as u64;
-    let output_str = String::from_utf8_lossy(&output).to_string();
-    let stderr_str = String::from_utf8_lossy(&stderr_output).to_string();
-
-    if !status.success() {
-        // Include both stdout and stderr in error for better debugging
-        let error_msg = if !stderr_str.trim().is_empty() {
-            stderr_str
-        } else if !output_str.trim().is_empty() {
-            // Sometimes FORM writes errors to stdout
-            output_str
-        } else {
-            format!("FORM exited with status: {}", status)
-        };
-        return Err(error_msg);
+    /// Clear the running process, returning whether it was cancelled
+    fn finish(&self) -> bool {
+        *self.child.lock().unwrap() = None;
+        self.cancelled.swap(false, Ordering::SeqCst)
     }
 
-    Ok((format_output(&output_str), duration_ms))
-}
+    fn is_running(&self) -> bool {
+        self.child.lock().unwrap().is_some()
+    }
 
-/// Format FORM output by removing metadata
-fn format_output(output: &str) -> String {
-    let mut result = Vec::new();
-    let mut in_header = true;
-
-    for line in output.lines() {
-        // Skip header lines
-        if in_header {
-            if line.starts_with("FORM ")
-                || line.contains("Version")
-                || line.trim().is_empty()
-                || line.contains("Run at:")
-                || line.trim_start().starts_with("Generated terms")
-            {
-                continue;
+    /// Kill the running process, returning whether anything was running
+    fn cancel(&self) -> bool {
+        match self.child.lock().unwrap().take() {
+            Some(child) => {
+                self.cancelled.store(true, Ordering::SeqCst);
+                let _ = child.lock().unwrap().kill();
+                true
             }
-            in_header = false;
+            None => false,
         }
+    }
+}
 
-        // Skip timing and statistics lines
-        if line.contains("sec out of") 
-            || line.trim_start().starts_with("Time =")
-            || line.contains("Terms in output")
-            || line.contains("Bytes used")
-            || line.contains("Terms active")
-            || line.contains("Bytes in use")
-        {
-            continue;
+/// Text fed to the persistent FORM for one cell.
+///
+/// `.end` would terminate the session, so it becomes `.sort`; the cell is
+/// closed with its own `.sort` and a `#write` of the sentinel, which the
+/// preprocessor only reaches once the cell's module has run and printed.
+fn cell_input(input: &str) -> String {
+    let mut text = String::new();
+    for line in input.lines() {
+        if line.trim() == ".end" {
+            text.push_str(".sort\n");
+        } else {
+            text.push_str(line);
+            text.push('\n');
         }
-
-        result.push(line);
     }
+    text.push_str(&format!(".sort\n#write \"{}\"\n", CELL_SENTINEL));
+    text
+}
 
-    // Remove leading empty lines
-    while result.first().map(|l| l.trim().is_empty()).unwrap_or(false) {
-        result.remove(0);
+/// Remove and return the output of the first complete cell in `buffer`.
+///
+/// Returns `None` (leaving `buffer` untouched) until a full sentinel line
+/// has arrived, so chunks may split lines anywhere.
+fn split_cell(buffer: &mut String) -> Option<String> {
+    let mut offset = 0;
+    for line in buffer.split_inclusive('\n') {
+        if line.ends_with('\n') && line.trim() == CELL_SENTINEL {
+            let output = buffer[..offset].to_string();
+            buffer.drain(..offset + line.len());
+            return Some(output);
+        }
+        offset += line.len();
     }
+    None
+}
 
-    // Remove trailing empty lines
-    while result.last().map(|l| l.trim().is_empty()).unwrap_or(false) {
-        result.pop();
-    }
+/// Format FORM output by removing the banner, timing and statistics
+fn format_output(output: &str) -> String {
+    let config = OutputConfig {
+        max_lines: 0,
+        show_stats: false,
+        ..OutputConfig::default()
+    };
+    form_core::format_output_with(output, false, &config)
+}
 
-    result.join("\n")
+/// Options for every FORM run: FORM works in the temp directory, where it
+/// can write its temp files, and a cell is stopped after `FORM_TIMEOUT`
+/// seconds if that is set
+fn run_options_from_env() -> RunOptions {
+    RunOptions {
+        workdir: Some(std::env::temp_dir()),
+        timeout: std::env::var("FORM_TIMEOUT")
+            .ok()
+            .and_then(|secs| secs.trim().parse::<f64>().ok())
+            .filter(|secs| *secs > 0.0)
+            .map(Duration::from_secs_f64),
+        ..RunOptions::default()
+    }
 }
 
-/// Tauri command: Execute FORM code
+/// Tauri command: Execute FORM code.
+///
+/// The cell runs on a blocking task, so it ties up neither the main thread
+/// nor the async runtime, and `cancel_execution` can still be handled while
+/// FORM is busy; the session's reader threads collect output.
 #[tauri::command]
-fn execute_form(input: String, state: State<AppState>) -> FormResult {
+async fn execute_form(input: String, app: AppHandle) -> FormResult {
+    tauri::async_runtime::spawn_blocking(move || execute_cell(&app.state::<AppState>(), input))
+        .await
+        .unwrap_or_else(|e| FormResult {
+            success: false,
+            output: String::new(),
+            error: Some(format!("FORM task failed: {}", e)),
+            duration_ms: 0,
+            session_number: 0,
+            stats: FormStats::default(),
+        })
+}
+
+/// Run one cell in the persistent FORM session and record it in history
+fn execute_cell(state: &AppState, input: String) -> FormResult {
     let form_path_guard = state.form_path.lock().unwrap();
     let form_path = match form_path_guard.as_ref() {
         Some(p) => p.clone(),
@@ -211,6 +359,7 @@ fn execute_form(input: String, state: State<AppState>) -> FormResult {
                 error: Some("FORM executable not found. Set FORM_PATH environment variable.".into()),
                 duration_ms: 0,
                 session_number: 0,
+                stats: FormStats::default(),
             }
         }
     };
@@ -222,9 +371,45 @@ fn execute_form(input: String, state: State<AppState>) -> FormResult {
     let current_session = *session_count;
     drop(session_count);
 
-    // Execute FORM
-    let result = match run_form(&input, &form_path) {
-        Ok((output, duration_ms)) => {
+    run_in_session(state, &form_path, current_session, input, |_| {})
+}
+
+/// Run a cell in the persistent FORM session, (re)starting it if needed,
+/// and record it in history; `on_line` gets each result line as it arrives
+fn run_in_session<F: FnMut(&str)>(
+    state: &AppState,
+    form_path: &PathBuf,
+    current_session: usize,
+    input: String,
+    on_line: F,
+) -> FormResult {
+    let outcome = {
+        let mut guard = state.form.lock().unwrap();
+        if !guard.as_mut().is_some_and(|s| s.is_alive()) {
+            *guard = None;
+        }
+        let session = match guard.as_mut() {
+            Some(session) => Ok(session),
+            None => FormSession::spawn(form_path, &state.run_options).map(|s| guard.insert(s)),
+        };
+        let outcome = session.and_then(|session| {
+            state.running.start(session.child());
+            let outcome = session.run_cell(&input, state.run_options.timeout, on_line);
+            if state.running.finish() {
+                Err("Execution cancelled".to_string())
+            } else {
+                outcome
+            }
+        });
+        if outcome.is_err() {
+            // FORM exited or was killed; the next cell starts a fresh process
+            *guard = None;
+        }
+        outcome
+    };
+
+    let result = match outcome {
+        Ok((output, duration_ms, stats)) => {
             // Add to history
             let mut history = state.history.lock().unwrap();
             history.push(HistoryEntry {
@@ -233,6 +418,7 @@ fn execute_form(input: String, state: State<AppState>) -> FormResult {
                 output: Some(output.clone()),
                 error: None,
                 duration_ms: Some(duration_ms),
+                stats: stats.clone(),
             });
 
             FormResult {
@@ -241,6 +427,7 @@ fn execute_form(input: String, state: State<AppState>) -> FormResult {
                 error: None,
                 duration_ms,
                 session_number: current_session,
+                stats,
             }
         }
         Err(e) => {
@@ -252,6 +439,7 @@ fn execute_form(input: String, state: State<AppState>) -> FormResult {
                 output: None,
                 error: Some(e.clone()),
                 duration_ms: None,
+                stats: FormStats::default(),
             });
 
             FormResult {
@@ -260,6 +448,7 @@ fn execute_form(input: String, state: State<AppState>) -> FormResult {
                 error: Some(e),
                 duration_ms: 0,
                 session_number: current_session,
+                stats: FormStats::default(),
             }
         }
     };
@@ -267,6 +456,84 @@ fn execute_form(input: String, state: State<AppState>) -> FormResult {
     result
 }
 
+/// Output line event emitted by `streaming_execute_form`
+#[derive(Debug, Clone, Serialize)]
+struct OutputLine {
+    session_number: usize,
+    line: String,
+}
+
+/// Tauri command: Execute FORM code, streaming output as it is produced.
+///
+/// Returns the session number immediately. The cell runs in the same
+/// persistent FORM session as `execute_form`, so it sees earlier cells'
+/// definitions and `cancel_execution` stops it. Each result line is
+/// emitted as a `form-output` event, and the final `FormResult` as a
+/// `form-done` event.
+#[tauri::command]
+fn streaming_execute_form(app: AppHandle, input: String, state: State<AppState>) -> Result<usize, String> {
+    let form_path = state
+        .form_path
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| "FORM executable not found. Set FORM_PATH environment variable.".to_string())?;
+
+    let mut session_count = state.session_count.lock().unwrap();
+    *session_count += 1;
+    let current_session = *session_count;
+    drop(session_count);
+
+    std::thread::spawn(move || {
+        let emitter = app.clone();
+        let result = run_in_session(&app.state::<AppState>(), &form_path, current_session, input, |line| {
+            let _ = emitter.emit(
+                "form-output",
+                OutputLine {
+                    session_number: current_session,
+                    line: line.to_string(),
+                },
+            );
+        });
+        let _ = app.emit("form-done", result);
+    });
+
+    Ok(current_session)
+}
+
+/// Tauri command: Kill the FORM process running the current cell.
+///
+/// Returns whether anything was running; the cell then finishes with
+/// "Execution cancelled".
+#[tauri::command]
+fn cancel_execution(state: State<AppState>) -> bool {
+    state.running.cancel()
+}
+
+/// Tauri command: Restart the persistent FORM process, discarding its state.
+///
+/// A cell still running is cancelled first rather than waited for.
+#[tauri::command]
+async fn restart_form(app: AppHandle) -> Result<String, String> {
+    tauri::async_runtime::spawn_blocking(move || -> Result<String, String> {
+        let state = app.state::<AppState>();
+        let form_path = state
+            .form_path
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| "FORM executable not found. Set FORM_PATH environment variable.".to_string())?;
+
+        state.running.cancel();
+        let mut session = state.form.lock().unwrap();
+        *session = None;
+        *session = Some(FormSession::spawn(&form_path, &state.run_options)?);
+        Ok("FORM restarted".to_string())
+    })
+    .await
+    .map_err(|e| format!("FORM task failed: {}", e))?
+}
+
 /// Tauri command: Get history
 #[tauri::command]
 fn get_history(count: Option<usize>, state: State<AppState>) -> Vec<HistoryEntry> {
@@ -275,6 +542,13 @@ fn get_history(count: Option<usize>, state: State<AppState>) -> Vec<HistoryEntry
     history.iter().rev().take(n).cloned().collect()
 }
 
+/// Tauri command: Statistics of the most recent run (all null if none)
+#[tauri::command]
+fn get_stats(state: State<AppState>) -> FormStats {
+    let history = state.history.lock().unwrap();
+    history.last().map(|entry| entry.stats.clone()).unwrap_or_default()
+}
+
 /// Tauri command: Clear history
 #[tauri::command]
 fn clear_history(state: State<AppState>) {
@@ -299,21 +573,41 @@ fn get_app_info(state: State<AppState>) -> AppInfo {
     }
 }
 
-/// Tauri command: Set FORM path manually
+/// Tauri command: Set FORM path manually.
+///
+/// A cell still running is cancelled; the next cell starts the new executable.
 #[tauri::command]
-fn set_form_path(path: String, state: State<AppState>) -> Result<String, String> {
+async fn set_form_path(path: String, app: AppHandle) -> Result<String, String> {
     let path_buf = PathBuf::from(&path);
     if !path_buf.exists() {
         return Err(format!("Path does not exist: {}", path));
     }
 
-    let mut form_path = state.form_path.lock().unwrap();
-    *form_path = Some(path_buf);
-    Ok(format!("FORM path set to: {}", path))
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app.state::<AppState>();
+        *state.form_path.lock().unwrap() = Some(path_buf);
+        state.running.cancel();
+        *state.form.lock().unwrap() = None;
+        Ok(format!("FORM path set to: {}", path))
+    })
+    .await
+    .map_err(|e| format!("FORM task failed: {}", e))?
+}
+
+/// Tauri command: Highlight FORM code as spans with CSS classes
+#[tauri::command]
+fn highlight_code(input: String) -> Vec<StyledSpan> {
+    form_core::highlight_code_with_spans(&input)
+}
+
+/// Tauri command: Highlight a cell's output as spans with CSS classes
+#[tauri::command]
+fn highlight_output(output: String) -> Vec<StyledSpan> {
+    form_core::highlight_output_with_spans(&output)
 }
 
 fn main() {
-    let form_path = find_form_executable();
+    let form_path = form_core::find_form_executable();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
@@ -321,14 +615,175 @@ fn main() {
             history: Mutex::new(Vec::new()),
             session_count: Mutex::new(0),
             form_path: Mutex::new(form_path),
+            form: Mutex::new(None),
+            running: RunningForm::default(),
+            run_options: run_options_from_env(),
         })
         .invoke_handler(tauri::generate_handler![
             execute_form,
+            streaming_execute_form,
+            restart_form,
+            cancel_execution,
             get_history,
+            get_stats,
             clear_history,
             get_app_info,
             set_form_path,
+            highlight_code,
+            highlight_output,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_stats_parse() {
+        let output = [
+            "   E = x;",
+            "",
+            "Time =       0.01 sec    Generated terms =          3",
+            "               E         Terms in output =          2",
+            "                         Bytes used      =         52",
+        ]
+        .join("\n");
+        assert_eq!(
+            FormStats::parse(&output),
+            FormStats {
+                time_sec: Some(0.01),
+                generated_terms: Some(3),
+                terms_in_output: Some(2),
+                bytes_used: Some(52),
+            }
+        );
+        assert_eq!(FormStats::parse("   E = x;"), FormStats::default());
+    }
+
+    #[test]
+    fn test_form_result_json_shape() {
+        let result = FormResult {
+            success: true,
+            output: "   E = x;".to_string(),
+            error: None,
+            duration_ms: 5,
+            session_number: 1,
+            stats: FormStats {
+                terms_in_output: Some(1),
+                ..FormStats::default()
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            serde_json::json!({
+                "success": true,
+                "output": "   E = x;",
+                "error": null,
+                "duration_ms": 5,
+                "session_number": 1,
+                "time_sec": null,
+                "generated_terms": null,
+                "terms_in_output": 1,
+                "bytes_used": null,
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_running_child_set_during_execution_and_cancelled() {
+        // `cat` echoes the cell but never the bare sentinel line, so it runs until killed
+        let mut session = FormSession::spawn(&PathBuf::from("cat"), &RunOptions::default()).unwrap();
+        let running = Arc::new(RunningForm::default());
+        assert!(!running.cancel());
+
+        let tracker = running.clone();
+        let worker = std::thread::spawn(move || {
+            tracker.start(session.child());
+            let outcome = session.run_cell("Symbol x;", None, |_| {});
+            (outcome, tracker.finish())
+        });
+
+        let start = Instant::now();
+        while !running.is_running() {
+            assert!(start.elapsed().as_secs() < 5, "child handle was never set");
+            std::thread::yield_now();
+        }
+        assert!(running.cancel());
+
+        let (outcome, cancelled) = worker.join().unwrap();
+        assert!(outcome.is_err());
+        assert!(cancelled);
+        assert!(!running.is_running());
+        assert!(!running.cancel());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_cell_stops_at_timeout() {
+        let mut session = FormSession::spawn(&PathBuf::from("cat"), &RunOptions::default()).unwrap();
+        let start = Instant::now();
+        let outcome = session.run_cell("Symbol x;", Some(Duration::from_millis(200)), |_| {});
+        assert_eq!(outcome.unwrap_err(), "FORM execution timed out");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_cell_streams_result_lines() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Prints each line it reads as a result, and the sentinel for `#write`
+        let dir = std::env::temp_dir().join(format!("form-repl-gui-stream-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("form");
+        let script = format!(
+            "#!/bin/sh\nwhile read -r line; do\n  case \"$line\" in\n    '#write'*) echo {};;\n    *) echo \"   $line\";;\n  esac\ndone\n",
+            CELL_SENTINEL
+        );
+        std::fs::write(&exe, script).unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut session = FormSession::spawn(&exe, &RunOptions::default()).unwrap();
+        let mut lines = Vec::new();
+        let outcome = session.run_cell("Local E = x;", None, |line| lines.push(line.to_string()));
+        assert!(outcome.is_ok());
+        assert_eq!(lines, ["   Local E = x;", "   .sort"]);
+
+        // The next cell's lines start afresh
+        lines.clear();
+        let outcome = session.run_cell("Local F = y;", None, |line| lines.push(line.to_string()));
+        assert!(outcome.is_ok());
+        assert_eq!(lines, ["   Local F = y;", "   .sort"]);
+        drop(session);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_split_cell_waits_for_complete_sentinel() {
+        let mut buffer = String::from("FORM 4.3\n\n   E = x;\n__FORM_REPL_CELL_");
+        assert_eq!(split_cell(&mut buffer), None);
+
+        buffer.push_str("END__\n   F = y;\n");
+        assert_eq!(split_cell(&mut buffer).as_deref(), Some("FORM 4.3\n\n   E = x;\n"));
+        assert_eq!(buffer, "   F = y;\n");
+        assert_eq!(split_cell(&mut buffer), None);
+    }
+
+    #[test]
+    fn test_split_cell_handles_several_cells_in_one_chunk() {
+        let mut buffer = format!("a\n{0}\n\n{0}\nb\n", CELL_SENTINEL);
+        assert_eq!(split_cell(&mut buffer).as_deref(), Some("a\n"));
+        assert_eq!(split_cell(&mut buffer).as_deref(), Some("\n"));
+        assert_eq!(buffer, "b\n");
+    }
+
+    #[test]
+    fn test_cell_input_keeps_session_open() {
+        let text = cell_input("Symbol x;\nLocal E = x;\nPrint;\n.end");
+        assert!(!text.lines().any(|l| l.trim() == ".end"));
+        assert!(text.ends_with(&format!(".sort\n#write \"{}\"\n", CELL_SENTINEL)));
+    }
+}
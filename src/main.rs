@@ -1,59 +1,90 @@
 mod modules;
 
+use std::collections::VecDeque;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
+use rustyline::config::{Configurer, EditMode};
 use rustyline::error::ReadlineError;
 use rustyline::history::FileHistory;
 use rustyline::Editor;
 
-use modules::config::Config;
-use modules::form::{self, find_form_executable};
+use modules::cache::ResultCache;
+use modules::completion::FormValidator;
+use modules::config::{self, Config, HistoryFormat, PromptConfig, SeparatorConfig, SubmitMode};
+use modules::form;
+use modules::formatter;
 use modules::highlight;
+use modules::history::{JsonHistoryBackend, JsonHistoryEntry};
 use modules::magic::{self, MagicResult, SessionState};
+use modules::prompt::Prompts;
+use modules::server::{Endpoint, JsonRpcServer};
 use modules::term::{self, ansi};
 use modules::theme::{self, Theme};
 
 /// Runtime configuration from CLI arguments
 struct CliConfig {
     highlight: bool,
+    /// --no-color / --color=never: no escape codes at all
+    no_color: bool,
     theme_name: String,
     verbose: bool,
     show_help: bool,
     show_version: bool,
     show_sample_config: bool,
+    dry_run: bool,
+    strict: bool,
+    format_input: bool,
+    no_cache: bool,
+    server: bool,
+    server_port: Option<u16>,
+    form_flags: Option<String>,
+    form_path: Option<PathBuf>,
+    edit_mode: Option<String>,
+    output_format: OutputFormat,
+    generate_completion: Option<String>,
 }
 
-/// Print the help message
-fn print_help(theme: &Theme, highlight: bool) {
+/// The help message shown by `.help`, `%help` and `--help`
+fn help_text(theme: &Theme, highlight: bool) -> String {
     let reset = ansi::RESET;
     let bold = ansi::BOLD;
     let h = if highlight { theme.prompt_in.as_str() } else { "" };
     let r = if highlight { reset } else { "" };
 
-    println!();
-    println!("{}{}FORM REPL{} - Interactive FORM environment with IPython-like UX", h, bold, r);
-    println!();
-    println!("{}Input modes:{}", bold, reset);
-    println!("  • Type FORM code, press Enter to continue on next line");
-    println!("  • Press Enter on empty line (or type .end) to submit");
-    println!("  • Use Up/Down arrows for command history");
-    println!("  • Press Ctrl+C to cancel current input");
-    println!("  • Press Ctrl+D to exit (or submit if buffer not empty)");
-    println!();
-    println!("{}REPL commands:{}", bold, reset);
-    println!("  {}{}help{}, {}.quit{}   - Show help / Exit", h, ".", r, h, r);
-    println!("  {}.clear{}          - Clear current input buffer", h, r);
-    println!();
-    println!("{}Magic commands:{}", bold, reset);
-    println!("  {}%history [N]{}    - Show last N history entries", h, r);
-    println!("  {}%time{}           - Toggle timing display", h, r);
-    println!("  {}%who{}            - List declared symbols", h, r);
-    println!("  {}%reset{}          - Clear session state", h, r);
-    println!("  {}%lsmagic{}        - List all magic commands", h, r);
-    println!();
+    format!(
+        "
+{h}{bold}FORM REPL{r} - Interactive FORM environment with IPython-like UX
+
+{bold}Input modes:{reset}
+  • Type FORM code, press Enter to continue on next line
+  • Press Enter on empty line (or type .end) to submit
+  • Use Up/Down arrows for command history
+  • Press Ctrl+R to search history; a multi-line cell is recalled in full
+  • Use _{{raw}} or _{{oneline}} to splice in the last output
+  • Press Ctrl+C to cancel current input
+  • Press Ctrl+D to exit (or submit if buffer not empty)
+
+{bold}REPL commands:{reset}
+  {h}.help{r}, {h}.quit{r}   - Show help / Exit
+  {h}.clear{r}          - Clear current input buffer
+
+{bold}Magic commands:{reset}
+  {h}%history [N]{r}    - Show last N history entries
+  {h}%time{r}           - Toggle timing display
+  {h}%who{r}            - List declared symbols
+  {h}%reset{r}          - Clear session state
+  {h}%lsmagic{r}        - List all magic commands
+"
+    )
+}
+
+/// Print the help message
+fn print_help(theme: &Theme, highlight: bool) {
+    println!("{}", help_text(theme, highlight));
 }
 
 /// Print version information
@@ -62,16 +93,243 @@ fn print_version() {
     println!("A modern interactive environment for FORM");
 }
 
+/// Command-line flags offered by shell completion: (long, short, description)
+const CLI_FLAGS: &[(&str, Option<&str>, &str)] = &[
+    ("--help", Some("-h"), "Show this help message"),
+    ("--version", Some("-V"), "Show version information"),
+    ("--highlight", Some("-H"), "Enable syntax highlighting"),
+    ("--no-highlight", None, "Disable syntax highlighting"),
+    ("--no-color", None, "No colors or escape codes in any output"),
+    ("--theme", Some("-t"), "Set color theme"),
+    ("--light", None, "Use a theme for light terminal backgrounds"),
+    ("--verbose", Some("-v"), "Enable verbose debug output"),
+    ("--list-themes", None, "List available themes"),
+    ("--sample-config", None, "Print sample configuration file"),
+    ("--dry-run", None, "Print the input sent to FORM instead of running it"),
+    ("--strict", None, "Warn about names used before they are declared"),
+    ("--format-input", None, "Pretty-print each cell before sending it to FORM"),
+    ("--no-cache", None, "Always run FORM, ignoring cache_results and cached results"),
+    ("--form-path", Some("-f"), "Use this FORM executable"),
+    ("--form-flags", None, "Extra arguments passed to FORM"),
+    ("--output", Some("-o"), "Output format: human, latex, plain or json"),
+    ("--server", None, "Serve completions, hover and diagnostics to editors"),
+    ("--server-port", None, "Like --server, on this TCP port"),
+    ("--vi", None, "Use vi keybindings"),
+    ("--emacs", None, "Use emacs keybindings"),
+    ("--generate-completion", None, "Print a shell completion script"),
+];
+
+/// Formats accepted by --output ("text" is an alias of "human")
+const OUTPUT_FORMATS: &[&str] = &["human", "text", "json", "plain", "latex"];
+
+/// How cell results are shown (--output)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Prompts, separators and colors (the interactive default)
+    Human,
+    /// One JSON object per executed cell, for scripts and CI
+    Json,
+    /// Results only: no prompts, separators or escape codes
+    Plain,
+    /// Like `Human`, with results rendered as LaTeX
+    Latex,
+}
+
+impl OutputFormat {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "human" | "text" => Some(OutputFormat::Human),
+            "json" => Some(OutputFormat::Json),
+            "plain" => Some(OutputFormat::Plain),
+            "latex" => Some(OutputFormat::Latex),
+            _ => None,
+        }
+    }
+
+    /// Whether the banner, prompts and separators are shown
+    fn is_interactive(self) -> bool {
+        matches!(self, OutputFormat::Human | OutputFormat::Latex)
+    }
+}
+
+/// The `--output json` record for one executed cell
+fn json_result(
+    session: usize,
+    input: &str,
+    output: &str,
+    duration: Option<Duration>,
+    success: bool,
+) -> String {
+    serde_json::json!({
+        "session": session,
+        "input": input,
+        "output": output,
+        "duration_ms": duration.map(|d| d.as_millis() as u64),
+        "success": success,
+    })
+    .to_string()
+}
+
+/// Prints the reply to a magic or REPL command. With `--output json` it is a
+/// record like a cell's, so stdout stays one JSON object per line.
+fn print_reply(format: OutputFormat, session: usize, input: &str, reply: &str, success: bool) {
+    if format == OutputFormat::Json {
+        let reply = term::strip_ansi(reply);
+        println!("{}", json_result(session, input, reply.trim_matches('\n'), None, success));
+    } else {
+        println!("{}", reply);
+    }
+}
+
+/// Shells supported by --generate-completion
+const COMPLETION_SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+/// Build a completion script for the given shell
+fn completion_script(shell: &str) -> Option<String> {
+    let themes = theme::list_themes().join(" ");
+    let shells = COMPLETION_SHELLS.join(" ");
+    let formats = OUTPUT_FORMATS.join(" ");
+    let all_flags: Vec<&str> = CLI_FLAGS
+        .iter()
+        .flat_map(|(long, short, _)| std::iter::once(*long).chain(*short))
+        .collect();
+
+    let script = match shell.to_lowercase().as_str() {
+        "bash" => format!(
+            r#"_form_repl() {{
+    local cur prev
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+        --theme|-t)
+            COMPREPLY=( $(compgen -W "{themes}" -- "$cur") )
+            return ;;
+        --generate-completion)
+            COMPREPLY=( $(compgen -W "{shells}" -- "$cur") )
+            return ;;
+        --output|-o)
+            COMPREPLY=( $(compgen -W "{formats}" -- "$cur") )
+            return ;;
+        --form-path|-f)
+            COMPREPLY=( $(compgen -f -- "$cur") )
+            return ;;
+        --form-flags|--server-port)
+            return ;;
+    esac
+    COMPREPLY=( $(compgen -W "{flags}" -- "$cur") )
+}}
+complete -F _form_repl form-repl
+"#,
+            flags = all_flags.join(" ")
+        ),
+        "zsh" => {
+            let specs: Vec<String> = CLI_FLAGS
+                .iter()
+                .map(|(long, short, desc)| {
+                    let spec = match *short {
+                        Some(short) => format!("'({short} {long})'{{{short},{long}}}'[{desc}]"),
+                        None => format!("'{long}[{desc}]"),
+                    };
+                    let values = match *long {
+                        "--theme" => format!(":theme:({themes})"),
+                        "--generate-completion" => format!(":shell:({shells})"),
+                        "--output" => format!(":format:({formats})"),
+                        "--form-path" => String::from(":path:_files"),
+                        "--form-flags" => String::from(":flags: "),
+                        "--server-port" => String::from(":port: "),
+                        _ => String::new(),
+                    };
+                    format!("  {}{}'", spec, values)
+                })
+                .collect();
+            format!("#compdef form-repl\n\n_arguments \\\n{}\n", specs.join(" \\\n"))
+        }
+        "fish" => {
+            let mut s = String::new();
+            for (long, short, desc) in CLI_FLAGS {
+                s.push_str("complete -c form-repl");
+                if let Some(short) = short {
+                    s.push_str(&format!(" -s {}", &short[1..]));
+                }
+                s.push_str(&format!(" -l {}", &long[2..]));
+                match *long {
+                    "--theme" => s.push_str(&format!(" -x -a '{}'", themes)),
+                    "--generate-completion" => s.push_str(&format!(" -x -a '{}'", shells)),
+                    "--output" => s.push_str(&format!(" -x -a '{}'", formats)),
+                    "--form-path" => s.push_str(" -r -F"),
+                    "--form-flags" | "--server-port" => s.push_str(" -r"),
+                    _ => {}
+                }
+                s.push_str(&format!(" -d '{}'\n", desc));
+            }
+            s
+        }
+        "powershell" => {
+            let quote = |items: &[&str]| {
+                items.iter().map(|i| format!("'{}'", i)).collect::<Vec<_>>().join(", ")
+            };
+            format!(
+                r#"Register-ArgumentCompleter -Native -CommandName form-repl -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $elements = $commandAst.CommandElements | ForEach-Object {{ $_.ToString() }}
+    $prev = if ($wordToComplete) {{ $elements[-2] }} else {{ $elements[-1] }}
+    $candidates = switch ($prev) {{
+        {{ $_ -in '--theme', '-t' }} {{ @({themes}) }}
+        '--generate-completion' {{ @({shells}) }}
+        {{ $_ -in '--output', '-o' }} {{ @({formats}) }}
+        default {{ @({flags}) }}
+    }}
+    $candidates | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }}
+}}
+"#,
+                themes = quote(&theme::list_themes()),
+                shells = quote(COMPLETION_SHELLS),
+                formats = quote(OUTPUT_FORMATS),
+                flags = quote(&all_flags)
+            )
+        }
+        _ => return None,
+    };
+
+    Some(script)
+}
+
+/// Print a shell completion script, exiting with an error for unknown shells
+fn print_completion(shell: &str) {
+    match completion_script(shell) {
+        Some(script) => print!("{}", script),
+        None => {
+            eprintln!("Unsupported shell: {}", shell);
+            eprintln!("Supported shells: {}", COMPLETION_SHELLS.join(", "));
+            std::process::exit(1);
+        }
+    }
+}
+
 /// Parse command line arguments
 fn parse_args() -> CliConfig {
     let args: Vec<String> = env::args().collect();
     let mut config = CliConfig {
         highlight: false,
+        no_color: false,
         theme_name: "default".to_string(),
         verbose: false,
         show_help: false,
         show_version: false,
         show_sample_config: false,
+        dry_run: false,
+        strict: false,
+        format_input: false,
+        no_cache: false,
+        server: false,
+        server_port: None,
+        form_flags: None,
+        form_path: None,
+        edit_mode: None,
+        output_format: OutputFormat::Human,
+        generate_completion: None,
     };
 
     let mut i = 1;
@@ -85,6 +343,13 @@ fn parse_args() -> CliConfig {
             "--highlight" | "-H" => config.highlight = true,
             "--no-highlight" => config.highlight = false,
             
+            "--no-color" | "--color=never" => config.no_color = true,
+            "--color=always" => {
+                config.highlight = true;
+                config.no_color = false;
+            }
+            "--color=auto" => {}
+            
             "--theme" | "-t" => {
                 if i + 1 < args.len() {
                     config.theme_name = args[i + 1].clone();
@@ -97,10 +362,85 @@ fn parse_args() -> CliConfig {
                 }
             }
             
+            "--light" => {
+                config.theme_name = theme::LIGHT_DEFAULT.to_string();
+                config.highlight = true;
+            }
+            
             "--verbose" | "-v" => config.verbose = true,
             
             "--sample-config" => config.show_sample_config = true,
             
+            "--dry-run" => config.dry_run = true,
+            
+            "--strict" => config.strict = true,
+            
+            "--format-input" => config.format_input = true,
+            
+            "--no-cache" => config.no_cache = true,
+            
+            "--form-path" | "-f" => {
+                if i + 1 < args.len() {
+                    config.form_path = Some(PathBuf::from(&args[i + 1]));
+                    i += 1;
+                } else {
+                    eprintln!("Error: --form-path requires a path to the FORM executable");
+                    std::process::exit(1);
+                }
+            }
+            
+            "--form-flags" => {
+                if i + 1 < args.len() {
+                    config.form_flags = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    eprintln!("Error: --form-flags requires an argument string, e.g. \"-w4\"");
+                    std::process::exit(1);
+                }
+            }
+            
+            "--server" => config.server = true,
+            
+            "--server-port" => match args.get(i + 1).and_then(|p| p.parse::<u16>().ok()) {
+                Some(port) => {
+                    config.server = true;
+                    config.server_port = Some(port);
+                    i += 1;
+                }
+                None => {
+                    eprintln!("Error: --server-port requires a port number");
+                    std::process::exit(1);
+                }
+            },
+            
+            "--output" | "-o" => {
+                match args.get(i + 1).and_then(|f| OutputFormat::parse(f)) {
+                    Some(format) => {
+                        config.output_format = format;
+                        i += 1;
+                    }
+                    _ => {
+                        eprintln!("Error: --output requires a format");
+                        eprintln!("Supported formats: {}", OUTPUT_FORMATS.join(", "));
+                        std::process::exit(1);
+                    }
+                }
+            }
+            
+            "--vi" => config.edit_mode = Some("vi".to_string()),
+            "--emacs" => config.edit_mode = Some("emacs".to_string()),
+            
+            "--generate-completion" => {
+                if i + 1 < args.len() {
+                    config.generate_completion = Some(args[i + 1].clone());
+                    i += 1;
+                } else {
+                    eprintln!("Error: --generate-completion requires a shell name");
+                    eprintln!("Supported shells: {}", COMPLETION_SHELLS.join(", "));
+                    std::process::exit(1);
+                }
+            }
+            
             "--list-themes" => {
                 println!("Available themes:");
                 for t in theme::list_themes() {
@@ -137,82 +477,74 @@ fn is_repl_command(line: &str) -> Option<&str> {
     }
 }
 
-/// Format the input prompt (IPython style)
-fn format_in_prompt(n: usize, theme: &Theme, highlight: bool) -> String {
-    if highlight {
-        format!(
-            "{}{}In [{}]:{} ",
-            theme.prompt_in,
-            ansi::BOLD,
-            n,
-            ansi::RESET
-        )
-    } else {
-        format!("In [{}]: ", n)
+/// Print the separator line, unless it is turned off
+fn print_separator(style: &SeparatorConfig, columns: usize, theme: &Theme, highlight: bool) {
+    if !style.enabled {
+        return;
     }
+    let width = style.width.columns(columns);
+    println!("{}", term::separator(&style.pattern, width, highlight, &theme.separator));
 }
 
-/// Format the continuation prompt
-fn format_cont_prompt(n: usize, theme: &Theme, highlight: bool) -> String {
-    let spaces = format!("{}", n).len();
-    let padding = " ".repeat(spaces + 5); // "In [" + n + "]"
-    
-    if highlight {
-        format!(
-            "{}{}...:{} ",
-            theme.prompt_cont,
-            padding,
-            ansi::RESET
-        )
-    } else {
-        format!("{}...: ", padding)
-    }
+/// Source of input lines for the multi-line reader
+trait LineReader {
+    fn read_line(&mut self, prompt: &str) -> rustyline::Result<String>;
 }
 
-/// Format the output prompt
-fn format_out_prompt(n: usize, theme: &Theme, highlight: bool) -> String {
-    if highlight {
-        format!(
-            "{}{}Out[{}]:{} ",
-            theme.prompt_out,
-            ansi::BOLD,
-            n,
-            ansi::RESET
-        )
-    } else {
-        format!("Out[{}]: ", n)
+impl LineReader for Editor<FormValidator, FileHistory> {
+    fn read_line(&mut self, prompt: &str) -> rustyline::Result<String> {
+        self.readline(prompt)
     }
 }
 
-/// Print separator line
-fn print_separator(theme: &Theme, highlight: bool) {
-    let width = 60;
-    if highlight {
-        println!("{}", term::separator(width, true, &theme.separator));
-    } else {
-        println!("{}", "─".repeat(width));
+/// Finish a cell: plain code is returned as input, while a code-taking
+/// magic is reported with its collected body on the following lines
+fn finish_cell(magic_header: Option<String>, body: String) -> Result<Option<String>, String> {
+    match magic_header {
+        Some(header) => Err(format!("MAGIC:{}\n{}", header, body)),
+        None => Ok(Some(body)),
     }
 }
 
 /// Read multi-line input from the user
-fn read_multiline_input(
-    rl: &mut Editor<(), FileHistory>,
+fn read_multiline_input<R: LineReader>(
+    rl: &mut R,
     session_num: usize,
+    prompts: &Prompts,
     theme: &Theme,
     highlight: bool,
+    auto_submit: bool,
+    submit_mode: SubmitMode,
 ) -> Result<Option<String>, String> {
     let mut full_input = String::new();
     let mut is_first_line = true;
+    let mut magic_header: Option<String> = None;
+    let mut last_was_blank = false;
+    // Later lines of a multi-line cell recalled from history (Up, Ctrl+R)
+    let mut recalled: VecDeque<String> = VecDeque::new();
 
     loop {
         let prompt = if is_first_line {
-            format_in_prompt(session_num, theme, highlight)
+            prompts.input(session_num, theme, highlight)
         } else {
-            format_cont_prompt(session_num, theme, highlight)
+            prompts.continuation(session_num, theme, highlight)
         };
 
-        match rl.readline(&prompt) {
+        let read = match recalled.pop_front() {
+            Some(line) => Ok(line),
+            None => rl.read_line(&prompt),
+        };
+        match read {
             Ok(line) => {
+                // A recalled cell arrives as one string; take it line by
+                // line as if it had been typed
+                let line = match line.split_once('\n') {
+                    Some((first, rest)) => {
+                        recalled.extend(rest.split('\n').map(String::from));
+                        first.to_string()
+                    }
+                    None => line,
+                };
                 let trimmed = line.trim();
 
                 // .end submits
@@ -221,25 +553,46 @@ fn read_multiline_input(
                         full_input.push('\n');
                     }
                     full_input.push_str(".end");
-                    return Ok(Some(full_input));
+                    return finish_cell(magic_header, full_input);
                 }
 
                 // Empty line handling
                 if line.is_empty() {
-                    if full_input.is_empty() {
-                        if is_first_line {
-                            // Completely empty - show hint
-                            println!(
-                                "{}Type FORM code, .help for help, or .quit to exit{}",
-                                if highlight { &theme.prompt_cont } else { "" },
-                                if highlight { ansi::RESET } else { "" }
-                            );
+                    if full_input.is_empty() && is_first_line {
+                        // Completely empty - show hint (unless prompts are off)
+                        if prompt.is_empty() {
+                            continue;
+                        }
+                        println!(
+                            "{}Type FORM code ({}), .help for help, or .quit to exit{}",
+                            if highlight { &theme.prompt_cont } else { "" },
+                            submit_mode.hint(),
+                            if highlight { ansi::RESET } else { "" }
+                        );
+                        continue;
+                    }
+                    match submit_mode {
+                        // A blank line inside a recalled cell is part of it
+                        _ if !recalled.is_empty() => {
+                            full_input.push('\n');
+                            last_was_blank = true;
+                            continue;
+                        }
+                        // Non-empty buffer + empty line = submit
+                        SubmitMode::EmptyLine => return finish_cell(magic_header, full_input),
+                        SubmitMode::DoubleEnter if last_was_blank => {
+                            let cell = full_input.trim_end_matches('\n').to_string();
+                            return finish_cell(magic_header, cell);
+                        }
+                        // Otherwise the blank line is part of the cell
+                        SubmitMode::DotEndOnly | SubmitMode::DoubleEnter => {
+                            full_input.push('\n');
+                            last_was_blank = true;
                             continue;
                         }
                     }
-                    // Non-empty buffer + empty line = submit
-                    return Ok(Some(full_input));
                 }
+                last_was_blank = false;
 
                 // Check for REPL commands on first line
                 if is_first_line {
@@ -247,9 +600,15 @@ fn read_multiline_input(
                         return Err(format!("CMD:{}", cmd));
                     }
                     
-                    // Check for magic commands
+                    // Check for magic commands; code-taking magics keep
+                    // reading their body from the continuation lines
                     if trimmed.starts_with('%') {
-                        return Err(format!("MAGIC:{}", trimmed));
+                        if !magic::takes_code(trimmed) {
+                            return Err(format!("MAGIC:{}", trimmed));
+                        }
+                        magic_header = Some(trimmed.to_string());
+                        is_first_line = false;
+                        continue;
                     }
                 }
 
@@ -259,18 +618,25 @@ fn read_multiline_input(
                 }
                 full_input.push_str(&line);
                 is_first_line = false;
+
+                // Smart submission: balanced brackets and a trailing ';'
+                // (not before a recalled or validated cell is all read)
+                if auto_submit && recalled.is_empty() && form::is_input_complete(&full_input) {
+                    return finish_cell(magic_header, full_input);
+                }
             }
             Err(ReadlineError::Interrupted) => {
-                // Ctrl+C - cancel current input
+                // Ctrl+C - drop the buffer and start a fresh prompt; it
+                // never exits the REPL (that's .quit or Ctrl+D)
                 println!("^C");
                 return Ok(None);
             }
             Err(ReadlineError::Eof) => {
                 // Ctrl+D
-                if full_input.is_empty() {
+                if full_input.is_empty() && magic_header.is_none() {
                     return Err("EXIT".to_string());
                 } else {
-                    return Ok(Some(full_input));
+                    return finish_cell(magic_header, full_input);
                 }
             }
             Err(e) => {
@@ -280,6 +646,32 @@ fn read_multiline_input(
     }
 }
 
+/// Map an `edit_mode` setting to rustyline's keybinding mode
+fn parse_edit_mode(name: &str) -> Option<EditMode> {
+    match name.to_lowercase().as_str() {
+        "emacs" => Some(EditMode::Emacs),
+        "vi" => Some(EditMode::Vi),
+        _ => None,
+    }
+}
+
+fn edit_mode_label(mode: EditMode) -> &'static str {
+    match mode {
+        EditMode::Vi => "vi",
+        _ => "emacs",
+    }
+}
+
+/// Format raw FORM output for display, returning (full, shown).
+///
+/// Enabled `%postproc` transformers run before `shown` is truncated
+/// to `max_output_lines`, so %expand, %full and %more see the same text.
+fn render_output(raw: &str, state: &SessionState) -> (String, String) {
+    let formatted = state.render_output(raw);
+    let shown = form::truncate_output(&formatted, state.max_output_lines);
+    (formatted, shown)
+}
+
 fn main() {
     let cli_config = parse_args();
     
@@ -289,6 +681,11 @@ fn main() {
         return;
     }
     
+    if let Some(ref shell) = cli_config.generate_completion {
+        print_completion(shell);
+        return;
+    }
+    
     if cli_config.show_sample_config {
         print!("{}", modules::config::sample_config());
         return;
@@ -296,15 +693,32 @@ fn main() {
     
     // Load file config (can be overridden by CLI)
     let file_config = Config::load();
+
+    // Editor server mode: no REPL and no FORM process
+    if cli_config.server {
+        let endpoint = Endpoint::resolve(cli_config.server_port, &file_config.server);
+        eprintln!("form-repl server listening on {}", endpoint);
+        if let Err(e) = JsonRpcServer::new().serve(&endpoint) {
+            eprintln!("Error: cannot serve on {}: {}", endpoint, e);
+            std::process::exit(1);
+        }
+        return;
+    }
     
     // Merge configs: CLI takes precedence
-    let highlight = cli_config.highlight || file_config.settings.highlight;
+    let no_color = cli_config.no_color
+        || matches!(cli_config.output_format, OutputFormat::Json | OutputFormat::Plain);
+    let highlight = !no_color && (cli_config.highlight || file_config.settings.highlight);
     let theme_name = if cli_config.theme_name != "default" {
         cli_config.theme_name.clone()
-    } else {
+    } else if file_config.settings.theme != "default" {
         file_config.settings.theme.clone()
+    } else {
+        // No theme chosen: follow the terminal background if it says
+        theme::auto_theme(env::var("COLORFGBG").ok().as_deref()).to_string()
     };
     let verbose = cli_config.verbose || file_config.settings.verbose;
+    term::init_logging(verbose);
     
     let theme = theme::get_theme(&theme_name);
     
@@ -316,16 +730,49 @@ fn main() {
         println!("  -h, --help          Show this help message");
         println!("  -V, --version       Show version information");
         println!("  -H, --highlight     Enable syntax highlighting");
+        println!("  --no-color          No colors or escape codes (same as --color=never)");
         println!("  -t, --theme NAME    Set color theme");
+        println!("  --light             Use a theme for light backgrounds (same as -t light)");
         println!("  -v, --verbose       Enable verbose debug output");
         println!("  --list-themes       List available themes");
         println!("  --sample-config     Print sample configuration file");
+        println!("  --dry-run           Print the input sent to FORM instead of running it");
+        println!("  --strict            Warn about names used before they are declared");
+        println!("  --format-input      Pretty-print each cell before sending it to FORM");
+        println!("  --no-cache          Always run FORM, ignoring cache_results and cached results");
+        println!("  -f, --form-path PATH");
+        println!("                      Use this FORM executable (skips FORM_PATH and PATH search)");
+        println!("  --form-flags ARGS   Extra FORM arguments (overrides config and FORM_FLAGS)");
+        println!("  -o, --output FORMAT Show results as human (default), latex, plain or json");
+        println!("  --server            Serve completions, hover and diagnostics to editors");
+        println!("  --server-port N     Like --server, on TCP port N of 127.0.0.1");
+        println!("  --vi, --emacs       Line editing keybindings (overrides edit_mode)");
+        println!("  --generate-completion SHELL");
+        println!("                      Print completion script (bash, zsh, fish, powershell)");
         println!();
         return;
     }
 
-    // Find FORM executable
-    let form_path: PathBuf = match find_form_executable() {
+    // Find FORM executable: an existing [form] path wins over discovery
+    let configured_path = file_config.form.path().and_then(|p| {
+        if p.is_file() {
+            Some(p)
+        } else if p.join("form").is_file() {
+            Some(p.join("form"))
+        } else {
+            eprintln!(
+                "Warning: configured FORM path {} not found, searching instead",
+                p.display()
+            );
+            None
+        }
+    });
+    let located = form::locate_form(cli_config.form_path.as_deref(), configured_path)
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        });
+    let mut form_path: PathBuf = match located {
         Some(p) => p,
         None => {
             let error_prefix = if highlight {
@@ -340,125 +787,318 @@ fn main() {
         }
     };
 
-    // Set verbose mode
-    if verbose {
-        term::set_verbose(true);
-        term::verbose_println(&format!("Using FORM at: {}", form_path.display()));
-        term::verbose_println(&format!("Theme: {}", theme_name));
-    }
+    let caching = file_config.settings.cache_results && !cli_config.no_cache;
+    let needs_version = verbose || caching || Prompts::needs_form_version(&file_config.prompt);
+    let form_version = needs_version.then(|| form::detect_version(&form_path)).flatten();
+    vprintln!("{}", form::form_info(&form_path, form_version.as_deref()));
+    // Scripted formats print no prompts at all
+    let prompt_config = if cli_config.output_format.is_interactive() {
+        file_config.prompt.clone()
+    } else {
+        PromptConfig {
+            input: Some(String::new()),
+            output: Some(String::new()),
+            continuation: Some(String::new()),
+        }
+    };
+    let mut prompts = Prompts::new(prompt_config, form_version.clone());
+    vprintln!("Theme: {}", theme_name);
 
     // Initialize session state
     let mut state = SessionState::new();
+    state.run_options = file_config.run_options();
+    state.form_flags = form::resolve_form_flags(
+        cli_config.form_flags.as_deref(),
+        &state.run_options.extra_args,
+        env::var("FORM_FLAGS").ok().as_deref(),
+    );
+    // Every FORM run of the session, magics included, gets only these
+    let unsupported = state.use_form_binary(form_path.clone());
+    if !unsupported.is_empty() {
+        eprintln!(
+            "Warning: {} doesn't support {}; not passing it",
+            form_path.display(),
+            unsupported.join(" ")
+        );
+    }
     state.show_timing = file_config.settings.show_timing;
+    state.separator = file_config.settings.separator.clone();
+    state.batch_threshold = file_config.form.batch_threshold;
+    state.cache_results = caching;
+    state.form_version = form_version;
+    if !cli_config.no_cache {
+        state.expression_cache = ResultCache::open(&config::cache_path());
+    }
+    state.max_output_lines = file_config.max_output_lines();
+    state.output_config = file_config.output.clone();
+    state.lint_config = file_config.linter.clone();
+    let macros_path = config::macros_path();
+    state.macros = file_config.macros.clone();
+    state.macros.extend(config::load_macros(&macros_path));
+    state.macros_path = Some(macros_path);
+    vprintln!("Working directory: {}", state.working_dir.display());
+    state.dry_run = cli_config.dry_run;
+    let strict = cli_config.strict || file_config.settings.strict;
+    let stream_output = file_config.settings.stream_output;
+    let output_format = cli_config.output_format;
+    let latex_output = output_format == OutputFormat::Latex;
+    let interactive = output_format.is_interactive();
+    // Never wait for an answer nobody can see
+    let confirm_destructive =
+        file_config.settings.confirm_destructive && interactive && ansi::is_tty();
 
     // Initialize rustyline
-    let mut rl: Editor<(), FileHistory> = match Editor::new() {
+    let mut rl: Editor<FormValidator, FileHistory> = match Editor::new() {
         Ok(editor) => editor,
         Err(e) => {
             eprintln!("Failed to initialize editor: {:?}", e);
             std::process::exit(1);
         }
     };
+    let edit_mode_name = cli_config
+        .edit_mode
+        .clone()
+        .unwrap_or_else(|| file_config.settings.edit_mode.clone());
+    let edit_mode = parse_edit_mode(&edit_mode_name).unwrap_or_else(|| {
+        eprintln!("Warning: unknown edit_mode \"{}\", using emacs", edit_mode_name);
+        EditMode::Emacs
+    });
+    rl.set_edit_mode(edit_mode);
+    // Smart submission edits the whole cell at once: Enter starts a new
+    // line until the cell is complete
+    if file_config.settings.auto_submit {
+        rl.set_helper(Some(FormValidator));
+    }
 
     // Load history
     let history_path = file_config.history_path();
-    let _ = rl.load_history(&history_path);
+    let mut history_format = file_config.history.format;
+    if history_format == HistoryFormat::Sqlite {
+        eprintln!("Warning: the sqlite history format is not supported yet, using plain");
+        history_format = HistoryFormat::Plain;
+    }
+    // JSON history keeps past entries so they can be written back with this session's
+    let mut json_history = None;
+    match history_format {
+        HistoryFormat::Json => {
+            let backend = JsonHistoryBackend::new(&history_path);
+            match backend.load() {
+                Ok(entries) => {
+                    for entry in &entries {
+                        let _ = rl.add_history_entry(&entry.input);
+                    }
+                    json_history = Some((backend, entries));
+                }
+                // Leave a corrupt file alone rather than overwrite it on exit
+                Err(e) => eprintln!("Warning: {}", e),
+            }
+        }
+        _ => {
+            let _ = rl.load_history(&history_path);
+        }
+    }
 
     // Print welcome banner
-    println!();
-    if highlight {
-        println!(
-            "{}{}FORM REPL{} v{} — Type {}%help{} for help, {}.quit{} to exit",
-            theme.prompt_in,
-            ansi::BOLD,
-            ansi::RESET,
-            env!("CARGO_PKG_VERSION"),
-            theme.prompt_out,
-            ansi::RESET,
-            theme.prompt_out,
-            ansi::RESET
-        );
-        if verbose {
-            println!("{}  Theme: {} | Verbose mode{}", theme.prompt_cont, theme_name, ansi::RESET);
+    if interactive {
+        println!();
+        if highlight {
+            println!(
+                "{}{}FORM REPL{} v{} — Type {}%help{} for help, {}.quit{} to exit",
+                theme.prompt_in,
+                ansi::BOLD,
+                ansi::RESET,
+                env!("CARGO_PKG_VERSION"),
+                theme.prompt_out,
+                ansi::RESET,
+                theme.prompt_out,
+                ansi::RESET
+            );
+            if verbose {
+                println!(
+                    "{}  Theme: {} | Edit mode: {} | Verbose mode{}",
+                    theme.prompt_cont,
+                    theme_name,
+                    edit_mode_label(edit_mode),
+                    ansi::RESET
+                );
+            }
+        } else {
+            println!(
+                "FORM REPL v{} — Type %help for help, .quit to exit",
+                env!("CARGO_PKG_VERSION")
+            );
+            if verbose {
+                println!("  Theme: {} | Edit mode: {} | Verbose mode", theme_name, edit_mode_label(edit_mode));
+            }
         }
-    } else {
-        println!(
-            "FORM REPL v{} — Type %help for help, .quit to exit",
-            env!("CARGO_PKG_VERSION")
-        );
+        println!();
+    }
+
+    // Startup file: run once now, then ahead of every cell
+    match magic::run_startup_file(&file_config.startup_path(), &mut state, &form_path) {
+        Ok(Some(output)) if file_config.settings.startup_verbose && interactive => {
+            for line in output.lines().filter(|l| !l.trim().is_empty()) {
+                println!(
+                    "{}{}{}",
+                    if highlight { ansi::DIM } else { "" },
+                    line,
+                    if highlight { ansi::RESET } else { "" }
+                );
+            }
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Warning: startup file not used: {}", e),
     }
-    println!();
 
-    // Set up Ctrl+C handler
-    let running = Arc::new(AtomicBool::new(true));
-    let r_clone = running.clone();
+    // Ctrl+C while FORM runs: FORM gets the signal too and stops, and the
+    // flag lets the error say why. While reading input readline reports
+    // Ctrl+C itself as Interrupted, which only clears the buffer.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
     ctrlc::set_handler(move || {
-        r_clone.store(false, Ordering::SeqCst);
+        flag.store(true, Ordering::SeqCst);
     })
     .expect("Error setting Ctrl+C handler");
 
     // Main REPL loop
-    while running.load(Ordering::SeqCst) {
+    loop {
+        // %form may have switched binaries
+        if let Some(path) = &state.form_path {
+            form_path = path.clone();
+        }
+        // A Ctrl+C from an earlier cell must not affect this one
+        interrupted.store(false, Ordering::SeqCst);
+        // For a separator as wide as the terminal
+        let columns = rl.dimensions().map(|(w, _)| w).unwrap_or_else(ansi::terminal_width);
+        // Set by magics that run a cell with timing forced on
+        let mut force_timing = false;
+        let mut capture: Option<Option<String>> = None;
+
+        prompts.set_session(state.name.clone());
+
+        // A %watch'ed file that changed is shown like a cell the user ran
+        let mut watched = None;
+        let read = match state.watch_run() {
+            Some(run) => {
+                if let Some(w) = &state.watch {
+                    // Not a result: keep it off stdout in JSON mode
+                    let notice = format!(
+                        "{}[watch] {} changed{}",
+                        if highlight { &theme.prompt_cont } else { "" },
+                        w.path.display(),
+                        if highlight { ansi::RESET } else { "" }
+                    );
+                    if output_format == OutputFormat::Json {
+                        eprintln!("{}", notice);
+                    } else {
+                        println!("{}", notice);
+                    }
+                }
+                watched = Some(run.result);
+                Ok(Some(run.input))
+            }
+            None => read_multiline_input(
+                &mut rl,
+                state.session_number,
+                &prompts,
+                &theme,
+                highlight,
+                file_config.settings.auto_submit,
+                file_config.settings.submit_mode,
+            ),
+        };
+
         // Read input
-        let input = match read_multiline_input(&mut rl, state.session_number, &theme, highlight) {
+        let input = match read {
             Ok(Some(input)) => input,
             Ok(None) => {
                 // Cancelled input
-                print_separator(&theme, highlight);
+                if interactive {
+                    print_separator(&state.separator, columns, &theme, highlight);
+                }
                 continue;
             }
             Err(msg) if msg == "EXIT" => {
-                println!();
+                if output_format != OutputFormat::Json {
+                    println!();
+                }
                 break;
             }
             Err(msg) if msg.starts_with("CMD:") => {
                 let cmd = &msg[4..];
-                match cmd {
+                let (reply, success) = match cmd {
                     ".quit" | ".exit" | ".q" => {
                         break;
                     }
-                    ".help" => {
-                        print_help(&theme, highlight);
-                    }
-                    ".clear" => {
-                        println!("Input cleared.");
-                    }
-                    _ => {
-                        println!(
+                    ".help" => (help_text(&theme, highlight), true),
+                    ".clear" => ("Input cleared.".to_string(), true),
+                    _ => (
+                        format!(
                             "{}Unknown command: {}{}",
                             if highlight { &theme.error } else { "" },
                             cmd,
                             if highlight { ansi::RESET } else { "" }
-                        );
-                    }
+                        ),
+                        false,
+                    ),
+                };
+                print_reply(output_format, state.session_number, cmd, &reply, success);
+                if interactive {
+                    print_separator(&state.separator, columns, &theme, highlight);
                 }
-                print_separator(&theme, highlight);
                 continue;
             }
             Err(msg) if msg.starts_with("MAGIC:") => {
                 let magic_cmd = &msg[6..];
-                match magic::process_magic(magic_cmd, &mut state, highlight, &theme_name) {
-                    MagicResult::Output(output) => {
-                        println!("{}", output);
+                if let Some(question) = magic::confirmation_prompt(magic_cmd).filter(|_| confirm_destructive) {
+                    if !rl.readline(question).is_ok_and(|answer| magic::is_yes(&answer)) {
+                        println!("Cancelled.");
+                        print_separator(&state.separator, columns, &theme, highlight);
+                        continue;
                     }
-                    MagicResult::Help => {
-                        print_help(&theme, highlight);
+                }
+                match magic::process_magic(magic_cmd, &mut state, highlight, &theme_name) {
+                    MagicResult::Execute { code, timed } => {
+                        force_timing = timed;
+                        code
                     }
-                    MagicResult::Exit => {
-                        break;
+                    MagicResult::Capture { code, name } => {
+                        capture = Some(name);
+                        code
                     }
-                    MagicResult::Error(e) => {
-                        println!(
-                            "{}{}{}",
-                            if highlight { &theme.error } else { "" },
-                            e,
-                            if highlight { ansi::RESET } else { "" }
-                        );
+                    result => {
+                        let reply = match result {
+                            MagicResult::Output(output) if no_color => {
+                                Some((term::strip_ansi(&output), true))
+                            }
+                            MagicResult::Output(output) => Some((output, true)),
+                            MagicResult::Help => Some((help_text(&theme, highlight), true)),
+                            MagicResult::Exit => {
+                                break;
+                            }
+                            MagicResult::Error(e) => Some((
+                                format!(
+                                    "{}{}{}",
+                                    if highlight { &theme.error } else { "" },
+                                    e,
+                                    if highlight { ansi::RESET } else { "" }
+                                ),
+                                false,
+                            )),
+                            MagicResult::Handled
+                            | MagicResult::NotMagic
+                            | MagicResult::Execute { .. }
+                            | MagicResult::Capture { .. } => None,
+                        };
+                        if let Some((reply, success)) = reply {
+                            print_reply(output_format, state.session_number, magic_cmd, &reply, success);
+                        }
+                        if interactive {
+                            print_separator(&state.separator, columns, &theme, highlight);
+                        }
+                        continue;
                     }
-                    MagicResult::Handled | MagicResult::NotMagic => {}
                 }
-                print_separator(&theme, highlight);
-                continue;
             }
             Err(e) => {
                 let error_prefix = if highlight {
@@ -473,7 +1113,9 @@ fn main() {
                     e,
                     error_suffix
                 );
-                print_separator(&theme, highlight);
+                if interactive {
+                    print_separator(&state.separator, columns, &theme, highlight);
+                }
                 continue;
             }
         };
@@ -482,18 +1124,50 @@ fn main() {
             continue;
         }
 
+        // Expand @name macros, then splice in _{raw} / _{oneline} references
+        let expanded = if watched.is_some() {
+            Ok(input.clone())
+        } else {
+            magic::expand_macros(&input, &state)
+                .and_then(|expanded| magic::substitute_output_refs(&expanded, &state))
+        };
+        let input = match expanded {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                let reply = format!(
+                    "{}{}{}",
+                    if highlight { &theme.error } else { "" },
+                    e,
+                    if highlight { ansi::RESET } else { "" }
+                );
+                print_reply(output_format, state.session_number, &input, &reply, false);
+                if interactive {
+                    print_separator(&state.separator, columns, &theme, highlight);
+                }
+                continue;
+            }
+        };
+
         // Add to readline history
         let hist_line: String = input
             .lines()
             .filter(|l| l.trim() != ".end")
             .collect::<Vec<_>>()
             .join("\n");
-        if !hist_line.is_empty() {
+        if !hist_line.is_empty() && watched.is_none() {
             let _ = rl.add_history_entry(&hist_line);
         }
 
+        // Tidy the cell as typed; the session history records what FORM ran
+        let input = if cli_config.format_input && watched.is_none() {
+            formatter::pretty_print_form(&input)
+        } else {
+            input
+        };
+
         // Validate input
-        if let Err(e) = form::validate_input(&input) {
+        let show_warnings = state.output_config.show_warnings && output_format != OutputFormat::Json;
+        if let Some(e) = form::validate_input(&input).err().filter(|_| show_warnings) {
             println!(
                 "{}{}Syntax warning: {}{}",
                 if highlight { &theme.error } else { "" },
@@ -502,44 +1176,179 @@ fn main() {
                 if highlight { ansi::RESET } else { "" }
             );
         }
+        if strict && output_format != OutputFormat::Json {
+            for warning in form::validate_input_strict(&input, &state.declared_names()) {
+                println!(
+                    "{}Strict check: {}{}",
+                    if highlight { &theme.error } else { "" },
+                    warning,
+                    if highlight { ansi::RESET } else { "" }
+                );
+            }
+        }
+        if let Some(warning) = form::clear_warning(&input).filter(|_| show_warnings) {
+            println!(
+                "{}Warning: {}{}",
+                if highlight { &theme.error } else { "" },
+                warning,
+                if highlight { ansi::RESET } else { "" }
+            );
+        }
 
-        // Execute FORM
-        if verbose {
-            term::verbose_println(&format!("Executing {} bytes of FORM code", input.len()));
+        // Dry run: show exactly what would be piped to FORM
+        if state.dry_run && watched.is_none() {
+            let full_input = form::terminate_input(&state.program(&input), state.run_options.terminator);
+            if output_format == OutputFormat::Json {
+                println!("{}", json_result(state.session_number, &input, &full_input, None, true));
+                continue;
+            }
+            println!(
+                "{}--- dry run: {} bytes to {} ---{}",
+                if highlight { &theme.prompt_cont } else { "" },
+                full_input.len(),
+                form_path.display(),
+                if highlight { ansi::RESET } else { "" }
+            );
+            println!("{}", full_input);
+            if interactive {
+                println!();
+                print_separator(&state.separator, columns, &theme, highlight);
+            }
+            continue;
         }
 
-        match form::run_form(&input, &form_path, verbose) {
+        // Execute FORM; streamed cells print each line here as FORM writes it
+        let stream = stream_output
+            && matches!(output_format, OutputFormat::Human | OutputFormat::Plain)
+            && capture.is_none();
+        let out_prompt = prompts.output(state.session_number, &theme, highlight);
+        let out_indent = " ".repeat(term::visible_width(&out_prompt));
+        let mut streamed = 0;
+        let program = state.program(&input);
+        let mut run = |path: &Path, options: &form::RunOptions| {
+            if !stream {
+                return form::run_form(&program, path, options);
+            }
+            form::run_form_with(&program, path, options, |line| {
+                if streamed == 0 && interactive {
+                    println!();
+                }
+                let prefix = if streamed == 0 { &out_prompt } else { &out_indent };
+                if highlight {
+                    println!("{}{}", prefix, highlight::highlight_output(line, &theme));
+                } else {
+                    println!("{}{}", prefix, line);
+                }
+                streamed += 1;
+            })
+        };
+        let cached = match &watched {
+            None => state.cached_result(&input).cloned(),
+            Some(_) => None,
+        };
+        if cached.is_some() && interactive {
+            println!(
+                "{}(cached result; %cache off to re-run){}",
+                if highlight { &theme.timing } else { "" },
+                if highlight { ansi::RESET } else { "" }
+            );
+        }
+        let outcome = match (watched, cached) {
+            (Some(result), _) => result,
+            (None, Some(result)) => Ok(result),
+            (None, None) => match run(&form_path, &state.run_options) {
+                // The binary went away mid-session: look for it again once
+                Err(form::FormError::NotFound) => match form::find_form_executable() {
+                    Some(found) => {
+                        let notice = format!(
+                            "{}FORM is no longer at {}; now using {}{}",
+                            if highlight { &theme.timing } else { "" },
+                            form_path.display(),
+                            found.display(),
+                            if highlight { ansi::RESET } else { "" }
+                        );
+                        if output_format == OutputFormat::Json {
+                            eprintln!("{}", notice);
+                        } else {
+                            println!("{}", notice);
+                        }
+                        form_path = found;
+                        state.use_form_binary(form_path.clone());
+                        run(&form_path, &state.run_options)
+                    }
+                    None => Err(form::FormError::NotFound),
+                },
+                result => result,
+            },
+        };
+        // One JSON record per cell and nothing else
+        if output_format == OutputFormat::Json {
+            if let Ok(result) = &outcome {
+                state.cache_result(&input, result);
+            }
+            let (output, duration) = match &outcome {
+                Ok(result) => (render_output(&result.output, &state).0, Some(result.duration)),
+                Err(e) => (e.to_string(), None),
+            };
+            let success = outcome.is_ok();
+            println!("{}", json_result(state.session_number, &input, &output, duration, success));
+            if success {
+                state.set_full_output(output.clone());
+                state.add_entry(input, Some(output), duration);
+            } else {
+                state.add_entry(input, None, None);
+            }
+            continue;
+        }
+        match outcome {
             Ok(result) => {
-                let formatted = form::format_output(&result.output, state.show_timing);
+                state.cache_result(&input, &result);
+                let (formatted, shown) = render_output(&result.output, &state);
+                state.set_full_output(formatted.clone());
                 
-                if !formatted.trim().is_empty() {
-                    println!();
-                    
-                    // Print output prompt for first line
-                    let out_prompt = format_out_prompt(state.session_number, &theme, highlight);
+                // Already on screen if it was streamed
+                if !formatted.trim().is_empty() && capture.is_none() && streamed == 0 {
+                    if interactive {
+                        println!();
+                    }
                     
-                    // Apply syntax highlighting to output
-                    let displayed = if highlight {
-                        highlight::highlight_output(&formatted, &theme)
+                    // Apply syntax highlighting to output (LaTeX is shown as-is)
+                    let displayed = if latex_output {
+                        form::format_output_latex(&result.output)
+                    } else if highlight {
+                        highlight::highlight_output(&shown, &theme)
+                    } else if no_color {
+                        term::strip_ansi(&shown)
                     } else {
-                        formatted.clone()
+                        shown
                     };
                     
-                    // Print with proper formatting
-                    let lines: Vec<&str> = displayed.lines().collect();
-                    for (i, line) in lines.iter().enumerate() {
-                        if i == 0 {
-                            println!("{}{}", out_prompt, line);
-                        } else {
-                            // Indent continuation lines to align with output
-                            let indent = " ".repeat(out_prompt.chars().filter(|c| !c.is_control()).count());
-                            println!("{}{}", indent, line);
+                    // Print with proper formatting, wrapping at the terminal width
+                    let indent_width = term::visible_width(&out_prompt);
+                    let indent = " ".repeat(indent_width);
+                    let width = match state.output_config.wrap_width {
+                        Some(0) => usize::MAX,
+                        Some(width) => width,
+                        None if ansi::is_tty() => {
+                            rl.dimensions().map(|(w, _)| w).unwrap_or_else(ansi::terminal_width)
+                        }
+                        None => usize::MAX,
+                    };
+                    for (i, line) in displayed.lines().enumerate() {
+                        // Indent continuation lines to align with output
+                        let prefix = if i == 0 { out_prompt.as_str() } else { indent.as_str() };
+                        for (j, piece) in term::wrap_line(line, width, indent_width).iter().enumerate() {
+                            if j == 0 {
+                                println!("{}{}", prefix, piece);
+                            } else {
+                                println!("{}", piece);
+                            }
                         }
                     }
                 }
                 
                 // Show timing if enabled
-                if state.show_timing {
+                if state.show_timing || force_timing {
                     println!(
                         "{}⏱ {}{}",
                         if highlight { &theme.timing } else { "" },
@@ -549,34 +1358,323 @@ fn main() {
                 }
                 
                 // Record in session history
-                state.add_entry(input, Some(formatted), Some(result.duration));
+                if let Some(name) = capture {
+                    match magic::record_captured(&mut state, input, formatted, result.duration, name.as_deref()) {
+                        Ok(msg) => println!("{}", msg),
+                        Err(e) => println!(
+                            "{}{}{}",
+                            if highlight { &theme.error } else { "" },
+                            e,
+                            if highlight { ansi::RESET } else { "" }
+                        ),
+                    }
+                } else {
+                    state.add_entry(input, Some(formatted), Some(result.duration));
+                }
             }
             Err(e) => {
+                // FORM's diagnostics, each followed by the program line it names
+                // (FORM counts the startup code ahead of the cell)
+                let (message, details) = match &e {
+                    form::FormError::ExecutionError { status, stderr, line, .. } => {
+                        let mut details = form::parse_form_error(stderr.trim(), &program);
+                        // FORM usually reports on stdout: quote the line it named
+                        if form::error_line_number(stderr).is_none() {
+                            if let Some(context) = line.and_then(|n| form::source_context(&program, n)) {
+                                details.push_str(&context);
+                                details.push('\n');
+                            }
+                        }
+                        (format!("FORM exited with status {}", status), details)
+                    }
+                    _ => (e.to_string(), String::new()),
+                };
                 println!(
                     "\n{}{}Error: {}{}",
                     if highlight { &theme.error } else { "" },
                     if highlight { ansi::BOLD } else { "" },
-                    e,
+                    message,
                     if highlight { ansi::RESET } else { "" }
                 );
+                print!("{}", details);
+                let hint = match &e {
+                    _ if interrupted.load(Ordering::SeqCst) => {
+                        Some("FORM was interrupted with Ctrl+C".to_string())
+                    }
+                    form::FormError::ExecutionError { kind, .. } => kind.hint(),
+                    form::FormError::NotFound => Some(format!(
+                        "FORM was moved or deleted and isn't on PATH; \
+                         set FORM_PATH and restart (last used: {})",
+                        form_path.display()
+                    )),
+                    _ => None,
+                };
+                if let Some(hint) = hint {
+                    println!(
+                        "{}Hint: {}{}",
+                        if highlight { &theme.timing } else { "" },
+                        hint,
+                        if highlight { ansi::RESET } else { "" }
+                    );
+                }
                 
                 // Still record the attempt
                 state.add_entry(input, None, None);
             }
         }
 
-        println!();
-        print_separator(&theme, highlight);
+        if interactive {
+            println!();
+            print_separator(&state.separator, columns, &theme, highlight);
+        }
     }
 
     // Save history
     if file_config.history.save_on_exit {
-        if let Err(e) = rl.save_history(&history_path) {
-            if verbose {
-                eprintln!("Warning: Could not save history: {}", e);
+        if let Some((backend, mut entries)) = json_history {
+            entries.extend(state.history.iter().map(JsonHistoryEntry::from));
+            if let Err(e) = backend.save(&entries, file_config.history.max_entries) {
+                log::warn!("Could not save history: {}", e);
+            }
+        } else if history_format == HistoryFormat::Plain {
+            if let Err(e) = rl.save_history(&history_path) {
+                log::warn!("Could not save history: {}", e);
             }
         }
     }
 
-    println!("Goodbye!");
+    if interactive {
+        println!("Goodbye!");
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line reader that replays a fixed script of lines, then signals EOF
+    struct ScriptedReader {
+        lines: VecDeque<String>,
+    }
+
+    impl ScriptedReader {
+        fn new(lines: &[&str]) -> Self {
+            ScriptedReader {
+                lines: lines.iter().map(|l| l.to_string()).collect(),
+            }
+        }
+    }
+
+    impl LineReader for ScriptedReader {
+        /// A `^C` line stands for the user pressing Ctrl+C
+        fn read_line(&mut self, _prompt: &str) -> rustyline::Result<String> {
+            match self.lines.pop_front() {
+                Some(line) if line == "^C" => Err(ReadlineError::Interrupted),
+                Some(line) => Ok(line),
+                None => Err(ReadlineError::Eof),
+            }
+        }
+    }
+
+    fn read(reader: &mut ScriptedReader, auto_submit: bool) -> Result<Option<String>, String> {
+        read_multiline_input(reader, 1, &Prompts::default(), &Theme::none(), false, auto_submit, SubmitMode::EmptyLine)
+    }
+
+    fn read_mode(reader: &mut ScriptedReader, mode: SubmitMode) -> Result<Option<String>, String> {
+        read_multiline_input(reader, 1, &Prompts::default(), &Theme::none(), false, false, mode)
+    }
+
+    #[test]
+    fn test_output_format_parse() {
+        assert_eq!(OutputFormat::parse("JSON"), Some(OutputFormat::Json));
+        assert_eq!(OutputFormat::parse("text"), Some(OutputFormat::Human));
+        assert_eq!(OutputFormat::parse("human"), Some(OutputFormat::Human));
+        assert_eq!(OutputFormat::parse("xml"), None);
+        for name in OUTPUT_FORMATS {
+            assert!(OutputFormat::parse(name).is_some());
+        }
+        assert!(OutputFormat::Latex.is_interactive());
+        assert!(!OutputFormat::Plain.is_interactive());
+    }
+
+    #[test]
+    fn test_json_result_is_parseable() {
+        let line = json_result(
+            3,
+            "Symbol x;\nLocal E = \"x\";",
+            "   E =\n      x;",
+            Some(Duration::from_millis(42)),
+            true,
+        );
+        assert!(!line.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["session"], 3);
+        assert_eq!(value["input"], "Symbol x;\nLocal E = \"x\";");
+        assert_eq!(value["output"], "   E =\n      x;");
+        assert_eq!(value["duration_ms"], 42);
+        assert_eq!(value["success"], true);
+
+        let failed: serde_json::Value =
+            serde_json::from_str(&json_result(4, "id", "FORM exited with status 1", None, false)).unwrap();
+        assert!(failed["duration_ms"].is_null());
+        assert_eq!(failed["success"], false);
+    }
+
+    #[test]
+    fn test_ctrl_c_clears_buffer_without_exiting() {
+        let mut reader = ScriptedReader::new(&["Symbol x;", "Local E = (x+", "^C", "Local F = x;", ""]);
+        // The half-typed cell is dropped...
+        assert_eq!(read(&mut reader, false), Ok(None));
+        // ...and the next prompt starts from an empty buffer
+        assert_eq!(read(&mut reader, false), Ok(Some("Local F = x;".to_string())));
+
+        // Ctrl+C on an empty prompt doesn't exit either
+        let mut reader = ScriptedReader::new(&["^C", "%who"]);
+        assert_eq!(read(&mut reader, false), Ok(None));
+        assert_eq!(read(&mut reader, false), Err("MAGIC:%who".to_string()));
+    }
+
+    #[test]
+    fn test_submit_mode_empty_line() {
+        let mut reader = ScriptedReader::new(&["Symbol x;", "", "Print;"]);
+        assert_eq!(read_mode(&mut reader, SubmitMode::EmptyLine), Ok(Some("Symbol x;".to_string())));
+    }
+
+    #[test]
+    fn test_submit_mode_dot_end_only_keeps_blank_lines() {
+        let mut reader = ScriptedReader::new(&["Symbol x;", "", "", "Print;", ".end", "next"]);
+        assert_eq!(
+            read_mode(&mut reader, SubmitMode::DotEndOnly),
+            Ok(Some("Symbol x;\n\n\nPrint;\n.end".to_string()))
+        );
+        assert_eq!(reader.lines.len(), 1);
+    }
+
+    #[test]
+    fn test_submit_mode_double_enter() {
+        let mut reader = ScriptedReader::new(&["Symbol x;", "", "Print;", "", "", "next"]);
+        assert_eq!(
+            read_mode(&mut reader, SubmitMode::DoubleEnter),
+            Ok(Some("Symbol x;\n\nPrint;".to_string()))
+        );
+        assert_eq!(reader.lines.len(), 1);
+    }
+
+    #[test]
+    fn test_history_search_recalls_multiline_cell() {
+        use rustyline::history::{History, SearchDirection};
+
+        // Ctrl+R searches entries as stored by `add_history_entry`
+        let mut history = FileHistory::new();
+        history.add("Symbol x;\nLocal E = (x+1)^2;\nPrint;").unwrap();
+        history.add("%who").unwrap();
+        let found = history
+            .search("(x+1)", history.len() - 1, SearchDirection::Reverse)
+            .unwrap()
+            .expect("substring of a later line is found");
+        assert_eq!(found.idx, 0);
+
+        // Accepting it hands back the whole cell, which is read line by line
+        let mut reader = ScriptedReader::new(&[&found.entry, ""]);
+        assert_eq!(
+            read(&mut reader, false),
+            Ok(Some("Symbol x;\nLocal E = (x+1)^2;\nPrint;".to_string()))
+        );
+
+        // A recalled magic cell keeps its header apart from its body, and
+        // blank lines inside it don't submit early
+        let mut reader = ScriptedReader::new(&["%timeit\nLocal E = x;\n\nPrint;", ""]);
+        assert_eq!(read(&mut reader, false), Err("MAGIC:%timeit\nLocal E = x;\n\nPrint;".to_string()));
+    }
+
+    #[test]
+    fn test_completion_script_for_each_shell() {
+        for shell in COMPLETION_SHELLS {
+            let script = completion_script(shell).unwrap();
+            assert!(!script.is_empty());
+            assert!(script.contains("theme"));
+            assert!(script.contains("dracula"));
+        }
+        assert!(completion_script("tcsh").is_none());
+    }
+
+    #[test]
+    fn test_auto_submit_on_complete_statement() {
+        let mut reader = ScriptedReader::new(&["Symbol x;", "Local E = x;"]);
+        assert_eq!(read(&mut reader, true), Ok(Some("Symbol x;".to_string())));
+        assert_eq!(reader.lines.len(), 1);
+    }
+
+    #[test]
+    fn test_auto_submit_takes_validated_cell_whole() {
+        // With auto_submit the validator hands over a finished cell at once;
+        // its complete first line must not submit on its own
+        let mut reader = ScriptedReader::new(&["Symbols x;\nLocal E = (x+1)^2;\nPrint;"]);
+        assert_eq!(
+            read(&mut reader, true),
+            Ok(Some("Symbols x;\nLocal E = (x+1)^2;\nPrint;".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_auto_submit_waits_for_balanced_brackets() {
+        let mut reader = ScriptedReader::new(&["Local E = f(x,", "  y);", "Print;"]);
+        assert_eq!(
+            read(&mut reader, true),
+            Ok(Some("Local E = f(x,\n  y);".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_code_magic_collects_body() {
+        let mut reader = ScriptedReader::new(&["%timeit", "Symbol x;", "Local E = x^2;", ""]);
+        assert_eq!(
+            read(&mut reader, false),
+            Err("MAGIC:%timeit\nSymbol x;\nLocal E = x^2;".to_string())
+        );
+    }
+
+    #[test]
+    fn test_line_magic_returns_immediately() {
+        let mut reader = ScriptedReader::new(&["%history 5", "Symbol x;"]);
+        assert_eq!(read(&mut reader, false), Err("MAGIC:%history 5".to_string()));
+        assert_eq!(reader.lines.len(), 1);
+    }
+
+    #[test]
+    fn test_auto_submit_disabled_waits_for_empty_line() {
+        let mut reader = ScriptedReader::new(&["Symbol x;", "Local E = x;", "", "Print;"]);
+        assert_eq!(
+            read(&mut reader, false),
+            Ok(Some("Symbol x;\nLocal E = x;".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_enabled_postproc_changes_displayed_output() {
+        let raw = "FORM 4.3\n\n   E =\n      + y\n      + x;\n\n  0.00 sec out of 0.00 sec\n";
+        let mut state = SessionState::new();
+        state.max_output_lines = 2;
+        assert_eq!(render_output(raw, &state).0, "   E =\n      + y\n      + x;");
+
+        assert!(matches!(
+            magic::process_magic("%postproc enable sort_terms", &mut state, false, "none"),
+            MagicResult::Output(_)
+        ));
+        let (full, shown) = render_output(raw, &state);
+        assert_eq!(full, "   E =\n      + x\n      + y;");
+        assert_eq!(shown, "   E =\n      + x\n... (1 more lines, %expand to show)");
+    }
+
+    #[test]
+    fn test_vi_edit_mode_accepted_by_editor() {
+        assert_eq!(parse_edit_mode("vi"), Some(EditMode::Vi));
+        assert_eq!(parse_edit_mode("Emacs"), Some(EditMode::Emacs));
+        assert_eq!(parse_edit_mode("nano"), None);
+
+        let mut rl: Editor<FormValidator, FileHistory> = Editor::new().unwrap();
+        rl.set_edit_mode(EditMode::Vi);
+        assert_eq!(rl.config_mut().edit_mode(), EditMode::Vi);
+    }
 }
@@ -0,0 +1,232 @@
+// Results of earlier cells, reused by %cache / [settings] cache_results
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::form::FormResult;
+
+/// Module instructions, statements and preprocessor instructions that act
+/// outside the cell's own output or read from files that may have changed
+/// since; a cell using any of them is always run
+const SIDE_EFFECTS: &[&str] = &[
+    ".store", ".global", "save", "load", "#system", "#pipe", "#create", "#append", "#remove",
+    "#close", "#include", "#call", "#external", "#toexternal", "#fromexternal", "#prompt",
+];
+
+/// Most results kept; past this the oldest are dropped
+const MAX_ENTRIES: usize = 200;
+
+/// A cached result as stored on disk, with the key it was stored under so
+/// a lookup compares the whole cell rather than a hash of it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CachedResult {
+    key: String,
+    output: String,
+    stderr: String,
+    duration_ms: u64,
+    exit_code: i32,
+}
+
+impl CachedResult {
+    fn new(key: &str, result: &FormResult) -> Self {
+        CachedResult {
+            key: key.to_string(),
+            output: result.output.clone(),
+            stderr: result.stderr.clone(),
+            duration_ms: result.duration.as_millis() as u64,
+            exit_code: result.exit_code,
+        }
+    }
+}
+
+impl From<CachedResult> for FormResult {
+    fn from(cached: CachedResult) -> Self {
+        FormResult {
+            output: cached.output,
+            stderr: cached.stderr,
+            duration: Duration::from_millis(cached.duration_ms),
+            exit_code: cached.exit_code,
+        }
+    }
+}
+
+/// Whether running `input` does more than print results, e.g. writes a
+/// `.store`d expression, a `save` file or runs a shell command, or depends
+/// on a file through `#include`, `#call` or `load`
+pub fn has_side_effects(input: &str) -> bool {
+    input.lines().any(|line| {
+        let line = line.trim_start().to_lowercase();
+        let after = |word: &str| {
+            line.strip_prefix(word)
+                .filter(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+        };
+        // `#write <file> "..."` writes to a file rather than the output
+        SIDE_EFFECTS.iter().any(|word| after(word).is_some())
+            || after("#write").is_some_and(|rest| rest.trim_start().starts_with('<'))
+    })
+}
+
+/// FORM results keyed by `SessionState::cache_key`, in memory and, when
+/// opened with a path, in a JSON file that outlives the session. At most
+/// `MAX_ENTRIES` are kept, the least recently stored going first.
+#[derive(Debug, Default)]
+pub struct ResultCache {
+    path: Option<PathBuf>,
+    entries: HashMap<String, FormResult>,
+    /// Keys from oldest to newest
+    order: VecDeque<String>,
+}
+
+impl ResultCache {
+    /// A cache kept in memory only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A cache backed by `path`, starting from what is already there. A
+    /// missing or unreadable file starts it empty.
+    pub fn open(path: &Path) -> Self {
+        let stored = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| match serde_json::from_str::<Vec<CachedResult>>(&content) {
+                Ok(stored) => Some(stored),
+                Err(e) => {
+                    log::warn!("ignoring corrupt result cache {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        let mut cache = ResultCache {
+            path: Some(path.to_path_buf()),
+            ..Self::default()
+        };
+        for cached in stored {
+            let key = cached.key.clone();
+            cache.remember(key, cached.into());
+        }
+        cache
+    }
+
+    pub fn get(&self, key: &str) -> Option<&FormResult> {
+        self.entries.get(key)
+    }
+
+    /// Stores a result, writing the file if there is one
+    pub fn insert(&mut self, key: String, result: &FormResult) {
+        self.remember(key, result.clone());
+        self.save();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Forgets every result, on disk too
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn remember(&mut self, key: String, result: FormResult) {
+        if self.entries.insert(key.clone(), result).is_some() {
+            self.order.retain(|k| *k != key);
+        }
+        self.order.push_back(key);
+        while self.order.len() > MAX_ENTRIES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else { return };
+        let stored: Vec<CachedResult> = self
+            .order
+            .iter()
+            .filter_map(|key| Some(CachedResult::new(key, self.entries.get(key)?)))
+            .collect();
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .map_err(|e| e.to_string())
+            .and_then(|_| serde_json::to_string(&stored).map_err(|e| e.to_string()))
+            .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            log::warn!("failed to write result cache {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(output: &str) -> FormResult {
+        FormResult {
+            output: output.to_string(),
+            stderr: String::new(),
+            duration: Duration::from_millis(1500),
+            exit_code: 0,
+        }
+    }
+
+    #[test]
+    fn test_has_side_effects() {
+        assert!(has_side_effects("Local E = x;\n.store"));
+        assert!(has_side_effects("  .global\nPrint;"));
+        assert!(has_side_effects("#system rm -f out.txt"));
+        assert!(has_side_effects("Save results.sav;"));
+        assert!(has_side_effects("#include defs.h"));
+        assert!(has_side_effects("#call expand(x)"));
+        assert!(has_side_effects("Load results.sav;"));
+        assert!(has_side_effects("#write <out.txt> \"%E\", E"));
+        assert!(!has_side_effects("#write \"E = %E\", E"));
+        assert!(!has_side_effects("Symbol x;\nLocal E = x^2;\nPrint;\n.sort"));
+        // Only whole words count
+        assert!(!has_side_effects("Local saved = 1;\nsaveX = 2;"));
+    }
+
+    #[test]
+    fn test_disk_cache_round_trip() {
+        let dir = std::env::temp_dir().join(format!("form-repl-cache-test-{}", std::process::id()));
+        let path = dir.join("cache.json");
+        let mut cache = ResultCache::open(&path);
+        assert_eq!(cache.len(), 0);
+        cache.insert("Local E = 1;".to_string(), &result("   E = 1;"));
+
+        // A later session sees it, under the same key only
+        let reopened = ResultCache::open(&path);
+        assert_eq!(reopened.len(), 1);
+        let hit = reopened.get("Local E = 1;").unwrap();
+        assert_eq!(hit.output, "   E = 1;");
+        assert_eq!(hit.duration, Duration::from_millis(1500));
+        assert!(reopened.get("Local E = 2;").is_none());
+
+        cache.clear();
+        assert_eq!(ResultCache::open(&path).len(), 0);
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(ResultCache::open(&path).len(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_cache_keeps_newest_entries() {
+        let mut cache = ResultCache::new();
+        for i in 0..MAX_ENTRIES + 5 {
+            cache.insert(format!("Local E = {};", i), &result("   E;"));
+        }
+        // Storing an old key again makes it the newest
+        cache.insert("Local E = 5;".to_string(), &result("   E = 5;"));
+        cache.insert("Local F = 1;".to_string(), &result("   F = 1;"));
+        assert_eq!(cache.len(), MAX_ENTRIES);
+        assert!(cache.get("Local E = 6;").is_none());
+        assert!(cache.get("Local E = 7;").is_some());
+        assert_eq!(cache.get("Local E = 5;").unwrap().output, "   E = 5;");
+    }
+}
@@ -0,0 +1,150 @@
+// Line-editor helper: decides when Enter submits a multi-line cell
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::Helper;
+
+use super::form;
+use super::highlight;
+use super::magic;
+
+/// Lets rustyline edit a whole cell at once (used with `auto_submit`).
+///
+/// Enter inserts a new line until the cell is complete, and submits it
+/// once it is; a blank last line always submits.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FormValidator;
+
+impl FormValidator {
+    /// Whether `input` is ready to submit.
+    ///
+    /// REPL commands and magics without a code body are complete at once.
+    /// Code is complete once its brackets balance and the last non-empty,
+    /// non-comment line ends with `;` or is `.end`; a closing bracket
+    /// without an opening one is an error to fix before submitting.
+    pub fn check(input: &str) -> ValidationResult {
+        if input.trim().is_empty() || input.ends_with('\n') {
+            return ValidationResult::Valid(None);
+        }
+        let (first, rest) = input.split_once('\n').unwrap_or((input, ""));
+        let first = first.trim();
+        let code = if first.starts_with('%') {
+            if !magic::takes_code(first) {
+                return ValidationResult::Valid(None);
+            }
+            rest
+        } else if first.starts_with('.') && rest.is_empty() {
+            return ValidationResult::Valid(None);
+        } else {
+            input
+        };
+
+        match form::validate_input(code) {
+            Err(e) if e.starts_with("Unmatched") => return ValidationResult::Invalid(Some(format!("  ({})", e))),
+            Err(_) => return ValidationResult::Incomplete,
+            Ok(()) => {}
+        }
+        let last_line = code
+            .lines()
+            .map(str::trim)
+            .rfind(|l| !l.is_empty() && !l.starts_with('*'));
+        match last_line {
+            Some(line) if line.ends_with(';') || line == ".end" => ValidationResult::Valid(None),
+            _ => ValidationResult::Incomplete,
+        }
+    }
+}
+
+impl FormValidator {
+    /// Hint shown after a closing bracket just typed at the end of the
+    /// input: the column of its opening bracket on the same line
+    pub fn bracket_hint(input: &str, pos: usize) -> Option<String> {
+        if pos == 0 || pos != input.len() {
+            return None;
+        }
+        let line_start = input[..pos].rfind('\n').map_or(0, |i| i + 1);
+        let line = &input[line_start..pos];
+        let close = line.len().checked_sub(1)?;
+        if !matches!(line.as_bytes()[close], b')' | b']' | b'}') {
+            return None;
+        }
+        let open = highlight::find_matching_bracket(line, close)?;
+        let opener = line[open..].chars().next()?;
+        Some(format!("  (matches '{}' at column {})", opener, line[..open].chars().count() + 1))
+    }
+}
+
+impl Validator for FormValidator {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(Self::check(ctx.input()))
+    }
+}
+
+impl Completer for FormValidator {
+    type Candidate = String;
+}
+
+impl Hinter for FormValidator {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> Option<String> {
+        Self::bracket_hint(line, pos)
+    }
+}
+
+impl Highlighter for FormValidator {}
+
+impl Helper for FormValidator {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_valid(input: &str) -> bool {
+        matches!(FormValidator::check(input), ValidationResult::Valid(_))
+    }
+
+    fn is_incomplete(input: &str) -> bool {
+        matches!(FormValidator::check(input), ValidationResult::Incomplete)
+    }
+
+    #[test]
+    fn test_validator_waits_for_complete_cells() {
+        assert!(is_valid(""));
+        assert!(is_valid(".quit"));
+        assert!(is_valid("%history"));
+        assert!(is_valid("Symbols x;"));
+        assert!(is_valid("Local E = x;\n.end"));
+        assert!(is_valid("Local E = f(x,\n  y);"));
+        assert!(is_incomplete("Local E = f(x,"));
+        assert!(is_incomplete("Local E = x"));
+        assert!(is_valid("Local E = x;\n* trailing comment"));
+        assert!(is_incomplete("%timeit 3"));
+        assert!(is_valid("%timeit 3\nLocal E = x;"));
+        // A blank line submits whatever is there
+        assert!(is_valid("Local E = f(x,\n"));
+    }
+
+    #[test]
+    fn test_bracket_hint_points_at_opening_bracket() {
+        let hint = |s: &str| FormValidator::bracket_hint(s, s.len());
+        assert_eq!(hint("Local E = f(x, g[y])"), Some("  (matches '(' at column 12)".to_string()));
+        assert_eq!(hint("Local E = f(x, g[y]"), Some("  (matches '[' at column 17)".to_string()));
+        // Only the line being typed counts
+        assert_eq!(hint("Symbols x;\n(x+1)"), Some("  (matches '(' at column 1)".to_string()));
+        assert_eq!(hint("Local E = f(x,\n  y)"), None);
+        assert_eq!(hint("Local E = x;"), None);
+        assert_eq!(hint(""), None);
+        // Nothing while the cursor isn't at the end
+        assert_eq!(FormValidator::bracket_hint("f(x) + y", 4), None);
+    }
+
+    #[test]
+    fn test_validator_rejects_stray_closing_bracket() {
+        match FormValidator::check("Local E = x);") {
+            ValidationResult::Invalid(Some(msg)) => assert!(msg.contains("Unmatched ')'")),
+            _ => panic!("expected an invalid cell"),
+        }
+    }
+}
@@ -1,7 +1,13 @@
 // Configuration module for FORM REPL settings
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+pub use form_core::OutputConfig;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::form::{RunOptions, Terminator};
 
 /// Main configuration structure
 #[derive(Debug, Deserialize, Default)]
@@ -9,6 +15,13 @@ use std::path::PathBuf;
 pub struct Config {
     pub settings: Settings,
     pub history: HistoryConfig,
+    pub form: FormConfig,
+    pub linter: LinterConfig,
+    pub output: OutputConfig,
+    pub prompt: PromptConfig,
+    pub server: ServerConfig,
+    /// Code snippets expanded from @name (see %macro)
+    pub macros: HashMap<String, String>,
 }
 
 /// General settings
@@ -25,6 +38,32 @@ pub struct Settings {
     pub verbose: bool,
     /// Auto-add .end to submissions
     pub auto_end: bool,
+    /// Submit as soon as brackets balance and the last line ends with `;`
+    pub auto_submit: bool,
+    /// What submits a multi-line cell
+    pub submit_mode: SubmitMode,
+    /// Extra FORM arguments; overrides FORM_FLAGS, overridden by --form-flags
+    pub form_flags: Vec<String>,
+    /// Truncate long outputs to this many lines (0 = unlimited)
+    pub max_output_lines: usize,
+    /// Line editing keybindings: "emacs" or "vi"
+    pub edit_mode: String,
+    /// Warn about names used before they are declared
+    pub strict: bool,
+    /// Print output lines as FORM produces them instead of when it exits
+    pub stream_output: bool,
+    /// Reuse results of identical cells instead of re-running FORM; the
+    /// results are kept on disk between sessions
+    #[serde(alias = "cache")]
+    pub cache_results: bool,
+    /// Ask before %reset and other magics that discard session state
+    pub confirm_destructive: bool,
+    /// FORM code run at launch whose declarations every cell gets
+    pub startup_file: String,
+    /// Show the startup file's output instead of hiding it
+    pub startup_verbose: bool,
+    /// Rule printed after each cell
+    pub separator: SeparatorConfig,
 }
 
 impl Default for Settings {
@@ -35,6 +74,122 @@ impl Default for Settings {
             show_timing: false,
             verbose: false,
             auto_end: true,
+            auto_submit: false,
+            submit_mode: SubmitMode::default(),
+            form_flags: Vec::new(),
+            max_output_lines: 50,
+            edit_mode: "emacs".to_string(),
+            strict: false,
+            stream_output: false,
+            cache_results: false,
+            confirm_destructive: true,
+            startup_file: "~/.form_repl_startup.frm".to_string(),
+            startup_verbose: false,
+            separator: SeparatorConfig::default(),
+        }
+    }
+}
+
+/// The rule printed between cells (`[settings.separator]`)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SeparatorConfig {
+    /// Print the rule at all
+    pub enabled: bool,
+    /// Text repeated to fill the width, e.g. `"─"`, `"="` or `"- "`
+    #[serde(rename = "char")]
+    pub pattern: String,
+    /// Width in columns, or the terminal's width
+    pub width: SeparatorWidth,
+}
+
+impl Default for SeparatorConfig {
+    fn default() -> Self {
+        SeparatorConfig {
+            enabled: true,
+            pattern: "─".to_string(),
+            width: SeparatorWidth::Columns(60),
+        }
+    }
+}
+
+/// Width of the separator: `"auto"` or a number of columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawSeparatorWidth")]
+pub enum SeparatorWidth {
+    /// As wide as the terminal
+    Auto,
+    /// A fixed number of columns
+    Columns(usize),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawSeparatorWidth {
+    Columns(usize),
+    Name(String),
+}
+
+impl TryFrom<RawSeparatorWidth> for SeparatorWidth {
+    type Error = String;
+
+    fn try_from(raw: RawSeparatorWidth) -> Result<Self, String> {
+        match raw {
+            RawSeparatorWidth::Columns(n) => SeparatorWidth::parse(&n.to_string()),
+            RawSeparatorWidth::Name(name) => SeparatorWidth::parse(&name),
+        }
+        .ok_or_else(|| "separator width must be \"auto\" or a positive number".to_string())
+    }
+}
+
+impl SeparatorWidth {
+    /// Parse `auto` or a positive number of columns
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("auto") {
+            return Some(SeparatorWidth::Auto);
+        }
+        s.parse().ok().filter(|n| *n > 0).map(SeparatorWidth::Columns)
+    }
+
+    /// Columns to fill, given the terminal's width
+    pub fn columns(self, terminal_width: usize) -> usize {
+        match self {
+            SeparatorWidth::Auto => terminal_width,
+            SeparatorWidth::Columns(n) => n,
+        }
+    }
+}
+
+impl std::fmt::Display for SeparatorWidth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeparatorWidth::Auto => write!(f, "auto"),
+            SeparatorWidth::Columns(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// How the REPL decides a multi-line cell is finished
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmitMode {
+    /// An empty line (or `.end`) submits
+    #[default]
+    EmptyLine,
+    /// Only `.end` (or Ctrl+D) submits; empty lines are kept
+    DotEndOnly,
+    /// Two empty lines in a row submit; a single one is kept
+    DoubleEnter,
+}
+
+impl SubmitMode {
+    /// How to submit, for the prompt hint
+    pub fn hint(self) -> &'static str {
+        match self {
+            SubmitMode::EmptyLine => "an empty line or .end submits",
+            SubmitMode::DotEndOnly => ".end submits",
+            SubmitMode::DoubleEnter => "two empty lines or .end submit",
         }
     }
 }
@@ -49,6 +204,21 @@ pub struct HistoryConfig {
     pub max_entries: usize,
     /// Save history on exit
     pub save_on_exit: bool,
+    /// History file format
+    pub format: HistoryFormat,
+}
+
+/// How the history file is stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryFormat {
+    /// rustyline's text file: inputs only
+    #[default]
+    Plain,
+    /// JSON array of inputs with their outputs and timings
+    Json,
+    /// SQLite database (not available yet; falls back to plain)
+    Sqlite,
 }
 
 impl Default for HistoryConfig {
@@ -57,11 +227,144 @@ impl Default for HistoryConfig {
             file: "~/.form_repl_history".to_string(),
             max_entries: 1000,
             save_on_exit: true,
+            format: HistoryFormat::default(),
+        }
+    }
+}
+
+/// Linter rule switches
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LinterConfig {
+    /// Warn on statements missing a trailing `;`
+    pub no_semicolon: bool,
+    /// Error on names used before they are declared
+    pub undeclared_symbol: bool,
+    /// Warn on declared but unused names
+    pub unused_symbol: bool,
+    /// Warn on keywords that are not lowercase
+    pub style: bool,
+}
+
+impl Default for LinterConfig {
+    fn default() -> Self {
+        LinterConfig {
+            no_semicolon: true,
+            undeclared_symbol: true,
+            unused_symbol: true,
+            style: false,
+        }
+    }
+}
+
+/// Prompt templates (see `prompt::Prompts` for the placeholders);
+/// unset ones use the built-in prompts
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PromptConfig {
+    #[serde(alias = "in_template")]
+    pub input: Option<String>,
+    #[serde(alias = "out_template")]
+    pub output: Option<String>,
+    #[serde(alias = "cont_template")]
+    pub continuation: Option<String>,
+}
+
+/// Where `--server` listens for editor plugins
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// TCP port on 127.0.0.1 (overridden by --server-port)
+    pub port: Option<u16>,
+    /// Unix socket path, used when no port is set (supports ~)
+    pub socket: Option<String>,
+}
+
+impl ServerConfig {
+    /// Port used off Unix when none is configured
+    #[cfg(not(unix))]
+    pub const DEFAULT_PORT: u16 = 7141;
+}
+
+/// FORM binary configuration
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct FormConfig {
+    /// Path to the FORM executable (overrides discovery, supports ~)
+    pub path: Option<String>,
+    /// Extra command-line arguments passed to FORM
+    pub extra_args: Vec<String>,
+    /// Kill FORM if a cell runs longer than this many seconds
+    pub timeout_secs: Option<f64>,
+    /// Number of TFORM workers (passed as -w<N>)
+    pub workers: Option<u32>,
+    /// Working directory for FORM's temporary files (supports ~)
+    pub temp_dir: Option<String>,
+    /// `%load` runs scripts with at least this many blocks in one FORM
+    /// process (0 = never)
+    pub batch_threshold: usize,
+}
+
+impl Default for FormConfig {
+    fn default() -> Self {
+        FormConfig {
+            path: None,
+            extra_args: Vec::new(),
+            timeout_secs: None,
+            workers: None,
+            temp_dir: None,
+            batch_threshold: 5,
+        }
+    }
+}
+
+impl FormConfig {
+    /// Configured executable path, expanded
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path.as_deref().map(expand_path)
+    }
+
+    /// Build the options used to invoke FORM
+    pub fn run_options(&self) -> RunOptions {
+        let mut extra_args = self.extra_args.clone();
+        if let Some(workers) = self.workers {
+            extra_args.push(format!("-w{}", workers));
+        }
+        RunOptions {
+            extra_args,
+            timeout: self
+                .timeout_secs
+                .filter(|secs| *secs > 0.0)
+                .map(Duration::from_secs_f64),
+            workdir: self.temp_dir.as_deref().map(expand_path),
+            ..RunOptions::default()
         }
     }
 }
 
 impl Config {
+    /// Options for invoking FORM; `[settings] form_flags` come before
+    /// `[form] extra_args`, and `auto_end = false` leaves cells unterminated
+    pub fn run_options(&self) -> RunOptions {
+        let mut options = self.form.run_options();
+        let mut flags = self.settings.form_flags.clone();
+        flags.append(&mut options.extra_args);
+        options.extra_args = flags;
+        // Each cell is a whole program, so it ends with .end
+        options.terminator = self.settings.auto_end.then_some(Terminator::End);
+        options
+    }
+
+    /// Output line limit: `[output] max_lines`, or the older
+    /// `[settings] max_output_lines` when only that one is changed
+    pub fn max_output_lines(&self) -> usize {
+        if self.output.max_lines != OutputConfig::default().max_lines {
+            self.output.max_lines
+        } else {
+            self.settings.max_output_lines
+        }
+    }
+
     /// Load configuration from file
     pub fn load() -> Self {
         // Try to load from multiple locations
@@ -102,13 +405,81 @@ impl Config {
     pub fn history_path(&self) -> PathBuf {
         expand_path(&self.history.file)
     }
+
+    /// Get the expanded startup file path
+    pub fn startup_path(&self) -> PathBuf {
+        expand_path(&self.settings.startup_file)
+    }
+}
+
+/// Location of the %store cache (next to the XDG-style config file)
+pub fn store_path() -> PathBuf {
+    dirs::home_dir()
+        .map(|h| h.join(".config/form-repl/store.json"))
+        .unwrap_or_else(|| PathBuf::from(".form_repl_store.json"))
+}
+
+/// File holding cached cell results (see %cache)
+pub fn cache_path() -> PathBuf {
+    dirs::home_dir()
+        .map(|h| h.join(".config/form-repl/cache.json"))
+        .unwrap_or_else(|| PathBuf::from(".form_repl_cache.json"))
+}
+
+/// Default socket for `--server`, next to the config file
+pub fn server_socket_path() -> PathBuf {
+    dirs::home_dir()
+        .map(|h| h.join(".config/form-repl/server.sock"))
+        .unwrap_or_else(|| PathBuf::from(".form_repl_server.sock"))
+}
+
+/// Directory holding the sessions saved with %session save
+pub fn sessions_dir() -> PathBuf {
+    dirs::home_dir()
+        .map(|h| h.join(".config/form-repl/sessions"))
+        .unwrap_or_else(|| PathBuf::from(".form_repl_sessions"))
+}
+
+/// Location of macros defined with %macro
+pub fn macros_path() -> PathBuf {
+    dirs::home_dir()
+        .map(|h| h.join(".config/form-repl/macros.toml"))
+        .unwrap_or_else(|| PathBuf::from(".form_repl_macros.toml"))
+}
+
+/// On-disk form of the macros file: a single `[macros]` table
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct MacrosFile {
+    #[serde(default)]
+    macros: BTreeMap<String, String>,
+}
+
+/// Read saved macros; a missing or unreadable file yields none
+pub fn load_macros(path: &Path) -> HashMap<String, String> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| toml::from_str::<MacrosFile>(&content).ok())
+        .map(|file| file.macros.into_iter().collect())
+        .unwrap_or_default()
+}
+
+/// Write macros as a `[macros]` table, creating the directory if needed
+pub fn save_macros(path: &Path, macros: &HashMap<String, String>) -> Result<(), String> {
+    let file = MacrosFile {
+        macros: macros.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+    };
+    let content = toml::to_string(&file).map_err(|e| e.to_string())?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
 }
 
 /// Expand ~ in paths to home directory
 pub fn expand_path(path: &str) -> PathBuf {
-    if path.starts_with('~') {
+    if let Some(rest) = path.strip_prefix('~') {
         if let Some(home) = dirs::home_dir() {
-            return home.join(&path[1..].trim_start_matches('/'));
+            return home.join(rest.trim_start_matches('/'));
         }
     }
     PathBuf::from(path)
@@ -123,7 +494,8 @@ pub fn sample_config() -> &'static str {
 # Enable syntax highlighting (default: true)
 highlight = true
 
-# Color theme: default, solarized-dark, monokai, dracula, nord, gruvbox, one-dark
+# Color theme: default, solarized-dark, monokai, dracula, nord, gruvbox, one-dark,
+# light, solarized-light (unset or "default": light if COLORFGBG says so)
 theme = "dracula"
 
 # Show timing information after each execution
@@ -132,9 +504,63 @@ show_timing = false
 # Verbose debug output
 verbose = false
 
-# Automatically add .end to submissions
+# Automatically add .end to submissions. With false, cells go to FORM as
+# typed and must supply their own .end
 auto_end = true
 
+# Submit without an empty line once brackets balance and the line ends with ;
+# The whole cell is then edited at once: Enter starts a new line until the
+# cell is complete, and a stray closing bracket is flagged before submitting
+auto_submit = false
+
+# Warn about names used in id statements and expressions before they are
+# declared (in this cell or an earlier one); same as --strict
+strict = false
+
+# Print each output line as soon as FORM writes it (useful for #write in
+# long loops); streamed output is not truncated to max_output_lines
+stream_output = false
+
+# Reuse the result of an identical earlier cell instead of running FORM
+# again, also across sessions (the 200 most recent results are kept in
+# ~/.config/form-repl/cache.json). Cells that write files or depend on
+# them (.store, .global, save, load, #include, #call, #system, #pipe,
+# #create, #append, #write <file>, ...) always run. Off by default; toggle
+# in a session with %cache on|off, or disable with --no-cache
+cache_results = false
+
+# Ask before %reset, %reset in/out and %cache clear (answer y to go ahead;
+# add -y to the command to skip the question). Never asked when output
+# isn't a terminal
+confirm_destructive = true
+
+# FORM code run when the REPL starts, like .pythonrc: its Symbols,
+# AutoDeclare and procedure definitions are available in every cell.
+# A missing file is ignored; an error in it is reported and the file is
+# not used. Its output is hidden unless startup_verbose is on
+startup_file = "~/.form_repl_startup.frm"
+startup_verbose = false
+
+# What submits a cell: "empty_line" (default), "dot_end_only" (blank lines
+# are kept as part of the cell), or "double_enter" (two blank lines in a row)
+submit_mode = "empty_line"
+
+# Extra FORM arguments. Precedence: --form-flags on the command line, then
+# this setting (and [form] extra_args), then the FORM_FLAGS environment variable
+# form_flags = ["-w4"]
+
+# Line editing keybindings: "emacs" (default) or "vi" for modal editing;
+# --vi / --emacs on the command line override this
+edit_mode = "emacs"
+
+# The rule printed after each cell. char may be several characters ("- ")
+# and is repeated to fill width: a number of columns or "auto" for the
+# terminal's width. Change it in a session with %set separator
+[settings.separator]
+enabled = true
+char = "─"
+width = 60
+
 [history]
 # History file location (supports ~ for home directory)
 file = "~/.form_repl_history"
@@ -144,6 +570,78 @@ max_entries = 1000
 
 # Save history when exiting
 save_on_exit = true
+
+# File format: "plain" (inputs only) or "json" (inputs, outputs and timings).
+# Switching a plain file to "json" converts it on the next save.
+format = "plain"
+
+[form]
+# Path to the FORM executable (default: search FORM_PATH, ./sources, PATH)
+# path = "/usr/local/bin/form"
+
+# Extra command-line arguments passed to FORM
+# extra_args = ["-q"]
+
+# Kill a cell that runs longer than this many seconds
+# timeout_secs = 60.0
+
+# Number of TFORM workers (passed as -w<N>)
+# workers = 4
+
+# Working directory for FORM's temporary files
+# temp_dir = "/tmp"
+
+# %load runs a script with at least this many blocks in a single FORM
+# process instead of one per block (0 = never)
+batch_threshold = 5
+
+[linter]
+# Rules used by %lint
+no_semicolon = true        # statement without a trailing ;
+undeclared_symbol = true   # name used before it is declared
+unused_symbol = true       # name declared but never used
+style = false              # keyword not written in lowercase
+
+[output]
+# Truncate outputs longer than this many lines (0 = unlimited), never
+# inside a term FORM wrapped; %expand [N] shows the whole output (of
+# session N) and %more pages through it.
+# (Older configs set this as max_output_lines in [settings].)
+max_lines = 50
+
+# Show FORM's per-module statistics (terms in output, bytes used)
+show_stats = true
+
+# Strip FORM's version banner from the output
+trim_header = true
+
+# Show FORM warnings and the REPL's warnings before running a cell
+show_warnings = true
+
+# Wrap long expression lines at this width instead of the terminal width
+# (0 = never wrap)
+# wrap_width = 100
+
+[prompt]
+# Templates for the prompts. Placeholders: {n} (cell number), {version},
+# {time}, {form_version}, {session}; color markers from the theme: {prompt_in},
+# {prompt_out}, {prompt_cont}, {timing}, {error}, plus {bold} and {reset}.
+# The continuation prompt is padded to line up with the input prompt.
+# input = "{prompt_in}{bold}In [{n}]{session}:{reset} "
+# output = "{prompt_out}{bold}Out[{n}]:{reset} "
+# continuation = "{prompt_cont}...:{reset} "
+# (in_template, out_template and cont_template are accepted as well)
+
+[server]
+# Where `form-repl --server` listens for editor plugins: a TCP port on
+# 127.0.0.1, or else a Unix socket (default ~/.config/form-repl/server.sock)
+# port = 7141
+# socket = "~/.config/form-repl/server.sock"
+
+[macros]
+# Snippets inserted by typing @name at the start of a line. Macros defined
+# with %macro are saved to ~/.config/form-repl/macros.toml instead.
+# header = "Symbols x, y, z;"
 "#
 }
 
@@ -156,9 +654,32 @@ mod tests {
         let config = Config::default();
         assert!(config.settings.highlight);
         assert_eq!(config.settings.theme, "default");
+        assert!(!config.settings.auto_submit);
+        assert!(!config.settings.stream_output);
+        assert!(!config.settings.cache_results);
+        assert!(config.settings.confirm_destructive);
+        assert!(!config.settings.startup_verbose);
+        assert!(config.startup_path().ends_with(".form_repl_startup.frm"));
+        assert_eq!(config.settings.submit_mode, SubmitMode::EmptyLine);
+        assert_eq!(config.settings.max_output_lines, 50);
+        assert!(config.linter.undeclared_symbol);
+        assert!(!config.linter.style);
         assert_eq!(config.history.max_entries, 1000);
     }
     
+    #[test]
+    fn test_macros_file_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("form-repl-macros-test-{}", std::process::id()))
+            .join("macros.toml");
+        let mut macros = HashMap::new();
+        macros.insert("hdr".to_string(), "Symbols x, y, z;".to_string());
+        save_macros(&path, &macros).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with("[macros]"));
+        assert_eq!(load_macros(&path), macros);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+    
     #[test]
     fn test_expand_path() {
         let path = expand_path("~/.form_repl_history");
@@ -171,9 +692,135 @@ mod tests {
 [settings]
 highlight = false
 theme = "monokai"
+stream_output = true
 "#;
         let config: Config = toml::from_str(config_str).unwrap();
         assert!(!config.settings.highlight);
         assert_eq!(config.settings.theme, "monokai");
+        assert!(config.settings.stream_output);
+        let config: Config = toml::from_str("[settings]\ncache = true\n").unwrap();
+        assert!(config.settings.cache_results);
+        assert_eq!(config.history.format, HistoryFormat::Plain);
+
+        assert_eq!(config.server, ServerConfig::default());
+
+        let config: Config = toml::from_str("[server]\nport = 7141\n").unwrap();
+        assert_eq!(config.server.port, Some(7141));
+
+        let config: Config = toml::from_str("[history]\nformat = \"json\"\n").unwrap();
+        assert_eq!(config.history.format, HistoryFormat::Json);
+        assert!(toml::from_str::<Config>("[history]\nformat = \"xml\"\n").is_err());
+    }
+    
+    #[test]
+    fn test_parse_separator_config() {
+        let config: Config = toml::from_str(sample_config()).unwrap();
+        assert_eq!(config.settings.separator, SeparatorConfig::default());
+
+        let config: Config = toml::from_str(
+            "[settings.separator]\nenabled = false\nchar = \"- \"\nwidth = \"auto\"\n",
+        )
+        .unwrap();
+        let sep = config.settings.separator;
+        assert!(!sep.enabled);
+        assert_eq!(sep.pattern, "- ");
+        assert_eq!(sep.width, SeparatorWidth::Auto);
+        assert_eq!(sep.width.columns(132), 132);
+
+        let config: Config = toml::from_str("[settings.separator]\nwidth = 40\n").unwrap();
+        assert_eq!(config.settings.separator.width.columns(132), 40);
+        assert_eq!(config.settings.separator.pattern, "─");
+        assert!(toml::from_str::<Config>("[settings.separator]\nwidth = \"wide\"\n").is_err());
+        assert!(toml::from_str::<Config>("[settings.separator]\nwidth = 0\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_prompt_config() {
+        let config: Config = toml::from_str(sample_config()).unwrap();
+        assert_eq!(config.prompt, PromptConfig::default());
+
+        let config: Config = toml::from_str(
+            "[prompt]\ninput = \"[{n}]> \"\nout_template = \"={n}= \"\ncont_template = \".. \"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.prompt,
+            PromptConfig {
+                input: Some("[{n}]> ".to_string()),
+                output: Some("={n}= ".to_string()),
+                continuation: Some(".. ".to_string()),
+            }
+        );
+    }
+    
+    #[test]
+    fn test_parse_output_config() {
+        let config_str = r#"
+[output]
+max_lines = 20
+show_stats = false
+trim_header = false
+show_warnings = false
+wrap_width = 100
+"#;
+        let config: Config = toml::from_str(config_str).unwrap();
+        assert_eq!(
+            config.output,
+            OutputConfig {
+                max_lines: 20,
+                show_stats: false,
+                trim_header: false,
+                show_warnings: false,
+                wrap_width: Some(100),
+            }
+        );
+        assert_eq!(config.max_output_lines(), 20);
+
+        let config: Config = toml::from_str("[settings]\nmax_output_lines = 10\n").unwrap();
+        assert_eq!(config.output, OutputConfig::default());
+        assert_eq!(config.max_output_lines(), 10);
+
+        let config: Config = toml::from_str(sample_config()).unwrap();
+        assert_eq!(config.output, OutputConfig::default());
+    }
+    
+    #[test]
+    fn test_parse_form_config() {
+        let config_str = r#"
+[form]
+path = "/opt/form/bin/form"
+extra_args = ["-q", "-M"]
+timeout_secs = 2.5
+workers = 4
+temp_dir = "/scratch"
+batch_threshold = 20
+"#;
+        let config: Config = toml::from_str(config_str).unwrap();
+        assert_eq!(config.form.path(), Some(PathBuf::from("/opt/form/bin/form")));
+        assert_eq!(config.form.workers, Some(4));
+        assert_eq!(config.form.batch_threshold, 20);
+        
+        let options = config.form.run_options();
+        assert_eq!(options.extra_args, vec!["-q", "-M", "-w4"]);
+        assert_eq!(options.timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(options.workdir, Some(PathBuf::from("/scratch")));
+    }
+    
+    #[test]
+    fn test_form_config_defaults() {
+        let config = Config::default();
+        assert!(config.form.path().is_none());
+        assert_eq!(config.form.batch_threshold, 5);
+        let options = config.form.run_options();
+        assert!(options.extra_args.is_empty());
+        assert!(options.timeout.is_none());
+        assert!(options.workdir.is_none());
+        assert_eq!(config.run_options().terminator, Some(Terminator::End));
+    }
+
+    #[test]
+    fn test_auto_end_off_leaves_cells_unterminated() {
+        let config: Config = toml::from_str("[settings]\nauto_end = false\n").unwrap();
+        assert_eq!(config.run_options().terminator, None);
     }
 }
@@ -0,0 +1,212 @@
+// Short reference for FORM statements, shown by %help <keyword>
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Keyword -> description and usage example
+static DOCS: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(|| {
+    HashMap::from([
+        ("symbol", "Declares symbols: plain commuting variables that may carry powers. \
+Options such as a power restriction can follow the name.\n\n\
+Example:\n    Symbols x, y, z^4;"),
+        ("index", "Declares indices for tensors and vectors. An optional dimension follows \
+`=`; indices without one take the default from Dimension.\n\n\
+Example:\n    Indices mu, nu, rho=4;"),
+        ("vector", "Declares vectors. A vector with an index is a component (p(mu)); two \
+vectors joined by a dot form a dot product (p.q).\n\n\
+Example:\n    Vectors p, q;\n    Local E = p.q + p(mu)*q(mu);"),
+        ("function", "Declares non-commuting functions. Use CFunction for commuting ones, \
+which FORM can reorder freely.\n\n\
+Example:\n    Functions f, g;"),
+        ("cfunction", "Declares commuting functions, the usual choice for mathematical \
+functions whose order in a term does not matter.\n\n\
+Example:\n    CFunctions f, g;\n    Local E = f(x)*g(x) - g(x)*f(x);"),
+        ("tensor", "Declares non-commuting tensors; CTensor declares commuting ones. \
+Tensors are functions whose arguments are indices or vectors.\n\n\
+Example:\n    CTensor T;\n    Local E = T(mu,nu)*p(mu);"),
+        ("set", "Defines a named set of objects, usable in patterns such as x?setname.\n\n\
+Example:\n    Set light: u, d, s;\n    id f(x?light) = 1;"),
+        ("dimension", "Sets the default dimension of indices declared afterwards and of \
+traces. It may be a number or a symbol.\n\n\
+Example:\n    Dimension n;\n    Indices mu, nu;"),
+        ("local", "Defines a local expression, which is dropped at the end of the \
+program. The main way to start a computation.\n\n\
+Example:\n    Local E = (x + y)^2;"),
+        ("global", "Defines a global expression, which survives .store and can be \
+reused by later modules.\n\n\
+Example:\n    Global G = x^2 - 1;\n    .store"),
+        ("id", "Substitutes a pattern in every term. Wildcards end in `?`; variants \
+such as `id all` and `id once` control how many matches are replaced.\n\n\
+Example:\n    id f(x?) = x^2;"),
+        ("also", "Adds another substitution to the preceding id, applied in the same \
+pass over each term.\n\n\
+Example:\n    id x = y;\n    also y = x;"),
+        ("repeat", "Repeats the enclosed statements on each term until it stops \
+changing. Close the block with endrepeat.\n\n\
+Example:\n    repeat;\n      id f(n?{>0}) = n*f(n-1);\n    endrepeat;"),
+        ("if", "Runs statements only for terms that satisfy a condition, such as \
+match(), count() or a coefficient test. Close with endif.\n\n\
+Example:\n    if ( count(x,1) > 3 ) Discard;"),
+        ("while", "Repeats the enclosed statements while the condition holds for the \
+term. Close with endwhile.\n\n\
+Example:\n    while ( match(f(x?)) );\n      id f(x?) = g(x);\n    endwhile;"),
+        ("argument", "Applies the enclosed statements inside the arguments of the given \
+functions. Close with endargument.\n\n\
+Example:\n    Argument f;\n      id x = y;\n    EndArgument;"),
+        ("bracket", "Groups the output by the listed objects, printing their \
+coefficients inside brackets.\n\n\
+Example:\n    Bracket x;\n    Print;"),
+        ("antibracket", "Groups the output by everything except the listed objects.\n\n\
+Example:\n    AntiBracket x;\n    Print;"),
+        ("print", "Prints the listed expressions (all active ones by default) at the \
+end of the module. Options include +s for one term per line.\n\n\
+Example:\n    Print +s E;"),
+        ("sum", "Sums over the listed indices, replacing them by dummy indices.\n\n\
+Example:\n    Local E = f(mu)*g(mu);\n    Sum mu;"),
+        ("multiply", "Multiplies every term by the given expression; `left` or `right` \
+controls the side for non-commuting objects.\n\n\
+Example:\n    Multiply 2*x;"),
+        ("contract", "Contracts Levi-Civita tensors (e_) in pairs into products of \
+Kronecker deltas.\n\n\
+Example:\n    Contract;"),
+        ("trace4", "Takes the trace of gamma matrices in 4 dimensions along the given \
+spin line.\n\n\
+Example:\n    Trace4, 1;"),
+        ("tracen", "Takes the trace of gamma matrices in n dimensions along the given \
+spin line.\n\n\
+Example:\n    Tracen, 1;"),
+        ("splitarg", "Splits sums inside function arguments into separate arguments.\n\n\
+Example:\n    SplitArg, f;"),
+        ("normalize", "Normalizes function arguments so their leading coefficient is 1, \
+moving it outside the function.\n\n\
+Example:\n    Normalize, f;"),
+        ("factarg", "Factorizes function arguments, splitting them into their factors.\n\n\
+Example:\n    FactArg, f;"),
+        ("hide", "Hides expressions from the following modules without discarding \
+them; Unhide brings them back.\n\n\
+Example:\n    Hide E;\n    .sort"),
+        ("drop", "Drops expressions at the end of the module to free memory.\n\n\
+Example:\n    Drop F;"),
+        ("keep", "Keeps the brackets of an expression unchanged while the module \
+works on their contents (used with Bracket).\n\n\
+Example:\n    Bracket x;\n    .sort\n    Keep Brackets;"),
+        ("format", "Changes the output format: e.g. Fortran, C, Mathematica, \
+Maple, or a line width.\n\n\
+Example:\n    Format Mathematica;\n    Print;"),
+        ("sort", "Ends the current module (written `.sort`): terms are collected, \
+sorted and combined before the next module starts.\n\n\
+Example:\n    Local E = (x+1)^2;\n    .sort"),
+        ("fill", "Fills elements of a previously declared table.\n\n\
+Example:\n    Table t(1:3);\n    Fill t(1) = x;"),
+        ("table", "Declares an array of expressions indexed by integers or \
+wildcards; CTable for commuting elements.\n\n\
+Example:\n    CTable fib(0:10);"),
+        ("abs_", "Absolute value of its numeric argument.\n\n\
+Example:\n    Local E = abs_(-3);"),
+        ("fac_", "Factorial of a non-negative integer argument.\n\n\
+Example:\n    Local E = fac_(5);"),
+        ("gcd_", "Greatest common divisor of its arguments, which may be numbers or \
+polynomials.\n\n\
+Example:\n    Local E = gcd_(x^2-1, x^2+2*x+1);"),
+        ("d_", "The Kronecker delta: d_(mu,nu) is 1 when the indices are equal.\n\n\
+Example:\n    Local E = d_(mu,nu)*p(mu);"),
+        ("e_", "The Levi-Civita tensor, fully antisymmetric in its indices.\n\n\
+Example:\n    Local E = e_(mu,nu,rho,si)*e_(mu,nu,rho,si);\n    Contract;"),
+    ])
+});
+
+/// All documented keywords, sorted
+pub fn topics() -> Vec<&'static str> {
+    let mut topics: Vec<&str> = DOCS.keys().copied().collect();
+    topics.sort_unstable();
+    topics
+}
+
+/// Documentation for a keyword, ignoring case and a plural (`Symbols`,
+/// `Indices`)
+pub fn lookup(topic: &str) -> Option<(&'static str, &'static str)> {
+    let topic = topic.trim().trim_start_matches(['%', '.', '#']).to_lowercase();
+    let singular = match topic.strip_suffix("ices") {
+        Some(stem) => format!("{}ex", stem),
+        None => topic.strip_suffix('s').unwrap_or(&topic).to_string(),
+    };
+    DOCS.get_key_value(topic.as_str())
+        .or_else(|| DOCS.get_key_value(singular.as_str()))
+        .map(|(k, v)| (*k, *v))
+}
+
+/// The documented keyword closest to `topic`, if any is close enough
+pub fn suggest(topic: &str) -> Option<&'static str> {
+    let topic = topic.trim().to_lowercase();
+    let limit = (topic.chars().count() / 3).max(2);
+    DOCS.keys()
+        .map(|name| (levenshtein(&topic, name), *name))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// Text shown by `%help <topic>`, or an error with a suggestion
+pub fn help_topic(topic: &str) -> Result<String, String> {
+    if let Some((name, text)) = lookup(topic) {
+        return Ok(format!("{}\n\n{}", name, text));
+    }
+    Err(match suggest(topic) {
+        Some(name) => format!("No help for '{}'. Did you mean '{}'?", topic, name),
+        None => format!(
+            "No help for '{}'. Documented keywords:\n{}",
+            topic,
+            topics().join(", ")
+        ),
+    })
+}
+
+/// Edit distance between two strings, counted in characters
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_lookup() {
+        let (name, text) = lookup("Symbols").unwrap();
+        assert_eq!(name, "symbol");
+        assert!(text.contains("Example:"));
+        assert!(help_topic("ID").unwrap().starts_with("id\n\n"));
+        assert_eq!(lookup("Indices").unwrap().0, "index");
+        assert_eq!(lookup("abs_").unwrap().0, "abs_");
+        assert!(lookup("abs").is_none());
+    }
+
+    #[test]
+    fn test_fuzzy_suggestion() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(suggest("repaet"), Some("repeat"));
+        assert_eq!(
+            help_topic("bracet").unwrap_err(),
+            "No help for 'bracet'. Did you mean 'bracket'?"
+        );
+        assert!(help_topic("zzzzzzzz").unwrap_err().contains("Documented keywords"));
+    }
+
+    #[test]
+    fn test_topics_are_highlighted_words() {
+        assert!(topics().len() >= 30);
+        for topic in topics() {
+            assert!(form_core::is_form_word(topic), "{} is not a known FORM word", topic);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// Bundled example cells behind %examples
+/// A demonstration cell
+#[derive(Debug, Clone, Copy)]
+pub struct Example {
+    pub title: &'static str,
+    pub description: &'static str,
+    pub code: &'static str,
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        title: "Symbols and powers",
+        description: "Declare symbols and expand (x+y)^3",
+        code: "\
+Symbols x, y;
+Local E = (x + y)^3;
+Print;
+.end",
+    },
+    Example {
+        title: "Substitution",
+        description: "Replace a symbol with an expression using id",
+        code: "\
+Symbols x, y, a, b;
+Local E = (a + b)^2;
+id a = x + y;
+Print;
+.end",
+    },
+    Example {
+        title: "Wildcards",
+        description: "Match any power of x inside a function with n?",
+        code: "\
+CFunction f;
+Symbols x, n;
+Local E = f(x^2) + f(x^5);
+id f(x^n?) = n*f(x);
+Print;
+.end",
+    },
+    Example {
+        title: "Repeated substitution",
+        description: "Compute 5! by applying a rule until it no longer matches",
+        code: "\
+CFunction fac;
+Symbol n;
+Local E = fac(5);
+repeat id fac(n?pos_) = n*fac(n-1);
+id fac(0) = 1;
+Print;
+.end",
+    },
+    Example {
+        title: "Bracketing",
+        description: "Group the terms of an expansion by powers of x",
+        code: "\
+Symbols x, y, z;
+Local E = (x + y + z)^3;
+Bracket x;
+Print;
+.end",
+    },
+    Example {
+        title: "Modules",
+        description: "Finish one module with .sort before substituting in the next",
+        code: "\
+Symbols x, y;
+Local E = (x + y)^2;
+.sort
+id x = 1 - y;
+Print;
+.end",
+    },
+];
+
+/// Example `n`, counting from 1
+pub fn get(n: usize) -> Option<&'static Example> {
+    n.checked_sub(1).and_then(|i| EXAMPLES.get(i))
+}
+
+/// The numbered list shown by `%examples`
+pub fn listing() -> String {
+    let mut out = String::from("Examples (run one with %examples run N):\n");
+    for (i, example) in EXAMPLES.iter().enumerate() {
+        out.push_str(&format!("  {}. {} - {}\n", i + 1, example.title, example.description));
+    }
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::form;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_examples_are_valid() {
+        for example in EXAMPLES {
+            let code = example.code;
+            assert_eq!(form::validate_input(code), Ok(()), "{}", example.title);
+            assert_eq!(form::clear_warning(code), None, "{}", example.title);
+            assert!(
+                form::validate_input_strict(code, &HashSet::new()).is_empty(),
+                "{}",
+                example.title
+            );
+            assert!(code.ends_with(".end"), "{}", example.title);
+        }
+        assert_eq!(get(1).unwrap().title, "Symbols and powers");
+        assert!(get(0).is_none());
+        assert!(get(EXAMPLES.len() + 1).is_none());
+    }
+}
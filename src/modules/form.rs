@@ -1,103 +1,321 @@
 // FORM execution module
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::env;
+//
+// The driver itself (locating, running and formatting FORM) lives in the
+// form-core crate, shared with the GUI; this module adds the REPL-side
+// checks and reports on top of it.
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::time::{Duration, Instant};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Mutex, OnceLock};
 
-/// Custom error type for FORM execution errors
-/// Provides better type safety and error context than String
-#[derive(Debug)]
-pub enum FormError {
-    SpawnError(std::io::Error),
-    WriteError(std::io::Error),
-    ReadError(std::io::Error),
-    ExecutionError { status: i32, stderr: String },
-    Timeout,
-    InvalidUtf8(std::string::FromUtf8Error),
-    NotFound,
+pub use form_core::{
+    explicit_form_path, find_form_executable, format_output, format_output_with, locate_form, resolve_form_flags,
+    run_form, run_form_batch, run_form_streaming, terminate_input, truncate_output, truncation_point, FormError,
+    FormResult, RunOptions, Terminator, STREAM_END,
+};
+
+use super::linter::{Level, Linter, UndeclaredSymbolRule};
+
+/// Runs FORM like `run_form`, calling `on_line` with each output line
+/// (headers and statistics already dropped) as soon as FORM prints it.
+///
+/// The returned result still holds the full raw output, so it can be
+/// formatted and recorded in history afterwards.
+pub fn run_form_with<F: FnMut(&str)>(
+    input: &str,
+    form_path: &Path,
+    options: &RunOptions,
+    on_line: F,
+) -> Result<FormResult, FormError> {
+    let (tx, rx) = mpsc::channel();
+    let handle = run_form_streaming(input, form_path, options, tx);
+    forward_stream(&rx, on_line);
+    handle
+        .join()
+        .unwrap_or_else(|_| Err(FormError::ReadError(io::Error::other("FORM runner panicked"))))
 }
 
-impl fmt::Display for FormError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            FormError::SpawnError(e) => write!(f, "Failed to spawn FORM: {}", e),
-            FormError::WriteError(e) => write!(f, "Failed to write to FORM: {}", e),
-            FormError::ReadError(e) => write!(f, "Failed to read from FORM: {}", e),
-            FormError::ExecutionError { status, stderr } => {
-                if stderr.is_empty() {
-                    write!(f, "FORM exited with status {}", status)
-                } else {
-                    write!(f, "FORM error (exit {}): {}", status, stderr.trim())
-                }
-            }
-            FormError::Timeout => write!(f, "FORM execution timed out"),
-            FormError::InvalidUtf8(e) => write!(f, "Invalid UTF-8 in output: {}", e),
-            FormError::NotFound => write!(f, "FORM executable not found"),
+/// Passes lines from a `run_form_streaming` channel to `on_line` until
+/// `STREAM_END` (or a closed channel); returns how many were passed
+pub fn forward_stream<F: FnMut(&str)>(rx: &Receiver<String>, mut on_line: F) -> usize {
+    let mut count = 0;
+    for line in rx {
+        if line == STREAM_END {
+            break;
         }
+        on_line(&line);
+        count += 1;
     }
+    count
 }
 
-impl std::error::Error for FormError {}
+/// Runs `form -v` (falling back to `--version`) and extracts the version
+/// string (e.g. `4.3.1`).
+pub fn detect_version(form_path: &Path) -> Option<String> {
+    version_banner(form_path).as_deref().and_then(parse_version)
+}
 
-/// Result of FORM execution with timing information
-#[derive(Debug)]
-pub struct FormResult {
-    pub output: String,
-    pub stderr: String,
-    pub duration: Duration,
-    pub exit_code: i32,
+/// Output of `form -v` (or `--version`) that names a version
+fn version_banner(form_path: &Path) -> Option<String> {
+    ["-v", "--version"].iter().find_map(|flag| {
+        let output = Command::new(form_path)
+            .arg(flag)
+            .stdin(Stdio::null())
+            .output()
+            .ok()?;
+        let text = String::from_utf8_lossy(&output.stdout).into_owned();
+        parse_version(&text).map(|_| text)
+    })
 }
 
-/// Finds the FORM executable in common locations.
-///
-/// Searches in this order:
-/// 1. `FORM_PATH` environment variable (if set)
-/// 2. `sources/form` (local directory)
-/// 3. `../sources/form` (parent directory)  
-/// 4. Directories in PATH environment variable
-///
-/// # Returns
-///
-/// `Some(PathBuf)` if found, `None` otherwise.
-pub fn find_form_executable() -> Option<PathBuf> {
-    // 1. Check FORM_PATH environment variable first
-    if let Ok(form_path) = env::var("FORM_PATH") {
-        let path = PathBuf::from(&form_path);
-        if path.exists() {
-            return Some(path);
+/// What a FORM binary supports, read from its version banner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormCapabilities {
+    /// Version number, or `"unknown"`
+    pub version: String,
+    /// Multi-threaded TFORM, which takes `-w` for the number of workers
+    pub supports_tform: bool,
+    /// Workers the banner reports (1 for sequential FORM)
+    pub max_workers: u32,
+    /// The `#pipe` instruction (FORM 3.2 and later)
+    pub supports_pipe: bool,
+    /// `-q` to leave the run statistics out of the output (FORM 4 and later)
+    pub supports_statistics: bool,
+}
+
+impl FormCapabilities {
+    /// Reads the capabilities from `form -v` output. Features of a binary
+    /// whose version can be read but that doesn't mention them are assumed
+    /// unsupported; an unreadable banner gives version `"unknown"`, for
+    /// which `filter_args` keeps every flag.
+    pub fn parse(banner: &str) -> Self {
+        use regex::Regex;
+        use std::sync::LazyLock;
+
+        static WORKERS_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(\d+)\s+workers").unwrap());
+
+        let version = parse_version(banner);
+        let (major, minor) = version
+            .as_deref()
+            .map(|v| {
+                let mut parts = v.split('.').map(|p| p.parse::<u32>().unwrap_or(0));
+                (parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+            })
+            .unwrap_or_default();
+        let supports_tform = banner.trim_start().starts_with("TFORM");
+        let max_workers = WORKERS_RE
+            .captures(banner)
+            .and_then(|c| c[1].parse().ok())
+            .filter(|_| supports_tform)
+            .unwrap_or(1);
+        FormCapabilities {
+            version: version.unwrap_or_else(|| "unknown".to_string()),
+            supports_tform,
+            max_workers,
+            supports_pipe: (major, minor) >= (3, 2),
+            supports_statistics: major >= 4,
         }
-        // Also try as directory containing 'form'
-        let form_in_dir = path.join("form");
-        if form_in_dir.exists() {
-            return Some(form_in_dir);
+    }
+
+    /// Whether the banner could be read at all
+    pub fn is_known(&self) -> bool {
+        self.version != "unknown"
+    }
+
+    /// Splits `args` into the flags this binary accepts and the ones it
+    /// doesn't (`-w` without TFORM, `-q` before FORM 4). With nothing known
+    /// about the binary, every flag is passed on as the user gave it.
+    pub fn filter_args(&self, args: &[String]) -> (Vec<String>, Vec<String>) {
+        if !self.is_known() {
+            return (args.to_vec(), Vec::new());
+        }
+        let mut kept = Vec::new();
+        let mut dropped = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let supported = if arg.starts_with("-w") {
+                self.supports_tform
+            } else if arg == "-q" {
+                self.supports_statistics
+            } else {
+                true
+            };
+            let target = if supported { &mut kept } else { &mut dropped };
+            target.push(arg.clone());
+            // `-w 4` as well as `-w4`
+            if arg == "-w" {
+                target.extend(iter.next().cloned());
+            }
         }
+        (kept, dropped)
     }
-    
-    // 2. Check local sources directory
-    let local = PathBuf::from("sources/form");
-    if local.exists() {
-        return Some(local);
+}
+
+/// The supported features, one per line (`form_info` shows the version)
+impl fmt::Display for FormCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        if self.supports_tform {
+            writeln!(f, "TFORM:          yes ({} workers)", self.max_workers)?;
+        } else {
+            writeln!(f, "TFORM:          no")?;
+        }
+        writeln!(f, "#pipe:          {}", yes_no(self.supports_pipe))?;
+        write!(f, "-q (no stats):  {}", yes_no(self.supports_statistics))
+    }
+}
+
+/// Capabilities of the binary at `form_path`, from `form --version`.
+///
+/// Detected once per binary; `%form` can switch binaries, so the cache
+/// is keyed by path.
+pub fn detect_form_capabilities(form_path: &Path) -> FormCapabilities {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, FormCapabilities>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(capabilities) = cache.lock().unwrap().get(form_path) {
+        return capabilities.clone();
     }
+    let capabilities = FormCapabilities::parse(&version_banner(form_path).unwrap_or_default());
+    cache
+        .lock()
+        .unwrap()
+        .insert(form_path.to_path_buf(), capabilities.clone());
+    capabilities
+}
+
+/// One-line summary of the selected FORM binary, e.g.
+/// `Using FORM 4.3.1 at /usr/local/bin/form`
+pub fn form_info(form_path: &Path, version: Option<&str>) -> String {
+    match version {
+        Some(v) => format!("Using FORM {} at {}", v, form_path.display()),
+        None => format!("Using FORM (unknown version) at {}", form_path.display()),
+    }
+}
+
+/// Extracts the version number from FORM's banner line
+pub fn parse_version(text: &str) -> Option<String> {
+    use regex::Regex;
+    use std::sync::LazyLock;
+
+    static VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?:T|Par)?FORM\s+v?(\d+\.\d+(?:\.\d+)?)").unwrap()
+    });
+
+    VERSION_RE
+        .captures(text)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str().to_string())
+}
 
-    // 3. Check parent sources directory
-    let parent = PathBuf::from("../sources/form");
-    if parent.exists() {
-        return Some(parent);
+/// Environment variables that influence FORM or its discovery
+pub const FORM_ENV_VARS: &[&str] = &[
+    "FORM_PATH",
+    "FORMPATH",
+    "FORMSETUP",
+    "FORMTMP",
+    "FORMTMPSORT",
+];
+
+/// Everything about the FORM setup, gathered for troubleshooting
+#[derive(Debug)]
+pub struct FormEnvReport {
+    pub form_path: Option<PathBuf>,
+    pub version: Option<String>,
+    pub backend: &'static str,
+    pub extra_args: Vec<String>,
+    pub workdir: PathBuf,
+    pub env_vars: Vec<(&'static str, Option<String>)>,
+    pub setup_file: Option<PathBuf>,
+}
+
+impl FormEnvReport {
+    /// Assembles a report from the resolved path, options and environment.
+    ///
+    /// `getenv` is injected so the report can be built from mocked values.
+    pub fn assemble<F>(
+        form_path: Option<&Path>,
+        version: Option<String>,
+        options: &RunOptions,
+        cwd: &Path,
+        getenv: F,
+    ) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let backend = match form_path
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_lowercase())
+        {
+            Some(name) if name.starts_with("tform") => "TFORM (multi-threaded)",
+            Some(name) if name.starts_with("parform") => "ParFORM (MPI)",
+            Some(_) => "FORM (sequential)",
+            None => "none",
+        };
+
+        let workdir = options.workdir.clone().unwrap_or_else(|| cwd.to_path_buf());
+
+        // FORM reads the setup file from -s, then FORMSETUP, then ./form.set
+        let setup_file = options
+            .extra_args
+            .iter()
+            .position(|a| a == "-s")
+            .and_then(|i| options.extra_args.get(i + 1))
+            .map(PathBuf::from)
+            .or_else(|| getenv("FORMSETUP").map(PathBuf::from))
+            .or_else(|| Some(workdir.join("form.set")).filter(|p| p.exists()));
+
+        FormEnvReport {
+            form_path: form_path.map(Path::to_path_buf),
+            version,
+            backend,
+            extra_args: options.extra_args.clone(),
+            workdir,
+            env_vars: FORM_ENV_VARS.iter().map(|name| (*name, getenv(name))).collect(),
+            setup_file,
+        }
     }
+}
 
-    // 4. Search in PATH
-    if let Ok(path) = env::var("PATH") {
-        for dir in env::split_paths(&path) {
-            let form_path = dir.join("form");
-            if form_path.exists() {
-                return Some(form_path);
+impl fmt::Display for FormEnvReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unset = "(not set)";
+        writeln!(
+            f,
+            "Executable:   {}",
+            self.form_path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "(not found)".to_string())
+        )?;
+        writeln!(f, "Version:      {}", self.version.as_deref().unwrap_or("(unknown)"))?;
+        writeln!(f, "Backend:      {}", self.backend)?;
+        writeln!(
+            f,
+            "Extra args:   {}",
+            if self.extra_args.is_empty() {
+                "(none)".to_string()
+            } else {
+                self.extra_args.join(" ")
             }
+        )?;
+        writeln!(f, "Working dir:  {}", self.workdir.display())?;
+        writeln!(
+            f,
+            "Setup file:   {}",
+            self.setup_file
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "(none)".to_string())
+        )?;
+        write!(f, "Environment:")?;
+        for (name, value) in &self.env_vars {
+            write!(f, "\n  {:<12} {}", name, value.as_deref().unwrap_or(unset))?;
         }
+        Ok(())
     }
-    
-    None
 }
 
 /// Validates FORM code for obvious errors before execution.
@@ -152,205 +370,655 @@ pub fn validate_input(input: &str) -> Result<(), String> {
     Ok(())
 }
 
-/// Executes FORM with the given input.
+/// A problem found by `validate_input_strict`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationWarning {
+    /// 1-based line of the input
+    pub line: u32,
+    pub message: String,
+    pub severity: Level,
+}
+
+impl fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.severity, self.message)
+    }
+}
+
+/// Checks for names used before they are declared (`--strict`).
 ///
-/// # Arguments
+/// Declarations (`Symbols`, `CFunction`, `Local`, ...) in `input` and the
+/// names in `declared` (from earlier cells) count; `Local`/`Global`
+/// definitions and `id`-style statements are scanned for other names.
+/// Built-in functions and `name_` built-ins are never reported.
+pub fn validate_input_strict(input: &str, declared: &HashSet<String>) -> Vec<ValidationWarning> {
+    Linter::new()
+        .with_rule(UndeclaredSymbolRule::with_known(declared.clone()))
+        .check(input)
+        .into_iter()
+        .map(|d| ValidationWarning {
+            line: d.span.line as u32,
+            message: d.message,
+            severity: d.level,
+        })
+        .collect()
+}
+
+/// Checks whether the accumulated input looks like a complete cell.
 ///
-/// * `input` - The FORM code to execute
-/// * `form_path` - Path to the FORM executable
-/// * `verbose` - Enable verbose debug output
+/// A cell is complete when all brackets are balanced and the last
+/// non-empty, non-comment line ends with `;`.
+pub fn is_input_complete(input: &str) -> bool {
+    let last_line = input
+        .lines()
+        .map(str::trim)
+        .rfind(|l| !l.is_empty() && !l.starts_with('*'));
+
+    match last_line {
+        Some(line) => line.ends_with(';') && validate_input(input).is_ok(),
+        None => false,
+    }
+}
+
+/// Module instructions: each one ends a FORM module
+const MODULE_INSTRUCTIONS: &[&str] = &[".sort", ".store", ".global", ".clear", ".end"];
+
+/// The module instruction on this line, if any (e.g. `.sort` for `.sort:first;`)
+fn module_instruction(line: &str) -> Option<String> {
+    let word = line
+        .trim()
+        .split(|c: char| c.is_whitespace() || c == ':' || c == ';')
+        .next()?
+        .to_lowercase();
+    MODULE_INSTRUCTIONS.contains(&word.as_str()).then_some(word)
+}
+
+/// Counts the FORM modules in a cell.
 ///
-/// # Returns
+/// Every module instruction (`.sort`, `.store`, `.global`, `.clear`, `.end`)
+/// closes a module; code after the last one forms a final module.
+pub fn count_modules(input: &str) -> usize {
+    let mut count = 0;
+    let mut pending = false;
+    for line in input.lines() {
+        let trimmed = line.trim();
+        if module_instruction(trimmed).is_some() {
+            count += 1;
+            pending = false;
+        } else if !trimmed.is_empty() && !trimmed.starts_with('*') {
+            pending = true;
+        }
+    }
+    count + usize::from(pending)
+}
+
+/// Size of an expression as printed by FORM (see `%size`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExprSize {
+    /// Top-level terms
+    pub terms: usize,
+    /// Deepest bracket nesting
+    pub depth: usize,
+    /// Distinct symbol and function names
+    pub symbols: usize,
+    /// Names and numbers, counting repeats
+    pub nodes: usize,
+}
+
+impl fmt::Display for ExprSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} term{}, depth {}, {} symbol{}, {} nodes",
+            self.terms,
+            if self.terms == 1 { "" } else { "s" },
+            self.depth,
+            self.symbols,
+            if self.symbols == 1 { "" } else { "s" },
+            self.nodes
+        )
+    }
+}
+
+/// Measures a `NAME = ...;` block of FORM output.
 ///
-/// `Ok(FormResult)` with FORM output on success, `Err(FormError)` on failure.
-pub fn run_form(input: &str, form_path: &PathBuf, verbose: bool) -> Result<FormResult, FormError> {
-    let start = Instant::now();
-    
-    if verbose {
-        eprintln!("[verbose] Running FORM with {} bytes of input", input.len());
-        eprintln!("[verbose] Using FORM at: {}", form_path.display());
-    }
-
-    let mut child = Command::new(form_path)
-        .arg("-")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(FormError::SpawnError)?;
-
-    // Get handles to stdin, stdout, and stderr
-    let mut stdin = child.stdin.take().unwrap();
-    let mut stdout = child.stdout.take().unwrap();
-    let mut stderr = child.stderr.take().unwrap();
-
-    // Prepare input - ensure it ends with .end
-    let full_input = if !input.trim_end().ends_with(".end") {
-        format!("{}\n.end", input)
-    } else {
-        input.to_string()
-    };
-
-    // Write input to FORM
-    stdin.write_all(full_input.as_bytes()).map_err(FormError::WriteError)?;
-    drop(stdin);
-
-    // Read stdout and stderr
-    let mut output = Vec::new();
-    stdout.read_to_end(&mut output).map_err(FormError::ReadError)?;
-    
-    let mut stderr_output = Vec::new();
-    stderr.read_to_end(&mut stderr_output).map_err(FormError::ReadError)?;
+/// A `+` or `-` outside brackets separates terms unless it is unary, i.e.
+/// follows `=`, an operator or an opening bracket (as in `x^-1`).
+pub fn measure_complexity(block: &str) -> ExprSize {
+    use regex::Regex;
+    use std::collections::HashSet;
+    use std::sync::LazyLock;
 
-    let status = child.wait().map_err(FormError::ReadError)?;
-    let duration = start.elapsed();
-    
-    let output_str = String::from_utf8(output).map_err(FormError::InvalidUtf8)?;
-    let stderr_str = String::from_utf8_lossy(&stderr_output).to_string();
+    static ATOM_RE: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]*|[0-9]+").unwrap());
+
+    let rhs = block.split_once('=').map_or(block, |(_, rhs)| rhs);
+    let rhs = rhs.trim().trim_end_matches(';').trim();
+    if rhs.is_empty() || rhs == "0" {
+        return ExprSize::default();
+    }
 
-    if verbose {
-        eprintln!("[verbose] FORM completed in {:?}", duration);
-        if !stderr_str.is_empty() {
-            eprintln!("[verbose] FORM stderr: {}", stderr_str);
+    let mut size = ExprSize { terms: 1, ..ExprSize::default() };
+    let mut depth = 0usize;
+    let mut prev: Option<char> = None;
+    for c in rhs.chars().filter(|c| !c.is_whitespace()) {
+        match c {
+            '(' | '[' => {
+                depth += 1;
+                size.depth = size.depth.max(depth);
+            }
+            ')' | ']' => depth = depth.saturating_sub(1),
+            '+' | '-' if depth == 0 => {
+                let binary = prev.is_some_and(|p| p.is_alphanumeric() || matches!(p, '_' | ')' | ']'));
+                size.terms += usize::from(binary);
+            }
+            _ => {}
         }
+        prev = Some(c);
     }
 
-    let exit_code = status.code().unwrap_or(-1);
-    
-    if !status.success() {
-        return Err(FormError::ExecutionError {
-            status: exit_code,
-            stderr: stderr_str,
-        });
+    let mut names = HashSet::new();
+    for atom in ATOM_RE.find_iter(rhs) {
+        size.nodes += 1;
+        if !atom.as_str().starts_with(|c: char| c.is_ascii_digit()) {
+            names.insert(atom.as_str());
+        }
     }
-    
-    Ok(FormResult {
-        output: output_str,
-        stderr: stderr_str,
-        duration,
-        exit_code,
-    })
+    size.symbols = names.len();
+    size
 }
 
-/// Formats FORM output by removing timing and metadata lines.
-///
-/// Filters out FORM version info, timing statistics, and other metadata,
-/// leaving only the actual computation results.
-///
-/// # Arguments
-///
-/// * `output` - Raw output from FORM execution
-/// * `show_timing` - Whether to include timing information
-///
-/// # Returns
+/// Warns about a `.clear` in the cell, which makes FORM forget everything
+/// declared and computed before it
+pub fn clear_warning(input: &str) -> Option<String> {
+    let line = input
+        .lines()
+        .position(|l| module_instruction(l).as_deref() == Some(".clear"))?;
+    Some(format!(
+        ".clear on line {} resets FORM: declarations and expressions from the {} module(s) before it are discarded",
+        line + 1,
+        count_modules(&input.lines().take(line).collect::<Vec<_>>().join("\n"))
+    ))
+}
+
+/// The kind of a name FORM lists under `On names;`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Symbol,
+    Index,
+    Vector,
+    Tensor,
+    Function,
+}
+
+impl SymbolKind {
+    /// The kind a listing heading such as `Symbols` or `Commuting
+    /// Functions` introduces
+    fn from_heading(line: &str) -> Option<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() > 3 || !words.iter().all(|w| w.chars().all(char::is_alphabetic)) {
+            return None;
+        }
+        match words.last()?.to_lowercase().as_str() {
+            "symbols" => Some(SymbolKind::Symbol),
+            "indices" => Some(SymbolKind::Index),
+            "vectors" => Some(SymbolKind::Vector),
+            "tensors" => Some(SymbolKind::Tensor),
+            "functions" => Some(SymbolKind::Function),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SymbolKind::Symbol => "Symbol",
+            SymbolKind::Index => "Index",
+            SymbolKind::Vector => "Vector",
+            SymbolKind::Tensor => "Tensor",
+            SymbolKind::Function => "Function",
+        };
+        f.pad(name)
+    }
+}
+
+/// A name as FORM itself knows it, with a symbol's power restriction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeclaredSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub min_power: Option<i32>,
+    pub max_power: Option<i32>,
+}
+
+impl fmt::Display for DeclaredSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<9} {}", self.kind, self.name)?;
+        if self.min_power.is_some() || self.max_power.is_some() {
+            let bound = |p: Option<i32>| p.map(|p| p.to_string()).unwrap_or_default();
+            write!(f, "  powers {}..{}", bound(self.min_power), bound(self.max_power))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the name listing FORM prints for `On names;`.
 ///
-/// Formatted output string with metadata removed.
-pub fn format_output(output: &str, show_timing: bool) -> String {
-    let lines: Vec<&str> = output.lines().collect();
-    let mut result = Vec::new();
-    let mut in_header = true;
-    let mut timing_line = None;
-    
-    for line in &lines {
-        // Skip FORM header lines
-        if in_header {
-            if line.starts_with("FORM ") 
-                || line.contains("Version")
-                || line.trim().is_empty()
-                || line.contains("Run at:")
-                || line.trim_start().starts_with("Generated terms")
-            {
+/// Each heading (`Symbols`, `Indices`, `Vectors`, `Tensors`, `Functions`,
+/// also with a qualifier such as `Commuting Functions`) is followed by the
+/// names of that kind, separated by spaces. A symbol's power restriction
+/// is written `x(-2:4)`, either bound may be left out. Built-in names
+/// (ending in `_`) are skipped.
+pub fn parse_form_symbols_output(output: &str) -> Vec<DeclaredSymbol> {
+    use regex::Regex;
+    use std::sync::LazyLock;
+
+    static NAME_RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^([A-Za-z][A-Za-z0-9]*_?)(?:#[A-Za-z]*)?(?:\((-?\d*):(-?\d*)\))?").unwrap()
+    });
+
+    let mut kind = None;
+    let mut symbols = Vec::new();
+    for line in output.lines() {
+        let trimmed = line.trim();
+        // A blank line or a statistics line ends the listing
+        if trimmed.is_empty() || trimmed.contains(" = ") {
+            kind = None;
+            continue;
+        }
+        if let Some(heading) = SymbolKind::from_heading(trimmed) {
+            kind = Some(heading);
+            continue;
+        }
+        let Some(kind) = kind else { continue };
+        for word in trimmed.split([' ', '\t', ',']).filter(|w| !w.is_empty()) {
+            let Some(caps) = NAME_RE.captures(word) else { continue };
+            let name = &caps[1];
+            if name.ends_with('_') || symbols.iter().any(|s: &DeclaredSymbol| s.name == name) {
                 continue;
             }
-            in_header = false;
+            let power = |i: usize| caps.get(i).and_then(|m| m.as_str().parse().ok());
+            symbols.push(DeclaredSymbol {
+                name: name.to_string(),
+                kind,
+                min_power: power(2),
+                max_power: power(3),
+            });
         }
-        
-        // Capture timing line separately
-        if line.contains("sec out of") || line.trim_start().starts_with("Time =") {
-            timing_line = Some(*line);
+    }
+    symbols
+}
+
+/// A program that declares what `inputs` declare and has FORM list the
+/// names (`On names;`), for `parse_form_symbols_output`
+pub fn names_listing_program<'a>(inputs: impl IntoIterator<Item = &'a str>) -> String {
+    let mut lines = declaration_lines(inputs);
+    lines.push("On names;".to_string());
+    lines.join("\n")
+}
+
+/// The declaration statements of `inputs` (`Symbols`, `CFunctions`, ...,
+/// but not `Local`/`Global`), as written
+pub fn declaration_lines<'a>(inputs: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for input in inputs {
+        let mut in_declaration = false;
+        for line in input.lines() {
+            let starts_declaration = form_core::tokenize(line)
+                .iter()
+                .find(|t| !t.text.trim().is_empty())
+                .is_some_and(|t| {
+                    // Expressions would be computed; only the names matter here
+                    t.token_type == form_core::TokenType::Declaration
+                        && !matches!(t.text.to_lowercase().as_str(), "local" | "global")
+                });
+            if starts_declaration || in_declaration {
+                lines.push(line.to_string());
+                in_declaration = !line.trim_end().ends_with(';');
+            }
+        }
+    }
+    lines
+}
+
+/// Converts FORM output to a LaTeX `align` environment for export.
+///
+/// Each `NAME = ...;` expression in the `format_output` result becomes
+/// one aligned row. Exponents are braced, `a/b` becomes `\frac{a}{b}`
+/// and `*` is dropped except between two numbers, where it becomes
+/// `\cdot`. Expressions that can't be parsed are copied verbatim.
+pub fn format_output_latex(output: &str) -> String {
+    let formatted = format_output(output, false, 0);
+    let mut rows = Vec::new();
+    let mut current: Option<(String, String)> = None;
+
+    for line in formatted.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
             continue;
         }
-        
-        result.push(*line);
+        let (name, text) = match current.take() {
+            Some((name, mut text)) => {
+                text.push(' ');
+                text.push_str(trimmed);
+                (name, text)
+            }
+            None => match trimmed.split_once('=') {
+                Some((name, rest)) => (name.trim().to_string(), rest.trim().to_string()),
+                None => continue,
+            },
+        };
+        match text.strip_suffix(';') {
+            Some(body) => rows.push(format!("{} &= {}", name, expression_to_latex(body))),
+            None => current = Some((name, text)),
+        }
     }
-    
-    // Remove trailing empty lines
-    while result.last().map(|l| l.trim().is_empty()).unwrap_or(false) {
-        result.pop();
+
+    format!("\\begin{{align}}\n{}\n\\end{{align}}", rows.join(" \\\\\n"))
+}
+
+/// Converts one FORM expression to LaTeX, or returns it unchanged if it
+/// doesn't parse
+pub fn expression_to_latex(expr: &str) -> String {
+    let tokens = latex_tokens(expr);
+    let mut parser = LatexParser { tokens: &tokens, pos: 0 };
+    match parser.sum() {
+        Some(latex) if parser.pos == tokens.len() => latex,
+        _ => expr.trim().to_string(),
     }
-    
-    let mut formatted = result.join("\n");
-    
-    // Optionally append timing
-    if show_timing {
-        if let Some(timing) = timing_line {
-            if !formatted.is_empty() {
-                formatted.push_str("\n\n");
+}
+
+fn latex_tokens(expr: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            let mut number = String::new();
+            while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                number.push(d);
+                chars.next();
+            }
+            tokens.push(number);
+        } else if c.is_alphabetic() || c == '_' || c == '[' {
+            // Names, including FORM's [bracketed names] and dotproducts
+            let mut name = String::new();
+            let mut depth = 0;
+            while let Some(&d) = chars.peek() {
+                match d {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ if depth == 0 && !(d.is_alphanumeric() || d == '_' || d == '.') => break,
+                    _ => {}
+                }
+                name.push(d);
+                chars.next();
             }
-            formatted.push_str(timing.trim());
+            tokens.push(name);
+        } else {
+            tokens.push(c.to_string());
+            chars.next();
         }
     }
-    
-    formatted
+    tokens
 }
 
-/// Extract just the timing information from FORM output
-pub fn extract_timing(output: &str) -> Option<String> {
-    for line in output.lines() {
-        if line.contains("sec out of") {
-            return Some(line.trim().to_string());
+/// Recursive-descent parser; the call stack tracks nested exponents,
+/// fractions and parentheses
+struct LatexParser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl LatexParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<&str> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    fn expect(&mut self, token: &str) -> Option<()> {
+        (self.next()? == token).then_some(())
+    }
+
+    /// term (('+' | '-') term)*
+    fn sum(&mut self) -> Option<String> {
+        let mut latex = String::new();
+        if let Some(sign @ ("+" | "-")) = self.peek() {
+            if sign == "-" {
+                latex.push('-');
+            }
+            self.pos += 1;
+        }
+        latex.push_str(&self.product()?);
+        while let Some(op @ ("+" | "-")) = self.peek() {
+            let op = op.to_string();
+            self.pos += 1;
+            latex.push_str(&format!(" {} {}", op, self.product()?));
+        }
+        Some(latex)
+    }
+
+    /// power (('*' | '/') power)*
+    fn product(&mut self) -> Option<String> {
+        let mut factors = vec![self.power()?];
+        while let Some(op @ ("*" | "/")) = self.peek() {
+            let divide = op == "/";
+            self.pos += 1;
+            let next = self.power()?;
+            if divide {
+                let numerator = factors.pop()?;
+                factors.push(format!("\\frac{{{}}}{{{}}}", numerator, next));
+            } else {
+                factors.push(next);
+            }
+        }
+
+        let mut latex = factors[0].clone();
+        for pair in factors.windows(2) {
+            let numeric = |s: &str, first: bool| {
+                let c = if first { s.chars().next() } else { s.chars().last() };
+                c.is_some_and(|c| c.is_ascii_digit())
+            };
+            if numeric(&pair[0], false) && numeric(&pair[1], true) {
+                latex.push_str(" \\cdot ");
+            } else {
+                latex.push(' ');
+            }
+            latex.push_str(&pair[1]);
         }
+        Some(latex)
+    }
+
+    /// atom ('^' exponent)?
+    fn power(&mut self) -> Option<String> {
+        let base = self.atom()?;
+        if self.peek() != Some("^") {
+            return Some(base);
+        }
+        self.pos += 1;
+        let exponent = match self.peek()? {
+            "-" | "+" => {
+                let sign = self.next()?.to_string();
+                format!("{}{}", if sign == "-" { "-" } else { "" }, self.power()?)
+            }
+            "(" => {
+                self.pos += 1;
+                let inner = self.sum()?;
+                self.expect(")")?;
+                inner
+            }
+            _ => self.power()?,
+        };
+        Some(format!("{}^{{{}}}", base, exponent))
+    }
+
+    /// number | name ['(' args ')'] | '(' sum ')'
+    fn atom(&mut self) -> Option<String> {
+        let token = self.next()?.to_string();
+        if token == "(" {
+            let inner = self.sum()?;
+            self.expect(")")?;
+            return Some(format!("\\left({}\\right)", inner));
+        }
+        let first = token.chars().next()?;
+        if !(first.is_alphanumeric() || first == '_' || first == '[') {
+            return None;
+        }
+        if self.peek() == Some("(") && !first.is_ascii_digit() {
+            self.pos += 1;
+            let mut args = vec![self.sum()?];
+            while self.peek() == Some(",") {
+                self.pos += 1;
+                args.push(self.sum()?);
+            }
+            self.expect(")")?;
+            return Some(format!("{}({})", token, args.join(", ")));
+        }
+        Some(token)
     }
-    None
 }
 
-/// Parse FORM error messages for better display
+/// Parse FORM error messages for better display.
+///
+/// Every diagnostic line is kept; one that names a line of `code` is
+/// followed by that line and its neighbours (see `source_context`).
 pub fn parse_form_error(stderr: &str, code: &str) -> String {
     let mut result = String::new();
-    let code_lines: Vec<&str> = code.lines().collect();
-    
     for line in stderr.lines() {
-        // Try to extract line numbers from error messages
-        if line.contains("Line") || line.contains("line") {
-            result.push_str(line);
-            result.push('\n');
-            
-            // Try to find line number and show context
-            if let Some(num_str) = extract_line_number(line) {
-                if let Ok(line_num) = num_str.parse::<usize>() {
-                    if line_num > 0 && line_num <= code_lines.len() {
-                        result.push_str("    → ");
-                        result.push_str(code_lines[line_num - 1]);
-                        result.push('\n');
-                    }
-                }
-            }
-        } else {
-            result.push_str(line);
+        result.push_str(line);
+        result.push('\n');
+        if let Some(context) = error_line_number(line).and_then(|n| source_context(code, n)) {
+            result.push_str(&context);
             result.push('\n');
         }
     }
-    
     result
 }
 
-fn extract_line_number(text: &str) -> Option<&str> {
-    // Look for patterns like "Line 5" or "line 12"
-    let text_lower = text.to_lowercase();
-    if let Some(pos) = text_lower.find("line") {
-        let after_line = &text[pos + 4..];
-        let trimmed = after_line.trim_start();
-        let num_end = trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(trimmed.len());
-        if num_end > 0 {
-            return Some(&trimmed[..num_end]);
-        }
+/// The line number in a FORM diagnostic: `stdin Line 3 --> ...`,
+/// `calc.frm Line 12 ==> ...`, `Line 7: ...` and the like
+pub fn error_line_number(text: &str) -> Option<usize> {
+    use regex::Regex;
+    use std::sync::LazyLock;
+    
+    static LINE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\bline\s+(\d+)\b").unwrap());
+    LINE_RE.captures(text)?[1].parse().ok().filter(|&n| n > 0)
+}
+
+/// Line `line` of `code` with one line either side, numbered, the
+/// offending one marked `-->`; `None` if `code` has no such line
+pub fn source_context(code: &str, line: usize) -> Option<String> {
+    let lines: Vec<&str> = code.lines().collect();
+    if line == 0 || line > lines.len() {
+        return None;
     }
-    None
+    let first = line.saturating_sub(1).max(1);
+    let last = (line + 1).min(lines.len());
+    let width = last.to_string().len();
+    let quoted: Vec<String> = (first..=last)
+        .map(|n| {
+            let marker = if n == line { "-->" } else { "   " };
+            format!("  {} {:>width$} | {}", marker, n, lines[n - 1], width = width)
+        })
+        .collect();
+    Some(quoted.join("\n"))
+}
+
+/// A stand-in FORM executable, `form`, running the shell `script` in a
+/// temporary directory of its own named after `name`. Tests remove the
+/// directory, `exe.parent()`, when done.
+#[cfg(all(test, unix))]
+pub(crate) fn mock_form(name: &str, script: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("form-repl-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let exe = dir.join("form");
+    std::fs::write(&exe, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+    exe
 }
 
+/// A `mock_form` script that prints a banner and, indented like a result,
+/// the text of each `#write "..."` line of its input
+#[cfg(all(test, unix))]
+pub(crate) const WRITE_FORM: &str = "echo 'FORM 4.3.1 (fake)'\necho\nsed -n 's/^#write \"\\(.*\\)\"/   \\1/p'";
+
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_forward_stream_passes_lines_as_they_arrive() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::Duration;
+
+        let (tx, rx) = mpsc::channel();
+        let done = Arc::new(AtomicBool::new(false));
+        let sender_done = Arc::clone(&done);
+        let sender = thread::spawn(move || {
+            for line in ["   E =", "      x^2;"] {
+                tx.send(line.to_string()).unwrap();
+                thread::sleep(Duration::from_millis(30));
+            }
+            tx.send(STREAM_END.to_string()).unwrap();
+            // Anything after the sentinel belongs to no cell
+            tx.send("stray".to_string()).unwrap();
+            sender_done.store(true, Ordering::SeqCst);
+        });
+
+        let mut seen = Vec::new();
+        let count = forward_stream(&rx, |line| {
+            if seen.is_empty() {
+                assert!(!done.load(Ordering::SeqCst), "first line arrived only at the end");
+            }
+            seen.push(line.to_string());
+        });
+        sender.join().unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(seen, ["   E =", "      x^2;"]);
+    }
+
+    #[test]
+    fn test_measure_complexity() {
+        let size = measure_complexity("   E =\n      x^2 + 2*x*y - y^-2;");
+        assert_eq!(size, ExprSize { terms: 3, depth: 0, symbols: 2, nodes: 7 });
+        assert_eq!(size.to_string(), "3 terms, depth 0, 2 symbols, 7 nodes");
+
+        // Terms inside brackets don't count at the top level
+        let size = measure_complexity("   F = - f(x + y, z)*(1 + (a - b));");
+        assert_eq!((size.terms, size.depth, size.symbols), (1, 2, 6));
+        assert_eq!(measure_complexity("   G = 0;"), ExprSize::default());
+    }
+    
+    #[test]
+    fn test_count_modules() {
+        assert_eq!(count_modules(""), 0);
+        assert_eq!(count_modules("Symbol x;\nLocal E = x;\nPrint;"), 1);
+        assert_eq!(count_modules("Symbol x;\nLocal E = x;\nPrint;\n.end"), 1);
+        let multi = "Symbol x;\nLocal E = (x+1)^2;\n.sort\nid x = 2;\n.sort:second;\nPrint;\n.end";
+        assert_eq!(count_modules(multi), 3);
+        assert_eq!(count_modules("Local E = 1;\n.store\n* just a comment\n"), 1);
+    }
+    
+    
+    #[test]
+    fn test_clear_warning() {
+        assert!(clear_warning("Symbol x;\n.sort\nPrint;").is_none());
+        let warning = clear_warning("Symbol x;\nLocal E = x;\n.sort\nPrint;\n.clear\nSymbol y;").unwrap();
+        assert!(warning.contains("line 5"));
+        assert!(warning.contains("2 module(s)"));
+    }
+    
     
     #[test]
     fn test_validate_balanced_parens() {
@@ -359,18 +1027,251 @@ mod tests {
         assert!(validate_input("id f(x)) = g(x);").is_err());
     }
     
+    
+    #[test]
+    fn test_parse_form_error_quotes_source_line() {
+        let code = "Symbol x;\nLocal E = x + y;\nid x = 1;\nPrint;";
+        for stderr in [
+            "stdin Line 2 --> Undeclared variable y",
+            "calc.frm Line 2 ==> Undeclared variable y",
+            "Line 2: Undeclared variable y",
+        ] {
+            assert_eq!(error_line_number(stderr), Some(2), "{}", stderr);
+            assert_eq!(
+                parse_form_error(stderr, code),
+                format!(
+                    "{}\n      1 | Symbol x;\n  --> 2 | Local E = x + y;\n      3 | id x = 1;\n",
+                    stderr
+                )
+            );
+        }
+
+        // First and last lines have a neighbour on one side only
+        assert_eq!(source_context(code, 1).unwrap().lines().count(), 2);
+        assert_eq!(source_context(code, 4).unwrap(), "      3 | id x = 1;\n  --> 4 | Print;");
+        // Out of range, or no line number at all: the message as is
+        assert_eq!(parse_form_error("stdin Line 9 --> oops", code), "stdin Line 9 --> oops\n");
+        assert_eq!(parse_form_error("Program terminated", code), "Program terminated\n");
+        assert_eq!(error_line_number("Multiline output"), None);
+    }
+    
+    #[test]
+    fn test_validate_input_strict() {
+        let code = "Symbols x, y;\nLocal E = x + z;\nid y = abs_(w);\nid x = sin(y);";
+        let warnings = validate_input_strict(code, &HashSet::new());
+        let shown: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            shown,
+            [
+                "line 2: error: 'z' is used before it is declared",
+                "line 3: error: 'w' is used before it is declared",
+            ]
+        );
+
+        // Declared in an earlier cell
+        let declared: HashSet<String> = ["z", "w"].iter().map(|s| s.to_string()).collect();
+        assert!(validate_input_strict(code, &declared).is_empty());
+    }
+    
+    
     #[test]
     fn test_validate_brackets() {
         assert!(validate_input("id f[x] = 1;").is_ok());
         assert!(validate_input("id f[x = 1;").is_err());
     }
     
+    
+    #[test]
+    fn test_is_input_complete() {
+        assert!(is_input_complete("Symbol x;\nLocal E = (x+1)^2;"));
+        assert!(!is_input_complete("Local E = (x+1"));
+        assert!(!is_input_complete("Local E = (x+1)^2"));
+        assert!(!is_input_complete("Local E = f(x;"));
+        assert!(is_input_complete("Print;\n* trailing comment"));
+        assert!(!is_input_complete(""));
+    }
+    
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("FORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits"),
+            Some("4.3.1".to_string())
+        );
+        assert_eq!(parse_version("TFORM 4.2.0 (Jul  6 2017) 64-bits"), Some("4.2.0".to_string()));
+        assert_eq!(parse_version("no version here"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_detect_version_from_binary() {
+        // Fake FORM that only answers --version
+        let exe = mock_form(
+            "version-test",
+            "[ \"$1\" = --version ] && echo 'FORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits'\nexit 0",
+        );
+
+        let version = detect_version(&exe);
+        assert_eq!(version.as_deref(), Some("4.3.1"));
+        assert_eq!(
+            form_info(&exe, version.as_deref()),
+            format!("Using FORM 4.3.1 at {}", exe.display())
+        );
+        assert!(form_info(&exe, None).contains("(unknown version)"));
+        std::fs::remove_dir_all(exe.parent().unwrap()).ok();
+    }
+    
+    
+    /// `On names;` after declaring a few names, echoed input included
+    const NAMES_LISTING: &str = "\
+FORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits         Run: Thu Oct 16 10:00:00 2026
+    Symbols x, y(-2:4), z(:3);
+    Indices mu;
+    Vectors p;
+    CFunctions f;
+    On names;
+    .end
+ Symbols
+ i_ pi_ coeff_ num_ den_ xarg_ dimension_ factor_ sep_ x y(-2:4) z(:3)
+ Indices
+ iarg_ mu=4
+ Vectors
+ parg_ p
+ Commuting Functions
+ exp_ denom_ f
+
+  0.00 sec out of 0.00 sec
+";
+
+    #[test]
+    fn test_parse_form_symbols_output() {
+        let symbols = parse_form_symbols_output(NAMES_LISTING);
+        let summary: Vec<(&str, SymbolKind, Option<i32>, Option<i32>)> = symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind, s.min_power, s.max_power))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("x", SymbolKind::Symbol, None, None),
+                ("y", SymbolKind::Symbol, Some(-2), Some(4)),
+                ("z", SymbolKind::Symbol, None, Some(3)),
+                ("mu", SymbolKind::Index, None, None),
+                ("p", SymbolKind::Vector, None, None),
+                ("f", SymbolKind::Function, None, None),
+            ]
+        );
+        assert_eq!(symbols[1].to_string(), "Symbol    y  powers -2..4");
+        assert_eq!(symbols[5].to_string(), "Function  f");
+        assert!(parse_form_symbols_output("   E = x;").is_empty());
+    }
+
+    #[test]
+    fn test_names_listing_program() {
+        let program = names_listing_program(["Symbols x,\n  y;\nLocal E = x;", "CFunction f;\nPrint;"]);
+        assert_eq!(program, "Symbols x,\n  y;\nCFunction f;\nOn names;");
+    }
+
+    #[test]
+    fn test_capabilities_of_form_3_and_4() {
+        let form3 = FormCapabilities::parse("FORM 3.3 (Nov 10 2010) 64-bits\n");
+        assert_eq!(
+            form3,
+            FormCapabilities {
+                version: "3.3".to_string(),
+                supports_tform: false,
+                max_workers: 1,
+                supports_pipe: true,
+                supports_statistics: false,
+            }
+        );
+        assert!(!FormCapabilities::parse("FORM 3.1 (Mar 13 2007) 32-bits").supports_pipe);
+
+        let tform4 = FormCapabilities::parse("TFORM 4.2.1 (Feb 11 2019, v4.2.1) 64-bits 8 workers");
+        assert_eq!(tform4.version, "4.2.1");
+        assert!(tform4.supports_tform && tform4.supports_pipe && tform4.supports_statistics);
+        assert_eq!(tform4.max_workers, 8);
+
+        let form4 = FormCapabilities::parse("FORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits");
+        assert!(!form4.supports_tform && form4.supports_statistics);
+        assert_eq!(form4.max_workers, 1);
+
+        let unknown = FormCapabilities::parse("no banner");
+        assert_eq!(unknown.version, "unknown");
+        assert!(!unknown.supports_pipe && !unknown.supports_statistics);
+    }
+
+    #[test]
+    fn test_capabilities_filter_args() {
+        let args: Vec<String> = ["-w4", "-q", "-w", "2", "-t", "/scratch"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let form3 = FormCapabilities::parse("FORM 3.3 (Nov 10 2010) 64-bits");
+        let (kept, dropped) = form3.filter_args(&args);
+        assert_eq!(kept, ["-t", "/scratch"]);
+        assert_eq!(dropped, ["-w4", "-q", "-w", "2"]);
+        let tform4 = FormCapabilities::parse("TFORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits 4 workers");
+        assert_eq!(tform4.filter_args(&args), (args.clone(), Vec::new()));
+        assert!(tform4.to_string().contains("yes (4 workers)"));
+        // An unreadable banner says nothing against the user's flags
+        let unknown = FormCapabilities::parse("");
+        assert!(!unknown.is_known());
+        assert_eq!(unknown.filter_args(&args), (args.clone(), Vec::new()));
+    }
+
+    #[test]
+    fn test_form_env_report_assembly() {
+        let options = RunOptions {
+            extra_args: vec!["-w4".to_string(), "-s".to_string(), "my.set".to_string()],
+            timeout: None,
+            workdir: Some(PathBuf::from("/scratch")),
+            ..RunOptions::default()
+        };
+        let report = FormEnvReport::assemble(
+            Some(Path::new("/opt/form/bin/tform")),
+            Some("4.3.1".to_string()),
+            &options,
+            Path::new("/home/user"),
+            |name| (name == "FORMPATH").then(|| "/opt/form/lib".to_string()),
+        );
+        
+        assert_eq!(report.backend, "TFORM (multi-threaded)");
+        assert_eq!(report.workdir, PathBuf::from("/scratch"));
+        assert_eq!(report.setup_file, Some(PathBuf::from("my.set")));
+        assert!(report.env_vars.contains(&("FORMPATH", Some("/opt/form/lib".to_string()))));
+        assert!(report.env_vars.contains(&("FORMTMP", None)));
+        
+        let text = report.to_string();
+        assert!(text.contains("Version:      4.3.1"));
+        assert!(text.contains("-w4 -s my.set"));
+    }
+    
+    
+    #[test]
+    fn test_expression_to_latex() {
+        assert_eq!(expression_to_latex("x^2 + 2*x + 1"), "x^{2} + 2 x + 1");
+        assert_eq!(expression_to_latex("- 1/2*x^-1"), "-\\frac{1}{2} x^{-1}");
+        assert_eq!(expression_to_latex("x^(n+1)*y"), "x^{n + 1} y");
+        assert_eq!(expression_to_latex("2*3*a"), "2 \\cdot 3 a");
+        assert_eq!(expression_to_latex("f(x,y^2)*(a+b)^2"), "f(x, y^{2}) \\left(a + b\\right)^{2}");
+        assert_eq!(expression_to_latex("x +* y"), "x +* y");
+    }
+    
+    #[test]
+    fn test_latex_keeps_big_integers_exact() {
+        // 2^60 + 1 and a value above i64::MAX must come through digit for digit
+        assert_eq!(
+            expression_to_latex("1152921504606846977*x - 92233720368547758079/2"),
+            "1152921504606846977 x - \\frac{92233720368547758079}{2}"
+        );
+    }
+    
     #[test]
-    fn test_format_output() {
-        let output = "FORM 4.3\n\n   E =\n      x^2;\n\n  0.00 sec out of 0.00 sec\n";
-        let formatted = format_output(output, false);
-        assert!(formatted.contains("E ="));
-        assert!(!formatted.contains("FORM"));
-        assert!(!formatted.contains("sec out of"));
+    fn test_format_output_latex() {
+        let output = "FORM 4.3\n\n   E =\n      + x^2\n      + 2*x\n      + 1;\n\n   F = 1/3;\n\n  0.00 sec out of 0.00 sec\n";
+        assert_eq!(
+            format_output_latex(output),
+            "\\begin{align}\nE &= x^{2} + 2 x + 1 \\\\\nF &= \\frac{1}{3}\n\\end{align}"
+        );
     }
 }
@@ -0,0 +1,271 @@
+// Textual pretty-printer for FORM code (%format, --format-input)
+use super::highlight::{self, LineState, Token, TokenType};
+
+/// Lines longer than this are wrapped at operator boundaries
+const MAX_WIDTH: usize = 80;
+
+/// One level of block indentation
+const INDENT: &str = "  ";
+
+/// How a statement affects the indentation of the lines after it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Block {
+    Open,
+    Middle,
+    Close,
+    None,
+}
+
+/// Reformats FORM code without changing what it means.
+///
+/// Statement keywords are lowercased (names are case-sensitive in FORM, so
+/// nothing else is), the bodies of `if`/`while`/`repeat`/`#procedure` and
+/// similar blocks are indented by two spaces per level, `=` gets a space on
+/// each side, and lines longer than 80 columns are wrapped before a `+`/`-`
+/// or after a `,`. No `;` is ever added: a line without one continues the
+/// statement on the next line and is indented as such. Comment lines are
+/// kept as they are, since FORM only sees a `*` in the first column.
+pub fn pretty_print_form(code: &str) -> String {
+    let mut out = Vec::new();
+    let mut level = 0usize;
+    let mut state = LineState::default();
+    // Inside a statement that started on an earlier line
+    let mut continuing = false;
+
+    for line in code.lines() {
+        if state.in_block_comment || line.trim_start().starts_with('*') {
+            highlight::tokenize_continued(line, &mut state);
+            out.push(line.trim_end().to_string());
+            continue;
+        }
+        if line.trim().is_empty() {
+            out.push(String::new());
+            continue;
+        }
+
+        let mut tokens = normalize(highlight::tokenize_continued(line.trim(), &mut state));
+        let first = tokens[0].text.clone();
+        let is_instruction = first.starts_with('#') || first.starts_with('.');
+        let block = if continuing { Block::None } else { block_kind(&tokens) };
+        if !continuing && is_statement_word(&tokens[0]) {
+            tokens[0].text = first.to_lowercase();
+        } else if first == "." && tokens.len() > 1 {
+            // A module instruction the tokenizer didn't recognise, e.g. `.Sort`
+            tokens[1].text = tokens[1].text.to_lowercase();
+        }
+
+        if matches!(block, Block::Middle | Block::Close) {
+            level = level.saturating_sub(1);
+        }
+        let indent = INDENT.repeat(level + usize::from(continuing));
+        if first.starts_with('#') {
+            // Preprocessor instructions must stay on one line
+            out.push(format!("{}{}", indent, join(&tokens)));
+        } else {
+            out.extend(wrap(&tokens, &indent, &format!("{}{}", indent, INDENT)));
+        }
+        if matches!(block, Block::Open | Block::Middle) {
+            level += 1;
+        }
+
+        continuing = !is_instruction && !join(&tokens).ends_with(';');
+    }
+    out.join("\n")
+}
+
+/// Trims the line and puts exactly one space on each side of `=`
+fn normalize(tokens: Vec<Token>) -> Vec<Token> {
+    let mut out: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut after_equals = false;
+    for token in tokens {
+        let is_space = token.token_type == TokenType::Whitespace;
+        if token.token_type == TokenType::Operator && token.text == "=" {
+            while out.last().is_some_and(|t| t.token_type == TokenType::Whitespace) {
+                out.pop();
+            }
+            if !out.is_empty() {
+                out.push(space());
+            }
+            out.push(token);
+            out.push(space());
+            after_equals = true;
+        } else if !(is_space && after_equals) {
+            out.push(token);
+            after_equals = false;
+        }
+    }
+    while out.last().is_some_and(|t| t.token_type == TokenType::Whitespace) {
+        out.pop();
+    }
+    out
+}
+
+fn space() -> Token {
+    Token { token_type: TokenType::Whitespace, text: " ".to_string() }
+}
+
+fn join(tokens: &[Token]) -> String {
+    tokens.iter().map(|t| t.text.as_str()).collect()
+}
+
+/// A word that starts a statement and can be safely lowercased
+fn is_statement_word(token: &Token) -> bool {
+    matches!(
+        token.token_type,
+        TokenType::Keyword | TokenType::Declaration | TokenType::Preprocessor
+    )
+}
+
+fn bracket_balance(token: &Token) -> i32 {
+    if highlight::is_opening_bracket(token) {
+        1
+    } else if highlight::is_closing_bracket(token) {
+        -1
+    } else {
+        0
+    }
+}
+
+/// Classifies a statement as opening, continuing or closing a block
+fn block_kind(tokens: &[Token]) -> Block {
+    let word = tokens[0].text.to_lowercase();
+    match word.as_str() {
+        "#procedure" | "#do" | "#if" | "#ifdef" | "#ifndef" | "#switch" => Block::Open,
+        "#else" | "#elseif" => Block::Middle,
+        "#endprocedure" | "#enddo" | "#endif" | "#endswitch" => Block::Close,
+        // Without a trailing `;` after the condition these are one-liners,
+        // as in `if (count(x,1) > 2) discard;`
+        "if" | "while" if condition_ends_statement(&tokens[1..]) => Block::Open,
+        "elseif" if condition_ends_statement(&tokens[1..]) => Block::Middle,
+        "else" => Block::Middle,
+        "repeat" if is_bare(&tokens[1..]) => Block::Open,
+        "do" | "argument" | "inside" | "term" | "switch" => Block::Open,
+        "endif" | "endwhile" | "endrepeat" | "enddo" | "endargument" | "endinside"
+        | "endterm" | "endswitch" => Block::Close,
+        _ => Block::None,
+    }
+}
+
+/// Whether `( condition )` is followed by nothing but an optional `;`
+fn condition_ends_statement(rest: &[Token]) -> bool {
+    let mut depth = 0;
+    for (i, token) in rest.iter().enumerate() {
+        depth += bracket_balance(token);
+        if depth == 0 && token.text == ")" {
+            return is_bare(&rest[i + 1..]);
+        }
+    }
+    // The condition goes on past this line
+    depth > 0
+}
+
+fn is_bare(rest: &[Token]) -> bool {
+    rest.iter()
+        .all(|t| t.token_type == TokenType::Whitespace || t.text == ";")
+}
+
+/// Splits a long line before a `+`/`-` or after a `,`, indenting the
+/// continuation lines by `cont_indent`
+fn wrap(tokens: &[Token], indent: &str, cont_indent: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = indent.to_string();
+    // Byte offset in `current` where it may be split
+    let mut split_at: Option<usize> = None;
+
+    for token in tokens {
+        let is_sign = token.token_type == TokenType::Operator && matches!(token.text.as_str(), "+" | "-");
+        if is_sign && !current.trim_start().is_empty() {
+            split_at = Some(current.len());
+        }
+        current.push_str(&token.text);
+        if token.token_type == TokenType::Operator && token.text == "," {
+            split_at = Some(current.len());
+        }
+        if current.chars().count() > MAX_WIDTH {
+            if let Some(at) = split_at.take() {
+                let rest = current[at..].trim_start().to_string();
+                lines.push(current[..at].trim_end().to_string());
+                current = format!("{}{}", cont_indent, rest);
+            }
+        }
+    }
+    lines.push(current);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pretty_print_blocks() {
+        let code = "\
+Symbols X, y;
+Local E=(X+y)^2;
+* a comment stays in column 1
+Repeat;
+id X^2=y;
+If (count(y,1) > 2);
+Discard;
+Else;
+id y = X;
+EndIf;
+EndRepeat;
+if ( count(y,1) > 4 ) discard;
+#Do i=1,2
+Print;
+#EndDo
+.Sort";
+        let expected = "\
+symbols X, y;
+local E = (X+y)^2;
+* a comment stays in column 1
+repeat;
+  id X^2 = y;
+  if (count(y,1) > 2);
+    discard;
+  else;
+    id y = X;
+  endif;
+endrepeat;
+if ( count(y,1) > 4 ) discard;
+#do i = 1,2
+  print;
+#enddo
+.sort";
+        assert_eq!(pretty_print_form(code), expected);
+        // Formatting is stable
+        assert_eq!(pretty_print_form(expected), expected);
+    }
+
+    #[test]
+    fn test_pretty_print_keeps_continued_statements() {
+        let code = "Local E = x +\n   y;\nid Sum = 1;";
+        // `Sum` is a name here, not the sum keyword of a statement
+        assert_eq!(pretty_print_form(code), "local E = x +\n  y;\nid Sum = 1;");
+    }
+
+    #[test]
+    fn test_pretty_print_never_adds_terminators() {
+        // A statement carried on by a leading operator is not cut short
+        let code = "Local E = x\n  + y;\nLocal F = 1\n + x;";
+        assert_eq!(pretty_print_form(code), "local E = x\n  + y;\nlocal F = 1\n  + x;");
+        // A missing `;` stays missing
+        assert_eq!(pretty_print_form("Print"), "print");
+    }
+
+    #[test]
+    fn test_pretty_print_wraps_long_lines() {
+        let terms: Vec<String> = (1..=12).map(|i| format!("a{}*x^{}", i, i)).collect();
+        let code = format!("Local E = {};", terms.join(" + "));
+        let formatted = pretty_print_form(&code);
+        let lines: Vec<&str> = formatted.lines().collect();
+        assert!(lines.len() > 1, "{}", formatted);
+        assert!(lines.iter().all(|l| l.chars().count() <= MAX_WIDTH), "{}", formatted);
+        // Each continuation line starts with the operator it was split before
+        assert!(lines[1..].iter().all(|l| l.starts_with("  + ")), "{}", formatted);
+        let rejoined: String = formatted.split_whitespace().collect();
+        let original: String = code.replacen("Local", "local", 1).split_whitespace().collect();
+        assert_eq!(rejoined, original);
+    }
+}
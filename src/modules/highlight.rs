@@ -1,238 +1,18 @@
 // Syntax highlighting for FORM language
-use regex::Regex;
-use std::sync::LazyLock;
-
 use super::theme::Theme;
 
-/// FORM language token types for syntax highlighting
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum TokenType {
-    Keyword,
-    Declaration,
-    Function,
-    Preprocessor,
-    Number,
-    Operator,
-    Comment,
-    String,
-    Identifier,
-    Punctuation,
-    Whitespace,
-}
-
-/// A token with its type and text content
-#[derive(Debug, Clone)]
-pub struct Token {
-    pub token_type: TokenType,
-    pub text: String,
-}
-
-/// Keywords that should be highlighted
-const KEYWORDS: &[&str] = &[
-    "if", "else", "elseif", "endif", "while", "endwhile", "repeat", "endrepeat",
-    "do", "enddo", "goto", "label", "exit", "break", "continue", "return",
-    "procedure", "endprocedure", "call", "argument", "endargument",
-    "switch", "case", "default", "endswitch", "inside", "endinside",
-    "term", "endterm", "sort", "endsort", "multiply", "also", "once", "only",
-    "multi", "all", "first", "last", "disorder", "antisymmetrize", "symmetrize",
-    "cyclesymmetrize", "rcyclesymmetrize", "identify", "idnew", "idold",
-    "chainout", "chainin", "splitarg", "splitfirstarg", "splitlastarg",
-    "factarg", "normalize", "makeinteger", "torat", "topolynomial",
-    "frompolynomial", "argtoextrasymbol", "dropcoefficient", "dropextrasymbols",
-    "polyratfun", "ratfun", "keep", "drop", "hide", "unhide", "skip", "nskip",
-    "moduleoption", "on", "off", "format", "write", "redefine", "renumber",
-    "contract", "trace4", "tracen", "chisholm", "unittrace", "delete", "discard",
-    "print", "nprint", "collect", "bracket", "antibracket", "putinside",
-    "polyfun", "sum", "id", "fill", "fillexpression", "table", "ctable",
-    "tablebase", "testuse", "apply", "transform", "replace", "replaceloop",
-    "totensor", "tovector", "fromtensor", "metric", "dimension", "load", "save",
-    "copyspecs", "setexitflag", "nwrite", "threadbucketsize", "processbucketsize",
-];
-
-/// Declaration keywords
-const DECLARATIONS: &[&str] = &[
-    "symbol", "symbols", "index", "indices", "vector", "vectors",
-    "tensor", "tensors", "ntensor", "ntensors", "function", "functions",
-    "cfunction", "cfunctions", "ctensor", "ctensors", "nfunction", "nfunctions",
-    "ncfunction", "ncfunctions", "table", "tables", "ctable", "ctables",
-    "set", "local", "global", "auto", "autodeclare", "dimension",
-    "fixindex", "unfixindex", "extrasymbol", "extrasymbol", "commuting",
-    "noncommuting",
-];
-
-/// Built-in functions (without the trailing parenthesis check for simplicity)
-const FUNCTIONS: &[&str] = &[
-    "abs", "sign", "min", "max", "mod", "div", "gcd", "fac", "binom",
-    "bernoulli", "sqrt", "sin", "cos", "tan", "asin", "acos", "atan",
-    "atan2", "sinh", "cosh", "tanh", "asinh", "acosh", "atanh", "exp",
-    "ln", "log", "log10", "li2", "li3", "nielsen", "hpl", "mzv", "zeta",
-    "gamma", "polygamma", "psi", "digamma", "theta", "delta_", "d_", "e_",
-    "i_", "f_", "g_", "gi_", "dd_", "conjg_", "deno", "farg", "nargs",
-    "firstarg", "lastarg", "numterms", "termsin", "maxpow", "minpow",
-    "exponent", "coeff", "content", "integer_", "symbol_", "index_",
-    "vector_", "fixed_", "match", "count", "occurs", "multipleof", "prime",
-    "random_", "tbl_", "term_", "expression_", "dummyindices", "extrasymbol_",
-    "getdummies", "nterms", "sump_", "sum_", "prod_", "inv_", "root_",
-    "replace_", "setfun", "putfirst", "addargs", "mulargs", "permute",
-    "reverse", "delta", "epsilon", "distrib_", "sig_", "factorin_", "gcd_",
-    "div_", "rem_", "inverse_", "makerational", "rat", "num_", "den_",
-    "derive", "accum", "pcount_", "firstbracket_", "table_", "defined_",
-    "termsinbracket_", "maxpower_", "minpower_", "ranperm_", "exists_",
-    "pattern_", "setspec_", "exec_", "partitions_", "compargs_",
-    "commutearg_", "sortarg_", "dedup_",
-];
-
-/// Compiled regex patterns for FORM syntax (without lookahead)
-struct FormPatterns {
-    preprocessor: Regex,
-    number: Regex,
-    operator: Regex,
-    string: Regex,
-    identifier: Regex,
-}
-
-// Lazily compiled regex patterns
-static PATTERNS: LazyLock<FormPatterns> = LazyLock::new(|| FormPatterns {
-    preprocessor: Regex::new(r"^(#[a-zA-Z]+|\.end|\.sort|\.store|\.global|\.clear)").unwrap(),
-    number: Regex::new(r"^-?[0-9]+\.?[0-9]*([eE][+-]?[0-9]+)?").unwrap(),
-    operator: Regex::new(r"^(==|!=|<=|>=|<>|<|>|&&|\|\||[+\-*/^?=,;:])").unwrap(),
-    string: Regex::new(r#"^"([^"\\]|\\.)*""#).unwrap(),
-    identifier: Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*").unwrap(),
-});
-
-/// Check if an identifier is a keyword (case-insensitive)
-fn is_keyword(word: &str) -> bool {
-    let lower = word.to_lowercase();
-    KEYWORDS.contains(&lower.as_str())
-}
-
-/// Check if an identifier is a declaration keyword (case-insensitive)
-fn is_declaration(word: &str) -> bool {
-    let lower = word.to_lowercase();
-    DECLARATIONS.contains(&lower.as_str())
-}
-
-/// Check if an identifier is a built-in function (case-insensitive)
-fn is_function(word: &str) -> bool {
-    let lower = word.to_lowercase();
-    FUNCTIONS.contains(&lower.as_str())
-}
-
-/// Tokenize a line of FORM code
-pub fn tokenize(line: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut remaining = line;
-    
-    // Check for comment (FORM comments start with * at the beginning of a line)
-    let trimmed = remaining.trim_start();
-    if trimmed.starts_with('*') {
-        tokens.push(Token {
-            token_type: TokenType::Comment,
-            text: line.to_string(),
-        });
-        return tokens;
-    }
-    
-    while !remaining.is_empty() {
-        // Skip whitespace but preserve it
-        if remaining.starts_with(|c: char| c.is_whitespace()) {
-            let ws_len = remaining
-                .chars()
-                .take_while(|c| c.is_whitespace())
-                .count();
-            let ws: String = remaining.chars().take(ws_len).collect();
-            let byte_len: usize = ws.len();
-            tokens.push(Token {
-                token_type: TokenType::Whitespace,
-                text: ws,
-            });
-            remaining = &remaining[byte_len..];
-            continue;
-        }
-        
-        // Check for string literal
-        if let Some(m) = PATTERNS.string.find(remaining) {
-            tokens.push(Token {
-                token_type: TokenType::String,
-                text: m.as_str().to_string(),
-            });
-            remaining = &remaining[m.end()..];
-            continue;
-        }
-        
-        // Check for preprocessor directives
-        if let Some(m) = PATTERNS.preprocessor.find(remaining) {
-            tokens.push(Token {
-                token_type: TokenType::Preprocessor,
-                text: m.as_str().to_string(),
-            });
-            remaining = &remaining[m.end()..];
-            continue;
-        }
-        
-        // Check for identifiers (then classify as keyword/declaration/function/identifier)
-        if let Some(m) = PATTERNS.identifier.find(remaining) {
-            let word = m.as_str();
-            let token_type = if is_declaration(word) {
-                TokenType::Declaration
-            } else if is_keyword(word) {
-                TokenType::Keyword
-            } else if is_function(word) {
-                // Check if followed by '(' to confirm it's a function call
-                let after = &remaining[m.end()..];
-                if after.trim_start().starts_with('(') {
-                    TokenType::Function
-                } else {
-                    TokenType::Identifier
-                }
-            } else {
-                TokenType::Identifier
-            };
-            
-            tokens.push(Token {
-                token_type,
-                text: word.to_string(),
-            });
-            remaining = &remaining[m.end()..];
-            continue;
-        }
-        
-        // Check for numbers
-        if let Some(m) = PATTERNS.number.find(remaining) {
-            tokens.push(Token {
-                token_type: TokenType::Number,
-                text: m.as_str().to_string(),
-            });
-            remaining = &remaining[m.end()..];
-            continue;
-        }
-        
-        // Check for operators
-        if let Some(m) = PATTERNS.operator.find(remaining) {
-            tokens.push(Token {
-                token_type: TokenType::Operator,
-                text: m.as_str().to_string(),
-            });
-            remaining = &remaining[m.end()..];
-            continue;
-        }
-        
-        // Punctuation and other characters
-        if let Some(c) = remaining.chars().next() {
-            tokens.push(Token {
-                token_type: TokenType::Punctuation,
-                text: c.to_string(),
-            });
-            remaining = &remaining[c.len_utf8()..];
-        }
-    }
-    
-    tokens
-}
+pub use form_core::{
+    form_words, is_closing_bracket, is_function, is_opening_bracket, tokenize,
+    tokenize_continued, LineState, Token, TokenType,
+};
 
 /// Highlight a single line of FORM code
 pub fn highlight_line(line: &str, theme: &Theme) -> String {
-    let tokens = tokenize(line);
+    colorize(tokenize(line), theme)
+}
+
+/// Wrap each token in its theme color
+fn colorize(tokens: Vec<Token>, theme: &Theme) -> String {
     let reset = "\x1b[0m";
     
     tokens
@@ -243,9 +23,12 @@ pub fn highlight_line(line: &str, theme: &Theme) -> String {
                 TokenType::Declaration => &theme.declaration,
                 TokenType::Function => &theme.function,
                 TokenType::Preprocessor => &theme.preprocessor,
+                TokenType::DollarVar => &theme.dollar_var,
+                TokenType::PreprocArg => &theme.preproc_arg,
                 TokenType::Number => &theme.number,
                 TokenType::Operator => &theme.operator,
-                TokenType::Comment => &theme.comment,
+                TokenType::SetOrIndex => &theme.set_or_index,
+                TokenType::Comment | TokenType::BlockComment => &theme.comment,
                 TokenType::String => &theme.string,
                 TokenType::Identifier => &theme.identifier,
                 TokenType::Punctuation | TokenType::Whitespace => "",
@@ -262,8 +45,9 @@ pub fn highlight_line(line: &str, theme: &Theme) -> String {
 
 /// Highlight multiple lines of FORM code
 pub fn highlight_code(code: &str, theme: &Theme) -> String {
+    let mut state = LineState::default();
     code.lines()
-        .map(|line| highlight_line(line, theme))
+        .map(|line| colorize(tokenize_continued(line, &mut state), theme))
         .collect::<Vec<_>>()
         .join("\n")
 }
@@ -300,33 +84,243 @@ pub fn highlight_output(output: &str, theme: &Theme) -> String {
     result.join("\n")
 }
 
+/// Kind of change for a token in a diff
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffOp {
+    Same,
+    Added,
+    Removed,
+}
+
+/// Tokenize multi-line text, keeping line breaks as whitespace tokens
+fn tokenize_lines(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+            tokens.push(Token {
+                token_type: TokenType::Whitespace,
+                text: "\n".to_string(),
+            });
+        }
+        tokens.extend(tokenize(line));
+    }
+    tokens
+}
+
+/// Past this many alignment table cells (changed tokens before × after),
+/// the changed part is shown as removed-then-added instead of aligned
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Tokens that don't count as a change; any whitespace matches any other
+fn same_token(x: &Token, y: &Token) -> bool {
+    x.text == y.text || (x.token_type == TokenType::Whitespace && y.token_type == TokenType::Whitespace)
+}
+
+/// Align the tokens of two texts and classify each as same, added or removed.
+///
+/// Whitespace is treated as interchangeable so that FORM re-wrapping a
+/// result doesn't show up as a change; removed whitespace is dropped. The
+/// common start and end are matched directly; the changed part between is
+/// aligned with a longest-common-subsequence table, unless that table
+/// would be too large (see `MAX_DIFF_CELLS`).
+pub fn diff_tokens(before: &str, after: &str) -> Vec<(DiffOp, String)> {
+    let a = tokenize_lines(before);
+    let b = tokenize_lines(after);
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| same_token(x, y)).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| same_token(x, y))
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops: Vec<(DiffOp, String)> = b[..prefix].iter().map(|t| (DiffOp::Same, t.text.clone())).collect();
+    if (a_mid.len() + 1).saturating_mul(b_mid.len() + 1) > MAX_DIFF_CELLS {
+        ops.extend(
+            a_mid
+                .iter()
+                .filter(|t| t.token_type != TokenType::Whitespace)
+                .map(|t| (DiffOp::Removed, t.text.clone())),
+        );
+        ops.extend(b_mid.iter().map(|t| (DiffOp::Added, t.text.clone())));
+    } else {
+        ops.extend(align_tokens(a_mid, b_mid));
+    }
+    ops.extend(b[b.len() - suffix..].iter().map(|t| (DiffOp::Same, t.text.clone())));
+    ops
+}
+
+/// Token-by-token alignment of `a` and `b` along a longest common subsequence
+fn align_tokens(a: &[Token], b: &[Token]) -> Vec<(DiffOp, String)> {
+    // lcs[i][j] = length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if same_token(&a[i], &b[j]) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && same_token(&a[i], &b[j]) {
+            ops.push((DiffOp::Same, b[j].text.clone()));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            if a[i].token_type != TokenType::Whitespace {
+                ops.push((DiffOp::Removed, a[i].text.clone()));
+            }
+            i += 1;
+        } else {
+            ops.push((DiffOp::Added, b[j].text.clone()));
+            j += 1;
+        }
+    }
+    
+    ops
+}
+
+/// Show `after` with changes relative to `before` colorized.
+///
+/// Added tokens use the theme's string color; removed tokens are shown
+/// inline in the error color with strikethrough.
+pub fn highlight_diff(before: &str, after: &str, theme: &Theme) -> String {
+    let reset = "\x1b[0m";
+    let strike = "\x1b[9m";
+    
+    diff_tokens(before, after)
+        .into_iter()
+        .map(|(op, text)| match op {
+            DiffOp::Same => text,
+            DiffOp::Added => format!("{}{}{}", theme.string, text, reset),
+            DiffOp::Removed => format!("{}{}{}{}", theme.error, strike, text, reset),
+        })
+        .collect()
+}
+
+/// Byte positions of the matching `()`, `[]` and `{}` pairs in `line`,
+/// as `(open, close)` ordered by where the pair starts.
+///
+/// Brackets in comments and strings don't count. A bracket without a
+/// partner gets `usize::MAX` for the missing side: `(open, usize::MAX)`
+/// for one never closed, `(usize::MAX, close)` for a stray closing one.
+pub fn bracket_match_positions(line: &str) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut open: Vec<(usize, char)> = Vec::new();
+    let mut pos = 0;
+    for token in tokenize(line) {
+        if is_opening_bracket(&token) {
+            open.push((pos, token.text.chars().next().unwrap_or('(')));
+        } else if is_closing_bracket(&token) {
+            let opener = match token.text.as_str() {
+                ")" => '(',
+                "]" => '[',
+                _ => '{',
+            };
+            match open.last() {
+                Some(&(start, ch)) if ch == opener => {
+                    open.pop();
+                    pairs.push((start, pos));
+                }
+                _ => pairs.push((usize::MAX, pos)),
+            }
+        }
+        pos += token.text.len();
+    }
+    pairs.extend(open.into_iter().map(|(start, _)| (start, usize::MAX)));
+    pairs.sort_by_key(|&(start, end)| if start == usize::MAX { end } else { start });
+    pairs
+}
+
+/// Byte position of the bracket matching the one at byte `pos` of `line`
+/// (None if there is no bracket at `pos` or it has no partner)
+pub fn find_matching_bracket(line: &str, pos: usize) -> Option<usize> {
+    bracket_match_positions(line)
+        .into_iter()
+        .find_map(|(start, end)| match (start == pos, end == pos) {
+            (true, _) => Some(end),
+            (_, true) => Some(start),
+            _ => None,
+        })
+        .filter(|&other| other != usize::MAX)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
-    fn test_tokenize_declaration() {
-        let tokens = tokenize("Symbol x,y;");
-        assert!(tokens.iter().any(|t| t.token_type == TokenType::Declaration && t.text == "Symbol"));
+    fn test_bracket_match_positions_nested() {
+        let line = "f(x,[a+{b}],g(y))";
+        assert_eq!(
+            bracket_match_positions(line),
+            vec![(1, 16), (4, 10), (7, 9), (13, 15)]
+        );
+        assert_eq!(find_matching_bracket(line, 1), Some(16));
+        assert_eq!(find_matching_bracket(line, 16), Some(1));
+        assert_eq!(find_matching_bracket(line, 9), Some(7));
+        // Not a bracket
+        assert_eq!(find_matching_bracket(line, 0), None);
+        assert_eq!(find_matching_bracket(line, 99), None);
+        // Brackets in comments and strings are ignored
+        assert!(bracket_match_positions("* f(x").is_empty());
+        assert_eq!(bracket_match_positions("#write \"(\" f(x)"), vec![(12, 14)]);
+    }
+
+    #[test]
+    fn test_bracket_match_positions_unmatched() {
+        assert_eq!(bracket_match_positions("f(x"), vec![(1, usize::MAX)]);
+        assert_eq!(bracket_match_positions("x)"), vec![(usize::MAX, 1)]);
+        // A closing bracket of the wrong kind doesn't close the open one
+        assert_eq!(bracket_match_positions("(x]"), vec![(0, usize::MAX), (usize::MAX, 2)]);
+        assert_eq!(bracket_match_positions("(a))"), vec![(0, 2), (usize::MAX, 3)]);
+        assert_eq!(find_matching_bracket("f(x", 1), None);
+        assert_eq!(find_matching_bracket("x)", 1), None);
     }
     
     #[test]
-    fn test_tokenize_keyword() {
-        let tokens = tokenize("id f(x) = g(x);");
-        assert!(tokens.iter().any(|t| t.token_type == TokenType::Keyword && t.text == "id"));
+    fn test_diff_tokens_single_change() {
+        let ops = diff_tokens("x^2 + 2*x", "x^2 + 3*x");
+        let changed: Vec<_> = ops.iter().filter(|(op, _)| *op != DiffOp::Same).collect();
+        assert_eq!(
+            changed,
+            vec![&(DiffOp::Removed, "2".to_string()), &(DiffOp::Added, "3".to_string())]
+        );
     }
     
     #[test]
-    fn test_tokenize_comment() {
-        let tokens = tokenize("* This is a comment");
-        assert_eq!(tokens.len(), 1);
-        assert_eq!(tokens[0].token_type, TokenType::Comment);
+    fn test_diff_tokens_large_inputs() {
+        // One change in a long result: only the changed token is aligned
+        let before: Vec<String> = (0..5000).map(|i| format!("x^{}", i)).collect();
+        let mut after = before.clone();
+        after[2500] = "y".to_string();
+        let ops = diff_tokens(&before.join(" + "), &after.join(" + "));
+        let changed: Vec<_> = ops.iter().filter(|(op, _)| *op != DiffOp::Same).map(|(_, t)| t.as_str()).collect();
+        assert_eq!(changed, ["x", "^", "2500", "y"]);
+        
+        // Nothing in common: too big to align, so all removed then all added
+        let before = vec!["a"; 3000].join(" + ");
+        let after = vec!["b"; 3000].join(" * ");
+        let ops = diff_tokens(&before, &after);
+        assert_eq!(ops.iter().filter(|(op, _)| *op == DiffOp::Removed).count(), 5999);
+        assert!(ops.iter().skip_while(|(op, _)| *op == DiffOp::Removed).all(|(op, _)| *op == DiffOp::Added));
     }
     
     #[test]
-    fn test_tokenize_number() {
-        let tokens = tokenize("x^10 + 2*y");
-        assert!(tokens.iter().any(|t| t.token_type == TokenType::Number && t.text == "10"));
-        assert!(tokens.iter().any(|t| t.token_type == TokenType::Number && t.text == "2"));
+    fn test_highlight_diff() {
+        let theme = Theme::default();
+        let diff = highlight_diff("x + y", "x + z", &theme);
+        assert!(diff.contains(&format!("{}z", theme.string)));
+        assert!(diff.contains(&format!("{}\x1b[9my", theme.error)));
+        assert!(diff.starts_with("x + "));
+        
+        // Re-wrapping alone is not a change
+        assert_eq!(highlight_diff("x +\n  y", "x + y", &theme), "x + y");
     }
 }
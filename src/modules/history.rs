@@ -0,0 +1,163 @@
+// History file backends - rustyline's plain text, or JSON with outputs and timings
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::magic::HistoryEntry;
+
+/// Header of rustyline's multi-line aware history files
+const PLAIN_V2_HEADER: &str = "#V2";
+
+/// One entry of a JSON history file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonHistoryEntry {
+    pub input: String,
+    pub output: Option<String>,
+    pub duration_ms: Option<u64>,
+}
+
+impl From<&HistoryEntry> for JsonHistoryEntry {
+    fn from(entry: &HistoryEntry) -> Self {
+        JsonHistoryEntry {
+            input: entry.input.clone(),
+            output: entry.output.clone(),
+            duration_ms: entry.duration.map(|d| d.as_millis() as u64),
+        }
+    }
+}
+
+/// Inputs of a plain (rustyline) history file.
+///
+/// Version 2 files escape newlines as `\n` and backslashes as `\\`;
+/// older files hold one single-line entry per line.
+pub fn parse_plain(content: &str) -> Vec<String> {
+    let mut lines = content.lines().peekable();
+    let v2 = lines.next_if(|line| *line == PLAIN_V2_HEADER).is_some();
+    lines
+        .filter(|line| !line.is_empty())
+        .map(|line| if v2 { unescape(line) } else { line.to_string() })
+        .collect()
+}
+
+fn unescape(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// History stored as a JSON array of `{input, output, duration_ms}`
+pub struct JsonHistoryBackend {
+    path: PathBuf,
+}
+
+impl JsonHistoryBackend {
+    pub fn new(path: &Path) -> Self {
+        JsonHistoryBackend {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Reads the history file.
+    ///
+    /// A missing file is an empty history. A file in the plain format is
+    /// migrated: its inputs are returned without outputs, and the next
+    /// `save` rewrites it as JSON. A JSON file that fails to parse is an
+    /// error, so it isn't overwritten.
+    pub fn load(&self) -> Result<Vec<JsonHistoryEntry>, String> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(_) => return Ok(Vec::new()),
+        };
+        if !content.trim_start().starts_with('[') {
+            let entries: Vec<JsonHistoryEntry> = parse_plain(&content)
+                .into_iter()
+                .map(|input| JsonHistoryEntry {
+                    input,
+                    output: None,
+                    duration_ms: None,
+                })
+                .collect();
+            log::info!(
+                "migrating {} plain history entries in {} to JSON",
+                entries.len(),
+                self.path.display()
+            );
+            return Ok(entries);
+        }
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Corrupt history file {}: {}", self.path.display(), e))
+    }
+
+    /// Writes the last `max_entries` entries, creating the directory if needed
+    pub fn save(&self, entries: &[JsonHistoryEntry], max_entries: usize) -> Result<(), String> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let start = entries.len().saturating_sub(max_entries);
+        let json = serde_json::to_string_pretty(&entries[start..]).map_err(|e| e.to_string())?;
+        fs::write(&self.path, json)
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_plain() {
+        assert_eq!(
+            parse_plain("#V2\nSymbol x;\\nLocal E = x;\n\na\\\\b\n"),
+            ["Symbol x;\nLocal E = x;", "a\\b"]
+        );
+        assert_eq!(parse_plain("Symbol x;\nid x = 1;\n"), ["Symbol x;", "id x = 1;"]);
+    }
+
+    #[test]
+    fn test_json_round_trip_and_migration() {
+        let dir = std::env::temp_dir().join(format!("form-repl-history-test-{}", std::process::id()));
+        let path = dir.join("history");
+        let backend = JsonHistoryBackend::new(&path);
+        assert!(backend.load().unwrap().is_empty());
+
+        // A plain file is read as inputs only
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "#V2\nSymbol x;\\nLocal E = x;\n").unwrap();
+        let mut entries = backend.load().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].input, "Symbol x;\nLocal E = x;");
+        assert_eq!(entries[0].output, None);
+
+        let cell = HistoryEntry {
+            number: 1,
+            input: "Local F = 2;".to_string(),
+            output: Some("   F =\n      2;".to_string()),
+            duration: Some(Duration::from_millis(12)),
+            loaded_with: None,
+        };
+        entries.push(JsonHistoryEntry::from(&cell));
+        backend.save(&entries, 1).unwrap();
+        let loaded = backend.load().unwrap();
+        assert_eq!(loaded, entries[1..]);
+        assert_eq!(loaded[0].duration_ms, Some(12));
+
+        fs::write(&path, "[{\"input\": 1}]").unwrap();
+        assert!(backend.load().unwrap_err().contains("Corrupt history file"));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
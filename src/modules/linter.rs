@@ -0,0 +1,491 @@
+// Linter for FORM code: style conventions and common mistakes
+use std::collections::HashSet;
+use std::fmt;
+
+use super::config::LinterConfig;
+use super::highlight::{tokenize_continued, LineState, Token, TokenType};
+
+/// Severity of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Warning => write!(f, "warning"),
+            Level::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Location of a diagnostic: 1-based line, byte columns `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A single problem reported by a rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintDiagnostic {
+    pub level: Level,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for LintDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}:{}: {}: {}",
+            self.span.line,
+            self.span.start + 1,
+            self.level,
+            self.message
+        )
+    }
+}
+
+/// A token together with where it sits in the source
+#[derive(Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// One `;`-terminated FORM statement, possibly spanning several lines
+#[derive(Debug, Clone)]
+pub struct Statement {
+    /// Tokens without whitespace and comments
+    pub tokens: Vec<SpannedToken>,
+    /// Whether the statement ended with `;`
+    pub terminated: bool,
+}
+
+impl Statement {
+    /// Lowercased text of the first token
+    fn head(&self) -> String {
+        self.tokens
+            .first()
+            .map(|t| t.token.text.to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Whether the statement declares names (Symbol, CFunction, ...)
+    fn is_declaration(&self) -> bool {
+        self.tokens
+            .first()
+            .is_some_and(|t| t.token.token_type == TokenType::Declaration)
+    }
+
+    /// Whether the statement defines an expression (Local/Global)
+    fn is_expression_definition(&self) -> bool {
+        matches!(self.head().as_str(), "local" | "global")
+    }
+}
+
+/// Splits FORM code into statements.
+///
+/// Comment lines, preprocessor lines (`#...`) and module instructions
+/// (`.sort`, `.end`, ...) are not statements and end any open statement.
+pub fn split_statements(code: &str) -> Vec<Statement> {
+    let mut statements = Vec::new();
+    let mut current: Vec<SpannedToken> = Vec::new();
+    let mut state = LineState::default();
+
+    let mut close = |current: &mut Vec<SpannedToken>, terminated: bool| {
+        if !current.is_empty() {
+            statements.push(Statement {
+                tokens: std::mem::take(current),
+                terminated,
+            });
+        }
+    };
+
+    for (idx, line) in code.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let tokens = tokenize_continued(line, &mut state);
+        if trimmed.starts_with('#') || (trimmed.starts_with('.') && !trimmed.starts_with(".."))
+        {
+            close(&mut current, false);
+            continue;
+        }
+
+        let mut col = 0;
+        for token in tokens {
+            let span = Span { line: idx + 1, start: col, end: col + token.text.len() };
+            col = span.end;
+            match token.token_type {
+                TokenType::Whitespace | TokenType::Comment | TokenType::BlockComment => {}
+                TokenType::Operator if token.text == ";" => close(&mut current, true),
+                _ => current.push(SpannedToken { token, span }),
+            }
+        }
+    }
+    close(&mut current, false);
+
+    statements
+}
+
+/// A single lint check
+pub trait LintRule {
+    /// Short identifier, matching the `[linter]` config key
+    fn name(&self) -> &'static str;
+    /// Inspect the statements and report problems
+    fn check(&self, statements: &[Statement]) -> Vec<LintDiagnostic>;
+}
+
+/// Built-in names end in `_` (`i_`, `d_`, `sum_`) and need no declaration
+fn is_builtin_name(name: &str) -> bool {
+    name.ends_with('_')
+}
+
+/// Names a declaration statement introduces, with the span of each
+fn declared_names(stmt: &Statement) -> Vec<&SpannedToken> {
+    let mut names = Vec::new();
+    if stmt.is_expression_definition() {
+        // Local E = ...;  declares E
+        if let Some(name) = stmt.tokens[1..]
+            .iter()
+            .take_while(|t| t.token.text != "=")
+            .find(|t| t.token.token_type == TokenType::Identifier)
+        {
+            names.push(name);
+        }
+        return names;
+    }
+
+    // Symbol x, y(:10), z;  declares the first identifier of each item
+    let mut depth = 0i32;
+    let mut expecting = true;
+    for t in &stmt.tokens[1..] {
+        match t.token.text.as_str() {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => depth -= 1,
+            "," if depth == 0 => expecting = true,
+            ":" if depth == 0 => expecting = false,
+            _ if expecting && depth == 0 && t.token.token_type == TokenType::Identifier => {
+                names.push(t);
+                expecting = false;
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Identifiers a statement uses (not declares)
+fn used_names(stmt: &Statement) -> Vec<&SpannedToken> {
+    let start = if stmt.is_expression_definition() {
+        match stmt.tokens.iter().position(|t| t.token.text == "=") {
+            Some(eq) => eq + 1,
+            None => return Vec::new(),
+        }
+    } else if stmt.is_declaration() {
+        return Vec::new();
+    } else {
+        0
+    };
+
+    let tokens = &stmt.tokens[start..];
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(i, t)| {
+            t.token.token_type == TokenType::Identifier
+                && !is_builtin_name(&t.token.text)
+                // ?a is an argument-list wildcard, not a name
+                && !(*i > 0 && tokens[i - 1].token.text == "?")
+        })
+        .map(|(_, t)| t)
+        .collect()
+}
+
+/// Statements whose identifiers must all be declared names
+fn checks_uses(stmt: &Statement) -> bool {
+    stmt.is_expression_definition()
+        || matches!(
+            stmt.head().as_str(),
+            "id" | "identify" | "idnew" | "idold" | "also" | "multiply" | "if" | "elseif"
+        )
+}
+
+/// Warns about statements that are not terminated with `;`
+pub struct NoSemicolonRule;
+
+impl LintRule for NoSemicolonRule {
+    fn name(&self) -> &'static str {
+        "no_semicolon"
+    }
+
+    fn check(&self, statements: &[Statement]) -> Vec<LintDiagnostic> {
+        statements
+            .iter()
+            .filter(|s| !s.terminated)
+            .filter_map(|s| s.tokens.last())
+            .map(|t| LintDiagnostic {
+                level: Level::Warning,
+                message: "statement does not end with ';'".to_string(),
+                span: t.span,
+            })
+            .collect()
+    }
+}
+
+/// Reports names used in expressions or substitutions before they are declared
+#[derive(Debug, Default)]
+pub struct UndeclaredSymbolRule {
+    /// Names declared before this code (e.g. in earlier cells)
+    known: HashSet<String>,
+}
+
+impl UndeclaredSymbolRule {
+    /// Treat `known` as already declared
+    pub fn with_known(known: HashSet<String>) -> Self {
+        UndeclaredSymbolRule { known }
+    }
+}
+
+impl LintRule for UndeclaredSymbolRule {
+    fn name(&self) -> &'static str {
+        "undeclared_symbol"
+    }
+
+    fn check(&self, statements: &[Statement]) -> Vec<LintDiagnostic> {
+        let mut declared: HashSet<&str> = HashSet::new();
+        let mut reported: HashSet<&str> = HashSet::new();
+        let mut diagnostics = Vec::new();
+
+        for stmt in statements {
+            if checks_uses(stmt) {
+                for t in used_names(stmt) {
+                    let name = t.token.text.as_str();
+                    if !declared.contains(name)
+                        && !self.known.contains(name)
+                        && reported.insert(name)
+                    {
+                        diagnostics.push(LintDiagnostic {
+                            level: Level::Error,
+                            message: format!("'{}' is used before it is declared", name),
+                            span: t.span,
+                        });
+                    }
+                }
+            }
+            if stmt.is_declaration() {
+                declared.extend(declared_names(stmt).iter().map(|t| t.token.text.as_str()));
+            }
+        }
+        diagnostics
+    }
+}
+
+/// Warns about declared symbols, functions, indices... that are never used
+pub struct UnusedSymbolRule;
+
+impl LintRule for UnusedSymbolRule {
+    fn name(&self) -> &'static str {
+        "unused_symbol"
+    }
+
+    fn check(&self, statements: &[Statement]) -> Vec<LintDiagnostic> {
+        let used: HashSet<&str> = statements
+            .iter()
+            .flat_map(used_names)
+            .map(|t| t.token.text.as_str())
+            .collect();
+
+        statements
+            .iter()
+            .filter(|s| s.is_declaration() && !s.is_expression_definition())
+            .flat_map(declared_names)
+            .filter(|t| !used.contains(t.token.text.as_str()))
+            .map(|t| LintDiagnostic {
+                level: Level::Warning,
+                message: format!("'{}' is declared but never used", t.token.text),
+                span: t.span,
+            })
+            .collect()
+    }
+}
+
+/// Warns about keywords that are not written in lowercase
+pub struct StyleRule;
+
+impl LintRule for StyleRule {
+    fn name(&self) -> &'static str {
+        "style"
+    }
+
+    fn check(&self, statements: &[Statement]) -> Vec<LintDiagnostic> {
+        statements
+            .iter()
+            .flat_map(|s| &s.tokens)
+            .filter(|t| {
+                t.token.token_type == TokenType::Keyword
+                    && t.token.text != t.token.text.to_lowercase()
+            })
+            .map(|t| LintDiagnostic {
+                level: Level::Warning,
+                message: format!(
+                    "keyword '{}' should be lowercase ('{}')",
+                    t.token.text,
+                    t.token.text.to_lowercase()
+                ),
+                span: t.span,
+            })
+            .collect()
+    }
+}
+
+/// Every name declared anywhere in `code`, expressions included
+pub fn declarations(code: &str) -> HashSet<String> {
+    split_statements(code)
+        .iter()
+        .filter(|s| s.is_declaration())
+        .flat_map(declared_names)
+        .map(|t| t.token.text.clone())
+        .collect()
+}
+
+/// Runs a set of lint rules over FORM code
+pub struct Linter {
+    rules: Vec<Box<dyn LintRule>>,
+}
+
+impl Default for Linter {
+    /// All rules enabled
+    fn default() -> Self {
+        Linter::new()
+            .with_rule(NoSemicolonRule)
+            .with_rule(UndeclaredSymbolRule::default())
+            .with_rule(UnusedSymbolRule)
+            .with_rule(StyleRule)
+    }
+}
+
+impl Linter {
+    /// A linter with no rules
+    pub fn new() -> Self {
+        Linter { rules: Vec::new() }
+    }
+
+    /// Add a rule
+    pub fn with_rule(mut self, rule: impl LintRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// A linter with the rules switched on in the `[linter]` config table
+    pub fn from_config(config: &LinterConfig) -> Self {
+        let mut linter = Linter::new();
+        if config.no_semicolon {
+            linter = linter.with_rule(NoSemicolonRule);
+        }
+        if config.undeclared_symbol {
+            linter = linter.with_rule(UndeclaredSymbolRule::default());
+        }
+        if config.unused_symbol {
+            linter = linter.with_rule(UnusedSymbolRule);
+        }
+        if config.style {
+            linter = linter.with_rule(StyleRule);
+        }
+        linter
+    }
+
+    /// Names of the active rules
+    pub fn rule_names(&self) -> Vec<&'static str> {
+        self.rules.iter().map(|r| r.name()).collect()
+    }
+
+    /// Lint `code`, returning diagnostics in source order
+    pub fn check(&self, code: &str) -> Vec<LintDiagnostic> {
+        let statements = split_statements(code);
+        let mut diagnostics: Vec<LintDiagnostic> = self
+            .rules
+            .iter()
+            .flat_map(|rule| rule.check(&statements))
+            .collect();
+        diagnostics.sort_by_key(|d| d.span);
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(linter: &Linter, code: &str) -> Vec<String> {
+        linter.check(code).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_no_semicolon_rule() {
+        let linter = Linter::new().with_rule(NoSemicolonRule);
+        let code = "Symbol x;\nLocal E = (x+1)^2\n.sort\nPrint;";
+        assert_eq!(
+            messages(&linter, code),
+            ["line 2:17: warning: statement does not end with ';'"]
+        );
+        // A statement may continue over several lines
+        assert!(linter.check("Local E = x\n    + 1;\n.end").is_empty());
+    }
+
+    #[test]
+    fn test_undeclared_symbol_rule() {
+        let linter = Linter::new().with_rule(UndeclaredSymbolRule::default());
+        let diagnostics = linter.check("Symbol x;\nLocal E = x + y;\nid y = x;\nPrint;");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, Level::Error);
+        assert_eq!(diagnostics[0].message, "'y' is used before it is declared");
+        assert_eq!(diagnostics[0].span, Span { line: 2, start: 14, end: 15 });
+
+        // Wildcards, built-ins and argument wildcards are fine
+        let ok = "CFunction f;\nSymbol x;\nLocal E = f(1,x,i_);\nid f(?a,x?) = f(?a)*x;\nPrint;";
+        assert!(linter.check(ok).is_empty());
+
+        // Names from earlier cells count as declared
+        let known = declarations("Symbol x, y(:4);\nCFunction f;\nLocal E = x;");
+        assert_eq!(known.len(), 4);
+        let linter = Linter::new().with_rule(UndeclaredSymbolRule::with_known(known));
+        assert!(linter.check("Local F = E*f(x,y);").is_empty());
+    }
+
+    #[test]
+    fn test_unused_symbol_rule() {
+        let linter = Linter::new().with_rule(UnusedSymbolRule);
+        assert_eq!(
+            messages(&linter, "Symbol x, y, z(:10);\nLocal E = x^2;\nid x = y;\nPrint;"),
+            ["line 1:14: warning: 'z' is declared but never used"]
+        );
+    }
+
+    #[test]
+    fn test_style_rule() {
+        let linter = Linter::new().with_rule(StyleRule);
+        assert_eq!(
+            messages(&linter, "Symbol x;\nLocal E = x;\nID x = 1;\nprint;"),
+            ["line 3:1: warning: keyword 'ID' should be lowercase ('id')"]
+        );
+    }
+
+    #[test]
+    fn test_from_config_selects_rules() {
+        let config = LinterConfig {
+            no_semicolon: true,
+            undeclared_symbol: false,
+            unused_symbol: true,
+            style: false,
+        };
+        assert_eq!(
+            Linter::from_config(&config).rule_names(),
+            ["no_semicolon", "unused_symbol"]
+        );
+        assert_eq!(Linter::default().rule_names().len(), 4);
+    }
+}
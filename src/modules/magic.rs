@@ -1,8 +1,24 @@
 // Magic commands module - IPython-like functionality
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use super::cache::{self, ResultCache};
+use super::config::{self, LinterConfig, OutputConfig, SeparatorConfig, SeparatorWidth};
+use super::doc;
+use super::examples;
+use super::form::{self, FormEnvReport, FormResult, RunOptions};
+use super::formatter;
+use super::highlight::{self, DiffOp};
+use super::linter::{self, Linter};
+use super::notebook;
+use super::postproc;
+use super::session::{self, SavedEntry, SavedSession};
+use super::store;
+use super::term;
 use super::theme;
+use super::watch::{Watch, WatchRun};
 
 /// Session history entry
 #[derive(Debug, Clone)]
@@ -11,6 +27,9 @@ pub struct HistoryEntry {
     pub input: String,
     pub output: Option<String>,
     pub duration: Option<Duration>,
+    /// For a block added by `%load`, the number of the script's first
+    /// block: it ran together with the blocks from there on
+    pub loaded_with: Option<usize>,
 }
 
 /// Session state for magic commands
@@ -19,10 +38,61 @@ pub struct SessionState {
     pub history: Vec<HistoryEntry>,
     /// Current session number
     pub session_number: usize,
+    /// Name of the saved session this is (see %session)
+    pub name: Option<String>,
+    /// Directory holding saved sessions
+    pub sessions_dir: PathBuf,
     /// Last N outputs for _ and __ style access
     pub last_outputs: VecDeque<String>,
     /// Show timing by default
     pub show_timing: bool,
+    /// Print the input that would be sent to FORM instead of running it
+    pub dry_run: bool,
+    /// Resolved FORM executable
+    pub form_path: Option<PathBuf>,
+    /// Options used when invoking FORM; its `extra_args` are the
+    /// `form_flags` the binary in use supports
+    pub run_options: RunOptions,
+    /// FORM flags asked for on the command line, in the config or in
+    /// `FORM_FLAGS`
+    pub form_flags: Vec<String>,
+    /// Snippets defined with %macro, expanded from @name
+    pub macros: HashMap<String, String>,
+    /// File where %macro definitions are saved (None = don't save)
+    pub macros_path: Option<PathBuf>,
+    /// File backing %store/%restore
+    pub store_path: PathBuf,
+    /// Rules used by %lint
+    pub lint_config: LinterConfig,
+    /// `[output]` display settings (its max_lines is `max_output_lines`)
+    pub output_config: OutputConfig,
+    /// Output transformers toggled with %postproc
+    pub postproc: postproc::Registry,
+    /// Current working directory, changed with %cd
+    pub working_dir: PathBuf,
+    /// Working directory at startup, where a bare %cd returns
+    pub initial_dir: PathBuf,
+    /// Untruncated text of the most recent output, for %full and %more
+    pub last_full_output: Option<String>,
+    /// Lines per %more page, and the truncation limit (0 = unlimited)
+    pub max_output_lines: usize,
+    /// Line where the next %more page starts
+    more_offset: usize,
+    /// File re-run on change, set with %watch
+    pub watch: Option<Watch>,
+    /// Reuse the result of an identical earlier run instead of re-running FORM
+    pub cache_results: bool,
+    /// Results of earlier runs, keyed by `cache_key`
+    pub expression_cache: ResultCache,
+    /// Version of the FORM binary in use, part of the cache key
+    pub form_version: Option<String>,
+    /// Scripts with at least this many blocks are %load'ed with one FORM
+    /// run (0 = never)
+    pub batch_threshold: usize,
+    /// Code of the startup file, run ahead of every cell
+    pub startup: Option<String>,
+    /// Rule printed after each cell, changed with %set separator
+    pub separator: SeparatorConfig,
     /// Max outputs to keep for _ access
     max_outputs: usize,
 }
@@ -38,12 +108,75 @@ impl SessionState {
         SessionState {
             history: Vec::new(),
             session_number: 1,
+            name: None,
+            sessions_dir: config::sessions_dir(),
             last_outputs: VecDeque::with_capacity(10),
             show_timing: false,
+            dry_run: false,
+            form_path: None,
+            run_options: RunOptions::default(),
+            form_flags: Vec::new(),
+            macros: HashMap::new(),
+            macros_path: None,
+            store_path: config::store_path(),
+            lint_config: LinterConfig::default(),
+            output_config: OutputConfig::default(),
+            postproc: postproc::Registry::default(),
+            working_dir: env::current_dir().unwrap_or_default(),
+            initial_dir: env::current_dir().unwrap_or_default(),
+            last_full_output: None,
+            max_output_lines: 50,
+            more_offset: 0,
+            watch: None,
+            cache_results: false,
+            expression_cache: ResultCache::new(),
+            form_version: None,
+            batch_threshold: 5,
+            startup: None,
+            separator: SeparatorConfig::default(),
             max_outputs: 10,
         }
     }
     
+    /// Names declared by the cells run so far (for `--strict` checks)
+    pub fn declared_names(&self) -> HashSet<String> {
+        self.startup
+            .iter()
+            .chain(self.history.iter().map(|entry| &entry.input))
+            .flat_map(|input| linter::declarations(input))
+            .collect()
+    }
+
+    /// `input` as piped to FORM: after the startup file's code, if any
+    pub fn program(&self, input: &str) -> String {
+        match &self.startup {
+            Some(startup) => format!("{}\n{}", startup, input),
+            None => input.to_string(),
+        }
+    }
+    
+    /// Switches to the FORM binary at `path`, passing it only those of
+    /// `form_flags` it supports. Returns the flags left out.
+    pub fn use_form_binary(&mut self, path: PathBuf) -> Vec<String> {
+        let (supported, unsupported) = match self.form_flags.as_slice() {
+            [] => (Vec::new(), Vec::new()),
+            flags => form::detect_form_capabilities(&path).filter_args(flags),
+        };
+        self.run_options.extra_args = supported;
+        self.form_path = Some(path);
+        unsupported
+    }
+
+    /// `run_options` for a program that must finish, such as one built from
+    /// the session's declarations: `.end` is appended whatever `auto_end`
+    /// says
+    pub fn finished_run_options(&self) -> RunOptions {
+        RunOptions {
+            terminator: Some(form::Terminator::End),
+            ..self.run_options.clone()
+        }
+    }
+
     /// Add a new history entry
     pub fn add_entry(&mut self, input: String, output: Option<String>, duration: Option<Duration>) {
         let entry = HistoryEntry {
@@ -51,22 +184,43 @@ impl SessionState {
             input,
             output: output.clone(),
             duration,
+            loaded_with: None,
         };
         self.history.push(entry);
         
         // Track last outputs
         if let Some(out) = output {
-            if !out.trim().is_empty() {
-                if self.last_outputs.len() >= self.max_outputs {
-                    self.last_outputs.pop_back();
-                }
-                self.last_outputs.push_front(out);
-            }
+            self.push_output(out);
         }
         
         self.session_number += 1;
     }
     
+    /// Raw FORM output as a cell records it: formatted with the session's
+    /// output settings and post-processed, but not truncated
+    pub fn render_output(&self, raw: &str) -> String {
+        let config = OutputConfig {
+            max_lines: 0,
+            ..self.output_config.clone()
+        };
+        self.postproc.apply(&form::format_output_with(raw, self.show_timing, &config))
+    }
+
+    /// Make `out` the latest output for _ access
+    pub fn push_output(&mut self, out: String) {
+        if !out.trim().is_empty() {
+            if self.last_outputs.len() >= self.max_outputs {
+                self.last_outputs.pop_back();
+            }
+            self.last_outputs.push_front(out);
+        }
+    }
+    
+    /// The latest run of the %watch'ed file since the last call
+    pub fn watch_run(&self) -> Option<WatchRun> {
+        self.watch.as_ref().and_then(Watch::latest)
+    }
+
     /// Get the last output (_)
     pub fn last_output(&self) -> Option<&String> {
         self.last_outputs.front()
@@ -77,16 +231,136 @@ impl SessionState {
         self.last_outputs.get(1)
     }
     
-    /// Get output by index (___N)
-    pub fn output_at(&self, idx: usize) -> Option<&String> {
-        self.last_outputs.get(idx)
+    /// Set an environment variable for FORM; this process's own environment
+    /// is left alone
+    pub fn set_env(&mut self, name: &str, value: &str) {
+        let env = &mut self.run_options.env;
+        match env.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => env.push((name.to_string(), value.to_string())),
+        }
+    }
+    
+    /// The value FORM sees for `name`: a %env override, else inherited
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        self.run_options
+            .env
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| env::var(name).ok())
+    }
+    
+    /// Remember the untruncated form of the output just shown
+    pub fn set_full_output(&mut self, full: String) {
+        let lines: Vec<&str> = full.lines().collect();
+        self.more_offset = form::truncation_point(&lines, self.max_output_lines);
+        self.last_full_output = Some(full);
+    }
+    
+    /// Next page of the last full output, cut where truncation would cut
+    fn next_page(&mut self) -> Option<String> {
+        let full = self.last_full_output.as_ref()?;
+        let lines: Vec<&str> = full.lines().collect();
+        if self.more_offset >= lines.len() {
+            return None;
+        }
+        let end = self.more_offset + form::truncation_point(&lines[self.more_offset..], self.max_output_lines);
+        let mut page = lines[self.more_offset..end].join("\n");
+        if end < lines.len() {
+            page.push_str(&format!("\n-- {} more lines, %more to continue --", lines.len() - end));
+        }
+        self.more_offset = end;
+        Some(page)
+    }
+    
+    /// Change the working directory of the REPL and of FORM
+    pub fn change_dir(&mut self, path: &str) -> Result<(), String> {
+        let target = if path.is_empty() {
+            self.initial_dir.clone()
+        } else {
+            self.working_dir.join(config::expand_path(path))
+        };
+        env::set_current_dir(&target)
+            .map_err(|e| format!("cd: {}: {}", target.display(), e))?;
+        self.working_dir = env::current_dir().unwrap_or(target);
+        self.run_options.workdir = Some(self.working_dir.clone());
+        Ok(())
+    }
+    
+    /// Save the macros to `macros_path`, if set
+    pub fn save_macros(&self) -> Result<(), String> {
+        match &self.macros_path {
+            Some(path) => config::save_macros(path, &self.macros),
+            None => Ok(()),
+        }
+    }
+    
+    /// Drop the cached outputs, keeping input history and numbering
+    pub fn clear_outputs(&mut self) {
+        self.last_outputs.clear();
+        self.last_full_output = None;
+    }
+    
+    /// Drop the input history, keeping cached outputs and numbering
+    pub fn clear_inputs(&mut self) {
+        self.history.clear();
     }
     
-    /// Clear session state
+    /// The identity of a cell as FORM would run it, stored with its cached
+    /// result and compared whole on lookup.
+    ///
+    /// Trailing whitespace and blank lines don't count, but indentation does:
+    /// a `*` in column 1 starts a comment, an indented one multiplies. The
+    /// FORM binary and its version, the startup file, its flags, the working
+    /// directory and %env overrides count too, since any of them can change
+    /// the result.
+    pub fn cache_key(&self, input: &str) -> String {
+        let mut env = self.run_options.env.clone();
+        env.sort();
+        let lossy = |path: &Option<PathBuf>| path.as_ref().map(|p| p.to_string_lossy().into_owned());
+        let context = (
+            lossy(&self.form_path),
+            &self.form_version,
+            &self.startup,
+            &self.run_options.extra_args,
+            lossy(&self.run_options.workdir),
+            env,
+        );
+        let mut key = serde_json::to_string(&context).unwrap_or_default();
+        for line in input.lines().map(str::trim_end).filter(|l| !l.is_empty()) {
+            key.push('\n');
+            key.push_str(line);
+        }
+        key
+    }
+
+    /// An earlier result for `input`, if caching is on
+    pub fn cached_result(&self, input: &str) -> Option<&FormResult> {
+        if !self.cache_results || cache::has_side_effects(input) {
+            return None;
+        }
+        self.expression_cache.get(&self.cache_key(input))
+    }
+
+    /// Remember a successful run of `input`, if caching is on and the cell
+    /// has no side effects that a cached result would skip
+    pub fn cache_result(&mut self, input: &str, result: &FormResult) {
+        if self.cache_results && !cache::has_side_effects(input) {
+            let key = self.cache_key(input);
+            self.expression_cache.insert(key, result);
+        }
+    }
+
+    /// Clear session state. The result cache is not session state, as a
+    /// cached result doesn't depend on earlier cells, and is kept; `%cache
+    /// clear` drops it.
     pub fn reset(&mut self) {
         self.history.clear();
         self.last_outputs.clear();
+        self.last_full_output = None;
         self.session_number = 1;
+        self.run_options.env.clear();
     }
 }
 
@@ -104,9 +378,73 @@ pub enum MagicResult {
     Exit,
     /// Show help
     Help,
+    /// Run the given FORM code as a cell
+    Execute { code: String, timed: bool },
+    /// Run the given FORM code without displaying its output
+    /// (see `record_captured`)
+    Capture { code: String, name: Option<String> },
+}
+
+/// Magics that take a multi-line code body after the header line
+const CODE_MAGICS: &[&str] = &["timeit", "include", "load_form", "capture"];
+
+/// The FORM executable `%form` names: a path (relative to the working
+/// directory, or a directory holding `form`), or a bare name such as
+/// `tform` looked up in PATH
+fn resolve_form_binary(arg: &str, working_dir: &Path) -> Result<PathBuf, String> {
+    let path = working_dir.join(config::expand_path(arg));
+    if path.exists() || arg.contains(std::path::MAIN_SEPARATOR) || arg.contains('/') {
+        return form::explicit_form_path(&path);
+    }
+    env::var_os("PATH")
+        .and_then(|paths| env::split_paths(&paths).map(|dir| dir.join(arg)).find(|p| p.is_file()))
+        .ok_or_else(|| format!("FORM executable '{}' not found in PATH", arg))
+        .and_then(|found| form::explicit_form_path(&found))
+}
+
+/// Magics that can throw session state away, and so ask first
+const DESTRUCTIVE_MAGICS: &[&str] = &["reset", "clear", "reset-out", "reset-in", "cache"];
+
+fn is_yes_flag(arg: &str) -> bool {
+    arg == "-y" || arg == "--yes"
+}
+
+/// The question to ask before running `cmd`, if it discards session state
+/// and wasn't given `-y`/`--yes`
+pub fn confirmation_prompt(cmd: &str) -> Option<&'static str> {
+    let header = cmd.trim().strip_prefix('%')?.lines().next()?;
+    let parts: Vec<String> = header.split_whitespace().map(str::to_lowercase).collect();
+    if parts.iter().any(|p| is_yes_flag(p)) {
+        return None;
+    }
+    let args: Vec<&str> = parts[1..].iter().map(String::as_str).collect();
+    match (parts.first()?.as_str(), args.as_slice()) {
+        ("reset" | "clear", []) => Some("Reset all session state? [y/N] "),
+        ("reset" | "clear", ["out"]) | ("reset-out", []) => Some("Clear cached outputs? [y/N] "),
+        ("reset" | "clear", ["in"]) | ("reset-in", []) => Some("Clear input history? [y/N] "),
+        ("cache", ["clear"]) => Some("Clear cached results? [y/N] "),
+        _ => None,
+    }
+}
+
+/// Whether an answer to a confirmation prompt means yes
+pub fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Check whether a magic header line expects a code body
+pub fn takes_code(cmd: &str) -> bool {
+    cmd.trim()
+        .strip_prefix('%')
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(|name| CODE_MAGICS.contains(&name.to_lowercase().as_str()))
+        .unwrap_or(false)
 }
 
 /// Process a magic command (starts with %)
+///
+/// For code-taking magics the command holds the header line followed by
+/// the code body on subsequent lines.
 pub fn process_magic(cmd: &str, state: &mut SessionState, highlight: bool, theme_name: &str) -> MagicResult {
     let trimmed = cmd.trim();
     
@@ -114,32 +452,121 @@ pub fn process_magic(cmd: &str, state: &mut SessionState, highlight: bool, theme
         return MagicResult::NotMagic;
     }
     
-    let parts: Vec<&str> = trimmed[1..].split_whitespace().collect();
+    let (header, body) = trimmed.split_once('\n').unwrap_or((trimmed, ""));
+    let parts: Vec<&str> = header[1..].split_whitespace().collect();
     if parts.is_empty() {
         return MagicResult::Error("Empty magic command".to_string());
     }
     
     let magic_name = parts[0].to_lowercase();
-    let args = &parts[1..];
+    // -y/--yes only matters to the confirmation main asks for
+    let args: Vec<&str> = if DESTRUCTIVE_MAGICS.contains(&magic_name.as_str()) {
+        parts[1..].iter().copied().filter(|a| !is_yes_flag(a)).collect()
+    } else {
+        parts[1..].to_vec()
+    };
+    let args = args.as_slice();
     
     match magic_name.as_str() {
-        "help" | "?" => MagicResult::Help,
+        "help" | "?" => match args.first() {
+            None => MagicResult::Help,
+            Some(topic) => match doc::help_topic(topic) {
+                Ok(text) => MagicResult::Output(text),
+                Err(e) => MagicResult::Error(e),
+            },
+        },
         
         "quit" | "exit" | "q" => MagicResult::Exit,
         
-        "history" | "hist" | "h" => {
-            let n: usize = args.first()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(10);
-            MagicResult::Output(format_history(&state.history, n))
+        "history" | "hist" | "h" => magic_history(args, state),
+
+        "session" => magic_session(args, state),
+
+        "save_history" => {
+            let mut full_args = vec!["--save"];
+            full_args.extend_from_slice(args);
+            magic_history(&full_args, state)
+        }
+        
+        "reset" | "clear" => match args.first().map(|a| a.to_lowercase()).as_deref() {
+            None => {
+                state.reset();
+                MagicResult::Output("Session reset. History cleared.".to_string())
+            }
+            Some("out") => {
+                state.clear_outputs();
+                MagicResult::Output("Cached outputs cleared.".to_string())
+            }
+            Some("in") => {
+                state.clear_inputs();
+                MagicResult::Output("Input history cleared.".to_string())
+            }
+            Some(other) => MagicResult::Error(format!(
+                "Unknown %reset target '{}'. Use %reset, %reset in or %reset out.",
+                other
+            )),
+        },
+        
+        "reset-out" => {
+            state.clear_outputs();
+            MagicResult::Output("Cached outputs cleared.".to_string())
+        }
+        
+        "reset-in" => {
+            state.clear_inputs();
+            MagicResult::Output("Input history cleared.".to_string())
         }
         
-        "reset" | "clear" => {
-            state.reset();
-            MagicResult::Output("Session reset. History cleared.".to_string())
+        "env" => magic_env(args, state),
+        
+        "timeit" => {
+            if body.trim().is_empty() {
+                MagicResult::Error(
+                    "Usage: %timeit followed by FORM code on the next lines".to_string()
+                )
+            } else {
+                MagicResult::Execute { code: body.to_string(), timed: true }
+            }
         }
         
-        "time" | "timeit" => {
+        "capture" => match args {
+            _ if body.trim().is_empty() => MagicResult::Error(
+                "Usage: %capture [NAME] followed by FORM code on the next lines".to_string(),
+            ),
+            [] => MagicResult::Capture { code: body.to_string(), name: None },
+            [name] => MagicResult::Capture { code: body.to_string(), name: Some(name.to_string()) },
+            _ => MagicResult::Error("Usage: %capture [NAME]".to_string()),
+        },
+
+        // Unlike %load, which inlines the file, FORM reads it itself
+        "include" | "load_form" => match args {
+            [path] => match include_cell(path, body, &state.working_dir) {
+                Ok(code) => MagicResult::Execute { code, timed: false },
+                Err(e) => MagicResult::Error(e),
+            },
+            _ => MagicResult::Error(
+                "Usage: %include FILE, optionally followed by FORM code on the next lines"
+                    .to_string(),
+            ),
+        },
+
+        "examples" | "example" => match args {
+            [] => MagicResult::Output(examples::listing()),
+            ["run", n] => match n.parse().ok().and_then(examples::get) {
+                Some(example) => MagicResult::Execute {
+                    code: example.code.to_string(),
+                    timed: false,
+                },
+                None => MagicResult::Error(format!(
+                    "No example '{}' (choose 1-{})",
+                    n,
+                    examples::EXAMPLES.len()
+                )),
+            },
+            _ => MagicResult::Error("Usage: %examples [run N]".to_string()),
+        },
+
+        "time" => {
             state.show_timing = !state.show_timing;
             MagicResult::Output(format!(
                 "Timing display: {}",
@@ -147,14 +574,88 @@ pub fn process_magic(cmd: &str, state: &mut SessionState, highlight: bool, theme
             ))
         }
         
-        "who" | "whos" => {
-            // List all declared symbols from history
+        "dryrun" | "dry_run" => {
+            match args.first().map(|s| s.to_lowercase()).as_deref() {
+                Some("on") => state.dry_run = true,
+                Some("off") => state.dry_run = false,
+                None => state.dry_run = !state.dry_run,
+                Some(other) => {
+                    return MagicResult::Error(format!(
+                        "Invalid argument '{}'. Usage: %dryrun [on|off]",
+                        other
+                    ));
+                }
+            }
+            MagicResult::Output(format!(
+                "Dry run: {}",
+                if state.dry_run { "ON" } else { "OFF" }
+            ))
+        }
+        
+        "cache" => match args.first().map(|s| s.to_lowercase()).as_deref() {
+            Some("on") => {
+                state.cache_results = true;
+                // Results of another FORM version must not match
+                if state.form_version.is_none() {
+                    state.form_version = state.form_path.as_deref().and_then(form::detect_version);
+                }
+                MagicResult::Output("Result cache: ON".to_string())
+            }
+            Some("off") => {
+                state.cache_results = false;
+                MagicResult::Output("Result cache: OFF".to_string())
+            }
+            Some("clear") => {
+                let n = state.expression_cache.len();
+                state.expression_cache.clear();
+                MagicResult::Output(format!("Cleared {} cached result(s).", n))
+            }
+            None => MagicResult::Output(format!(
+                "Result cache: {} ({} entries)",
+                if state.cache_results { "ON" } else { "OFF" },
+                state.expression_cache.len()
+            )),
+            Some(other) => MagicResult::Error(format!(
+                "Invalid argument '{}'. Usage: %cache [on|off|clear]",
+                other
+            )),
+        },
+
+        "set" => {
+            let rest = header[1..].trim_start()[parts[0].len()..].trim();
+            magic_set(rest, state)
+        }
+
+        "who" => MagicResult::Output(who_listing(&state.history)),
+
+        "whos" => match whos(state) {
+            Ok(symbols) if symbols.is_empty() => MagicResult::Output("No names declared in this session.".to_string()),
+            Ok(symbols) => MagicResult::Output(
+                symbols.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("\n")
+            ),
+            // Without FORM's listing, the names found in the inputs
+            Err(e) => MagicResult::Output(format!("{}\n({})", who_listing(&state.history), e)),
+        },
+        
+        "benchmark" | "bench" => MagicResult::Output(benchmark_report(&state.history)),
+        
+        "vars" => {
+            // Each cell is a separate FORM run, so there is no live process
+            // to ask; the expressions FORM itself printed are the next best
+            // thing, and the declared symbols come from the inputs
+            let expressions = expression_names(&state.history);
             let symbols = extract_symbols(&state.history);
-            if symbols.is_empty() {
-                MagicResult::Output("No symbols declared in this session.".to_string())
-            } else {
-                MagicResult::Output(format!("Declared symbols: {}", symbols.join(", ")))
+            if expressions.is_empty() && symbols.is_empty() {
+                return MagicResult::Output("No expressions or symbols in this session.".to_string());
+            }
+            let mut lines = Vec::new();
+            if !expressions.is_empty() {
+                lines.push(format!("Expressions: {}", expressions.join(", ")));
+            }
+            if !symbols.is_empty() {
+                lines.push(format!("Symbols:     {}", symbols.join(", ")));
             }
+            MagicResult::Output(lines.join("\n"))
         }
         
         "last" | "_" => {
@@ -164,6 +665,272 @@ pub fn process_magic(cmd: &str, state: &mut SessionState, highlight: bool, theme
             }
         }
         
+        "clip" | "copy" => {
+            let n = args.first().and_then(|s| s.parse::<usize>().ok());
+            let text = match clip_text(state, n) {
+                Ok(text) => text,
+                Err(e) => return MagicResult::Error(e),
+            };
+            let copied = arboard::Clipboard::new().and_then(|mut c| c.set_text(text.as_str()));
+            match copied {
+                Ok(()) => MagicResult::Output(format!(
+                    "Copied {} characters to clipboard",
+                    text.chars().count()
+                )),
+                Err(e) => MagicResult::Error(format!(
+                    "Clipboard unavailable ({}). Select the text below manually:\n\
+                     ----- BEGIN OUTPUT -----\n{}\n----- END OUTPUT -----",
+                    e, text
+                )),
+            }
+        }
+        
+        "diff" => {
+            let color = args.contains(&"--color");
+            let sessions: Vec<usize> = args.iter().filter_map(|a| a.parse().ok()).collect();
+            let pair = match sessions.as_slice() {
+                [m, n] => output_of(state, *m).and_then(|a| Ok((a, output_of(state, *n)?))),
+                [] => match (state.prev_output(), state.last_output()) {
+                    (Some(a), Some(b)) => Ok((a, b)),
+                    _ => Err("Need at least two outputs to diff.".to_string()),
+                },
+                _ => Err("Usage: %diff [--color] [M N]".to_string()),
+            };
+            match pair {
+                Ok((before, after)) if color => MagicResult::Output(
+                    highlight::highlight_diff(before, after, &theme::get_theme(theme_name))
+                ),
+                Ok((before, after)) => MagicResult::Output(plain_diff(before, after)),
+                Err(e) => MagicResult::Error(e),
+            }
+        }
+        
+        "macro" => {
+            // Keep the body verbatim: everything after the name on the header line
+            let rest = header[1..].trim_start()[parts[0].len()..].trim();
+            let (name, body) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if name.is_empty() {
+                return MagicResult::Error("Usage: %macro NAME [BODY]".to_string());
+            }
+            if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return MagicResult::Error(format!("Invalid macro name: {}", name));
+            }
+            let body = body.trim();
+            if body.is_empty() {
+                return match state.macros.get(name) {
+                    Some(body) => MagicResult::Output(body.clone()),
+                    None => MagicResult::Error(format!("No macro named {}", name)),
+                };
+            }
+            state.macros.insert(name.to_string(), body.to_string());
+            MagicResult::Output(with_save_warning(
+                format!("Defined @{}", name),
+                state.save_macros(),
+            ))
+        }
+        
+        "macros" => {
+            if state.macros.is_empty() {
+                return MagicResult::Output("No macros defined.".to_string());
+            }
+            let mut names: Vec<&String> = state.macros.keys().collect();
+            names.sort();
+            let lines: Vec<String> = names
+                .into_iter()
+                .map(|name| format!("@{:<12} {}", name, state.macros[name]))
+                .collect();
+            MagicResult::Output(lines.join("\n"))
+        }
+        
+        "delmacro" => match args.first() {
+            Some(name) if state.macros.remove(*name).is_some() => MagicResult::Output(
+                with_save_warning(format!("Deleted @{}", name), state.save_macros()),
+            ),
+            Some(name) => MagicResult::Error(format!("No macro named {}", name)),
+            None => MagicResult::Error("Usage: %delmacro NAME".to_string()),
+        },
+        
+        "store" => {
+            let (mut cache, warning) = store::load(&state.store_path);
+            let mut lines: Vec<String> = warning.into_iter().collect();
+            match args.first() {
+                None if cache.is_empty() => lines.push("No stored expressions.".to_string()),
+                None => lines.push(format!(
+                    "Stored expressions: {}",
+                    cache.keys().cloned().collect::<Vec<_>>().join(", ")
+                )),
+                Some(name) => {
+                    let Some(text) = latest_expression(state, name) else {
+                        return MagicResult::Error(format!("No output found for expression {}", name));
+                    };
+                    cache.insert(name.to_string(), text);
+                    if let Err(e) = store::save(&state.store_path, &cache) {
+                        return MagicResult::Error(e);
+                    }
+                    lines.push(format!("Stored {}", name));
+                }
+            }
+            MagicResult::Output(lines.join("\n"))
+        }
+        
+        "sub" => {
+            let rest = header[1..].trim_start()[parts[0].len()..].trim();
+            let subs = match parse_substitutions(rest) {
+                Ok(subs) => subs,
+                Err(e) => return MagicResult::Error(e),
+            };
+            match substitute_last_output(state, &subs) {
+                Ok(output) => {
+                    state.push_output(output.clone());
+                    MagicResult::Output(output)
+                }
+                Err(e) => MagicResult::Error(e),
+            }
+        }
+
+        "size" => {
+            let Some(name) = args.first() else {
+                return MagicResult::Error("Usage: %size NAME".to_string());
+            };
+            match latest_expression(state, name) {
+                Some(block) => MagicResult::Output(format!(
+                    "{}: {}",
+                    name,
+                    form::measure_complexity(&block)
+                )),
+                None => MagicResult::Error(format!("No output found for expression {}", name)),
+            }
+        }
+
+        "restore" => {
+            let Some(name) = args.first() else {
+                return MagicResult::Error("Usage: %restore NAME (use %store to list names)".to_string());
+            };
+            let (cache, warning) = store::load(&state.store_path);
+            match cache.get(*name) {
+                Some(text) => {
+                    state.push_output(text.clone());
+                    let mut lines: Vec<String> = warning.into_iter().collect();
+                    lines.push(text.clone());
+                    MagicResult::Output(lines.join("\n"))
+                }
+                None => MagicResult::Error(match warning {
+                    Some(w) => format!("{}\nNo stored expression named {}", w, name),
+                    None => format!("No stored expression named {}", name),
+                }),
+            }
+        }
+        
+        "lint" => {
+            let entry = match args.first() {
+                Some(n) => n.parse::<usize>().ok().and_then(|n| state.history.iter().find(|e| e.number == n)),
+                None => state.history.last(),
+            };
+            match entry {
+                Some(entry) => {
+                    let linter = Linter::from_config(&state.lint_config);
+                    let diagnostics = linter.check(&entry.input);
+                    if linter.rule_names().is_empty() {
+                        MagicResult::Error("Every lint rule is off; enable some under [linter] in the config".to_string())
+                    } else if diagnostics.is_empty() {
+                        MagicResult::Output(format!("In [{}]: no issues found", entry.number))
+                    } else {
+                        let lines: Vec<String> = diagnostics
+                            .iter()
+                            .map(|d| format!("In [{}] {}", entry.number, d))
+                            .collect();
+                        MagicResult::Output(lines.join("\n"))
+                    }
+                }
+                None => MagicResult::Error("No input to lint".to_string()),
+            }
+        }
+        
+        "format" | "fmt" => {
+            let entry = match args.first() {
+                Some(n) => n.parse::<usize>().ok().and_then(|n| state.history.iter().find(|e| e.number == n)),
+                None => state.history.last(),
+            };
+            match entry {
+                Some(entry) => {
+                    let code = formatter::pretty_print_form(&entry.input);
+                    if highlight {
+                        MagicResult::Output(highlight::highlight_code(&code, &theme::get_theme(theme_name)))
+                    } else {
+                        MagicResult::Output(code)
+                    }
+                }
+                None => MagicResult::Error("No input to format".to_string()),
+            }
+        }
+
+        "postproc" => match (args.first().copied(), args.get(1)) {
+            (None, _) => MagicResult::Output(format!(
+                "Output post-processors (* = enabled):\n{}",
+                state.postproc.describe()
+            )),
+            (Some("enable"), Some(name)) => match state.postproc.enable(name) {
+                Ok(()) => MagicResult::Output(format!("Post-processor {} enabled", name)),
+                Err(e) => MagicResult::Error(e),
+            },
+            (Some("disable"), Some(name)) => match state.postproc.disable(name) {
+                Ok(()) => MagicResult::Output(format!("Post-processor {} disabled", name)),
+                Err(e) => MagicResult::Error(e),
+            },
+            _ => MagicResult::Error("Usage: %postproc [enable|disable NAME]".to_string()),
+        },
+        
+        "cd" => match state.change_dir(&args.join(" ")) {
+            Ok(()) => MagicResult::Output(state.working_dir.display().to_string()),
+            Err(e) => MagicResult::Error(e),
+        },
+        
+        "pwd" => MagicResult::Output(state.working_dir.display().to_string()),
+        
+        "full" => match &state.last_full_output {
+            Some(full) => MagicResult::Output(full.clone()),
+            None => MagicResult::Error("No output yet".to_string()),
+        },
+        
+        "expand" => {
+            let pager = args.iter().any(|a| matches!(*a, "-p" | "--pager"));
+            let n = args.iter().find_map(|a| a.parse::<usize>().ok());
+            let text = match expand_text(state, n) {
+                Ok(text) => text,
+                Err(e) => return MagicResult::Error(e),
+            };
+            if pager && term::ansi::is_tty() {
+                match term::page(&text) {
+                    Ok(()) => MagicResult::Handled,
+                    Err(e) => MagicResult::Error(format!("Cannot start the pager: {}", e)),
+                }
+            } else {
+                MagicResult::Output(text)
+            }
+        }
+        
+        "more" => match state.next_page() {
+            Some(page) => MagicResult::Output(page),
+            None if state.last_full_output.is_none() => MagicResult::Error("No output yet".to_string()),
+            None => MagicResult::Output("(end of output)".to_string()),
+        },
+        
+        "edit" => {
+            let initial = match args.first() {
+                None => String::new(),
+                Some(arg) => match arg.parse::<usize>().ok().and_then(|n| state.history.iter().find(|e| e.number == n)) {
+                    Some(entry) => entry.input.clone(),
+                    None => return MagicResult::Error(format!("No entry found for session {}", arg)),
+                },
+            };
+            let editor = term::editor();
+            match term::edit_text(&initial, &editor) {
+                Ok(Some(code)) => MagicResult::Execute { code, timed: false },
+                Ok(None) => MagicResult::Handled,
+                Err(e) => MagicResult::Error(format!("Not running the cell: {}", e)),
+            }
+        }
+
         "recall" | "r" => {
             let n: usize = args.first()
                 .and_then(|s| s.parse().ok())
@@ -193,6 +960,163 @@ pub fn process_magic(cmd: &str, state: &mut SessionState, highlight: bool, theme
             }
         }
         
+        "which" => match state.form_path.as_deref() {
+            Some(path) if path.is_file() => MagicResult::Output(path.display().to_string()),
+            Some(path) => MagicResult::Output(format!("{} (missing!)", path.display())),
+            None => MagicResult::Error("No FORM executable configured".to_string()),
+        },
+
+        "form" => match args {
+            [] => match state.form_path.as_deref() {
+                Some(path) => MagicResult::Output(format!("FORM path: {}", path.display())),
+                None => MagicResult::Error("No FORM executable configured".to_string()),
+            },
+            [path] => match resolve_form_binary(path, &state.working_dir) {
+                Ok(found) => {
+                    let version = form::detect_version(&found);
+                    let mut msg = form::form_info(&found, version.as_deref());
+                    let unsupported = state.use_form_binary(found);
+                    if !unsupported.is_empty() {
+                        msg.push_str(&format!("\nNot passing unsupported flags: {}", unsupported.join(" ")));
+                    }
+                    state.form_version = version;
+                    MagicResult::Output(msg)
+                }
+                Err(e) => MagicResult::Error(e),
+            },
+            _ => MagicResult::Error("Usage: %form [PATH | NAME]".to_string()),
+        },
+
+        "forminfo" => match state.form_path.as_deref() {
+            Some(path) => MagicResult::Output(format!(
+                "{}\n{}",
+                form::form_info(path, form::detect_version(path).as_deref()),
+                form::detect_form_capabilities(path)
+            )),
+            None => MagicResult::Error("No FORM executable configured".to_string()),
+        },
+
+        "form-env" | "formenv" => {
+            let form_path = state.form_path.as_deref();
+            let version = form_path.and_then(form::detect_version);
+            let cwd = env::current_dir().unwrap_or_default();
+            let report = FormEnvReport::assemble(
+                form_path,
+                version,
+                &state.run_options,
+                &cwd,
+                |name| env::var(name).ok(),
+            );
+            MagicResult::Output(report.to_string())
+        }
+        
+        "notebook" => {
+            let path = match args.first() {
+                Some(p) => p,
+                None => return MagicResult::Error("Usage: %notebook <filename.ipynb>".to_string()),
+            };
+            let json = notebook::session_to_notebook(state);
+            match std::fs::write(path, json) {
+                Ok(()) => MagicResult::Output(format!(
+                    "Exported {} cells to {}",
+                    state.history.len(),
+                    path
+                )),
+                Err(e) => MagicResult::Error(format!("Failed to write {}: {}", path, e)),
+            }
+        }
+        
+        "run" => {
+            let path = match args.first() {
+                Some(p) => p,
+                None => return MagicResult::Error("Usage: %run <filename.ipynb>".to_string()),
+            };
+            let content = match std::fs::read_to_string(path) {
+                Ok(c) => c,
+                Err(e) => return MagicResult::Error(format!("Failed to read {}: {}", path, e)),
+            };
+            match notebook::session_from_notebook(&content) {
+                // Cells share one FORM run, so drop their individual .end lines
+                Ok(cells) => {
+                    let code = cells
+                        .iter()
+                        .flat_map(|(input, _)| input.lines())
+                        .filter(|line| line.trim() != ".end")
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    if code.trim().is_empty() {
+                        MagicResult::Error(format!("No code cells in {}", path))
+                    } else {
+                        MagicResult::Execute { code, timed: false }
+                    }
+                }
+                Err(e) => MagicResult::Error(e),
+            }
+        }
+        
+        "rerun-all" | "rerunall" => {
+            let Some(form_path) = state.form_path.clone() else {
+                return MagicResult::Error("FORM executable not configured".to_string());
+            };
+            if state.history.is_empty() {
+                return MagicResult::Error("No history to re-run".to_string());
+            }
+            let keep_going = args.contains(&"--continue");
+            let (report, all_matched) = rerun_all(state, &form_path, keep_going);
+            if all_matched {
+                MagicResult::Output(report)
+            } else {
+                MagicResult::Error(report)
+            }
+        }
+
+        "load" => {
+            let Some(path) = args.first() else {
+                return MagicResult::Error("Usage: %load <file.frm>".to_string());
+            };
+            let Some(form_path) = state.form_path.clone() else {
+                return MagicResult::Error("FORM executable not configured".to_string());
+            };
+            match import_frm_to_history(Path::new(path), state, &form_path) {
+                Ok(0) => MagicResult::Error(format!("No code in {}", path)),
+                Ok(n) => MagicResult::Output(format!(
+                    "Imported {} block{} from {} (see %history)",
+                    n,
+                    if n == 1 { "" } else { "s" },
+                    path
+                )),
+                Err(e) => MagicResult::Error(e),
+            }
+        }
+        
+        "watch" => {
+            let Some(path) = args.first() else {
+                return MagicResult::Output(match &state.watch {
+                    Some(w) => format!("Watching {}", w.path.display()),
+                    None => "Not watching any file. Usage: %watch <file.frm>".to_string(),
+                });
+            };
+            let Some(form_path) = state.form_path.clone() else {
+                return MagicResult::Error("FORM executable not configured".to_string());
+            };
+            match Watch::start(Path::new(path), &form_path, &state.run_options) {
+                Ok(w) => {
+                    let msg = format!(
+                        "Watching {}; it is re-run when saved and the output shown at the next prompt.",
+                        w.path.display()
+                    );
+                    state.watch = Some(w);
+                    MagicResult::Output(msg)
+                }
+                Err(e) => MagicResult::Error(e),
+            }
+        }
+
+        "unwatch" => match state.watch.take() {
+            Some(w) => MagicResult::Output(format!("Stopped watching {}", w.path.display())),
+            None => MagicResult::Error("Not watching any file".to_string()),
+        },
+
         "info" | "about" => {
             MagicResult::Output(format!(
                 "FORM REPL v{}\n\
@@ -210,15 +1134,61 @@ pub fn process_magic(cmd: &str, state: &mut SessionState, highlight: bool, theme
             MagicResult::Output(
                 "Available magic commands:\n\
                  %help, %?        - Show REPL help\n\
+                 %help KEYWORD    - Describe a FORM statement or function\n\
                  %quit, %exit, %q - Exit the REPL\n\
                  %history [N]     - Show last N history entries (default 10)\n\
-                 %reset           - Clear session state and history\n\
+                 %history --full [--range M-N] [--save FILE]\n\
+                                  - Untruncated history; --save writes a transcript\n\
+                 %reset [-y]      - Clear session state and history (-y: don't ask)\n\
+                 %session [list | save [NAME] | load NAME | delete NAME | rename OLD NEW]\n\
+                                  - Manage named sessions saved on disk\n\
+                 %reset out|in    - Clear only cached outputs / input history\n\
                  %time            - Toggle timing display\n\
+                 %capture [NAME]  - Run the code below without showing its output\n\
+                 %timeit          - Time the code cell that follows\n\
+                 %dryrun [on|off] - Show input sent to FORM instead of running\n\
+                 %cache [on|off|clear] - Reuse results of identical cells\n\
+                 %set separator [on|off|char TEXT|width auto|N]\n\
+                                  - Turn off or restyle the rule after each cell\n\
+                 %sub NAME=VALUE, ... - Apply substitutions to the last output (history is kept)\n\
                  %who             - List declared symbols\n\
+                 %whos            - List every declared name with its kind and power range (asks FORM)\n\
+                 %examples [run N] - List or run bundled example cells\n\
+                 %benchmark       - Rank the timed cells, slowest first\n\
+                 %vars            - List expressions FORM printed, and declared symbols\n\
+                 %lint [N]        - Check the last input (or session N) for problems\n\
+                 %format [N]      - Pretty-print the last input (or session N)\n\
                  %last, %_        - Show last output\n\
+                 %expand [N] [-p] - Show the last (or session N) output in full; -p: in $PAGER\n\
+                 %full            - Show the last output without truncation\n\
+                 %more            - Show the next page of a truncated output\n\
+                 %postproc [enable|disable NAME] - List or toggle output post-processors\n\
                  %recall [N]      - Recall input from session N\n\
+                 %rerun-all [--continue] - Re-run every cell from scratch and report changed outputs\n\
+                 %edit [N]        - Write a cell (or revise session N's) in $EDITOR and run it\n\
+                 %macro NAME [BODY] - Define (or show) a snippet used as @NAME\n\
+                 %macros          - List macros\n\
+                 %delmacro NAME   - Delete a macro\n\
+                 %store [NAME]    - Save expression NAME's last output to disk (or list)\n\
+                 %size NAME       - Count terms, depth and symbols of expression NAME\n\
+                 %restore NAME    - Load a stored expression as the last output\n\
+                 %watch [FILE]    - Re-run FILE whenever it is saved\n\
+                 %unwatch         - Stop watching\n\
+                 %clip, %copy [N] - Copy last (or session N) output to clipboard\n\
+                 %diff [--color] [M N] - Diff the last two outputs (or M and N)\n\
                  %theme           - List available themes\n\
                  %info            - Show session info\n\
+                 %cd [DIR]        - Change directory (no DIR: back to the start)\n\
+                 %pwd             - Show the working directory\n\
+                 %which           - Show the FORM path in use and whether it exists\n\
+                 %form [PATH]     - Show or switch the FORM executable (e.g. %form tform)\n\
+                 %forminfo        - Show the selected FORM binary, version and capabilities\n\
+                 %form-env        - Show the resolved FORM environment\n\
+                 %env [NAME[=VAL]] - Show FORM-related env vars, or set one\n\
+                 %notebook FILE   - Export session as a Jupyter notebook\n\
+                 %run FILE        - Run the code cells of a notebook\n\
+                 %include FILE    - Run FORM's #include FILE, then any code lines below\n\
+                 %load FILE.frm   - Run a FORM script block by block into history\n\
                  %lsmagic         - List magic commands".to_string()
             )
         }
@@ -230,67 +1200,934 @@ pub fn process_magic(cmd: &str, state: &mut SessionState, highlight: bool, theme
     }
 }
 
-/// Format history for display
-fn format_history(history: &[HistoryEntry], n: usize) -> String {
-    let start = history.len().saturating_sub(n);
-    let mut output = String::new();
-    
-    for entry in history.iter().skip(start) {
-        output.push_str(&format!("In [{}]: {}\n", entry.number, 
-            entry.input.lines().next().unwrap_or("")));
-        
-        // Show truncated input if multi-line
-        if entry.input.lines().count() > 1 {
-            output.push_str("        ...\n");
+/// Record the result of a %capture'd cell.
+///
+/// History and the last output are updated as for any cell; only the
+/// display is replaced by a summary. With a `name`, the output is also
+/// stored in the %store cache, so `%restore NAME` brings it back. If that
+/// fails the cell is still recorded, and the error says what was lost.
+pub fn record_captured(
+    state: &mut SessionState,
+    input: String,
+    output: String,
+    duration: Duration,
+    name: Option<&str>,
+) -> Result<String, String> {
+    let lines = output.lines().count();
+    let mut msg = format!(
+        "Output captured ({} line{} suppressed)",
+        lines,
+        if lines == 1 { "" } else { "s" }
+    );
+    state.add_entry(input, Some(output.clone()), Some(duration));
+    if let Some(name) = name {
+        let (mut cache, _) = store::load(&state.store_path);
+        cache.insert(name.to_string(), output);
+        if let Err(e) = store::save(&state.store_path, &cache) {
+            return Err(format!("{}, but not stored as {}: {}", msg, name, e));
         }
-        
-        if let Some(ref out) = entry.output {
-            let first_line = out.lines().next().unwrap_or("");
-            if !first_line.trim().is_empty() {
-                output.push_str(&format!("Out[{}]: {}\n", entry.number, first_line));
-                if out.lines().count() > 1 {
-                    output.push_str("        ...\n");
-                }
+        msg.push_str(&format!(", stored as {}", name));
+    }
+    Ok(msg)
+}
+
+/// `x=1, y=2` as (name, value) pairs for `%sub`
+fn parse_substitutions(text: &str) -> Result<Vec<(String, String)>, String> {
+    const USAGE: &str = "Usage: %sub NAME=VALUE[, NAME=VALUE...]";
+    if text.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    text.split(',')
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').ok_or(USAGE)?;
+            let (name, value) = (name.trim(), value.trim());
+            let valid_name = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid_name {
+                return Err(format!("Not a name to substitute: '{}'", name));
+            }
+            if value.is_empty() {
+                return Err(format!("No value given for {}", name));
             }
+            Ok((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// The last output with `subs` applied, for `%sub`.
+///
+/// FORM does the work: each expression of the last output is defined
+/// again after the session's declarations, `id NAME = VALUE;` is applied
+/// and the result printed. Nothing is kept, so the session is unchanged
+/// apart from the new output.
+fn substitute_last_output(state: &SessionState, subs: &[(String, String)]) -> Result<String, String> {
+    let form_path = state.form_path.as_deref().ok_or("FORM executable not configured")?;
+    let last = state.last_output().ok_or("No output to substitute into")?;
+    let inputs = state
+        .startup
+        .iter()
+        .map(String::as_str)
+        .chain(state.history.iter().map(|e| e.input.as_str()));
+    let mut program = form::declaration_lines(inputs);
+    let mut names = Vec::new();
+    for name in parse_expression_names(last) {
+        let Some(block) = store::extract_expression(last, &name) else { continue };
+        let Some((_, rhs)) = block.split_once('=') else { continue };
+        program.push(format!("Local {} = {};", name, rhs.trim().trim_end_matches(';').trim()));
+        names.push(name);
+    }
+    if names.is_empty() {
+        return Err("The last output has no expressions".to_string());
+    }
+    program.extend(subs.iter().map(|(name, value)| format!("id {} = {};", name, value)));
+    program.push(format!("Print {};", names.join(", ")));
+    let result = form::run_form(&program.join("\n"), form_path, &state.finished_run_options())
+        .map_err(|e| e.to_string())?;
+    Ok(state.render_output(&result.output).trim_matches('\n').to_string())
+}
+
+/// The most recent printed `NAME = ...;` block for an expression
+fn latest_expression(state: &SessionState, name: &str) -> Option<String> {
+    state
+        .history
+        .iter()
+        .rev()
+        .filter_map(|e| e.output.as_deref())
+        .find_map(|out| store::extract_expression(out, name))
+}
+
+/// Split a `.frm` script into blocks ending at `.sort` or a blank line
+pub fn split_frm_blocks(content: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            if !current.is_empty() {
+                blocks.push(current.join("\n"));
+                current.clear();
+            }
+            continue;
         }
-        
-        if let Some(dur) = entry.duration {
-            output.push_str(&format!("        ({:.3}s)\n", dur.as_secs_f64()));
+        current.push(line);
+        if trimmed.to_lowercase().starts_with(".sort") {
+            blocks.push(current.join("\n"));
+            current.clear();
         }
-        output.push('\n');
     }
-    
+    if !current.is_empty() {
+        blocks.push(current.join("\n"));
+    }
+    blocks
+}
+
+/// Word diff of two outputs with `{+added+}` and `[-removed-]` markers
+fn plain_diff(before: &str, after: &str) -> String {
+    highlight::diff_tokens(before, after)
+        .into_iter()
+        .map(|(op, text)| match op {
+            DiffOp::Same => text,
+            DiffOp::Added => format!("{{+{}+}}", text),
+            DiffOp::Removed => format!("[-{}-]", text),
+        })
+        .collect()
+}
+
+/// `output` without the lines that change from run to run (FORM's timing line)
+fn without_timing(output: &str) -> String {
     output
+        .lines()
+        .filter(|l| !l.contains(" sec out of "))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_start_matches('\n')
+        .trim_end()
+        .to_string()
 }
 
-/// Extract declared symbols from session history
-fn extract_symbols(history: &[HistoryEntry]) -> Vec<String> {
-    use regex::Regex;
-    use std::collections::HashSet;
-    use std::sync::LazyLock;
-    
-    static SYMBOL_RE: LazyLock<Regex> = LazyLock::new(|| {
-        Regex::new(r"(?i)\b(?:Symbol|Symbols)\s+([^;]+);").unwrap()
-    });
-    
-    let mut symbols = HashSet::new();
-    
-    for entry in history {
-        for cap in SYMBOL_RE.captures_iter(&entry.input) {
-            if let Some(m) = cap.get(1) {
-                for sym in m.as_str().split(',') {
-                    let clean = sym.trim()
-                        .split('(').next().unwrap_or("")
-                        .trim();
-                    if !clean.is_empty() && clean.chars().next().map(|c| c.is_alphabetic()).unwrap_or(false) {
-                        symbols.insert(clean.to_string());
-                    }
+/// Runs every history cell again from scratch and compares each output
+/// with the recorded one, for `%rerun-all`.
+///
+/// Blocks added by `%load` are replayed as they were imported, each with
+/// the script's blocks before it. Stops at the first cell whose output
+/// differs unless `keep_going`. Returns the report and whether every cell
+/// that ran matched. A cell recorded without output matches a run that
+/// fails or prints nothing.
+pub fn rerun_all(state: &SessionState, form_path: &Path, keep_going: bool) -> (String, bool) {
+    let mut report = Vec::new();
+    let (mut checked, mut matched) = (0, 0);
+    // The script being replayed, by the number of its first block
+    let mut script: Option<(usize, Result<ScriptReplay, String>)> = None;
+    for entry in &state.history {
+        checked += 1;
+        let recorded = entry.output.as_deref().map(without_timing).unwrap_or_default();
+        let rerun = match entry.loaded_with {
+            Some(first) => {
+                if script.as_ref().is_some_and(|(number, _)| *number != first) {
+                    script = None;
+                }
+                let (_, replay) = script.get_or_insert_with(|| {
+                    (first, ScriptReplay::start(state, form_path).map_err(|e| e.to_string()))
+                });
+                match replay {
+                    Ok(replay) => replay
+                        .run(&entry.input, state, form_path)
+                        .map(|(output, _)| without_timing(&output.unwrap_or_default()))
+                        .map_err(|e| e.to_string()),
+                    Err(e) => Err(e.clone()),
+                }
+            }
+            None => form::run_form(&state.program(&entry.input), form_path, &state.run_options)
+                .map(|result| without_timing(&state.render_output(&result.output)))
+                .map_err(|e| e.to_string()),
+        };
+        let diverged = match rerun {
+            Ok(output) if output == recorded => None,
+            Err(_) if recorded.is_empty() => None,
+            Ok(output) => Some(plain_diff(&recorded, &output)),
+            Err(e) => Some(format!("now fails: {}", e)),
+        };
+        match diverged {
+            None => matched += 1,
+            Some(diff) => {
+                report.push(format!("In [{}] differs:\n{}", entry.number, diff));
+                if !keep_going {
+                    break;
                 }
             }
         }
     }
-    
-    let mut result: Vec<_> = symbols.into_iter().collect();
+    let total = state.history.len();
+    if checked < total {
+        report.push("Stopped at the first difference; %rerun-all --continue checks the rest".to_string());
+    }
+    report.push(format!("{} of {} cells re-run, {} matched", checked, total, matched));
+    (report.join("\n"), matched == checked)
+}
+
+/// Runs the startup file and keeps its code to run ahead of every cell.
+///
+/// A missing file is not an error and gives `Ok(None)`; otherwise returns
+/// the formatted output. A file FORM rejects is not used.
+pub fn run_startup_file(path: &Path, state: &mut SessionState, form_path: &Path) -> Result<Option<String>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e)),
+    };
+    let code = content
+        .lines()
+        .filter(|l| l.trim() != ".end")
+        .collect::<Vec<_>>()
+        .join("\n");
+    // The `.end` stripped above goes back on, whatever auto_end says
+    let result = form::run_form(&code, form_path, &state.finished_run_options())
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    state.startup = Some(code);
+    Ok(Some(form::format_output(&result.output, false, 0)))
+}
+
+/// Runs a `.frm` script block by block, adding each block to the history.
+///
+/// Every run is a fresh FORM process, so block N is executed after the
+/// startup code, as every cell is, together with the blocks before it, and
+/// its entry gets only the output that block added. A script of
+/// `batch_threshold` blocks or more is instead run once with
+/// `form::run_form_batch`. Stops at the first failing block. The entries
+/// are marked with `loaded_with` so `%rerun-all` replays them the same way.
+///
+/// # Returns
+///
+/// The number of history entries added.
+pub fn import_frm_to_history(path: &Path, state: &mut SessionState, form_path: &Path) -> Result<usize, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    let blocks = split_frm_blocks(&content);
+    let start = state.history.len();
+    let first = state.session_number;
+    let imported = if state.batch_threshold > 0 && blocks.len() >= state.batch_threshold {
+        import_frm_batch(path, blocks, state, form_path)
+    } else {
+        import_frm_blocks(path, blocks, state, form_path)
+    };
+    for entry in &mut state.history[start..] {
+        entry.loaded_with = Some(first);
+    }
+    imported
+}
+
+/// Runs the blocks of a `%load`ed script one at a time, each after the
+/// startup code and the blocks before it, and tells what each one added
+struct ScriptReplay {
+    program: Vec<String>,
+    /// Formatted output of the run before
+    previous: String,
+}
+
+impl ScriptReplay {
+    /// Starts with the startup code, whatever it prints by itself belonging
+    /// to no block
+    fn start(state: &SessionState, form_path: &Path) -> Result<Self, form::FormError> {
+        let previous = match &state.startup {
+            Some(startup) => form::run_form(startup, form_path, &state.finished_run_options())
+                .map(|result| form::format_output(&result.output, false, 0))?,
+            None => String::new(),
+        };
+        Ok(ScriptReplay { program: Vec::new(), previous })
+    }
+
+    /// Runs the script up to `block`, giving the output `block` added and
+    /// how long the run took
+    fn run(&mut self, block: &str, state: &SessionState, form_path: &Path) -> Result<(Option<String>, Duration), form::FormError> {
+        self.program.extend(block.lines().filter(|l| l.trim() != ".end").map(String::from));
+        let program = state.program(&self.program.join("\n"));
+        let result = form::run_form(&program, form_path, &state.finished_run_options())?;
+        let formatted = form::format_output(&result.output, false, 0);
+        let added = formatted.strip_prefix(self.previous.as_str()).unwrap_or(&formatted);
+        let output = Some(added.trim_matches('\n').to_string()).filter(|o| !o.trim().is_empty());
+        self.previous = formatted;
+        Ok((output, result.duration))
+    }
+}
+
+/// `import_frm_to_history` for short scripts: one FORM run per block
+fn import_frm_blocks(path: &Path, blocks: Vec<String>, state: &mut SessionState, form_path: &Path) -> Result<usize, String> {
+    let mut replay = ScriptReplay::start(state, form_path)
+        .map_err(|e| format!("Startup code failed before importing {}: {}", path.display(), e))?;
+    let mut imported = 0;
+    for block in blocks {
+        let (output, duration) = replay.run(&block, state, form_path).map_err(|e| {
+            format!("Block {} of {} failed after importing {}: {}", imported + 1, path.display(), imported, e)
+        })?;
+        state.add_entry(block, output, Some(duration));
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+/// `import_frm_to_history` for long scripts: one FORM run for all blocks
+fn import_frm_batch(path: &Path, blocks: Vec<String>, state: &mut SessionState, form_path: &Path) -> Result<usize, String> {
+    // The startup code goes first, as a block of its own
+    let inputs: Vec<String> = state.startup.iter().chain(&blocks).cloned().collect();
+    let mut results = form::run_form_batch(&inputs, form_path, &state.run_options);
+    if state.startup.is_some() {
+        results.remove(0)
+            .map_err(|e| format!("Startup code failed before importing {}: {}", path.display(), e))?;
+    }
+    let mut imported = 0;
+    for (block, result) in blocks.into_iter().zip(results) {
+        let result = result.map_err(|e| {
+            format!("Block {} of {} failed after importing {}: {}", imported + 1, path.display(), imported, e)
+        })?;
+        let formatted = form::format_output(&result.output, false, 0);
+        let output = Some(formatted.trim_matches('\n').to_string()).filter(|o| !o.trim().is_empty());
+        state.add_entry(block, output, Some(result.duration));
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+/// Output recorded for session N
+fn output_of(state: &SessionState, n: usize) -> Result<&String, String> {
+    state.history.iter()
+        .find(|e| e.number == n)
+        .ok_or_else(|| format!("No entry found for session {}", n))?
+        .output.as_ref()
+        .ok_or_else(|| format!("Session {} has no output", n))
+}
+
+/// Plain text of the last output, or of session N's output, for copying
+fn clip_text(state: &SessionState, n: Option<usize>) -> Result<String, String> {
+    let output = match n {
+        Some(n) => output_of(state, n)?,
+        None => state.last_output().ok_or_else(|| "No output to copy.".to_string())?,
+    };
+    Ok(term::strip_ansi(output))
+}
+
+/// The untruncated last output, or session N's, for %expand
+fn expand_text(state: &SessionState, n: Option<usize>) -> Result<String, String> {
+    match n {
+        Some(n) => output_of(state, n).cloned(),
+        None => state.last_full_output.clone().ok_or_else(|| "No output yet".to_string()),
+    }
+}
+
+/// Variables that change how programs are found or loaded
+const SENSITIVE_ENV_VARS: &[&str] = &[
+    "PATH", "LD_PRELOAD", "LD_LIBRARY_PATH", "DYLD_INSERT_LIBRARIES",
+    "DYLD_LIBRARY_PATH", "HOME", "SHELL", "IFS",
+];
+
+/// A cell that `#include`s `path` (resolved against `workdir`) before `body`
+pub fn include_cell(path: &str, body: &str, workdir: &Path) -> Result<String, String> {
+    let resolved = workdir.join(config::expand_path(path));
+    if !resolved.is_file() {
+        return Err(format!("No such file: {}", resolved.display()));
+    }
+    if resolved.to_string_lossy().contains(char::is_whitespace) {
+        return Err(format!(
+            "FORM can't #include a path with spaces: {}",
+            resolved.display()
+        ));
+    }
+    let mut cell = format!("#include {}", resolved.display());
+    if !body.trim().is_empty() {
+        cell.push('\n');
+        cell.push_str(body);
+    }
+    Ok(cell)
+}
+
+/// `%set separator [on|off|char TEXT|width auto|N]`
+///
+/// `rest` is the text after `%set`, so that a pattern can keep its spaces
+/// when quoted (`char "- "`).
+fn magic_set(rest: &str, state: &mut SessionState) -> MagicResult {
+    const USAGE: &str = "Usage: %set separator [on|off|char TEXT|width auto|N]";
+    let (name, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if !name.eq_ignore_ascii_case("separator") {
+        return MagicResult::Error(if name.is_empty() {
+            USAGE.to_string()
+        } else {
+            format!("Unknown setting '{}'. {}", name, USAGE)
+        });
+    }
+    let (option, value) = value.trim().split_once(char::is_whitespace).unwrap_or((value.trim(), ""));
+    let sep = &mut state.separator;
+    match option.to_lowercase().as_str() {
+        "" => {}
+        "on" => sep.enabled = true,
+        "off" => sep.enabled = false,
+        "char" => {
+            let value = value.trim();
+            let pattern = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            if pattern.is_empty() {
+                return MagicResult::Error(format!("No separator text given. {}", USAGE));
+            }
+            sep.pattern = pattern.to_string();
+            sep.enabled = true;
+        }
+        "width" => match SeparatorWidth::parse(value) {
+            Some(width) => {
+                sep.width = width;
+                sep.enabled = true;
+            }
+            None => {
+                return MagicResult::Error(format!(
+                    "Invalid width '{}': expected auto or a positive number",
+                    value.trim()
+                ))
+            }
+        },
+        other => return MagicResult::Error(format!("Invalid argument '{}'. {}", other, USAGE)),
+    }
+    MagicResult::Output(if sep.enabled {
+        format!("Separator: ON (\"{}\", width {})", sep.pattern, sep.width)
+    } else {
+        "Separator: OFF".to_string()
+    })
+}
+
+/// `%session list|save|load|delete|rename`: named sessions on disk
+fn magic_session(args: &[&str], state: &mut SessionState) -> MagicResult {
+    let dir = state.sessions_dir.clone();
+    let result = match args {
+        [] => Ok(match &state.name {
+            Some(name) => format!("Current session: {}", name),
+            None => "Unnamed session. Use %session save NAME to name it.".to_string(),
+        }),
+        ["list"] => {
+            let names = session::list(&dir);
+            if names.is_empty() {
+                Ok("No saved sessions.".to_string())
+            } else {
+                Ok(names
+                    .iter()
+                    .map(|n| {
+                        let mark = if state.name.as_ref() == Some(n) { '*' } else { ' ' };
+                        format!("{} {}", mark, n)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+        }
+        ["save", rest @ ..] if rest.len() <= 1 => {
+            match rest.first().map(|n| n.to_string()).or_else(|| state.name.clone()) {
+                Some(name) => session::save(&dir, &name, &saved_session(state)).map(|()| {
+                    let msg = format!("Saved {} entries as session '{}'", state.history.len(), name);
+                    state.name = Some(name);
+                    msg
+                }),
+                None => Err("Usage: %session save NAME".to_string()),
+            }
+        }
+        ["load", name] => session::load(&dir, name).map(|saved| {
+            restore_session(state, saved);
+            state.name = Some(name.to_string());
+            format!("Loaded session '{}' ({} entries)", name, state.history.len())
+        }),
+        ["delete", name] => session::delete(&dir, name).map(|()| {
+            if state.name.as_deref() == Some(*name) {
+                state.name = None;
+            }
+            format!("Deleted session '{}'", name)
+        }),
+        ["rename", old, new] => session::rename(&dir, old, new).map(|()| {
+            if state.name.as_deref() == Some(*old) {
+                state.name = Some(new.to_string());
+            }
+            format!("Renamed session '{}' to '{}'", old, new)
+        }),
+        _ => Err("Usage: %session [list | save [NAME] | load NAME | delete NAME | rename OLD NEW]"
+            .to_string()),
+    };
+    match result {
+        Ok(msg) => MagicResult::Output(msg),
+        Err(e) => MagicResult::Error(e),
+    }
+}
+
+/// The parts of the session that %session save writes to disk
+fn saved_session(state: &SessionState) -> SavedSession {
+    SavedSession {
+        session_number: state.session_number,
+        history: state
+            .history
+            .iter()
+            .map(|e| SavedEntry {
+                number: e.number,
+                input: e.input.clone(),
+                output: e.output.clone(),
+                duration: e.duration.map(|d| d.as_secs_f64()),
+                loaded_with: e.loaded_with,
+            })
+            .collect(),
+    }
+}
+
+/// Replace the history with a saved session's
+fn restore_session(state: &mut SessionState, saved: SavedSession) {
+    state.reset();
+    for entry in saved.history {
+        if let Some(out) = &entry.output {
+            state.push_output(out.clone());
+        }
+        state.history.push(HistoryEntry {
+            number: entry.number,
+            input: entry.input,
+            output: entry.output,
+            duration: entry.duration.map(Duration::from_secs_f64),
+            loaded_with: entry.loaded_with,
+        });
+    }
+    state.session_number = saved.session_number;
+}
+
+/// `%history [N] [--full] [--range M-N] [--save PATH]`
+fn magic_history(args: &[&str], state: &SessionState) -> MagicResult {
+    let usage = "Usage: %history [N] [--full] [--range M-N] [--save FILE]";
+    let mut count = 10;
+    let mut full = false;
+    let mut range = None;
+    let mut save = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match *arg {
+            "--full" => full = true,
+            "--range" => match iter.next().and_then(|r| parse_range(r)) {
+                Some(r) => range = Some(r),
+                None => return MagicResult::Error(format!("Invalid --range, expected M-N\n{}", usage)),
+            },
+            "--save" => match iter.next() {
+                Some(path) => save = Some(*path),
+                None => return MagicResult::Error(usage.to_string()),
+            },
+            n => match n.parse() {
+                Ok(n) => count = n,
+                Err(_) => return MagicResult::Error(usage.to_string()),
+            },
+        }
+    }
+
+    let entries: Vec<&HistoryEntry> = match range {
+        Some((first, last)) => state
+            .history
+            .iter()
+            .filter(|e| e.number >= first && e.number <= last)
+            .collect(),
+        // A saved transcript covers the whole session unless a range is given
+        None if save.is_some() => state.history.iter().collect(),
+        None => {
+            let start = state.history.len().saturating_sub(count);
+            state.history[start..].iter().collect()
+        }
+    };
+
+    let Some(path) = save else {
+        return MagicResult::Output(if full {
+            format_history_full(&entries)
+        } else {
+            format_history_entries(&entries)
+        });
+    };
+
+    let path = Path::new(path);
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.is_dir() {
+            return MagicResult::Error(format!("Directory does not exist: {}", parent.display()));
+        }
+    }
+    match std::fs::write(path, format_history_full(&entries)) {
+        Ok(()) => MagicResult::Output(format!(
+            "Saved {} entr{} to {}",
+            entries.len(),
+            if entries.len() == 1 { "y" } else { "ies" },
+            path.display()
+        )),
+        Err(e) => MagicResult::Error(format!("Failed to write {}: {}", path.display(), e)),
+    }
+}
+
+/// Parse an inclusive `M-N` range of history numbers
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (first, last) = s.split_once('-')?;
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    (first <= last).then_some((first, last))
+}
+
+/// %env: list FORM-related variables, show one, or set NAME=VALUE
+fn magic_env(args: &[&str], state: &mut SessionState) -> MagicResult {
+    if args.is_empty() {
+        let mut vars: Vec<(String, String)> = env::vars()
+            .filter(|(name, _)| name.to_uppercase().contains("FORM"))
+            .filter(|(name, _)| !state.run_options.env.iter().any(|(n, _)| n == name))
+            .chain(state.run_options.env.iter().cloned())
+            .collect();
+        vars.sort();
+        if vars.is_empty() {
+            return MagicResult::Output("No FORM-related environment variables set.".to_string());
+        }
+        let lines: Vec<String> = vars
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        return MagicResult::Output(lines.join("\n"));
+    }
+    
+    let arg = args.join(" ");
+    match arg.split_once('=') {
+        Some((name, value)) => {
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return MagicResult::Error(format!("Invalid variable name: '{}'", name));
+            }
+            state.set_env(name, value.trim());
+            let mut msg = format!("{}={}", name, value.trim());
+            if SENSITIVE_ENV_VARS.contains(&name.to_uppercase().as_str()) {
+                msg.push_str(&format!(
+                    "\nWarning: {} affects how programs are found and loaded; %reset restores it.",
+                    name
+                ));
+            }
+            MagicResult::Output(msg)
+        }
+        None => match state.env_var(&arg) {
+            Some(value) => MagicResult::Output(format!("{}={}", arg, value)),
+            None => MagicResult::Error(format!("{} is not set", arg)),
+        },
+    }
+}
+
+/// Append a warning to a success message if saving failed
+fn with_save_warning(msg: String, saved: Result<(), String>) -> String {
+    match saved {
+        Ok(()) => msg,
+        Err(e) => format!("{}\nWarning: macro not saved: {}", msg, e),
+    }
+}
+
+/// Expand `@name` macros at the start of input lines.
+///
+/// The `@name` is replaced by the macro body; anything after it on the
+/// line is kept.
+pub fn expand_macros(input: &str, state: &SessionState) -> Result<String, String> {
+    let mut lines = Vec::new();
+    for line in input.lines() {
+        let trimmed = line.trim_start();
+        let Some(rest) = trimmed.strip_prefix('@') else {
+            lines.push(line.to_string());
+            continue;
+        };
+        let name_len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let (name, tail) = rest.split_at(name_len);
+        let body = state
+            .macros
+            .get(name)
+            .ok_or_else(|| format!("Unknown macro @{} (see %macros)", name))?;
+        let indent = &line[..line.len() - trimmed.len()];
+        lines.push(format!("{}{}{}", indent, body, tail));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Substitute references to the last output in a cell.
+///
+/// `_{raw}` splices the last output verbatim. `_{oneline}` splices the
+/// expression body on a single line, with the `E =` label and trailing
+/// `;` removed, so it can be used as a right-hand side.
+pub fn substitute_output_refs(input: &str, state: &SessionState) -> Result<String, String> {
+    use regex::{Captures, Regex};
+    use std::sync::LazyLock;
+    
+    static REF_RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(^|[^A-Za-z0-9_])_\{([A-Za-z]+)\}").unwrap()
+    });
+    
+    if !REF_RE.is_match(input) {
+        return Ok(input.to_string());
+    }
+    
+    let last = state.last_output()
+        .ok_or_else(|| "No previous output to substitute for _{...}".to_string())?;
+    
+    let mut error = None;
+    let result = REF_RE.replace_all(input, |caps: &Captures| {
+        let value = match &caps[2] {
+            "raw" => last.clone(),
+            "oneline" => oneline_output(last),
+            other => {
+                error.get_or_insert_with(|| format!(
+                    "Unknown output modifier _{{{}}}; use _{{raw}} or _{{oneline}}",
+                    other
+                ));
+                String::new()
+            }
+        };
+        format!("{}{}", &caps[1], value)
+    });
+    
+    match error {
+        Some(e) => Err(e),
+        None => Ok(result.into_owned()),
+    }
+}
+
+/// Collapse a FORM output block to its expression body on one line
+fn oneline_output(output: &str) -> String {
+    let joined = output.split_whitespace().collect::<Vec<_>>().join(" ");
+    let body = match joined.split_once(" = ") {
+        Some((label, rest)) if !label.contains(' ') => rest,
+        _ => joined.as_str(),
+    };
+    body.trim_end_matches(';').trim_end().to_string()
+}
+
+/// Entries with only the first line of each input and output
+fn format_history_entries(entries: &[&HistoryEntry]) -> String {
+    let mut output = String::new();
+    
+    for entry in entries {
+        output.push_str(&format!("In [{}]: {}\n", entry.number, 
+            entry.input.lines().next().unwrap_or("")));
+        
+        // Show truncated input if multi-line
+        if entry.input.lines().count() > 1 {
+            output.push_str("        ...\n");
+        }
+        
+        if let Some(ref out) = entry.output {
+            let first_line = out.lines().next().unwrap_or("");
+            if !first_line.trim().is_empty() {
+                output.push_str(&format!("Out[{}]: {}\n", entry.number, first_line));
+                if out.lines().count() > 1 {
+                    output.push_str("        ...\n");
+                }
+            }
+        }
+        
+        if let Some(dur) = entry.duration {
+            output.push_str(&format!("        ({:.3}s)\n", dur.as_secs_f64()));
+        }
+        output.push('\n');
+    }
+    
+    output
+}
+
+/// Transcript of the entries with every line of input and output
+fn format_history_full(entries: &[&HistoryEntry]) -> String {
+    let mut output = String::new();
+
+    for entry in entries {
+        push_block(&mut output, &format!("In [{}]: ", entry.number), &entry.input);
+        if let Some(out) = entry.output.as_deref().filter(|o| !o.trim().is_empty()) {
+            push_block(&mut output, &format!("Out[{}]: ", entry.number), out);
+        }
+        if let Some(dur) = entry.duration {
+            output.push_str(&format!("        ({:.3}s)\n", dur.as_secs_f64()));
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Append `text` after `label`, indenting continuation lines to match
+fn push_block(output: &mut String, label: &str, text: &str) {
+    let indent = " ".repeat(label.len());
+    for (i, line) in text.lines().enumerate() {
+        let prefix = if i == 0 { label } else { indent.as_str() };
+        output.push_str(format!("{}{}", prefix, line).trim_end());
+        output.push('\n');
+    }
+}
+
+/// Width of the longest bar in the %benchmark chart
+const BENCHMARK_BAR_WIDTH: usize = 40;
+
+/// The timed cells, slowest first, as a table plus a bar chart
+fn benchmark_report(history: &[HistoryEntry]) -> String {
+    let mut timed: Vec<(&HistoryEntry, Duration)> = history
+        .iter()
+        .filter_map(|entry| entry.duration.map(|d| (entry, d)))
+        .collect();
+    if timed.is_empty() {
+        return "No timed cells in this session.".to_string();
+    }
+    timed.sort_by_key(|(_, d)| std::cmp::Reverse(*d));
+    
+    let bars: Vec<(String, Duration)> = timed
+        .iter()
+        .map(|(entry, d)| (format!("In [{}]", entry.number), *d))
+        .collect();
+    let label_width = bars.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+    let shown: Vec<String> = bars.iter().map(|(_, d)| term::format_duration(*d)).collect();
+    let duration_width = shown.iter().map(|d| d.chars().count()).max().unwrap_or(0);
+    
+    let mut lines: Vec<String> = timed
+        .iter()
+        .zip(bars.iter().zip(&shown))
+        .map(|((entry, _), ((label, _), duration))| {
+            format!(
+                "{:<lw$} | {:>dw$} | {}",
+                label,
+                duration,
+                entry.input.lines().next().unwrap_or(""),
+                lw = label_width,
+                dw = duration_width
+            )
+        })
+        .collect();
+    let total: Duration = timed.iter().map(|(_, d)| *d).sum();
+    lines.push(String::new());
+    lines.push(format!(
+        "Total: {} over {} cell{}",
+        term::format_duration(total),
+        timed.len(),
+        if timed.len() == 1 { "" } else { "s" }
+    ));
+    lines.push(String::new());
+    lines.push(term::render_bar_chart(&bars, BENCHMARK_BAR_WIDTH));
+    lines.join("\n")
+}
+
+/// Names of the expressions in a FORM output listing, in order.
+///
+/// FORM prints each expression as `   NAME =` (or `   NAME = terms;`),
+/// indented; the `Time =` statistics line is not an expression.
+fn parse_expression_names(output: &str) -> Vec<String> {
+    use regex::Regex;
+    use std::sync::LazyLock;
+    
+    static EXPR_RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^\s+([A-Za-z][A-Za-z0-9]*(?:\([^)]*\))?)\s*=").unwrap()
+    });
+    
+    let mut names: Vec<String> = Vec::new();
+    for line in output.lines() {
+        if let Some(cap) = EXPR_RE.captures(line) {
+            let name = &cap[1];
+            if name != "Time" && !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Expressions printed anywhere in the session, sorted
+fn expression_names(history: &[HistoryEntry]) -> Vec<String> {
+    let mut names: Vec<String> = history
+        .iter()
+        .filter_map(|entry| entry.output.as_deref())
+        .flat_map(parse_expression_names)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The symbols declared in the inputs, for `%who`
+fn who_listing(history: &[HistoryEntry]) -> String {
+    let symbols = extract_symbols(history);
+    if symbols.is_empty() {
+        "No symbols declared in this session.".to_string()
+    } else {
+        format!("Declared symbols: {}", symbols.join(", "))
+    }
+}
+
+/// Everything the session declared, as FORM lists it, for `%whos`: the
+/// declarations of the startup file and every cell, run with `On names;`
+fn whos(state: &SessionState) -> Result<Vec<form::DeclaredSymbol>, String> {
+    let form_path = state.form_path.as_deref().ok_or("FORM executable not configured")?;
+    let inputs = state
+        .startup
+        .iter()
+        .map(String::as_str)
+        .chain(state.history.iter().map(|e| e.input.as_str()));
+    let program = form::names_listing_program(inputs);
+    let result = form::run_form(&program, form_path, &state.finished_run_options())
+        .map_err(|e| format!("FORM could not list the names: {}", e))?;
+    Ok(form::parse_form_symbols_output(&result.output))
+}
+
+/// Extract declared symbols from session history
+fn extract_symbols(history: &[HistoryEntry]) -> Vec<String> {
+    use regex::Regex;
+    use std::collections::HashSet;
+    use std::sync::LazyLock;
+    
+    static SYMBOL_RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)\b(?:Symbol|Symbols)\s+([^;]+);").unwrap()
+    });
+    
+    let mut symbols = HashSet::new();
+    
+    for entry in history {
+        for cap in SYMBOL_RE.captures_iter(&entry.input) {
+            if let Some(m) = cap.get(1) {
+                for sym in m.as_str().split(',') {
+                    let clean = sym.trim()
+                        .split('(').next().unwrap_or("")
+                        .trim();
+                    if !clean.is_empty() && clean.chars().next().map(|c| c.is_alphabetic()).unwrap_or(false) {
+                        symbols.insert(clean.to_string());
+                    }
+                }
+            }
+        }
+    }
+    
+    let mut result: Vec<_> = symbols.into_iter().collect();
     result.sort();
     result
 }
@@ -299,6 +2136,77 @@ fn extract_symbols(history: &[HistoryEntry]) -> Vec<String> {
 mod tests {
     use super::*;
     
+    #[test]
+    fn test_session_save_load_round_trip() {
+        let dir = env::temp_dir().join(format!("form-repl-sessions-test-{}", std::process::id()));
+        let mut state = SessionState::new();
+        state.sessions_dir = dir.clone();
+        state.add_entry("Local E = x;".to_string(), Some("   E = x;".to_string()), Some(Duration::from_millis(500)));
+        state.add_entry("Local F = y;".to_string(), Some("   F = y;".to_string()), None);
+
+        assert!(matches!(process_magic("%session save", &mut state, false, "none"), MagicResult::Error(_)));
+        process_magic("%session save work", &mut state, false, "none");
+        assert_eq!(state.name.as_deref(), Some("work"));
+
+        let mut other = SessionState::new();
+        other.sessions_dir = dir.clone();
+        match process_magic("%session load work", &mut other, false, "none") {
+            MagicResult::Output(msg) => assert_eq!(msg, "Loaded session 'work' (2 entries)"),
+            _ => panic!("Expected Output result"),
+        }
+        assert_eq!(other.name.as_deref(), Some("work"));
+        assert_eq!(other.session_number, 3);
+        assert_eq!(other.history[0].input, "Local E = x;");
+        assert_eq!(other.history[0].duration, Some(Duration::from_millis(500)));
+        assert_eq!(other.last_output().map(String::as_str), Some("   F = y;"));
+
+        process_magic("%session rename work done", &mut other, false, "none");
+        assert_eq!(other.name.as_deref(), Some("done"));
+        match process_magic("%session list", &mut other, false, "none") {
+            MagicResult::Output(list) => assert_eq!(list, "* done"),
+            _ => panic!("Expected Output result"),
+        }
+        process_magic("%session delete done", &mut other, false, "none");
+        assert_eq!(other.name, None);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_include_cell() {
+        let dir = env::temp_dir().join(format!("form-repl-include-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.h"), "Symbol x;\n").unwrap();
+
+        let cell = include_cell("lib.h", "Local E = x;\nPrint;", &dir).unwrap();
+        assert_eq!(cell, format!("#include {}\nLocal E = x;\nPrint;", dir.join("lib.h").display()));
+        assert!(include_cell("missing.h", "", &dir).unwrap_err().starts_with("No such file"));
+
+        let mut state = SessionState::new();
+        state.working_dir = dir.clone();
+        match process_magic("%include lib.h", &mut state, false, "none") {
+            MagicResult::Execute { code, .. } => {
+                assert_eq!(code, format!("#include {}", dir.join("lib.h").display()))
+            }
+            _ => panic!("Expected Execute result"),
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_magic_size() {
+        let mut state = SessionState::new();
+        state.add_entry(
+            "Local E = (x+y)^2;".to_string(),
+            Some("   E =\n      x^2 + 2*x*y + y^2;".to_string()),
+            None,
+        );
+        match process_magic("%size E", &mut state, false, "none") {
+            MagicResult::Output(text) => assert_eq!(text, "E: 3 terms, depth 0, 2 symbols, 7 nodes"),
+            _ => panic!("Expected Output result"),
+        }
+        assert!(matches!(process_magic("%size F", &mut state, false, "none"), MagicResult::Error(_)));
+    }
+
     #[test]
     fn test_session_state() {
         let mut state = SessionState::new();
@@ -307,6 +2215,331 @@ mod tests {
         assert_eq!(state.last_output(), Some(&"output".to_string()));
     }
     
+    #[test]
+    fn test_split_frm_blocks() {
+        let content = "* header\n\nSymbol x;\nLocal E = x;\n.sort\nPrint;\n.end\n";
+        assert_eq!(
+            split_frm_blocks(content),
+            vec!["* header", "Symbol x;\nLocal E = x;\n.sort", "Print;\n.end"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_import_frm_to_history() {
+        let exe = form::mock_form("load-test", form::WRITE_FORM);
+        let dir = exe.parent().unwrap().to_path_buf();
+        let script = dir.join("calc.frm");
+        std::fs::write(
+            &script,
+            "* header\n\nSymbol x;\n#write \"a\"\n.sort\nLocal E = x;\n#write \"b\"\n.sort\n#write \"c\"\n.end\n",
+        )
+        .unwrap();
+
+        let mut state = SessionState::new();
+        state.form_path = Some(exe);
+        let result = process_magic(&format!("%load {}", script.display()), &mut state, false, "none");
+        assert!(matches!(result, MagicResult::Output(ref msg) if msg.starts_with("Imported 4 blocks")));
+
+        let outputs: Vec<Option<&str>> = state.history.iter().map(|e| e.output.as_deref()).collect();
+        assert_eq!(outputs, vec![None, Some("   a"), Some("   b"), Some("   c")]);
+        assert_eq!(state.history[3].input, "#write \"c\"\n.end");
+        assert_eq!(state.session_number, 5);
+
+        assert!(import_frm_to_history(&dir.join("missing.frm"), &mut state, Path::new("form")).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_startup_file_declarations_reach_first_cell() {
+        // Fake FORM that only knows x once it has been declared
+        let exe = form::mock_form(
+            "startup-test",
+            "case \"$(cat)\" in *'Symbols x;'*) echo '   E = x;' ;; \
+             *) echo 'x is not a variable'; exit 1 ;; esac",
+        );
+        let dir = exe.parent().unwrap().to_path_buf();
+        let startup = dir.join("startup.frm");
+        let cell = "Local E = x;\nPrint;";
+
+        let mut state = SessionState::new();
+        assert_eq!(run_startup_file(&startup, &mut state, &exe), Ok(None));
+        assert_eq!(state.program(cell), cell);
+        assert!(form::run_form(&state.program(cell), &exe, &state.run_options).is_err());
+
+        std::fs::write(&startup, "Symbols y;\n").unwrap();
+        assert!(run_startup_file(&startup, &mut state, &exe).is_err());
+        assert_eq!(state.startup, None);
+
+        std::fs::write(&startup, "Symbols x;\n.end\n").unwrap();
+        assert!(run_startup_file(&startup, &mut state, &exe).unwrap().is_some());
+        assert_eq!(state.startup.as_deref(), Some("Symbols x;"));
+        let result = form::run_form(&state.program(cell), &exe, &state.run_options).unwrap();
+        assert_eq!(form::format_output(&result.output, false, 0), "   E = x;");
+        assert!(state.declared_names().contains("x"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load_runs_after_startup_code() {
+        // Fake FORM that only knows x once it has been declared
+        let exe = form::mock_form(
+            "load-startup-test",
+            "input=$(cat)\ncase \"$input\" in *'Symbols x;'*) ;; *) echo 'x is not a variable'; exit 1 ;; esac\n\
+             echo 'FORM 4.3.1 (fake)'\necho\n\
+             printf '%s\\n' \"$input\" | sed -n -e 's/^#write \"\\(.*\\)\"/   \\1/p' -e 's/^#message \\(.*\\)/~~~\\1/p'",
+        );
+        let dir = exe.parent().unwrap().to_path_buf();
+        let script = dir.join("calc.frm");
+        std::fs::write(&script, "Local E = x;\n#write \"a\"\n.sort\n#write \"b\"\n.end\n").unwrap();
+
+        for batch_threshold in [0, 1] {
+            let mut state = SessionState::new();
+            state.batch_threshold = batch_threshold;
+            assert!(import_frm_to_history(&script, &mut state, &exe).is_err());
+            state.startup = Some("Symbols x;".to_string());
+            assert_eq!(import_frm_to_history(&script, &mut state, &exe), Ok(2), "{}", batch_threshold);
+            let outputs: Vec<Option<&str>> = state.history.iter().map(|e| e.output.as_deref()).collect();
+            assert_eq!(outputs, [Some("   a"), Some("   b")], "{}", batch_threshold);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_startup_and_load_end_without_auto_end() {
+        // Fake FORM that fails on a program never ended with .end
+        let exe = form::mock_form(
+            "no-auto-end-test",
+            "case \"$(cat)\" in *.end) echo '   E = 1;' ;; *) echo 'missing .end'; exit 1 ;; esac",
+        );
+        let dir = exe.parent().unwrap().to_path_buf();
+        let startup = dir.join("startup.frm");
+        std::fs::write(&startup, "Symbols x;\n.end\n").unwrap();
+        let script = dir.join("calc.frm");
+        std::fs::write(&script, "Local E = 1;\n.sort\nPrint;\n.end\n").unwrap();
+
+        let mut state = SessionState::new();
+        state.run_options.terminator = None;
+        assert!(run_startup_file(&startup, &mut state, &exe).unwrap().is_some());
+        assert_eq!(import_frm_to_history(&script, &mut state, &exe), Ok(2));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_substitutions() {
+        assert_eq!(
+            parse_substitutions("x=1, y = 2*z"),
+            Ok(vec![("x".to_string(), "1".to_string()), ("y".to_string(), "2*z".to_string())])
+        );
+        assert!(parse_substitutions("").is_err());
+        assert!(parse_substitutions("x").is_err());
+        assert!(parse_substitutions("x=").is_err());
+        assert!(parse_substitutions("2x=1").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_magic_sub_substitutes_into_last_output() {
+        // Fake FORM that keeps the program it was given and prints E = 3
+        let exe = form::mock_form(
+            "sub-test",
+            "cat > \"$(dirname \"$0\")/program\"\necho 'FORM 4.3.1 (fake)'\necho\n\
+             echo '   E ='\necho '      3;'",
+        );
+        let dir = exe.parent().unwrap().to_path_buf();
+
+        let mut state = SessionState::new();
+        state.form_path = Some(exe);
+        assert!(matches!(process_magic("%sub x=1", &mut state, false, "none"), MagicResult::Error(_)));
+        state.add_entry(
+            "Symbols x, y;\nLocal E = x + y;\nPrint;".to_string(),
+            Some("   E =\n      x + y;".to_string()),
+            None,
+        );
+        match process_magic("%sub x=1, y=2", &mut state, false, "none") {
+            MagicResult::Output(out) => assert_eq!(out, "   E =\n      3;"),
+            _ => panic!("expected the substituted output"),
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir.join("program")).unwrap(),
+            "Symbols x, y;\nLocal E = x + y;\nid x = 1;\nid y = 2;\nPrint E;\n.end"
+        );
+        assert_eq!(state.last_output().map(String::as_str), Some("   E =\n      3;"));
+        assert_eq!(state.history.len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rerun_all_reports_diverging_cell() {
+        let exe = form::mock_form("rerun-test", form::WRITE_FORM);
+        let dir = exe.parent().unwrap().to_path_buf();
+
+        let mut state = SessionState::new();
+        state.form_path = Some(exe);
+        state.add_entry("#write \"r1\"".to_string(), Some("   r1".to_string()), None);
+        // Recorded before something changed: re-running gives r2
+        state.add_entry("#write \"r2\"".to_string(), Some("   old".to_string()), None);
+        state.add_entry("#write \"r3\"".to_string(), Some("   r3".to_string()), None);
+
+        match process_magic("%rerun-all", &mut state, false, "none") {
+            MagicResult::Error(report) => {
+                assert!(report.contains("In [2] differs:\n   [-old-]{+r2+}"), "{}", report);
+                assert!(report.ends_with("2 of 3 cells re-run, 1 matched"));
+            }
+            _ => panic!("expected a divergence"),
+        }
+        match process_magic("%rerun-all --continue", &mut state, false, "none") {
+            MagicResult::Error(report) => assert!(report.ends_with("3 of 3 cells re-run, 2 matched")),
+            _ => panic!("expected a divergence"),
+        }
+        state.history[1].output = Some("   r2".to_string());
+        assert!(matches!(
+            process_magic("%rerun-all", &mut state, false, "none"),
+            MagicResult::Output(ref report) if report == "3 of 3 cells re-run, 3 matched"
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rerun_all_replays_loaded_blocks_together() {
+        // Fake FORM that only knows x once it has been declared
+        let exe = form::mock_form(
+            "rerun-load-test",
+            "input=$(cat)\ncase \"$input\" in *'Symbols x;'*) ;; *) echo 'x is not a variable'; exit 1 ;; esac\n\
+             echo 'FORM 4.3.1 (fake)'\necho\nprintf '%s\\n' \"$input\" | sed -n 's/^#write \"\\(.*\\)\"/   \\1/p'",
+        );
+        let dir = exe.parent().unwrap().to_path_buf();
+        let script = dir.join("calc.frm");
+        std::fs::write(&script, "Symbols x;\n#write \"a\"\n.sort\n#write \"b\"\n.end\n").unwrap();
+
+        let mut state = SessionState::new();
+        state.batch_threshold = 0;
+        state.add_entry("Symbols x;\n#write \"c\"".to_string(), Some("   c".to_string()), None);
+        assert_eq!(import_frm_to_history(&script, &mut state, &exe), Ok(2));
+        let marks: Vec<Option<usize>> = state.history.iter().map(|e| e.loaded_with).collect();
+        assert_eq!(marks, [None, Some(2), Some(2)]);
+        // On its own, the second block would fail for want of x
+        let (report, ok) = rerun_all(&state, &exe, false);
+        assert!(ok, "{}", report);
+        assert_eq!(report, "3 of 3 cells re-run, 3 matched");
+
+        // Saved sessions keep the mark
+        let saved = saved_session(&state);
+        let mut restored = SessionState::new();
+        restore_session(&mut restored, saved);
+        assert_eq!(restored.history[2].loaded_with, Some(2));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_import_frm_in_one_batch() {
+        // Fake FORM that counts its runs, prints a banner and echoes #write and #message text
+        let exe = form::mock_form(
+            "batch-load-test",
+            "echo run >> \"$(dirname \"$0\")/runs\"\necho 'FORM 4.3.1 (fake)'\necho\n\
+             sed -n -e 's/^#write \"\\(.*\\)\"/   \\1/p' -e 's/^#message \\(.*\\)/~~~\\1/p'",
+        );
+        let dir = exe.parent().unwrap().to_path_buf();
+        let script = dir.join("long.frm");
+        let blocks: Vec<String> = (1..=6).map(|i| format!("#write \"r{}\"\n.sort", i)).collect();
+        std::fs::write(&script, blocks.join("\n")).unwrap();
+
+        let mut state = SessionState::new();
+        state.form_path = Some(exe);
+        let result = process_magic(&format!("%load {}", script.display()), &mut state, false, "none");
+        assert!(matches!(result, MagicResult::Output(ref msg) if msg.starts_with("Imported 6 blocks")));
+        let outputs: Vec<Option<&str>> = state.history.iter().map(|e| e.output.as_deref()).collect();
+        let expected: Vec<String> = (1..=6).map(|i| format!("   r{}", i)).collect();
+        assert_eq!(outputs, expected.iter().map(|s| Some(s.as_str())).collect::<Vec<_>>());
+        assert_eq!(std::fs::read_to_string(dir.join("runs")).unwrap().lines().count(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_magic_form_switches_binary() {
+        let exe = form::mock_form("switch-test", "cat >/dev/null");
+        let dir = exe.parent().unwrap().to_path_buf();
+
+        let mut state = SessionState::new();
+        let old = PathBuf::from("/usr/bin/form");
+        state.form_path = Some(old.clone());
+        match process_magic("%form", &mut state, false, "none") {
+            MagicResult::Output(s) => assert_eq!(s, "FORM path: /usr/bin/form"),
+            _ => panic!("Expected Output result"),
+        }
+
+        // A bad path is an error and keeps the binary in use
+        let missing = format!("%form {}", dir.join("missing").display());
+        assert!(matches!(process_magic(&missing, &mut state, false, "none"), MagicResult::Error(_)));
+        assert!(matches!(
+            process_magic("%form no-such-form-binary", &mut state, false, "none"),
+            MagicResult::Error(_)
+        ));
+        assert_eq!(state.form_path, Some(old));
+
+        // Relative to the working directory
+        state.working_dir = dir.clone();
+        let result = process_magic("%form form", &mut state, false, "none");
+        assert!(matches!(result, MagicResult::Output(ref s) if s.starts_with("Using FORM")));
+        assert_eq!(state.form_path, Some(exe));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_use_form_binary_filters_flags_for_every_run() {
+        // Sequential FORM 4: takes -q but not -w
+        let exe = form::mock_form("flags-test", "echo 'FORM 4.3.1 (Apr 11 2023, v4.3.1) 64-bits'");
+        let mut state = SessionState::new();
+        state.form_flags = vec!["-w4".to_string(), "-q".to_string()];
+        assert_eq!(state.use_form_binary(exe.clone()), ["-w4"]);
+        assert_eq!(state.run_options.extra_args, ["-q"]);
+        assert_eq!(state.finished_run_options().extra_args, ["-q"]);
+        assert_eq!(state.form_path, Some(exe.clone()));
+        // The flags asked for are kept for the next binary
+        assert_eq!(state.form_flags, ["-w4", "-q"]);
+        let _ = std::fs::remove_dir_all(exe.parent().unwrap());
+    }
+
+    #[test]
+    fn test_history_save_full_transcript() {
+        let mut state = SessionState::new();
+        state.add_entry(
+            "Symbol x;\nLocal E = (x+1)^2;\nPrint;".to_string(),
+            Some("   E =\n      1 + 2*x + x^2;".to_string()),
+            Some(Duration::from_millis(250)),
+        );
+        state.add_entry("Local F = 3;\nPrint;".to_string(), Some("   F = 3;".to_string()), None);
+
+        let dir = env::temp_dir().join(format!("form-repl-magic-history-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("transcript.txt");
+        let result = process_magic(&format!("%history --save {}", path.display()), &mut state, false, "none");
+        assert!(matches!(result, MagicResult::Output(ref msg) if msg.starts_with("Saved 2 entries")));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "In [1]: Symbol x;\n        Local E = (x+1)^2;\n        Print;\n\
+             Out[1]:    E =\n              1 + 2*x + x^2;\n        (0.250s)\n\n\
+             In [2]: Local F = 3;\n        Print;\nOut[2]:    F = 3;\n\n"
+        );
+
+        process_magic(&format!("%save_history {} --range 2-2", path.display()), &mut state, false, "none");
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("In [2]: Local F = 3;"));
+
+        let missing = dir.join("no-such-dir").join("t.txt");
+        let result = process_magic(&format!("%history --save {}", missing.display()), &mut state, false, "none");
+        assert!(matches!(result, MagicResult::Error(ref e) if e.starts_with("Directory does not exist")));
+        assert!(matches!(process_magic("%history --range 3-1", &mut state, false, "none"), MagicResult::Error(_)));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
     #[test]
     fn test_magic_help() {
         let mut state = SessionState::new();
@@ -314,6 +2547,588 @@ mod tests {
             MagicResult::Help => {}
             _ => panic!("Expected Help result"),
         }
+        match process_magic("%help repeat", &mut state, false, "default") {
+            MagicResult::Output(text) => assert!(text.contains("endrepeat")),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%? bracet", &mut state, false, "default") {
+            MagicResult::Error(e) => assert!(e.contains("Did you mean 'bracket'?")),
+            _ => panic!("Expected Error result"),
+        }
+    }
+    
+    #[test]
+    fn test_magic_env_set_list_and_reset() {
+        let name = "FORM_REPL_TEST_ENV";
+        let mut state = SessionState::new();
+        match process_magic(&format!("%env {}=/tmp/form work", name), &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, format!("{}=/tmp/form work", name)),
+            _ => panic!("Expected Output result"),
+        }
+        assert_eq!(state.run_options.env, vec![(name.to_string(), "/tmp/form work".to_string())]);
+        assert!(env::var(name).is_err());
+        
+        match process_magic("%env", &mut state, false, "default") {
+            MagicResult::Output(out) => assert!(out.contains(&format!("{}=/tmp/form work", name))),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic(&format!("%env {}", name), &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, format!("{}=/tmp/form work", name)),
+            _ => panic!("Expected Output result"),
+        }
+        
+        state.reset();
+        assert!(state.run_options.env.is_empty());
+        assert!(matches!(process_magic(&format!("%env {}", name), &mut state, false, "default"), MagicResult::Error(_)));
+    }
+    
+    #[test]
+    fn test_magic_reset_variants() {
+        let filled = || {
+            let mut state = SessionState::new();
+            state.add_entry("Local E = 1;".to_string(), Some("E = 1;".to_string()), None);
+            state.add_entry("Local F = 2;".to_string(), Some("F = 2;".to_string()), None);
+            state
+        };
+        
+        let mut state = filled();
+        process_magic("%reset out", &mut state, false, "default");
+        assert!(state.last_outputs.is_empty());
+        assert_eq!(state.history.len(), 2);
+        assert_eq!(state.session_number, 3);
+        
+        let mut state = filled();
+        process_magic("%reset-out", &mut state, false, "default");
+        assert!(state.last_outputs.is_empty());
+        assert_eq!(state.history.len(), 2);
+        
+        let mut state = filled();
+        process_magic("%reset in", &mut state, false, "default");
+        assert!(state.history.is_empty());
+        assert_eq!(state.last_outputs.len(), 2);
+        assert_eq!(state.session_number, 3);
+        
+        let mut state = filled();
+        state.cache_results = true;
+        let result = FormResult {
+            output: "   E = 1;".to_string(),
+            stderr: String::new(),
+            duration: Duration::from_millis(1),
+            exit_code: 0,
+        };
+        state.cache_result("Local E = 1;", &result);
+        process_magic("%reset", &mut state, false, "default");
+        assert!(state.history.is_empty());
+        assert!(state.last_outputs.is_empty());
+        assert_eq!(state.session_number, 1);
+        // Cached results, which may live on disk, survive a reset
+        assert!(state.cached_result("Local E = 1;").is_some());
+        
+        assert!(matches!(
+            process_magic("%reset everything", &mut filled(), false, "default"),
+            MagicResult::Error(_)
+        ));
+    }
+    
+    #[test]
+    fn test_magic_full_and_more() {
+        let mut state = SessionState::new();
+        state.max_output_lines = 2;
+        state.set_full_output("a\nb\nc\nd\ne".to_string());
+        
+        match process_magic("%full", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "a\nb\nc\nd\ne"),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%more", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "c\nd\n-- 1 more lines, %more to continue --"),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%more", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "e"),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%more", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "(end of output)"),
+            _ => panic!("Expected Output result"),
+        }
+    }
+    
+    #[test]
+    fn test_magic_expand() {
+        let mut state = SessionState::new();
+        assert!(matches!(process_magic("%expand", &mut state, false, "default"), MagicResult::Error(_)));
+
+        let full = "   E =\n      + x\n      + y\n      + z;";
+        state.max_output_lines = 2;
+        let shown = form::truncate_output(full, state.max_output_lines);
+        assert_eq!(shown, "   E =\n      + x\n... (2 more lines, %expand to show)");
+        state.add_entry("Local E = x+y+z;".to_string(), Some(full.to_string()), None);
+        state.set_full_output(full.to_string());
+        state.add_entry("Local F = 1;".to_string(), Some("   F = 1;".to_string()), None);
+        state.set_full_output("   F = 1;".to_string());
+
+        match process_magic("%expand", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "   F = 1;"),
+            _ => panic!("Expected Output result"),
+        }
+        // Session 1's output is whole in history; -p falls back to printing off a terminal
+        match process_magic("%expand 1 -p", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, full),
+            _ => panic!("Expected Output result"),
+        }
+        assert!(matches!(process_magic("%expand 9", &mut state, false, "default"), MagicResult::Error(_)));
+    }
+    
+    #[test]
+    fn test_magic_cd_and_pwd() {
+        let dir = env::temp_dir().join(format!("form-repl-cd-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        
+        let mut state = SessionState::new();
+        let start = state.initial_dir.clone();
+        match process_magic(&format!("%cd {}", dir.display()), &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, dir.display().to_string()),
+            _ => panic!("Expected Output result"),
+        }
+        assert_eq!(env::current_dir().unwrap(), dir);
+        assert_eq!(state.run_options.workdir.as_deref(), Some(dir.as_path()));
+        match process_magic("%pwd", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, dir.display().to_string()),
+            _ => panic!("Expected Output result"),
+        }
+        
+        assert!(matches!(
+            process_magic("%cd no-such-subdir", &mut state, false, "default"),
+            MagicResult::Error(_)
+        ));
+        assert_eq!(state.working_dir, dir);
+        
+        process_magic("%cd", &mut state, false, "default");
+        assert_eq!(env::current_dir().unwrap(), start);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn test_magic_lint_last_input() {
+        let mut state = SessionState::new();
+        assert!(matches!(process_magic("%lint", &mut state, false, "default"), MagicResult::Error(_)));
+        
+        state.add_entry("Symbol x;\nLocal E = x + y;\nPrint;".to_string(), None, None);
+        match process_magic("%lint", &mut state, false, "default") {
+            MagicResult::Output(out) => {
+                assert_eq!(out, "In [1] line 2:15: error: 'y' is used before it is declared")
+            }
+            _ => panic!("Expected Output result"),
+        }
+        
+        state.add_entry("Symbol x;\nLocal E = x;\nPrint;".to_string(), None, None);
+        match process_magic("%lint", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "In [2]: no issues found"),
+            _ => panic!("Expected Output result"),
+        }
+
+        // "No issues" would be misleading with nothing checked
+        state.lint_config = LinterConfig {
+            no_semicolon: false,
+            undeclared_symbol: false,
+            unused_symbol: false,
+            style: false,
+        };
+        assert!(matches!(process_magic("%lint", &mut state, false, "default"), MagicResult::Error(_)));
+    }
+    
+    #[test]
+    fn test_magic_store_and_restore_round_trip() {
+        let dir = env::temp_dir().join(format!("form-repl-magic-store-{}", std::process::id()));
+        let mut state = SessionState::new();
+        state.store_path = dir.join("store.json");
+        state.add_entry(
+            "Symbol x;\nLocal E = (x+1)^2;\nPrint;".to_string(),
+            Some("   E =\n      x^2 + 2*x + 1;".to_string()),
+            None,
+        );
+        
+        assert!(matches!(process_magic("%store F", &mut state, false, "default"), MagicResult::Error(_)));
+        match process_magic("%store E", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "Stored E"),
+            _ => panic!("Expected Output result"),
+        }
+        
+        // A new session sees the stored value
+        let mut fresh = SessionState::new();
+        fresh.store_path = state.store_path.clone();
+        match process_magic("%store", &mut fresh, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "Stored expressions: E"),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%restore E", &mut fresh, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "   E =\n      x^2 + 2*x + 1;"),
+            _ => panic!("Expected Output result"),
+        }
+        assert_eq!(fresh.last_output().map(String::as_str), Some("   E =\n      x^2 + 2*x + 1;"));
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn test_macro_define_expand_delete() {
+        let path = env::temp_dir()
+            .join(format!("form-repl-magic-macros-{}", std::process::id()))
+            .join("macros.toml");
+        let mut state = SessionState::new();
+        state.macros_path = Some(path.clone());
+        
+        match process_magic("%macro hdr Symbols x, y,  z;", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "Defined @hdr"),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%macro hdr", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "Symbols x, y,  z;"),
+            _ => panic!("Expected Output result"),
+        }
+        assert_eq!(config::load_macros(&path), state.macros);
+        
+        let expanded = expand_macros("@hdr\nLocal E = x+y;\n  @hdr * again", &state).unwrap();
+        assert_eq!(expanded, "Symbols x, y,  z;\nLocal E = x+y;\n  Symbols x, y,  z; * again");
+        assert!(expand_macros("@nope", &state).is_err());
+        
+        match process_magic("%macros", &mut state, false, "default") {
+            MagicResult::Output(out) => assert!(out.starts_with("@hdr")),
+            _ => panic!("Expected Output result"),
+        }
+        assert!(matches!(process_magic("%delmacro hdr", &mut state, false, "default"), MagicResult::Output(_)));
+        assert!(state.macros.is_empty());
+        assert!(config::load_macros(&path).is_empty());
+        assert!(matches!(process_magic("%delmacro hdr", &mut state, false, "default"), MagicResult::Error(_)));
+        
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+    
+    #[test]
+    fn test_takes_code() {
+        assert!(takes_code("%timeit"));
+        assert!(takes_code("  %TimeIt "));
+        assert!(!takes_code("%time"));
+        assert!(takes_code("%include lib.h"));
+        assert!(!takes_code("%history 5"));
+        assert!(takes_code("%capture out"));
+    }
+    
+    #[test]
+    fn test_magic_capture() {
+        let dir = env::temp_dir().join(format!("form-repl-magic-capture-{}", std::process::id()));
+        let mut state = SessionState::new();
+        state.store_path = dir.join("store.json");
+        
+        assert!(matches!(process_magic("%capture", &mut state, false, "default"), MagicResult::Error(_)));
+        let (code, name) = match process_magic("%capture out\nLocal E = x;", &mut state, false, "default") {
+            MagicResult::Capture { code, name } => (code, name),
+            _ => panic!("Expected Capture result"),
+        };
+        assert_eq!(code, "Local E = x;");
+        assert_eq!(name.as_deref(), Some("out"));
+        
+        // The captured cell is recorded in history but only summarized
+        let msg = record_captured(
+            &mut state,
+            code,
+            "   E =\n      x;".to_string(),
+            Duration::from_millis(5),
+            name.as_deref(),
+        )
+        .unwrap();
+        assert_eq!(msg, "Output captured (2 lines suppressed), stored as out");
+        assert_eq!(state.history.len(), 1);
+        assert_eq!(state.last_output().map(String::as_str), Some("   E =\n      x;"));
+        match process_magic("%restore out", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "   E =\n      x;"),
+            _ => panic!("Expected Output result"),
+        }
+
+        // A store that can't be written doesn't lose the cell
+        state.store_path = dir.join("store.json").join("not-a-dir");
+        let err = record_captured(
+            &mut state,
+            "Local F = x;".to_string(),
+            "   F =\n      x;".to_string(),
+            Duration::from_millis(5),
+            Some("f"),
+        )
+        .unwrap_err();
+        assert!(err.starts_with("Output captured (2 lines suppressed), but not stored as f"), "{}", err);
+        assert_eq!(state.history.len(), 2);
+        assert_eq!(state.last_output().map(String::as_str), Some("   F =\n      x;"));
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn test_magic_timeit_body() {
+        let mut state = SessionState::new();
+        match process_magic("%timeit\nSymbol x;\nPrint;", &mut state, false, "default") {
+            MagicResult::Execute { code, timed } => {
+                assert_eq!(code, "Symbol x;\nPrint;");
+                assert!(timed);
+            }
+            _ => panic!("Expected Execute result"),
+        }
+        assert!(matches!(
+            process_magic("%timeit", &mut state, false, "default"),
+            MagicResult::Error(_)
+        ));
+    }
+    
+    #[test]
+    fn test_magic_dryrun() {
+        let mut state = SessionState::new();
+        process_magic("%dryrun on", &mut state, false, "default");
+        assert!(state.dry_run);
+        process_magic("%dryrun off", &mut state, false, "default");
+        assert!(!state.dry_run);
+        process_magic("%dryrun", &mut state, false, "default");
+        assert!(state.dry_run);
+    }
+    
+    #[test]
+    fn test_magic_format() {
+        let mut state = SessionState::new();
+        assert!(matches!(process_magic("%format", &mut state, false, "none"), MagicResult::Error(_)));
+        state.add_entry("Symbol x;\nRepeat;\nid x=1;\nEndRepeat;".to_string(), None, None);
+        state.add_entry("Print;".to_string(), None, None);
+        match process_magic("%format 1", &mut state, false, "none") {
+            MagicResult::Output(s) => assert_eq!(s, "symbol x;\nrepeat;\n  id x = 1;\nendrepeat;"),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%format", &mut state, false, "none") {
+            MagicResult::Output(s) => assert_eq!(s, "print;"),
+            _ => panic!("Expected Output result"),
+        }
+    }
+
+    #[test]
+    fn test_confirmation_prompt() {
+        assert_eq!(confirmation_prompt("%reset"), Some("Reset all session state? [y/N] "));
+        assert_eq!(confirmation_prompt("%clear out"), Some("Clear cached outputs? [y/N] "));
+        assert_eq!(confirmation_prompt("%reset-in"), Some("Clear input history? [y/N] "));
+        assert_eq!(confirmation_prompt("%cache clear"), Some("Clear cached results? [y/N] "));
+        // -y skips the question, and harmless magics never ask
+        assert_eq!(confirmation_prompt("%reset -y"), None);
+        assert_eq!(confirmation_prompt("%reset --yes in"), None);
+        assert_eq!(confirmation_prompt("%cache on"), None);
+        assert_eq!(confirmation_prompt("%history"), None);
+        assert!(is_yes(" Y\n") && is_yes("yes") && !is_yes("") && !is_yes("no"));
+
+        // The flag is not mistaken for a %reset target
+        let mut state = SessionState::new();
+        state.add_entry("Symbol x;".to_string(), None, None);
+        match process_magic("%reset -y", &mut state, false, "default") {
+            MagicResult::Output(s) => assert_eq!(s, "Session reset. History cleared."),
+            _ => panic!("Expected Output result"),
+        }
+        assert!(state.history.is_empty());
+    }
+
+    #[test]
+    fn test_result_cache() {
+        let mut state = SessionState::new();
+        let result = FormResult {
+            output: "   E =\n      x^2;".to_string(),
+            stderr: String::new(),
+            duration: Duration::from_millis(40),
+            exit_code: 0,
+        };
+        // Off by default: nothing is stored or found
+        state.cache_result("Local E = x^2;", &result);
+        assert!(state.cached_result("Local E = x^2;").is_none());
+
+        process_magic("%cache on", &mut state, false, "default");
+        assert!(state.cache_results);
+        assert!(state.cached_result("Local E = x^2;").is_none());
+        state.cache_result("Local E = x^2;", &result);
+        // Trailing whitespace and blank lines don't make a cell different
+        let hit = state.cached_result("Local E = x^2;  \n\n").unwrap();
+        assert_eq!(hit.output, result.output);
+        assert!(state.cached_result("Local E = x^3;").is_none());
+        // Indentation does: `*` in column 1 is a comment
+        assert_ne!(state.cache_key("Local E = x\n*y;"), state.cache_key("Local E = x\n  *y;"));
+        // The same code run with other FORM flags, or another version, misses
+        state.run_options.extra_args = vec!["-w4".to_string()];
+        assert!(state.cached_result("Local E = x^2;").is_none());
+        state.run_options.extra_args.clear();
+        state.form_version = Some("4.3.1".to_string());
+        assert!(state.cached_result("Local E = x^2;").is_none());
+        state.form_version = None;
+        // Cells with side effects always run
+        state.cache_result("Local F = 1;\n.store", &result);
+        assert!(state.cached_result("Local F = 1;\n.store").is_none());
+
+        match process_magic("%cache", &mut state, false, "default") {
+            MagicResult::Output(s) => assert_eq!(s, "Result cache: ON (1 entries)"),
+            _ => panic!("Expected Output result"),
+        }
+        process_magic("%cache clear", &mut state, false, "default");
+        assert!(state.cached_result("Local E = x^2;").is_none());
+        process_magic("%cache off", &mut state, false, "default");
+        assert!(!state.cache_results);
+        assert!(matches!(
+            process_magic("%cache maybe", &mut state, false, "default"),
+            MagicResult::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_magic_set_separator() {
+        let mut state = SessionState::new();
+        assert!(state.separator.enabled);
+        match process_magic("%set separator off", &mut state, false, "default") {
+            MagicResult::Output(s) => assert_eq!(s, "Separator: OFF"),
+            _ => panic!("Expected Output result"),
+        }
+        assert!(!state.separator.enabled);
+        // Changing the style turns it back on; quotes keep the spaces
+        match process_magic("%set separator char \"- \"", &mut state, false, "default") {
+            MagicResult::Output(s) => assert_eq!(s, "Separator: ON (\"- \", width 60)"),
+            _ => panic!("Expected Output result"),
+        }
+        assert_eq!(state.separator.pattern, "- ");
+        process_magic("%set separator width auto", &mut state, false, "default");
+        assert_eq!(state.separator.width, SeparatorWidth::Auto);
+        process_magic("%set separator width 30", &mut state, false, "default");
+        assert_eq!(state.separator.width, SeparatorWidth::Columns(30));
+        for bad in ["%set separator width 0", "%set separator char", "%set separator thick", "%set colour on", "%set"] {
+            assert!(matches!(process_magic(bad, &mut state, false, "default"), MagicResult::Error(_)), "{}", bad);
+        }
+        assert_eq!(state.separator.width, SeparatorWidth::Columns(30));
+    }
+
+    #[test]
+    fn test_substitute_output_refs() {
+        let mut state = SessionState::new();
+        let output = "   E =\n      x^2 + 2*x*y\n      + y^2;".to_string();
+        state.add_entry("Print;".to_string(), Some(output.clone()), None);
+        
+        let raw = substitute_output_refs("* _{raw}", &state).unwrap();
+        assert_eq!(raw, format!("* {}", output));
+        
+        let oneline = substitute_output_refs("Local F = _{oneline};", &state).unwrap();
+        assert_eq!(oneline, "Local F = x^2 + 2*x*y + y^2;");
+        
+        assert!(substitute_output_refs("Local F = _{bad};", &state).is_err());
+        assert_eq!(substitute_output_refs("id f_{x} = 1;", &state).unwrap(), "id f_{x} = 1;");
+    }
+    
+    #[test]
+    fn test_substitute_output_refs_without_output() {
+        let state = SessionState::new();
+        assert!(substitute_output_refs("Local F = _{raw};", &state).is_err());
+        assert_eq!(substitute_output_refs("Symbol x;", &state).unwrap(), "Symbol x;");
+    }
+    
+    #[test]
+    fn test_clip_text_strips_ansi() {
+        let mut state = SessionState::new();
+        state.add_entry("Print;".to_string(), Some("\x1b[38;5;81m   E =\x1b[0m\n      x;".to_string()), None);
+        state.add_entry("Symbol y;".to_string(), None, None);
+        
+        assert_eq!(clip_text(&state, None).unwrap(), "   E =\n      x;");
+        assert_eq!(clip_text(&state, Some(1)).unwrap(), "   E =\n      x;");
+        assert!(clip_text(&state, Some(2)).is_err());
+        assert!(clip_text(&SessionState::new(), None).is_err());
+    }
+    
+    #[test]
+    fn test_magic_examples() {
+        let mut state = SessionState::new();
+        match process_magic("%examples", &mut state, false, "default") {
+            MagicResult::Output(out) => assert!(out.contains("1. Symbols and powers")),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%examples run 1", &mut state, false, "default") {
+            MagicResult::Execute { code, timed } => {
+                assert_eq!(code, examples::EXAMPLES[0].code);
+                assert!(!timed);
+            }
+            _ => panic!("Expected Execute result"),
+        }
+        assert!(matches!(process_magic("%examples run 99", &mut state, false, "default"), MagicResult::Error(_)));
+        assert!(matches!(process_magic("%examples 1", &mut state, false, "default"), MagicResult::Error(_)));
+    }
+    
+    #[test]
+    fn test_magic_benchmark() {
+        let mut state = SessionState::new();
+        match process_magic("%bench", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "No timed cells in this session."),
+            _ => panic!("Expected Output result"),
+        }
+        state.add_entry("Symbol x;\nLocal E = x;".to_string(), None, Some(Duration::from_millis(10)));
+        state.add_entry("%who".to_string(), None, None);
+        state.add_entry("Local F = (1+x)^20;".to_string(), None, Some(Duration::from_millis(40)));
+        match process_magic("%benchmark", &mut state, false, "default") {
+            MagicResult::Output(out) => {
+                let lines: Vec<&str> = out.lines().collect();
+                assert_eq!(lines[0], "In [3] | 40.00ms | Local F = (1+x)^20;");
+                assert_eq!(lines[1], "In [1] | 10.00ms | Symbol x;");
+                assert_eq!(lines[3], "Total: 50.00ms over 2 cells");
+                assert!(lines[5].ends_with(&"#".repeat(BENCHMARK_BAR_WIDTH)));
+                assert!(lines[6].ends_with("|##########"));
+            }
+            _ => panic!("Expected Output result"),
+        }
+    }
+    
+    #[test]
+    fn test_parse_expression_names() {
+        let listing = "\
+Time =       0.00 sec    Generated terms =          3
+               E         Terms in output =          3
+                         Bytes used      =         52
+
+   E =
+      x^2 + 2*x*y + y^2;
+
+   F(x) = 1;
+
+   G =
+      E;
+";
+        assert_eq!(parse_expression_names(listing), ["E", "F(x)", "G"]);
+        assert!(parse_expression_names("x^2 + 1;").is_empty());
+    }
+    
+    #[test]
+    fn test_magic_vars() {
+        let mut state = SessionState::new();
+        assert!(matches!(
+            process_magic("%vars", &mut state, false, "default"),
+            MagicResult::Output(out) if out.starts_with("No expressions")
+        ));
+        state.add_entry(
+            "Symbols x, y;\nLocal F = x;\nLocal E = (x+y)^2;\nPrint;".to_string(),
+            Some("   F =\n      x;\n\n   E =\n      x^2 + 2*x*y + y^2;".to_string()),
+            None,
+        );
+        match process_magic("%vars", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "Expressions: E, F\nSymbols:     x, y"),
+            _ => panic!("Expected Output result"),
+        }
+    }
+    
+    #[test]
+    fn test_magic_diff() {
+        let mut state = SessionState::new();
+        state.add_entry("a".to_string(), Some("E = x + 2*y;".to_string()), None);
+        state.add_entry("b".to_string(), Some("E = x + 3*y;".to_string()), None);
+        match process_magic("%diff", &mut state, false, "default") {
+            MagicResult::Output(out) => assert_eq!(out, "E = x + [-2-]{+3+}*y;"),
+            _ => panic!("Expected Output result"),
+        }
+        match process_magic("%diff --color 1 2", &mut state, false, "default") {
+            MagicResult::Output(out) => assert!(out.contains("\x1b[9m2")),
+            _ => panic!("Expected Output result"),
+        }
     }
     
     #[test]
@@ -1,8 +1,22 @@
 // FORM REPL modules
 
+pub mod cache;
+pub mod completion;
 pub mod config;
+pub mod doc;
+pub mod examples;
 pub mod form;
+pub mod formatter;
 pub mod highlight;
+pub mod history;
+pub mod linter;
 pub mod magic;
+pub mod notebook;
+pub mod postproc;
+pub mod prompt;
+pub mod server;
+pub mod session;
+pub mod store;
 pub mod term;
 pub mod theme;
+pub mod watch;
@@ -0,0 +1,159 @@
+// Jupyter notebook export/import for REPL sessions
+use serde_json::{json, Value};
+
+use super::magic::SessionState;
+
+/// Split text into notebook source lines, keeping the trailing newlines
+fn to_source_lines(text: &str) -> Vec<String> {
+    text.split_inclusive('\n').map(str::to_string).collect()
+}
+
+/// Join a notebook multiline string (either a string or an array of strings)
+fn from_source_lines(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// Converts the session history into a Jupyter (nbformat 4.5) notebook.
+///
+/// Each history entry becomes a code cell with a unique `id` (required
+/// since 4.5); its output, if any, is stored as an `execute_result` with a
+/// `text/plain` payload.
+pub fn session_to_notebook(state: &SessionState) -> String {
+    let cells: Vec<Value> = state
+        .history
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let outputs = match entry.output {
+                Some(ref out) if !out.trim().is_empty() => vec![json!({
+                    "output_type": "execute_result",
+                    "execution_count": entry.number,
+                    "data": { "text/plain": to_source_lines(out) },
+                    "metadata": {},
+                })],
+                _ => Vec::new(),
+            };
+            json!({
+                "id": format!("cell-{}", i + 1),
+                "cell_type": "code",
+                "execution_count": entry.number,
+                "metadata": {},
+                "outputs": outputs,
+                "source": to_source_lines(&entry.input),
+            })
+        })
+        .collect();
+
+    let notebook = json!({
+        "cells": cells,
+        "metadata": {
+            "kernelspec": {
+                "name": "form",
+                "display_name": "FORM",
+                "language": "form",
+            },
+            "language_info": {
+                "name": "form",
+                "file_extension": ".frm",
+            },
+        },
+        "nbformat": 4,
+        "nbformat_minor": 5,
+    });
+
+    // Serializing a json! value cannot fail
+    serde_json::to_string_pretty(&notebook).unwrap_or_default()
+}
+
+/// Reads the code cells of a notebook back as `(input, output)` pairs.
+///
+/// Markdown and raw cells are skipped. Outputs are taken from
+/// `execute_result`/`display_data` text or from `stream` text.
+pub fn session_from_notebook(json: &str) -> Result<Vec<(String, String)>, String> {
+    let notebook: Value =
+        serde_json::from_str(json).map_err(|e| format!("Invalid notebook JSON: {}", e))?;
+
+    let cells = notebook
+        .get("cells")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "Notebook has no 'cells' array".to_string())?;
+
+    let mut entries = Vec::new();
+    for cell in cells {
+        if cell.get("cell_type").and_then(Value::as_str) != Some("code") {
+            continue;
+        }
+
+        let input = cell.get("source").map(from_source_lines).unwrap_or_default();
+
+        let output: String = cell
+            .get("outputs")
+            .and_then(Value::as_array)
+            .map(|outputs| {
+                outputs
+                    .iter()
+                    .filter_map(|o| {
+                        o.get("data")
+                            .and_then(|d| d.get("text/plain"))
+                            .or_else(|| o.get("text"))
+                    })
+                    .map(from_source_lines)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        entries.push((input, output));
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notebook_round_trip() {
+        let mut state = SessionState::new();
+        state.add_entry(
+            "Symbol x,y;\nLocal E = (x+y)^2;\nPrint;".to_string(),
+            Some("   E =\n      x^2 + 2*x*y + y^2;".to_string()),
+            None,
+        );
+        state.add_entry("Symbol z;".to_string(), None, None);
+
+        let json = session_to_notebook(&state);
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["metadata"]["kernelspec"]["name"], "form");
+        assert_eq!(value["nbformat"], 4);
+        assert_eq!(value["nbformat_minor"], 5);
+        let ids: Vec<&str> = value["cells"].as_array().unwrap().iter().map(|c| c["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["cell-1", "cell-2"]);
+
+        let entries = session_from_notebook(&json).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "Symbol x,y;\nLocal E = (x+y)^2;\nPrint;");
+        assert_eq!(entries[0].1, "   E =\n      x^2 + 2*x*y + y^2;");
+        assert_eq!(entries[1], ("Symbol z;".to_string(), String::new()));
+    }
+
+    #[test]
+    fn test_session_from_notebook_minimal() {
+        let json = r##"{
+            "cells": [
+                {"cell_type": "markdown", "metadata": {}, "source": "# Title"},
+                {"cell_type": "code", "metadata": {}, "outputs": [
+                    {"output_type": "stream", "name": "stdout", "text": ["E = 1;\n"]}
+                ], "source": "Local E = 1;\nPrint;"}
+            ],
+            "metadata": {}, "nbformat": 4, "nbformat_minor": 5
+        }"##;
+        let entries = session_from_notebook(json).unwrap();
+        assert_eq!(entries, vec![("Local E = 1;\nPrint;".to_string(), "E = 1;\n".to_string())]);
+        assert!(session_from_notebook("not json").is_err());
+    }
+}
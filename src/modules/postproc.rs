@@ -0,0 +1,261 @@
+// Output post-processors - transform FORM output before it is displayed
+
+/// A named transformation applied to formatted FORM output
+pub trait PostProcessor {
+    /// Name used with `%postproc enable|disable`
+    fn name(&self) -> &str;
+    /// One-line summary shown by `%postproc`
+    fn description(&self) -> &str;
+    /// Transform the output text
+    fn process(&self, output: &str) -> String;
+}
+
+/// Adapts a plain `fn(&str) -> String` into a `PostProcessor`
+pub struct FnProcessor {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub func: fn(&str) -> String,
+}
+
+impl PostProcessor for FnProcessor {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn description(&self) -> &str {
+        self.description
+    }
+
+    fn process(&self, output: &str) -> String {
+        (self.func)(output)
+    }
+}
+
+/// Available post-processors and the ones currently enabled
+pub struct Registry {
+    processors: Vec<Box<dyn PostProcessor>>,
+    /// Enabled processor names, in the order they are applied
+    enabled: Vec<String>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+            .with(FnProcessor {
+                name: "collapse_whitespace",
+                description: "Collapse runs of spaces inside each line",
+                func: collapse_whitespace,
+            })
+            .with(FnProcessor {
+                name: "sort_terms",
+                description: "Sort the terms of each expression",
+                func: sort_terms,
+            })
+    }
+}
+
+impl Registry {
+    /// An empty registry with no processors
+    pub fn new() -> Self {
+        Registry {
+            processors: Vec::new(),
+            enabled: Vec::new(),
+        }
+    }
+
+    /// Add a processor (builder style)
+    pub fn with(mut self, processor: impl PostProcessor + 'static) -> Self {
+        self.register(Box::new(processor));
+        self
+    }
+
+    /// Add a processor, replacing any existing one with the same name
+    pub fn register(&mut self, processor: Box<dyn PostProcessor>) {
+        self.processors.retain(|p| p.name() != processor.name());
+        self.processors.push(processor);
+    }
+
+    /// Enable a processor; it runs after those already enabled
+    pub fn enable(&mut self, name: &str) -> Result<(), String> {
+        if !self.processors.iter().any(|p| p.name() == name) {
+            return Err(format!(
+                "Unknown post-processor: {} (available: {})",
+                name,
+                self.names().join(", ")
+            ));
+        }
+        if !self.is_enabled(name) {
+            self.enabled.push(name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Disable a processor
+    pub fn disable(&mut self, name: &str) -> Result<(), String> {
+        if !self.is_enabled(name) {
+            return Err(format!("Post-processor {} is not enabled", name));
+        }
+        self.enabled.retain(|n| n != name);
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.iter().any(|n| n == name)
+    }
+
+    /// Names of all registered processors
+    pub fn names(&self) -> Vec<&str> {
+        self.processors.iter().map(|p| p.name()).collect()
+    }
+
+    /// Listing for `%postproc`, marking enabled processors with `*`
+    pub fn describe(&self) -> String {
+        let width = self.processors.iter().map(|p| p.name().len()).max().unwrap_or(0);
+        self.processors
+            .iter()
+            .map(|p| {
+                let mark = if self.is_enabled(p.name()) { '*' } else { ' ' };
+                format!("{} {:<width$}  {}", mark, p.name(), p.description(), width = width)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Run the enabled processors over `output`, in enable order
+    pub fn apply(&self, output: &str) -> String {
+        let mut text = output.to_string();
+        for name in &self.enabled {
+            if let Some(p) = self.processors.iter().find(|p| p.name() == name) {
+                text = p.process(&text);
+            }
+        }
+        text
+    }
+}
+
+/// Collapse runs of whitespace to one space, keeping each line's indentation
+pub fn collapse_whitespace(output: &str) -> String {
+    output
+        .lines()
+        .map(|line| {
+            let body = line.trim_start();
+            let indent = &line[..line.len() - body.len()];
+            format!("{}{}", indent, body.split_whitespace().collect::<Vec<_>>().join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Sort the `+ term` / `- term` lines of each `NAME =` ... `;` block.
+///
+/// Lines that don't start with a sign are continuations of the term above
+/// and move with it. The closing `;` stays on the last line.
+pub fn sort_terms(output: &str) -> String {
+    let mut result: Vec<String> = Vec::new();
+    let mut terms: Vec<Vec<&str>> = Vec::new();
+    let mut in_expr = false;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if !in_expr {
+            result.push(line.to_string());
+            in_expr = trimmed.ends_with('=');
+            continue;
+        }
+
+        let starts_term = trimmed.starts_with('+') || trimmed.starts_with('-');
+        match terms.last_mut() {
+            Some(term) if !starts_term => term.push(line),
+            _ => terms.push(vec![line]),
+        }
+        if trimmed.ends_with(';') {
+            flush_terms(&mut terms, &mut result);
+            in_expr = false;
+        }
+    }
+    // An unterminated block (e.g. truncated output) is left as it was
+    for term in terms {
+        result.extend(term.into_iter().map(String::from));
+    }
+    result.join("\n")
+}
+
+fn flush_terms(terms: &mut Vec<Vec<&str>>, result: &mut Vec<String>) {
+    let mut sorted: Vec<Vec<String>> = terms
+        .drain(..)
+        .map(|term| term.into_iter().map(String::from).collect())
+        .collect();
+    if let Some(last) = sorted.last_mut().and_then(|t| t.last_mut()) {
+        *last = last.trim_end().trim_end_matches(';').to_string();
+    }
+    sorted.sort_by_key(|term| sort_key(term));
+    if let Some(last) = sorted.last_mut().and_then(|t| t.last_mut()) {
+        last.push(';');
+    }
+    result.extend(sorted.into_iter().flatten());
+}
+
+/// Compare terms by their text, ignoring the sign and layout
+fn sort_key(term: &[String]) -> String {
+    let text: String = term.iter().flat_map(|l| l.split_whitespace()).collect();
+    text.trim_start_matches(['+', '-']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collapse_whitespace() {
+        assert_eq!(
+            collapse_whitespace("   E =\n      x  +   2*y;"),
+            "   E =\n      x + 2*y;"
+        );
+    }
+
+    #[test]
+    fn test_sort_terms() {
+        let output = "   E =\n      + c\n      - a*b\n       + a*b^2\n      + b;\n\n   F = 1;";
+        assert_eq!(
+            sort_terms(output),
+            "   E =\n      - a*b\n       + a*b^2\n      + b\n      + c;\n\n   F = 1;"
+        );
+        assert_eq!(sort_terms("   E =\n      + b\n      + a"), "   E =\n      + b\n      + a");
+    }
+
+    #[test]
+    fn test_registry_enable_order_and_errors() {
+        let mut registry = Registry::default();
+        assert_eq!(registry.apply("  E  =  1;"), "  E  =  1;");
+
+        registry.enable("collapse_whitespace").unwrap();
+        assert_eq!(registry.apply("  E  =  1;"), "  E = 1;");
+        assert!(registry.describe().contains("* collapse_whitespace"));
+
+        assert!(registry.enable("nope").unwrap_err().contains("sort_terms"));
+        registry.disable("collapse_whitespace").unwrap();
+        assert!(registry.disable("collapse_whitespace").is_err());
+        assert_eq!(registry.apply("  E  =  1;"), "  E  =  1;");
+    }
+
+    #[test]
+    fn test_registry_custom_processor() {
+        struct Upper;
+        impl PostProcessor for Upper {
+            fn name(&self) -> &str {
+                "upper"
+            }
+            fn description(&self) -> &str {
+                "Upper-case everything"
+            }
+            fn process(&self, output: &str) -> String {
+                output.to_uppercase()
+            }
+        }
+
+        let mut registry = Registry::default().with(Upper);
+        registry.enable("upper").unwrap();
+        registry.enable("collapse_whitespace").unwrap();
+        assert_eq!(registry.apply("  e =  x;"), "  E = X;");
+    }
+}
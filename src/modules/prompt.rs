@@ -0,0 +1,155 @@
+// Prompt templates - the In/Out/continuation prompts, configurable via [prompt]
+use regex::{Captures, Regex};
+use std::sync::LazyLock;
+
+use super::config::PromptConfig;
+use super::term::{self, ansi};
+use super::theme::Theme;
+
+/// Built-in templates, matching the IPython-style defaults
+const DEFAULT_INPUT: &str = "{prompt_in}{bold}In [{n}]{session}:{reset} ";
+const DEFAULT_OUTPUT: &str = "{prompt_out}{bold}Out[{n}]:{reset} ";
+const DEFAULT_CONTINUATION: &str = "{prompt_cont}...:{reset} ";
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{(\w+)\}").unwrap());
+
+/// Renders the prompts from the `[prompt]` templates, falling back to the
+/// built-in ones for any template that isn't set.
+///
+/// Templates may use `{n}` (cell number), `{version}` (REPL version),
+/// `{time}` (HH:MM:SS), `{form_version}`, `{session}` (` (name)` for a
+/// named session, else empty), and the color markers
+/// `{prompt_in}`, `{prompt_out}`, `{prompt_cont}`, `{timing}`, `{error}`,
+/// `{bold}` and `{reset}`, which are empty when highlighting is off.
+/// Unknown `{names}` are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct Prompts {
+    config: PromptConfig,
+    form_version: Option<String>,
+    session: Option<String>,
+}
+
+impl Prompts {
+    pub fn new(config: PromptConfig, form_version: Option<String>) -> Self {
+        Prompts {
+            config,
+            form_version,
+            session: None,
+        }
+    }
+
+    /// Set the session name shown by `{session}`
+    pub fn set_session(&mut self, name: Option<String>) {
+        self.session = name;
+    }
+
+    /// Whether any template needs `{form_version}` (so it's worth detecting)
+    pub fn needs_form_version(config: &PromptConfig) -> bool {
+        [&config.input, &config.output, &config.continuation]
+            .iter()
+            .any(|t| t.as_deref().is_some_and(|t| t.contains("{form_version}")))
+    }
+
+    pub fn input(&self, n: usize, theme: &Theme, highlight: bool) -> String {
+        let template = self.config.input.as_deref().unwrap_or(DEFAULT_INPUT);
+        self.render(template, n, theme, highlight)
+    }
+
+    pub fn output(&self, n: usize, theme: &Theme, highlight: bool) -> String {
+        let template = self.config.output.as_deref().unwrap_or(DEFAULT_OUTPUT);
+        self.render(template, n, theme, highlight)
+    }
+
+    /// The continuation prompt, right-aligned under the input prompt.
+    ///
+    /// With no templates configured the padding is the historical
+    /// `len(n) + 5` spaces.
+    pub fn continuation(&self, n: usize, theme: &Theme, highlight: bool) -> String {
+        let template = self.config.continuation.as_deref().unwrap_or(DEFAULT_CONTINUATION);
+        let prompt = self.render(template, n, theme, highlight);
+        let padding = if self.config.input.is_none() && self.config.continuation.is_none() {
+            n.to_string().len() + 5
+        } else {
+            let input_width = term::visible_width(&self.input(n, theme, highlight));
+            input_width.saturating_sub(term::visible_width(&prompt))
+        };
+        format!("{}{}", " ".repeat(padding), prompt)
+    }
+
+    /// Substitute the placeholders in `template`
+    pub fn render(&self, template: &str, n: usize, theme: &Theme, highlight: bool) -> String {
+        PLACEHOLDER
+            .replace_all(template, |caps: &Captures| {
+                let color = |code: &str| if highlight { code.to_string() } else { String::new() };
+                match &caps[1] {
+                    "n" => n.to_string(),
+                    "version" => env!("CARGO_PKG_VERSION").to_string(),
+                    "time" => chrono::Local::now().format("%H:%M:%S").to_string(),
+                    "form_version" => self.form_version.clone().unwrap_or_else(|| "?".to_string()),
+                    "session" => self.session.as_ref().map(|s| format!(" ({})", s)).unwrap_or_default(),
+                    "prompt_in" => color(&theme.prompt_in),
+                    "prompt_out" => color(&theme.prompt_out),
+                    "prompt_cont" => color(&theme.prompt_cont),
+                    "timing" => color(&theme.timing),
+                    "error" => color(&theme.error),
+                    "bold" => color(ansi::BOLD),
+                    "reset" => color(ansi::RESET),
+                    _ => caps[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_prompts() {
+        let prompts = Prompts::default();
+        let theme = Theme::none();
+        assert_eq!(prompts.input(12, &theme, false), "In [12]: ");
+        assert_eq!(prompts.output(12, &theme, false), "Out[12]: ");
+        assert_eq!(prompts.continuation(12, &theme, false), "       ...: ");
+
+        let mut named = Prompts::default();
+        named.set_session(Some("qcd".to_string()));
+        assert_eq!(named.input(3, &theme, false), "In [3] (qcd): ");
+
+        let theme = Theme::default();
+        assert_eq!(
+            prompts.input(1, &theme, true),
+            format!("{}{}In [1]:{} ", theme.prompt_in, ansi::BOLD, ansi::RESET)
+        );
+    }
+
+    #[test]
+    fn test_custom_template_and_padding() {
+        let config = PromptConfig {
+            input: Some("{prompt_in}form {form_version} [{n}]{reset}> ".to_string()),
+            output: Some("={n}= {unknown}".to_string()),
+            continuation: None,
+        };
+        let prompts = Prompts::new(config.clone(), Some("4.3.1".to_string()));
+        assert!(Prompts::needs_form_version(&config));
+        let theme = Theme::default();
+
+        assert_eq!(prompts.input(7, &theme, false), "form 4.3.1 [7]> ");
+        assert_eq!(prompts.output(7, &theme, false), "=7= {unknown}");
+
+        // {time} is the wall clock as HH:MM:SS
+        let time = prompts.render("{time}", 1, &theme, false);
+        assert_eq!(time.len(), 8);
+        assert!(time.chars().enumerate().all(|(i, c)| if i % 3 == 2 { c == ':' } else { c.is_ascii_digit() }));
+
+        // The continuation prompt ends where the input prompt does,
+        // with or without color codes
+        for highlight in [false, true] {
+            let input = prompts.input(7, &theme, highlight);
+            let cont = prompts.continuation(7, &theme, highlight);
+            assert_eq!(term::visible_width(&cont), term::visible_width(&input));
+            assert!(term::strip_ansi(&cont).ends_with("...: "));
+        }
+    }
+}
@@ -0,0 +1,566 @@
+// JSON-RPC server speaking a small subset of LSP, for editor plugins (--server)
+//
+// Messages use LSP's `Content-Length` framing. Supported methods:
+// initialize, shutdown, exit, textDocument/didOpen, textDocument/didChange
+// (full text), textDocument/didClose, textDocument/completion,
+// textDocument/hover and textDocument/diagnostics (pull model). Requests
+// may also carry the text directly as `textDocument.text` instead of
+// opening the document first.
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::config::{self, ServerConfig};
+use super::doc;
+use super::form;
+use super::highlight;
+use super::linter::{self, Level};
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Largest message body accepted, so a bad header can't exhaust memory
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// LSP `CompletionItemKind`s used in completions
+const KIND_FUNCTION: u32 = 3;
+const KIND_VARIABLE: u32 = 6;
+const KIND_KEYWORD: u32 = 14;
+
+/// Where the server listens
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Tcp(u16),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    /// `--server-port` wins, then `[server] port`, then `[server] socket`,
+    /// then a socket next to the config file (a TCP port off Unix)
+    pub fn resolve(cli_port: Option<u16>, config: &ServerConfig) -> Endpoint {
+        if let Some(port) = cli_port.or(config.port) {
+            return Endpoint::Tcp(port);
+        }
+        #[cfg(unix)]
+        {
+            Endpoint::Unix(
+                config
+                    .socket
+                    .as_deref()
+                    .map(config::expand_path)
+                    .unwrap_or_else(config::server_socket_path),
+            )
+        }
+        #[cfg(not(unix))]
+        {
+            Endpoint::Tcp(ServerConfig::DEFAULT_PORT)
+        }
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp(port) => write!(f, "127.0.0.1:{}", port),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Reads one `Content-Length`-framed message; `None` at end of stream
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is over the {}-byte limit", length, MAX_MESSAGE_LEN),
+        ));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes `body` with its `Content-Length` header
+pub fn write_message(writer: &mut impl Write, body: &str) -> io::Result<()> {
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Answers LSP-style requests about FORM code
+#[derive(Default)]
+pub struct JsonRpcServer {
+    /// Open documents, uri -> full text
+    documents: Mutex<HashMap<String, String>>,
+}
+
+impl JsonRpcServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles one message. Returns the response to a request, or `None`
+    /// for a notification (a message without an `id`).
+    pub fn handle_message(&self, text: &str) -> Option<String> {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        let id = message.get("id").cloned();
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            return Some(error_response(id.unwrap_or(Value::Null), INVALID_REQUEST, "missing method"));
+        };
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let result = self.dispatch(method, &params);
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}).to_string(),
+            Err((code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    fn dispatch(&self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": {},
+                    "hoverProvider": true,
+                    "diagnosticProvider": {
+                        "interFileDependencies": false,
+                        "workspaceDiagnostics": false
+                    }
+                },
+                "serverInfo": {"name": "form-repl", "version": env!("CARGO_PKG_VERSION")}
+            })),
+            "initialized" | "shutdown" | "exit" => Ok(Value::Null),
+            "textDocument/didOpen" => {
+                let doc = &params["textDocument"];
+                if let (Some(uri), Some(text)) = (doc["uri"].as_str(), doc["text"].as_str()) {
+                    self.documents.lock().unwrap().insert(uri.to_string(), text.to_string());
+                }
+                Ok(Value::Null)
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str();
+                // Full sync: the last change holds the whole text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    self.documents.lock().unwrap().insert(uri.to_string(), text.to_string());
+                }
+                Ok(Value::Null)
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                    self.documents.lock().unwrap().remove(uri);
+                }
+                Ok(Value::Null)
+            }
+            "textDocument/completion" => {
+                let text = self.document_text(params)?;
+                let (line, character) = position(params)?;
+                Ok(completions(&text, &word_before(&text, line, character)))
+            }
+            "textDocument/hover" => {
+                let text = self.document_text(params)?;
+                let (line, character) = position(params)?;
+                Ok(hover(&word_at(&text, line, character)))
+            }
+            "textDocument/diagnostics" | "textDocument/diagnostic" => {
+                let text = self.document_text(params)?;
+                Ok(json!({"kind": "full", "items": diagnostics(&text)}))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("method not found: {}", method))),
+        }
+    }
+
+    /// The text a request is about: given inline, or an open document's
+    fn document_text(&self, params: &Value) -> Result<String, (i64, String)> {
+        let doc = &params["textDocument"];
+        if let Some(text) = doc["text"].as_str() {
+            return Ok(text.to_string());
+        }
+        let uri = doc["uri"]
+            .as_str()
+            .ok_or((INVALID_PARAMS, "missing textDocument".to_string()))?;
+        self.documents
+            .lock()
+            .unwrap()
+            .get(uri)
+            .cloned()
+            .ok_or((INVALID_PARAMS, format!("document not open: {}", uri)))
+    }
+
+    /// Answers messages from one client until it disconnects or sends `exit`
+    pub fn serve_connection(&self, mut reader: impl BufRead, mut writer: impl Write) -> io::Result<()> {
+        while let Some(message) = read_message(&mut reader)? {
+            if let Some(response) = self.handle_message(&message) {
+                write_message(&mut writer, &response)?;
+            }
+            let is_exit = serde_json::from_str::<Value>(&message)
+                .is_ok_and(|m| m["method"] == "exit");
+            if is_exit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Accepts clients on `endpoint` until the process is stopped, one
+    /// thread per connection
+    pub fn serve(self, endpoint: &Endpoint) -> io::Result<()> {
+        let server = Arc::new(self);
+        match endpoint {
+            Endpoint::Tcp(port) => {
+                let listener = TcpListener::bind(("127.0.0.1", *port))?;
+                Self::accept_loop(&server, listener.incoming(), TcpStream::try_clone);
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                use std::os::unix::net::{UnixListener, UnixStream};
+
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                // A socket left over from an earlier run blocks the bind;
+                // anything else at that path is not ours to remove
+                match std::fs::symlink_metadata(path) {
+                    Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} exists and is not a socket", path.display()),
+                        ))
+                    }
+                    Err(_) => {}
+                }
+                let listener = UnixListener::bind(path)?;
+                Self::accept_loop(&server, listener.incoming(), UnixStream::try_clone);
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves each client from `incoming` on its own thread; `try_clone`
+    /// gives the connection a separate reading half. One failed accept
+    /// (e.g. out of file descriptors) doesn't stop the server.
+    fn accept_loop<S>(
+        server: &Arc<Self>,
+        incoming: impl Iterator<Item = io::Result<S>>,
+        try_clone: fn(&S) -> io::Result<S>,
+    ) where
+        S: Read + Write + Send + 'static,
+    {
+        for stream in incoming {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("server: cannot accept a client: {}", e);
+                    continue;
+                }
+            };
+            let server = Arc::clone(server);
+            thread::spawn(move || {
+                let reader = BufReader::new(try_clone(&stream)?);
+                server.serve_connection(reader, stream)
+            });
+        }
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> String {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}).to_string()
+}
+
+/// The 0-based `position` of a request. LSP counts `character` in UTF-16
+/// code units; see `char_index`.
+fn position(params: &Value) -> Result<(usize, usize), (i64, String)> {
+    let pos = &params["position"];
+    match (pos["line"].as_u64(), pos["character"].as_u64()) {
+        (Some(line), Some(character)) => Ok((line as usize, character as usize)),
+        _ => Err((INVALID_PARAMS, "missing position".to_string())),
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '$' | '#')
+}
+
+/// The index in `chars` of the UTF-16 offset `character` (LSP's default
+/// position encoding)
+fn char_index(chars: &[char], character: usize) -> usize {
+    let mut units = 0;
+    chars
+        .iter()
+        .position(|c| {
+            units += c.len_utf16();
+            units > character
+        })
+        .unwrap_or(chars.len())
+}
+
+/// The part of the word before the cursor
+fn word_before(text: &str, line: usize, character: usize) -> String {
+    let chars: Vec<char> = text.lines().nth(line).unwrap_or("").chars().collect();
+    let end = char_index(&chars, character);
+    let start = chars[..end].iter().rposition(|c| !is_word_char(*c)).map_or(0, |i| i + 1);
+    chars[start..end].iter().collect()
+}
+
+/// The whole word under the cursor
+fn word_at(text: &str, line: usize, character: usize) -> String {
+    let chars: Vec<char> = text.lines().nth(line).unwrap_or("").chars().collect();
+    let rest: String = chars
+        .iter()
+        .skip(char_index(&chars, character))
+        .take_while(|c| is_word_char(**c))
+        .collect();
+    word_before(text, line, character) + &rest
+}
+
+/// Keywords, built-ins and names declared in `text` that start with `prefix`
+fn completions(text: &str, prefix: &str) -> Value {
+    let prefix = prefix.to_lowercase();
+    let matches = |word: &str| word.to_lowercase().starts_with(&prefix);
+    // Sorted, and a declared name wins over a keyword spelled the same
+    let mut items: BTreeMap<String, u32> = BTreeMap::new();
+    for word in highlight::form_words().filter(|w| matches(w)) {
+        let kind = if highlight::is_function(word) { KIND_FUNCTION } else { KIND_KEYWORD };
+        items.insert(word.to_string(), kind);
+    }
+    let declared: HashSet<String> = linter::declarations(text);
+    for name in declared.into_iter().filter(|n| matches(n)) {
+        items.insert(name, KIND_VARIABLE);
+    }
+    let items: Vec<Value> = items
+        .into_iter()
+        .map(|(label, kind)| json!({"label": label, "kind": kind}))
+        .collect();
+    json!({"isIncomplete": false, "items": items})
+}
+
+/// The `%help` text for `word`, if it is documented
+fn hover(word: &str) -> Value {
+    match doc::lookup(word) {
+        Some((name, text)) => json!({
+            "contents": {"kind": "markdown", "value": format!("**{}**\n\n```\n{}\n```", name, text)}
+        }),
+        None => Value::Null,
+    }
+}
+
+/// Bracket and declaration problems as LSP diagnostics
+fn diagnostics(text: &str) -> Vec<Value> {
+    let lines: Vec<&str> = text.lines().collect();
+    let line_range = |line: usize| {
+        let len = lines.get(line).map_or(0, |l| l.encode_utf16().count());
+        json!({"start": {"line": line, "character": 0}, "end": {"line": line, "character": len}})
+    };
+    let mut items = Vec::new();
+    if let Err(message) = form::validate_input(text) {
+        items.push(json!({
+            "range": line_range(0),
+            "severity": 1,
+            "source": "form-repl",
+            "message": message
+        }));
+    }
+    for warning in form::validate_input_strict(text, &HashSet::new()) {
+        let severity = match warning.severity {
+            Level::Error => 1,
+            Level::Warning => 2,
+        };
+        items.push(json!({
+            "range": line_range((warning.line as usize).saturating_sub(1)),
+            "severity": severity,
+            "source": "form-repl",
+            "message": warning.message
+        }));
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn request(server: &JsonRpcServer, method: &str, params: Value) -> Value {
+        let message = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        let response = server.handle_message(&message.to_string()).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn test_message_framing() {
+        let mut out = Vec::new();
+        write_message(&mut out, "{\"a\":1}").unwrap();
+        write_message(&mut out, "{}").unwrap();
+        assert!(out.starts_with(b"Content-Length: 7\r\n\r\n{\"a\":1}"));
+
+        let mut reader = Cursor::new(out);
+        assert_eq!(read_message(&mut reader).unwrap().as_deref(), Some("{\"a\":1}"));
+        assert_eq!(read_message(&mut reader).unwrap().as_deref(), Some("{}"));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_oversized_message_is_rejected() {
+        let header = format!("Content-Length: {}\r\n\r\n{{}}", MAX_MESSAGE_LEN + 1);
+        let error = read_message(&mut Cursor::new(header)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_positions_count_utf16_units() {
+        // `𝛼` is two UTF-16 units, `é` one
+        let text = "* 𝛼é\nLocal E = alpha;";
+        assert_eq!(word_before("𝛼 al", 0, 5), "al");
+        assert_eq!(word_at(text, 1, 11), "alpha");
+        assert_eq!(word_before("x = é𝛼ab", 0, 9), "é𝛼ab");
+        let report = diagnostics("Local F = (𝛼;");
+        assert_eq!(report[0]["range"]["end"]["character"], 14);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_keeps_a_file_that_is_not_a_socket() {
+        let path = std::env::temp_dir().join(format!("form-repl-server-test-{}", std::process::id()));
+        std::fs::write(&path, "keep me").unwrap();
+        let error = JsonRpcServer::new().serve(&Endpoint::Unix(path.clone())).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_accept_loop_survives_a_failed_accept() {
+        use std::os::unix::net::UnixStream;
+
+        let (client, connection) = UnixStream::pair().unwrap();
+        let incoming = vec![Err(io::Error::other("out of file descriptors")), Ok(connection)];
+        JsonRpcServer::accept_loop(&Arc::new(JsonRpcServer::new()), incoming.into_iter(), UnixStream::try_clone);
+
+        let mut writer = client.try_clone().unwrap();
+        write_message(&mut writer, r#"{"jsonrpc":"2.0","id":7,"method":"shutdown"}"#).unwrap();
+        let response: Value =
+            serde_json::from_str(&read_message(&mut BufReader::new(client)).unwrap().unwrap()).unwrap();
+        assert_eq!(response["id"], 7);
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let server = JsonRpcServer::new();
+        let response: Value = serde_json::from_str(&server.handle_message("{oops").unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+        assert_eq!(request(&server, "workspace/symbol", json!({}))["error"]["code"], METHOD_NOT_FOUND);
+        let unopened = json!({"textDocument": {"uri": "file:///a.frm"}, "position": {"line": 0, "character": 0}});
+        assert_eq!(request(&server, "textDocument/hover", unopened)["error"]["code"], INVALID_PARAMS);
+        // Notifications get no response
+        assert!(server.handle_message(r#"{"jsonrpc":"2.0","method":"initialized"}"#).is_none());
+        let init = request(&server, "initialize", json!({}));
+        assert_eq!(init["result"]["capabilities"]["hoverProvider"], true);
+    }
+
+    #[test]
+    fn test_completion_from_open_document() {
+        let server = JsonRpcServer::new();
+        let open = json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": {"uri": "file:///a.frm", "text": "Symbols alpha, beta;\nLocal E = al"}
+        }});
+        assert!(server.handle_message(&open.to_string()).is_none());
+        let params = json!({"textDocument": {"uri": "file:///a.frm"}, "position": {"line": 1, "character": 12}});
+        let items = request(&server, "textDocument/completion", params)["result"]["items"].clone();
+        let labels: Vec<&str> = items.as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap()).collect();
+        assert_eq!(labels, ["all", "alpha", "also"]);
+        assert_eq!(items[0]["kind"], KIND_KEYWORD);
+        assert_eq!(items[1]["kind"], KIND_VARIABLE);
+    }
+
+    #[test]
+    fn test_hover_and_diagnostics() {
+        let server = JsonRpcServer::new();
+        let text = "Symbol x;\nLocal E = x + y;\nid x = 1;";
+        let hover = request(&server, "textDocument/hover", json!({
+            "textDocument": {"text": text}, "position": {"line": 2, "character": 1}
+        }));
+        assert!(hover["result"]["contents"]["value"].as_str().unwrap().starts_with("**id**"));
+        let nothing = request(&server, "textDocument/hover", json!({
+            "textDocument": {"text": text}, "position": {"line": 1, "character": 6}
+        }));
+        assert_eq!(nothing["result"], Value::Null);
+        let function = request(&server, "textDocument/hover", json!({
+            "textDocument": {"text": "Local F = abs_(-3);"}, "position": {"line": 0, "character": 11}
+        }));
+        assert!(function["result"]["contents"]["value"].as_str().unwrap().starts_with("**abs_**"));
+
+        let report = request(&server, "textDocument/diagnostics", json!({"textDocument": {"text": text}}));
+        let items = report["result"]["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["range"]["start"]["line"], 1);
+        assert!(items[0]["message"].as_str().unwrap().contains('y'));
+    }
+
+    #[test]
+    fn test_serve_connection_stops_at_exit() {
+        let server = JsonRpcServer::new();
+        let mut input = Vec::new();
+        write_message(&mut input, r#"{"jsonrpc":"2.0","id":1,"method":"shutdown"}"#).unwrap();
+        write_message(&mut input, r#"{"jsonrpc":"2.0","method":"exit"}"#).unwrap();
+        write_message(&mut input, r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#).unwrap();
+        let mut output = Vec::new();
+        server.serve_connection(Cursor::new(input), &mut output).unwrap();
+
+        let mut reader = Cursor::new(output);
+        let response: Value = serde_json::from_str(&read_message(&mut reader).unwrap().unwrap()).unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_endpoint_resolution() {
+        let config = ServerConfig { port: Some(9000), socket: None };
+        assert_eq!(Endpoint::resolve(Some(9100), &config), Endpoint::Tcp(9100));
+        assert_eq!(Endpoint::resolve(None, &config), Endpoint::Tcp(9000));
+        #[cfg(unix)]
+        {
+            let config = ServerConfig { port: None, socket: Some("/tmp/f.sock".to_string()) };
+            assert_eq!(Endpoint::resolve(None, &config), Endpoint::Unix(PathBuf::from("/tmp/f.sock")));
+        }
+    }
+}
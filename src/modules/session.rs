@@ -0,0 +1,129 @@
+// Named sessions behind %session - history saved as JSON, one file per name
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One history entry as stored on disk
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedEntry {
+    pub number: usize,
+    pub input: String,
+    pub output: Option<String>,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    /// First block of the `%load`ed script this block came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loaded_with: Option<usize>,
+}
+
+/// A saved session: its history and the next cell number
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub session_number: usize,
+    pub history: Vec<SavedEntry>,
+}
+
+/// Session names become file names, so keep them to a safe alphabet
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid session name '{}': use letters, digits, '_' and '-'",
+            name
+        ))
+    }
+}
+
+fn session_file(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    validate_name(name)?;
+    Ok(dir.join(format!("{}.json", name)))
+}
+
+/// Names of the saved sessions, sorted
+pub fn list(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let path = e.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            path.file_stem()?.to_str().map(String::from)
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Writes a session, creating the directory if needed
+pub fn save(dir: &Path, name: &str, session: &SavedSession) -> Result<(), String> {
+    let path = session_file(dir, name)?;
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let json = serde_json::to_string_pretty(session).map_err(|e| e.to_string())?;
+    fs::write(&path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+pub fn load(dir: &Path, name: &str) -> Result<SavedSession, String> {
+    let path = session_file(dir, name)?;
+    let content = fs::read_to_string(&path).map_err(|_| format!("No saved session '{}'", name))?;
+    serde_json::from_str(&content)
+        .map_err(|e| format!("Corrupt session file {}: {}", path.display(), e))
+}
+
+pub fn delete(dir: &Path, name: &str) -> Result<(), String> {
+    let path = session_file(dir, name)?;
+    fs::remove_file(&path).map_err(|_| format!("No saved session '{}'", name))
+}
+
+/// Renames a session, refusing to overwrite an existing one
+pub fn rename(dir: &Path, old: &str, new: &str) -> Result<(), String> {
+    let from = session_file(dir, old)?;
+    let to = session_file(dir, new)?;
+    if !from.is_file() {
+        return Err(format!("No saved session '{}'", old));
+    }
+    if to.exists() {
+        return Err(format!("Session '{}' already exists", new));
+    }
+    fs::rename(&from, &to).map_err(|e| format!("Failed to rename {}: {}", from.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_files() {
+        let dir = std::env::temp_dir().join(format!("form-repl-session-test-{}", std::process::id()));
+        let session = SavedSession {
+            session_number: 2,
+            history: vec![SavedEntry {
+                number: 1,
+                input: "Local E = x;".to_string(),
+                output: Some("   E = x;".to_string()),
+                duration: Some(0.5),
+                loaded_with: None,
+            }],
+        };
+
+        assert!(list(&dir).is_empty());
+        save(&dir, "alpha", &session).unwrap();
+        save(&dir, "beta", &session).unwrap();
+        assert_eq!(list(&dir), ["alpha", "beta"]);
+        assert_eq!(load(&dir, "alpha").unwrap(), session);
+
+        assert!(rename(&dir, "alpha", "beta").unwrap_err().contains("already exists"));
+        rename(&dir, "alpha", "gamma").unwrap();
+        delete(&dir, "beta").unwrap();
+        assert_eq!(list(&dir), ["gamma"]);
+        assert!(load(&dir, "beta").is_err());
+        assert!(save(&dir, "../escape", &session).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
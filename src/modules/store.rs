@@ -0,0 +1,95 @@
+// Persistent named-expression cache behind %store/%restore
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Stored expressions, name -> FORM output text
+pub type Store = BTreeMap<String, String>;
+
+/// Reads the cache file.
+///
+/// A missing file is an empty store. A corrupt file is also treated as
+/// empty, with a warning for the user as the second element.
+pub fn load(path: &Path) -> (Store, Option<String>) {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return (Store::new(), None),
+    };
+    match serde_json::from_str(&content) {
+        Ok(store) => (store, None),
+        Err(e) => (
+            Store::new(),
+            Some(format!(
+                "Warning: ignoring corrupt store file {}: {}",
+                path.display(),
+                e
+            )),
+        ),
+    }
+}
+
+/// Writes the cache file, creating its directory if needed
+pub fn save(path: &Path, store: &Store) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let json = serde_json::to_string_pretty(store).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Pulls the `NAME = ...;` block for one expression out of FORM output
+pub fn extract_expression(output: &str, name: &str) -> Option<String> {
+    let mut lines = output.lines();
+    let first = lines.find(|line| {
+        line.trim_start()
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.trim_start().starts_with('='))
+    })?;
+
+    let mut block = vec![first];
+    if !first.trim_end().ends_with(';') {
+        for line in lines {
+            block.push(line);
+            if line.trim_end().ends_with(';') {
+                break;
+            }
+        }
+    }
+    Some(block.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_expression() {
+        let output = "   E =\n      x^2 + 2*x + 1;\n\n   F =\n      y;";
+        assert_eq!(
+            extract_expression(output, "E").as_deref(),
+            Some("   E =\n      x^2 + 2*x + 1;")
+        );
+        assert_eq!(extract_expression(output, "F").as_deref(), Some("   F =\n      y;"));
+        assert_eq!(extract_expression("   EF = 1;", "E"), None);
+        assert_eq!(extract_expression(output, "G"), None);
+    }
+
+    #[test]
+    fn test_store_round_trip_and_corrupt_file() {
+        let path = std::env::temp_dir()
+            .join(format!("form-repl-store-test-{}", std::process::id()))
+            .join("store.json");
+
+        let mut store = Store::new();
+        store.insert("E".to_string(), "   E =\n      x^2;".to_string());
+        save(&path, &store).unwrap();
+        assert_eq!(load(&path), (store, None));
+
+        fs::write(&path, "{ not json").unwrap();
+        let (store, warning) = load(&path);
+        assert!(store.is_empty());
+        assert!(warning.unwrap().contains("corrupt"));
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -1,43 +1,105 @@
 // Terminal utilities
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
 
-/// Thread-safe verbose flag using AtomicBool
-/// This prevents data races and follows Rust's safety guarantees
-pub static VERBOSE: AtomicBool = AtomicBool::new(false);
+use log::{LevelFilter, Log, Metadata, Record};
+use regex::Regex;
 
-/// Check if verbose mode is enabled
-#[inline]
-pub fn is_verbose() -> bool {
-    VERBOSE.load(Ordering::Relaxed)
+/// Macro for verbose printing with formatting, routed through the logger
+#[macro_export]
+macro_rules! vprintln {
+    () => {
+        log::debug!("")
+    };
+    ($($arg:tt)*) => {
+        log::debug!($($arg)*)
+    };
 }
 
-/// Set verbose mode
-#[inline]
-pub fn set_verbose(enabled: bool) {
-    VERBOSE.store(enabled, Ordering::Relaxed);
+/// Crates whose records are logged without `RUST_LOG`: this one and the
+/// FORM driver it is built on
+const OWN_CRATES: [&str; 2] = [env!("CARGO_CRATE_NAME"), "form_core"];
+
+/// Leveled logger writing timestamped lines to stderr or a log file
+pub struct ReplLogger {
+    level: LevelFilter,
+    /// Only log records from this crate and form-core (dependencies such
+    /// as rustyline are chatty at debug level). Cleared when `RUST_LOG` is set.
+    own_crate_only: bool,
+    sink: Mutex<Box<dyn Write + Send>>,
 }
 
-/// Print message only if verbose mode is enabled
-#[inline]
-pub fn verbose_println(msg: &str) {
-    if is_verbose() {
-        eprintln!("[verbose] {}", msg);
+impl ReplLogger {
+    pub fn new(level: LevelFilter, sink: Box<dyn Write + Send>) -> Self {
+        ReplLogger {
+            level,
+            own_crate_only: true,
+            sink: Mutex::new(sink),
+        }
     }
 }
 
-/// Macro for conditional verbose printing with formatting
-#[macro_export]
-macro_rules! vprintln {
-    () => {
-        if $crate::modules::term::is_verbose() {
-            eprintln!();
+impl Log for ReplLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+            && (!self.own_crate_only
+                || OWN_CRATES.iter().any(|name| metadata.target().starts_with(name)))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
         }
-    };
-    ($($arg:tt)*) => {
-        if $crate::modules::term::is_verbose() {
-            eprintln!("[verbose] {}", format!($($arg)*));
+        if let Ok(mut sink) = self.sink.lock() {
+            let _ = writeln!(
+                sink,
+                "{} {:<5} {}",
+                chrono::Local::now().format("%H:%M:%S%.3f"),
+                record.level(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut sink) = self.sink.lock() {
+            let _ = sink.flush();
         }
+    }
+}
+
+/// Install the global logger.
+///
+/// The level comes from `RUST_LOG` (`error`, `warn`, `info`, `debug`,
+/// `trace`, `off`) if set, else `debug` in verbose mode and `warn`
+/// otherwise. Without `RUST_LOG`, only this crate's and form-core's records
+/// are shown.
+/// Logs go to the file named by `FORM_REPL_LOG`, else stderr.
+pub fn init_logging(verbose: bool) {
+    let default = if verbose { LevelFilter::Debug } else { LevelFilter::Warn };
+    let from_env: Option<LevelFilter> = env::var("RUST_LOG").ok().and_then(|v| v.parse().ok());
+    let level = from_env.unwrap_or(default);
+
+    let sink: Box<dyn Write + Send> = match env::var("FORM_REPL_LOG") {
+        Ok(path) => match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("Warning: Could not open log file {}: {}", path, e);
+                Box::new(io::stderr())
+            }
+        },
+        Err(_) => Box::new(io::stderr()),
     };
+
+    let mut logger = ReplLogger::new(level, sink);
+    logger.own_crate_only = from_env.is_none();
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level);
+    }
 }
 
 /// ANSI escape code utilities
@@ -48,17 +110,6 @@ pub mod ansi {
     pub const BOLD: &str = "\x1b[1m";
     /// Dim text
     pub const DIM: &str = "\x1b[2m";
-    /// Italic text
-    pub const ITALIC: &str = "\x1b[3m";
-    /// Underline text
-    pub const UNDERLINE: &str = "\x1b[4m";
-    
-    /// Clear the current line
-    pub const CLEAR_LINE: &str = "\x1b[2K";
-    /// Move cursor to beginning of line
-    pub const LINE_START: &str = "\r";
-    /// Move cursor up one line
-    pub const CURSOR_UP: &str = "\x1b[A";
     
     /// Check if stdout is a terminal
     pub fn is_tty() -> bool {
@@ -68,9 +119,12 @@ pub mod ansi {
     
     /// Get terminal width (returns 80 as default if unable to determine)
     pub fn terminal_width() -> usize {
-        // Try to get terminal size using a simple method
-        // In a real implementation, you might use the `terminal_size` crate
-        80
+        // The line editor knows the real size; this is the fallback
+        std::env::var("COLUMNS")
+            .ok()
+            .and_then(|c| c.parse().ok())
+            .filter(|w| *w > 0)
+            .unwrap_or(80)
     }
 }
 
@@ -90,9 +144,182 @@ pub fn format_duration(duration: std::time::Duration) -> String {
     }
 }
 
-/// Horizontal separator line
-pub fn separator(width: usize, colored: bool, color: &str) -> String {
-    let line: String = "─".repeat(width);
+/// CSI sequences (`ESC [` params, intermediates, final byte in `@..~`;
+/// covers SGR colors and cursor movement) and OSC sequences (`ESC ]` ...
+/// ended by BEL or `ESC \`, e.g. titles and hyperlinks). A sequence cut
+/// off at the end of the text matches too.
+static ANSI_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b\[[0-?]*[ -/]*(?:[@-~]|$)|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\|$)").unwrap()
+});
+
+/// Remove ANSI escape sequences (colors, cursor movement, OSC) from a string
+pub fn strip_ansi(s: &str) -> String {
+    ANSI_RE.replace_all(s, "").into_owned()
+}
+
+/// Number of columns `s` occupies on screen.
+///
+/// Complete CSI escape sequences (`ESC [` params final-byte) take no space;
+/// every other character counts as one column.
+pub fn visible_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+    
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            let mut rest = chars.clone();
+            if rest.next() == Some('[') && rest.any(|c| ('@'..='~').contains(&c)) {
+                chars = rest;
+                continue;
+            }
+        }
+        width += 1;
+    }
+    
+    width
+}
+
+/// Split `text` into a visible prefix of at most `width` characters and the rest.
+///
+/// Returns the byte offset to split at, preferring the last break before
+/// a `+`/`-`, then the last one after a `*`, or `None` if the text fits.
+/// ANSI escape sequences take no width and stay with the text they precede.
+fn wrap_point(text: &str, width: usize) -> Option<usize> {
+    let mut visible = 0;
+    let mut prev: Option<char> = None;
+    let mut last_visible_end = 0;
+    let mut before_sign = None;
+    let mut after_star = None;
+    let mut chars = text.char_indices().peekable();
+    
+    while let Some((i, c)) = chars.next() {
+        if c == '\x1b' && matches!(chars.peek(), Some((_, '['))) {
+            chars.next();
+            for (_, c) in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+            continue;
+        }
+        
+        if visible == width {
+            return Some(before_sign.or(after_star).unwrap_or(last_visible_end));
+        }
+        
+        // Break before a sign, unless it belongs to an exponent or argument
+        if (c == '+' || c == '-')
+            && visible > 0
+            && !matches!(prev, Some('^' | '(' | ',' | '*' | '/' | '='))
+        {
+            before_sign = Some(last_visible_end);
+        }
+        visible += 1;
+        last_visible_end = i + c.len_utf8();
+        if c == '*' {
+            after_star = Some(last_visible_end);
+        }
+        prev = Some(c);
+    }
+    
+    None
+}
+
+/// Wrap a line to `width` columns with a hanging indent.
+///
+/// The first piece is returned as is (the caller prints it after a prompt
+/// that is `indent` columns wide); later pieces are prefixed with `indent`
+/// spaces. Breaks fall on operator boundaries where possible.
+pub fn wrap_line(text: &str, width: usize, indent: usize) -> Vec<String> {
+    let avail = width.saturating_sub(indent).max(1);
+    let pad = " ".repeat(indent);
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    
+    while let Some(at) = wrap_point(rest, avail) {
+        let (head, tail) = rest.split_at(at);
+        pieces.push(head.trim_end().to_string());
+        rest = tail.trim_start();
+    }
+    pieces.push(rest.to_string());
+    
+    for piece in pieces.iter_mut().skip(1) {
+        piece.insert_str(0, &pad);
+    }
+    pieces
+}
+
+/// Shows `text` through `$PAGER` (default `less -R`) and waits for it
+pub fn page(text: &str) -> io::Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| "less -R".to_string());
+    let mut words = pager.split_whitespace();
+    let program = words.next().unwrap_or("less");
+    let mut child = Command::new(program).args(words).stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may be quit before it has read everything
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// `$EDITOR`, else `vi` (`notepad` on Windows)
+pub fn editor() -> String {
+    env::var("EDITOR")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string())
+}
+
+/// Opens `text` in `editor` on a temp file and returns what was saved.
+///
+/// `Ok(None)` if the file was left empty or unchanged; an editor that
+/// exits with an error is an `Err`. The temp file is removed either way.
+pub fn edit_text(text: &str, editor: &str) -> io::Result<Option<String>> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!(
+        "form-repl-edit-{}-{}.frm",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    // End on a new line so the cursor starts below the pre-filled cell
+    let prefill = if text.is_empty() || text.ends_with('\n') {
+        text.to_string()
+    } else {
+        format!("{}\n", text)
+    };
+    std::fs::write(&path, prefill)?;
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let edited = Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .and_then(|status| {
+            if !status.success() {
+                return Err(io::Error::other(format!("{} exited with {}", program, status)));
+            }
+            std::fs::read_to_string(&path)
+        });
+    let _ = std::fs::remove_file(&path);
+    let edited = edited?;
+    if edited.trim().is_empty() || edited.trim() == text.trim() {
+        return Ok(None);
+    }
+    Ok(Some(edited.trim_end().to_string()))
+}
+
+/// `pattern` repeated to exactly `width` characters, the last copy cut short
+pub fn tile(pattern: &str, width: usize) -> String {
+    pattern.chars().cycle().take(width).collect()
+}
+
+/// Horizontal separator line of `pattern` tiled to `width`
+pub fn separator(pattern: &str, width: usize, colored: bool, color: &str) -> String {
+    let line = tile(pattern, width);
     if colored && !color.is_empty() {
         format!("{}{}{}", color, line, ansi::RESET)
     } else {
@@ -100,11 +327,77 @@ pub fn separator(width: usize, colored: bool, color: &str) -> String {
     }
 }
 
+/// ASCII bar chart, one `label  duration |####` line per entry.
+///
+/// The longest duration gets a bar of `width` characters and the others
+/// are scaled to it; any non-zero duration gets at least one `#`.
+pub fn render_bar_chart(entries: &[(String, std::time::Duration)], width: usize) -> String {
+    let max = entries.iter().map(|(_, d)| *d).max().unwrap_or_default();
+    let label_width = entries.iter().map(|(l, _)| l.chars().count()).max().unwrap_or(0);
+    let durations: Vec<String> = entries.iter().map(|(_, d)| format_duration(*d)).collect();
+    let duration_width = durations.iter().map(|d| d.chars().count()).max().unwrap_or(0);
+
+    entries
+        .iter()
+        .zip(&durations)
+        .map(|((label, d), shown)| {
+            let len = if max.is_zero() {
+                0
+            } else {
+                let scaled = (d.as_secs_f64() / max.as_secs_f64() * width as f64).round() as usize;
+                if d.is_zero() { 0 } else { scaled.max(1) }
+            };
+            format!(
+                "{:<lw$}  {:>dw$} |{}",
+                label,
+                shown,
+                "#".repeat(len),
+                lw = label_width,
+                dw = duration_width
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::time::Duration;
     
+    #[cfg(unix)]
+    #[test]
+    fn test_edit_text_with_mock_editor() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("form-repl-editor-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let editor = |name: &str, script: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.display().to_string()
+        };
+
+        // The saved file, pre-filled text included, becomes the cell
+        let append = editor("append", "printf 'Print;\\n' >> \"$1\"");
+        assert_eq!(
+            edit_text("Local E = x;", &append).unwrap().as_deref(),
+            Some("Local E = x;\nPrint;")
+        );
+        let unchanged = editor("unchanged", "true");
+        assert_eq!(edit_text("Local E = x;", &unchanged).unwrap(), None);
+        let empty = editor("empty", ": > \"$1\"");
+        assert_eq!(edit_text("Local E = x;", &empty).unwrap(), None);
+        let failing = editor("failing", "echo 'Print;' >> \"$1\"; exit 1");
+        assert!(edit_text("", &failing).is_err());
+        assert!(std::fs::read_dir(env::temp_dir())
+            .unwrap()
+            .filter_map(Result::ok)
+            .all(|e| !e.file_name().to_string_lossy().starts_with(&format!("form-repl-edit-{}-", std::process::id()))));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_format_duration() {
         assert!(format_duration(Duration::from_micros(500)).contains("µs"));
@@ -113,9 +406,161 @@ mod tests {
         assert!(format_duration(Duration::from_secs(90)).contains("m"));
     }
     
+    #[test]
+    fn test_logger_writes_to_sink_only() {
+        use std::sync::Arc;
+        
+        #[derive(Clone)]
+        struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+        impl Write for SharedBuf {
+            fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(data)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        
+        let buf = SharedBuf(Arc::new(Mutex::new(Vec::new())));
+        let logger = ReplLogger::new(LevelFilter::Debug, Box::new(buf.clone()));
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Debug)
+                .target("form_repl::modules::form")
+                .args(format_args!("run_form: exit code 0"))
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Trace)
+                .target("form_repl::modules::form")
+                .args(format_args!("too detailed"))
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Debug)
+                .target("rustyline")
+                .args(format_args!("dependency noise"))
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Debug)
+                .target("form_core::run")
+                .args(format_args!("run_form: 12 bytes of input"))
+                .build(),
+        );
+        
+        let logged = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert!(logged.contains("DEBUG run_form: exit code 0"));
+        assert!(!logged.contains("too detailed"));
+        assert!(!logged.contains("dependency noise"));
+        assert!(logged.contains("DEBUG run_form: 12 bytes of input"));
+    }
+    
+    #[test]
+    fn test_strip_ansi() {
+        let colored = "\x1b[38;5;208m\x1b[1mOut[1]:\x1b[0m x\x1b[38;5;251m^\x1b[0m2";
+        assert_eq!(strip_ansi(colored), "Out[1]: x^2");
+        assert_eq!(strip_ansi("plain text"), "plain text");
+    }
+
+    #[test]
+    fn test_strip_ansi_cursor_and_stray_escapes() {
+        // Cursor movement and erase sequences go too, multi-line text is kept
+        assert_eq!(strip_ansi("\x1b[2K\x1b[1A   E =\n\x1b[32m      x;\x1b[m"), "   E =\n      x;");
+        // A lone ESC that doesn't start a CSI sequence is left alone
+        assert_eq!(strip_ansi("a\x1bb"), "a\x1bb");
+        // An unterminated sequence at the end is dropped
+        assert_eq!(strip_ansi("x^2\x1b[38;5"), "x^2");
+    }
+
+    #[test]
+    fn test_strip_ansi_osc() {
+        // Window title (BEL) and hyperlink (ST) sequences
+        assert_eq!(strip_ansi("\x1b]0;form-repl\x07In [1]: "), "In [1]: ");
+        assert_eq!(
+            strip_ansi("\x1b]8;;https://example.org\x1b\\link\x1b]8;;\x1b\\ text"),
+            "link text"
+        );
+        let all = "\x1b]2;t\x07\x1b[1m\x1b[38;5;39mE\x1b[0m = \x1b[?25lx\x1b[?25h;";
+        let stripped = strip_ansi(all);
+        assert_eq!(stripped, "E = x;");
+        assert!(!stripped.contains('\x1b'));
+    }
+    
+    #[test]
+    fn test_wrap_line_polynomial() {
+        let poly: String = (1..=40).map(|i| format!(" + {}*x^{}", i % 10, i)).collect();
+        let poly = poly.trim_start().to_string();
+        assert!(poly.len() >= 200);
+        
+        let pieces = wrap_line(&poly, 80, 8);
+        assert!(pieces.len() > 2);
+        assert!(pieces[0].chars().count() <= 72);
+        for piece in &pieces[1..] {
+            assert!(piece.starts_with("        + "));
+            assert!(piece.chars().count() <= 80);
+        }
+        // Nothing is lost apart from the whitespace at the breaks
+        let joined: String = pieces.iter().map(|p| p.trim()).collect::<Vec<_>>().join(" ");
+        assert_eq!(joined, poly);
+    }
+    
+    #[test]
+    fn test_wrap_line_ignores_ansi_and_hard_breaks() {
+        assert_eq!(wrap_line("\x1b[1mshort\x1b[0m", 10, 2), ["\x1b[1mshort\x1b[0m"]);
+        assert_eq!(wrap_line("abcdefghij", 6, 2), ["abcd", "  efgh", "  ij"]);
+        assert_eq!(wrap_line("x^-1+y", 5, 0), ["x^-1", "+y"]);
+    }
+    
+    #[test]
+    fn test_visible_width() {
+        let plain = "Out[12]: ";
+        let colored = "\x1b[38;5;208m\x1b[1mOut[12]:\x1b[0m ";
+        assert_eq!(visible_width(plain), 9);
+        assert_eq!(visible_width(colored), visible_width(plain));
+        assert_eq!(visible_width(""), 0);
+        assert_eq!(visible_width("µs ─"), 4);
+        // A truncated sequence is not an escape code, so it is counted
+        assert_eq!(visible_width("x\x1b[38"), 5);
+    }
+    
+    #[test]
+    fn test_render_bar_chart() {
+        let entries = vec![
+            ("In [2]".to_string(), Duration::from_millis(400)),
+            ("In [10]".to_string(), Duration::from_millis(200)),
+            ("In [3]".to_string(), Duration::from_millis(1)),
+        ];
+        assert_eq!(
+            render_bar_chart(&entries, 10),
+            "In [2]   400.00ms |##########\n\
+             In [10]  200.00ms |#####\n\
+             In [3]     1.00ms |#"
+        );
+        assert_eq!(render_bar_chart(&entries, 4).lines().next().unwrap(), "In [2]   400.00ms |####");
+        assert_eq!(render_bar_chart(&[("a".to_string(), Duration::ZERO)], 10), "a  0.00µs |");
+        assert_eq!(render_bar_chart(&[], 10), "");
+    }
+    
     #[test]
     fn test_separator() {
-        let sep = separator(10, false, "");
-        assert_eq!(sep.chars().count(), 10);
+        let sep = separator("─", 10, false, "");
+        assert_eq!(sep, "─".repeat(10));
+        let colored = separator("=", 3, true, "\x1b[2m");
+        assert_eq!(strip_ansi(&colored), "===");
+    }
+
+    #[test]
+    fn test_tile_pattern_to_width() {
+        assert_eq!(tile("- ", 7), "- - - -");
+        assert_eq!(tile("- ", 6), "- - - ");
+        assert_eq!(tile("=", 4), "====");
+        assert_eq!(tile("─·", 5), "─·─·─");
+        assert_eq!(tile("abc", 2), "ab");
+        assert_eq!(tile("abc", 0), "");
+        assert_eq!(tile("", 5), "");
     }
 }
@@ -1,5 +1,4 @@
 // Theme definitions for syntax highlighting
-use serde::Deserialize;
 
 /// Color theme for REPL output and syntax highlighting
 /// Contains ANSI escape codes for different token types
@@ -19,8 +18,11 @@ pub struct Theme {
     pub declaration: String,    // Declarations (Symbol, Local, etc.)
     pub function: String,       // Built-in functions
     pub preprocessor: String,   // Preprocessor (#, .end, etc.)
+    pub dollar_var: String,     // Dollar variables ($x, $sum)
+    pub preproc_arg: String,    // Preprocessor variable substitutions (`N')
     pub number: String,         // Numbers
     pub operator: String,       // Operators
+    pub set_or_index: String,   // Set braces {...} and [name] brackets
     pub comment: String,        // Comments
     pub string: String,         // String literals
     pub identifier: String,     // User identifiers
@@ -42,8 +44,11 @@ impl Default for Theme {
             declaration: String::from("\x1b[38;5;39m"),  // Blue
             function: String::from("\x1b[38;5;221m"),    // Yellow
             preprocessor: String::from("\x1b[38;5;208m"),// Orange
+            dollar_var: String::from("\x1b[38;5;208m"),  // Orange
+            preproc_arg: String::from("\x1b[38;5;208m"), // Orange
             number: String::from("\x1b[38;5;147m"),      // Light purple
             operator: String::from("\x1b[38;5;251m"),    // Light gray
+            set_or_index: String::from("\x1b[38;5;180m"),// Tan
             comment: String::from("\x1b[38;5;242m\x1b[3m"), // Gray italic
             string: String::from("\x1b[38;5;113m"),      // Green
             identifier: String::new(),                   // No color (default)
@@ -67,8 +72,11 @@ impl Theme {
             declaration: String::new(),
             function: String::new(),
             preprocessor: String::new(),
+            dollar_var: String::new(),
+            preproc_arg: String::new(),
             number: String::new(),
             operator: String::new(),
+            set_or_index: String::new(),
             comment: String::new(),
             string: String::new(),
             identifier: String::new(),
@@ -90,14 +98,69 @@ impl Theme {
             declaration: String::from("\x1b[38;5;33m"),  // Blue
             function: String::from("\x1b[38;5;166m"),    // Orange
             preprocessor: String::from("\x1b[38;5;136m"),// Yellow
+            dollar_var: String::from("\x1b[38;5;136m"),  // Yellow
+            preproc_arg: String::from("\x1b[38;5;136m"), // Yellow
             number: String::from("\x1b[38;5;37m"),       // Cyan
             operator: String::from("\x1b[38;5;245m"),    // Base0
+            set_or_index: String::from("\x1b[38;5;37m"), // Cyan
             comment: String::from("\x1b[38;5;240m\x1b[3m"), // Base01 italic
             string: String::from("\x1b[38;5;64m"),       // Green
             identifier: String::new(),
         }
     }
 
+    /// Solarized Light theme (same accents, darker tones for a light background)
+    pub fn solarized_light() -> Self {
+        Theme {
+            prompt_in: String::from("\x1b[38;5;25m"),    // Blue
+            prompt_out: String::from("\x1b[38;5;130m"),  // Orange
+            prompt_cont: String::from("\x1b[38;5;245m"), // Base1
+            separator: String::from("\x1b[38;5;187m"),   // Base2
+            error: String::from("\x1b[38;5;160m"),       // Red
+            timing: String::from("\x1b[38;5;245m"),      // Base1
+            output_label: String::from("\x1b[38;5;30m"), // Cyan
+            
+            keyword: String::from("\x1b[38;5;125m"),     // Magenta
+            declaration: String::from("\x1b[38;5;25m"),  // Blue
+            function: String::from("\x1b[38;5;130m"),    // Orange
+            preprocessor: String::from("\x1b[38;5;94m"), // Yellow
+            dollar_var: String::from("\x1b[38;5;94m"),   // Yellow
+            preproc_arg: String::from("\x1b[38;5;94m"),  // Yellow
+            number: String::from("\x1b[38;5;30m"),       // Cyan
+            operator: String::from("\x1b[38;5;241m"),    // Base00
+            set_or_index: String::from("\x1b[38;5;37m"), // Cyan
+            comment: String::from("\x1b[38;5;245m\x1b[3m"), // Base1 italic
+            string: String::from("\x1b[38;5;64m"),       // Green
+            identifier: String::new(),
+        }
+    }
+
+    /// Light theme - dark colors for light terminal backgrounds
+    pub fn light() -> Self {
+        Theme {
+            prompt_in: String::from("\x1b[38;5;19m"),    // Navy
+            prompt_out: String::from("\x1b[38;5;124m"),  // Dark red
+            prompt_cont: String::from("\x1b[38;5;244m"), // Gray
+            separator: String::from("\x1b[38;5;250m"),   // Light gray
+            error: String::from("\x1b[38;5;160m"),       // Red
+            timing: String::from("\x1b[38;5;244m"),      // Gray
+            output_label: String::from("\x1b[38;5;23m"), // Teal
+            
+            keyword: String::from("\x1b[38;5;90m"),      // Purple
+            declaration: String::from("\x1b[38;5;19m"),  // Navy
+            function: String::from("\x1b[38;5;94m"),     // Brown
+            preprocessor: String::from("\x1b[38;5;124m"),// Dark red
+            dollar_var: String::from("\x1b[38;5;124m"),  // Dark red
+            preproc_arg: String::from("\x1b[38;5;124m"), // Dark red
+            number: String::from("\x1b[38;5;55m"),       // Indigo
+            operator: String::from("\x1b[38;5;238m"),    // Dark gray
+            set_or_index: String::from("\x1b[38;5;30m"), // Teal
+            comment: String::from("\x1b[38;5;244m\x1b[3m"), // Gray italic
+            string: String::from("\x1b[38;5;28m"),       // Green
+            identifier: String::new(),                   // No color (default)
+        }
+    }
+
     /// Monokai theme
     pub fn monokai() -> Self {
         Theme {
@@ -113,8 +176,11 @@ impl Theme {
             declaration: String::from("\x1b[38;5;81m"),  // Cyan
             function: String::from("\x1b[38;5;148m"),    // Green
             preprocessor: String::from("\x1b[38;5;208m"),// Orange
+            dollar_var: String::from("\x1b[38;5;208m"),  // Orange
+            preproc_arg: String::from("\x1b[38;5;208m"), // Orange
             number: String::from("\x1b[38;5;141m"),      // Purple
             operator: String::from("\x1b[38;5;197m"),    // Pink
+            set_or_index: String::from("\x1b[38;5;81m"), // Blue
             comment: String::from("\x1b[38;5;242m\x1b[3m"), // Gray italic
             string: String::from("\x1b[38;5;186m"),      // Yellow
             identifier: String::from("\x1b[38;5;231m"), // White
@@ -136,8 +202,11 @@ impl Theme {
             declaration: String::from("\x1b[38;5;117m"), // Cyan
             function: String::from("\x1b[38;5;84m"),     // Green
             preprocessor: String::from("\x1b[38;5;215m"),// Orange
+            dollar_var: String::from("\x1b[38;5;215m"),  // Orange
+            preproc_arg: String::from("\x1b[38;5;215m"), // Orange
             number: String::from("\x1b[38;5;141m"),      // Purple
             operator: String::from("\x1b[38;5;212m"),    // Pink
+            set_or_index: String::from("\x1b[38;5;117m"),// Cyan
             comment: String::from("\x1b[38;5;61m\x1b[3m"), // Comment italic
             string: String::from("\x1b[38;5;228m"),      // Yellow
             identifier: String::from("\x1b[38;5;231m"), // Foreground
@@ -159,8 +228,11 @@ impl Theme {
             declaration: String::from("\x1b[38;5;110m"),// Nord9 (blue)
             function: String::from("\x1b[38;5;109m"),   // Nord8 (cyan)
             preprocessor: String::from("\x1b[38;5;180m"),// Nord13 (yellow)
+            dollar_var: String::from("\x1b[38;5;180m"),  // Nord13 (yellow)
+            preproc_arg: String::from("\x1b[38;5;180m"), // Nord13 (yellow)
             number: String::from("\x1b[38;5;139m"),     // Nord15 (purple)
             operator: String::from("\x1b[38;5;109m"),   // Nord8
+            set_or_index: String::from("\x1b[38;5;110m"),// Nord9
             comment: String::from("\x1b[38;5;60m\x1b[3m"), // Nord3 italic
             string: String::from("\x1b[38;5;150m"),     // Nord14 (green)
             identifier: String::from("\x1b[38;5;254m"),// Nord6 (white)
@@ -182,8 +254,11 @@ impl Theme {
             declaration: String::from("\x1b[38;5;214m"), // Orange
             function: String::from("\x1b[38;5;142m"),    // Green
             preprocessor: String::from("\x1b[38;5;175m"),// Purple
+            dollar_var: String::from("\x1b[38;5;175m"),  // Purple
+            preproc_arg: String::from("\x1b[38;5;175m"), // Purple
             number: String::from("\x1b[38;5;175m"),      // Purple
             operator: String::from("\x1b[38;5;223m"),    // Light
+            set_or_index: String::from("\x1b[38;5;108m"),// Aqua
             comment: String::from("\x1b[38;5;245m\x1b[3m"), // Gray italic
             string: String::from("\x1b[38;5;142m"),      // Green
             identifier: String::from("\x1b[38;5;223m"), // Light
@@ -205,8 +280,11 @@ impl Theme {
             declaration: String::from("\x1b[38;5;39m"),  // Blue
             function: String::from("\x1b[38;5;38m"),     // Cyan
             preprocessor: String::from("\x1b[38;5;209m"),// Orange
+            dollar_var: String::from("\x1b[38;5;209m"),  // Orange
+            preproc_arg: String::from("\x1b[38;5;209m"), // Orange
             number: String::from("\x1b[38;5;209m"),      // Orange
             operator: String::from("\x1b[38;5;176m"),    // Purple
+            set_or_index: String::from("\x1b[38;5;180m"),// Yellow
             comment: String::from("\x1b[38;5;241m\x1b[3m"), // Gray italic
             string: String::from("\x1b[38;5;113m"),      // Green
             identifier: String::from("\x1b[38;5;204m"), // Red (for contrast)
@@ -214,13 +292,6 @@ impl Theme {
     }
 }
 
-/// Configuration for theme from TOML file
-#[derive(Debug, Deserialize, Default)]
-#[serde(default)]
-pub struct ThemeConfig {
-    pub name: String,
-}
-
 /// Gets a theme by name.
 ///
 /// # Arguments
@@ -234,6 +305,8 @@ pub fn get_theme(name: &str) -> Theme {
     match name.to_lowercase().as_str() {
         "none" | "plain" | "no-color" => Theme::none(),
         "solarized-dark" | "solarized" | "solarized_dark" => Theme::solarized_dark(),
+        "solarized-light" | "solarized_light" => Theme::solarized_light(),
+        "light" => Theme::light(),
         "monokai" => Theme::monokai(),
         "dracula" => Theme::dracula(),
         "nord" => Theme::nord(),
@@ -249,6 +322,8 @@ pub fn list_themes() -> Vec<&'static str> {
         "default",
         "none",
         "solarized-dark",
+        "solarized-light",
+        "light",
         "monokai",
         "dracula",
         "nord",
@@ -256,3 +331,51 @@ pub fn list_themes() -> Vec<&'static str> {
         "one-dark",
     ]
 }
+
+/// Theme picked by --light
+pub const LIGHT_DEFAULT: &str = "light";
+
+/// Whether a `COLORFGBG` value ("fg;bg", set by rxvt, Konsole and others)
+/// describes a light background. Of the 16 base colors, 7 (light gray)
+/// and 9-15 are light backgrounds; `None` if the value can't be read.
+pub fn background_is_light(colorfgbg: &str) -> Option<bool> {
+    let bg: u8 = colorfgbg.rsplit(';').next()?.trim().parse().ok()?;
+    Some(bg == 7 || (9..=15).contains(&bg))
+}
+
+/// Theme to use when none was chosen: `light` on a light background
+/// (according to `COLORFGBG`), otherwise `default`
+pub fn auto_theme(colorfgbg: Option<&str>) -> &'static str {
+    match colorfgbg.and_then(background_is_light) {
+        Some(true) => LIGHT_DEFAULT,
+        _ => "default",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_light_themes() {
+        let light = get_theme("solarized-light");
+        let dark = get_theme("solarized-dark");
+        assert_ne!(light.prompt_in, dark.prompt_in);
+        assert_ne!(light.prompt_out, dark.prompt_out);
+        assert_ne!(light.prompt_cont, dark.prompt_cont);
+        assert_ne!(get_theme("light").prompt_in, Theme::default().prompt_in);
+        assert!(list_themes().contains(&"solarized-light"));
+        assert!(list_themes().contains(&LIGHT_DEFAULT));
+    }
+
+    #[test]
+    fn test_colorfgbg_picks_theme() {
+        assert_eq!(background_is_light("15;0"), Some(false));
+        assert_eq!(background_is_light("0;15"), Some(true));
+        assert_eq!(background_is_light("0;default;7"), Some(true));
+        assert_eq!(background_is_light("bogus"), None);
+        assert_eq!(auto_theme(Some("0;15")), "light");
+        assert_eq!(auto_theme(Some("15;0")), "default");
+        assert_eq!(auto_theme(None), "default");
+    }
+}
@@ -0,0 +1,133 @@
+// File watching behind %watch - re-run a .frm file whenever it changes
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::form::{self, FormError, FormResult, RunOptions};
+
+/// Pause after a change event so a save in progress can finish
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// One run of a watched file
+pub struct WatchRun {
+    /// The file contents that were run
+    pub input: String,
+    pub result: Result<FormResult, FormError>,
+}
+
+/// Watch `path` and run it with FORM each time its contents change,
+/// sending every run through `tx`.
+///
+/// The parent directory is watched rather than the file itself, so editors
+/// that save by replacing the file are still noticed. Events that leave the
+/// contents unchanged (or the file unreadable) don't trigger a run. Watching
+/// stops when the returned watcher is dropped.
+///
+/// Saves often truncate the file before writing it, so an empty file is
+/// taken to be mid-save and skipped.
+pub fn watch_and_run(
+    path: &Path,
+    form_path: &Path,
+    options: &RunOptions,
+    tx: Sender<WatchRun>,
+) -> notify::Result<RecommendedWatcher> {
+    let path = path.to_path_buf();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let form_path = form_path.to_path_buf();
+    let options = options.clone();
+    let mut last_input: Option<String> = fs::read_to_string(&path).ok();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            return;
+        }
+        if !event.paths.iter().any(|p| p.file_name() == path.file_name()) {
+            return;
+        }
+        thread::sleep(SETTLE);
+        let Ok(input) = fs::read_to_string(&path) else { return };
+        if input.trim().is_empty() || last_input.as_deref() == Some(input.as_str()) {
+            return;
+        }
+        last_input = Some(input.clone());
+        log::debug!("watch: {} changed, re-running", path.display());
+        let result = form::run_form(&input, &form_path, &options);
+        let _ = tx.send(WatchRun { input, result });
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// An active %watch: the watcher and the runs it has produced
+pub struct Watch {
+    pub path: PathBuf,
+    _watcher: RecommendedWatcher,
+    rx: Receiver<WatchRun>,
+}
+
+impl Watch {
+    /// Start watching an existing file
+    pub fn start(path: &Path, form_path: &Path, options: &RunOptions) -> Result<Self, String> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| format!("Cannot watch {}: {}", path.display(), e))?;
+        if !path.is_file() {
+            return Err(format!("Cannot watch {}: not a file", path.display()));
+        }
+        let (tx, rx) = mpsc::channel();
+        let watcher = watch_and_run(&path, form_path, options, tx)
+            .map_err(|e| format!("Cannot watch {}: {}", path.display(), e))?;
+        Ok(Watch {
+            path,
+            _watcher: watcher,
+            rx,
+        })
+    }
+
+    /// The most recent run since the last call, skipping any older ones
+    pub fn latest(&self) -> Option<WatchRun> {
+        self.rx.try_iter().last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_watch_reruns_on_change() {
+        // Fake FORM that echoes its input
+        let exe = form::mock_form("watch-test", "cat");
+        let dir = exe.parent().unwrap().to_path_buf();
+        let script = dir.join("calc.frm");
+        fs::write(&script, "Local E = 1;\n").unwrap();
+
+        let watch = Watch::start(&script, &exe, &RunOptions::default()).unwrap();
+        assert!(watch.latest().is_none());
+
+        fs::write(&script, "Local E = 2;\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let run = loop {
+            if let Ok(run) = watch.rx.recv_timeout(Duration::from_millis(100)) {
+                break run;
+            }
+            assert!(Instant::now() < deadline, "no run after modifying the file");
+        };
+        assert_eq!(run.input, "Local E = 2;\n");
+        assert!(run.result.unwrap().output.contains("Local E = 2;"));
+
+        assert!(Watch::start(&dir.join("missing.frm"), &exe, &RunOptions::default()).is_err());
+        drop(watch);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,76 @@
+// End-to-end check of `--output json`: every line on stdout is a JSON record
+#![cfg(unix)]
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A fake FORM that prints a banner and the text of each `#write "..."`
+fn fake_form(dir: &Path) -> PathBuf {
+    let exe = dir.join("form");
+    fs::write(
+        &exe,
+        "#!/bin/sh\necho 'FORM 4.3.1 (fake)'\necho\nsed -n 's/^#write \"\\(.*\\)\"/   \\1/p'\n",
+    )
+    .unwrap();
+    fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).unwrap();
+    exe
+}
+
+#[test]
+fn test_json_output_is_one_record_per_line() {
+    let dir = std::env::temp_dir().join(format!("form-repl-json-output-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let form = fake_form(&dir);
+
+    // A cell, magics with and without output, a bad magic, REPL commands,
+    // an unknown @macro and a dry run
+    let input = "\
+#write \"hello\"
+
+%time
+%nosuchmagic
+.help
+.nosuchcommand
+%history
+@nosuchmacro
+
+%dryrun on
+#write \"not run\"
+
+";
+    let mut child = Command::new(env!("CARGO_BIN_EXE_form-repl"))
+        .args(["--output", "json", "--form-path"])
+        .arg(&form)
+        .env("HOME", &dir)
+        .env_remove("FORM_FLAGS")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let records: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{}: {:?}\n{}", e, line, stdout)))
+        .collect();
+
+    let cell = &records[0];
+    assert_eq!(cell["input"], "#write \"hello\"");
+    assert_eq!(cell["output"].as_str().unwrap().trim(), "hello");
+    assert_eq!(cell["success"], true);
+    assert!(records.iter().any(|r| r["input"] == "%nosuchmagic" && r["success"] == false), "{}", stdout);
+    assert!(records.iter().any(|r| r["input"] == ".help" && r["output"].as_str().unwrap().contains("FORM REPL")));
+    assert!(records.iter().any(|r| r["input"] == ".nosuchcommand" && r["success"] == false), "{}", stdout);
+    assert!(records.iter().any(|r| r["input"] == "@nosuchmacro" && r["success"] == false), "{}", stdout);
+    let dry_run = records.last().unwrap();
+    assert!(dry_run["output"].as_str().unwrap().contains("#write \"not run\""), "{}", stdout);
+    assert!(dry_run["duration_ms"].is_null());
+}